@@ -0,0 +1,170 @@
+// helpers shared between shaders, pulled in with #include "common.glsl"
+
+vec3 rotate_y(vec3 direction, float angle) {
+    float s = sin(angle);
+    float c = cos(angle);
+    return vec3(c * direction.x + s * direction.z, direction.y, -s * direction.x + c * direction.z);
+}
+
+// output_params is the global uniform's x: output encoding, y: paper white in nits, z: peak luminance in nits,
+// w: exposure the scene's radiance is scaled by before tone mapping
+// MUST KEEP IN SYNC WITH OutputEncoding
+const float OUTPUT_ENCODING_SRGB_FORMAT = 0.0;
+const float OUTPUT_ENCODING_SRGB = 1.0;
+const float OUTPUT_ENCODING_SCRGB = 2.0;
+const float OUTPUT_ENCODING_HDR10 = 3.0;
+
+// reinhard with the curve approaching max_luminance instead of 1.0
+vec3 reinhard_tonemap(vec3 color, float max_luminance) {
+    return color / (color / max_luminance + vec3(1.0));
+}
+
+vec3 inverse_reinhard_tonemap(vec3 color, float max_luminance) {
+    vec3 clamped = min(color, vec3(max_luminance * 0.999));
+    return clamped / (vec3(1.0) - clamped / max_luminance);
+}
+
+// hdr displays can go above paper white, up to their peak luminance
+float tonemap_max_luminance(vec4 output_params) {
+    return output_params.x > OUTPUT_ENCODING_SCRGB - 0.5 ? max(output_params.z / output_params.y, 1.0) : 1.0;
+}
+
+// maps hdr radiance to display range, shared by the lit geometry and the skybox so a bright background clips the
+// same way as its reflections
+vec3 tonemap_scene_color(vec3 hdr_color, vec4 output_params) {
+    return reinhard_tonemap(hdr_color * output_params.w, tonemap_max_luminance(output_params));
+}
+
+// the exact sRGB transfer functions, matching what the hardware does for _SRGB formats
+vec3 srgb_from_linear(vec3 linear) {
+    bvec3 cutoff = lessThan(linear, vec3(0.0031308));
+    vec3 lower = linear * 12.92;
+    vec3 higher = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(higher, lower, cutoff);
+}
+
+vec3 linear_from_srgb(vec3 srgb) {
+    bvec3 cutoff = lessThan(srgb, vec3(0.04045));
+    vec3 lower = srgb / 12.92;
+    vec3 higher = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(higher, lower, cutoff);
+}
+
+// the SMPTE ST 2084 inverse EOTF, from absolute luminance to the PQ signal
+vec3 pq_from_nits(vec3 nits) {
+    const float m1 = 2610.0 / 16384.0;
+    const float m2 = 2523.0 / 4096.0 * 128.0;
+    const float c1 = 3424.0 / 4096.0;
+    const float c2 = 2413.0 / 4096.0 * 32.0;
+    const float c3 = 2392.0 / 4096.0 * 32.0;
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+// the SMPTE ST 2084 EOTF, from the PQ signal back to absolute luminance
+vec3 nits_from_pq(vec3 pq) {
+    const float m1 = 2610.0 / 16384.0;
+    const float m2 = 2523.0 / 4096.0 * 128.0;
+    const float c1 = 3424.0 / 4096.0;
+    const float c2 = 2413.0 / 4096.0 * 32.0;
+    const float c3 = 2392.0 / 4096.0 * 32.0;
+    vec3 e = pow(clamp(pq, 0.0, 1.0), vec3(1.0 / m2));
+    return pow(max(e - c1, 0.0) / (c2 - c3 * e), vec3(1.0 / m1)) * 10000.0;
+}
+
+// columns of the linear BT.709 to BT.2020 primaries conversion
+const mat3 BT709_TO_BT2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+// and back again
+const mat3 BT2020_TO_BT709 = mat3(
+    1.6605, -0.1246, -0.0182,
+    -0.5876, 1.1329, -0.1006,
+    -0.0728, -0.0083, 1.1187
+);
+
+// scRGB maps 1.0 to 80 nits
+const float SCRGB_WHITE_NITS = 80.0;
+
+// encodes linear sRGB primaries color, with 1.0 as paper white, for the swapchain's format and color space
+vec3 encode_output_color(vec3 color, vec4 output_params) {
+    float encoding = output_params.x;
+    if (encoding < OUTPUT_ENCODING_SRGB - 0.5) {
+        return color;
+    }
+    if (encoding < OUTPUT_ENCODING_SCRGB - 0.5) {
+        return srgb_from_linear(clamp(color, 0.0, 1.0));
+    }
+    float paper_white_nits = output_params.y;
+    if (encoding < OUTPUT_ENCODING_HDR10 - 0.5) {
+        return color * paper_white_nits / SCRGB_WHITE_NITS;
+    }
+    return pq_from_nits(max(BT709_TO_BT2020 * color, 0.0) * paper_white_nits);
+}
+
+// the inverse of encode_output_color, for reading back what was rendered. _SRGB formats are already linear when read
+vec3 decode_output_color(vec3 encoded, vec4 output_params) {
+    float encoding = output_params.x;
+    if (encoding < OUTPUT_ENCODING_SRGB - 0.5) {
+        return encoded;
+    }
+    if (encoding < OUTPUT_ENCODING_SCRGB - 0.5) {
+        return linear_from_srgb(encoded);
+    }
+    float paper_white_nits = output_params.y;
+    if (encoding < OUTPUT_ENCODING_HDR10 - 0.5) {
+        return encoded * SCRGB_WHITE_NITS / paper_white_nits;
+    }
+    return BT2020_TO_BT709 * nits_from_pq(encoded) / paper_white_nits;
+}
+
+// applies a KHR_texture_transform packed as the two rows of a 2x3 matrix
+vec2 transform_uv(vec4 row_u, vec4 row_v, vec2 uv) {
+    vec3 homogeneous_uv = vec3(uv, 1.0);
+    return vec2(dot(row_u.xyz, homogeneous_uv), dot(row_v.xyz, homogeneous_uv));
+}
+
+// only the fragment shader variants compiled with LOGARITHMIC_DEPTH defined write gl_FragDepth, as writing it at all
+// turns off early depth testing. Other stages including this file never see gl_FragCoord
+#ifdef LOGARITHMIC_DEPTH
+// depth_params is the global uniform's x: logarithmic depth enabled, y: far plane, z: reverse z enabled
+// the view depth is recovered from gl_FragCoord.w, which is 1 / clip space w
+float logarithmic_fragment_depth(vec4 depth_params) {
+    float view_depth = 1.0 / gl_FragCoord.w;
+    float depth = log2(1.0 + view_depth) / log2(1.0 + depth_params.y);
+    return depth_params.z > 0.5 ? 1.0 - depth : depth;
+}
+
+// the offset pulls the depth towards the camera, for pipelines whose depth bias is lost by writing it
+void write_fragment_depth(vec4 depth_params, float offset) {
+    float depth = logarithmic_fragment_depth(depth_params);
+    gl_FragDepth = depth_params.z > 0.5 ? depth + offset : depth - offset;
+}
+#else
+void write_fragment_depth(vec4 depth_params, float offset) {}
+#endif
+
+// fog_color is the global uniform's rgb: color, w: mode, fog_params is x: density, y: linear start, z: linear end
+// MUST KEEP IN SYNC WITH FogMode
+const float FOG_MODE_LINEAR = 1.0;
+const float FOG_MODE_EXPONENTIAL = 2.0;
+const float FOG_MODE_EXPONENTIAL_SQUARED = 3.0;
+
+// how much of the fog color to show at the given view distance, from 0 to 1
+float fog_amount(float view_distance, vec4 fog_color, vec4 fog_params) {
+    float mode = fog_color.w;
+    if (mode < FOG_MODE_LINEAR - 0.5) {
+        return 0.0;
+    }
+    if (mode < FOG_MODE_EXPONENTIAL - 0.5) {
+        return clamp((view_distance - fog_params.y) / max(fog_params.z - fog_params.y, 0.0001), 0.0, 1.0);
+    }
+    float density_distance = fog_params.x * view_distance;
+    if (mode < FOG_MODE_EXPONENTIAL_SQUARED - 0.5) {
+        return 1.0 - exp(-density_distance);
+    }
+    return 1.0 - exp(-density_distance * density_distance);
+}
@@ -0,0 +1,18 @@
+// the global uniform every scene pass binds at set 0, pulled in with #include "global_uniform.glsl"
+// MUST KEEP IN SYNC WITH GlobalUniformData
+#ifndef GLOBAL_UNIFORM_GLSL
+#define GLOBAL_UNIFORM_GLSL
+layout(set = 0, binding = 0) uniform TransformationMatrices {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 environment_params; // x: intensity, y: yaw rotation
+    vec4 depth_params; // x: logarithmic depth enabled, y: far plane, z: reverse z enabled
+    vec4 fog_color; // rgb: color, w: mode
+    vec4 fog_params; // x: density, y: linear start, z: linear end
+    vec4 debug_params; // x: debug render mode, y: normal mapping enabled
+    vec4 ambient_params; // rgb: ambient light used without an environment, w: environment loaded
+    vec4 output_params; // x: output encoding, y: paper white in nits, z: peak luminance in nits, w: exposure
+    vec4 frame_params; // x: seconds since startup, yz: viewport size in pixels, w: light count
+} transforms;
+#endif
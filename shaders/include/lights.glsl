@@ -0,0 +1,56 @@
+// the punctual lights written by update_lights_system, shaded one after another by the lit shaders
+
+// MUST KEEP IN SYNC WITH MAX_LIGHTS in light_source.rs
+const int MAX_LIGHTS = 16;
+
+// MUST KEEP IN SYNC WITH LightKind
+const int LIGHT_KIND_POINT = 0;
+const int LIGHT_KIND_DIRECTIONAL = 1;
+const int LIGHT_KIND_SPOT = 2;
+
+// MUST KEEP IN SYNC WITH LightUniform
+struct Light {
+    vec4 position; // xyz: world position, w: kind
+    vec4 direction; // xyz: the direction the light shines in, w: range, 0 for unlimited
+    vec4 color; // rgb: color, w: emissivity
+    vec4 cone; // x: cosine of the inner cone angle, y: cosine of the outer cone angle
+};
+
+layout(set = 2, binding = 0) uniform Lights {
+    uvec4 light_count; // x: the number of lights used
+    Light lights[MAX_LIGHTS];
+} lights;
+
+uint used_light_count() {
+    return min(lights.light_count.x, uint(MAX_LIGHTS));
+}
+
+// the normalized direction from the position towards the light
+vec3 direction_to_light(Light light, vec3 position) {
+    if (int(light.position.w) == LIGHT_KIND_DIRECTIONAL) {
+        return -light.direction.xyz;
+    }
+    return normalize(light.position.xyz - position);
+}
+
+// the light arriving at the position before the angle to the surface is accounted for
+vec3 light_radiance(Light light, vec3 position) {
+    vec3 radiance = light.color.rgb * light.color.w;
+    if (int(light.position.w) == LIGHT_KIND_DIRECTIONAL) {
+        return radiance;
+    }
+    float light_distance = length(light.position.xyz - position);
+    float attenuation = 1.0 / (light_distance * light_distance);
+    // smoothly fades the inverse square falloff to nothing at the range, as recommended by KHR_lights_punctual
+    float range = light.direction.w;
+    if (range > 0.0) {
+        attenuation *= pow(clamp(1.0 - pow(light_distance / range, 4.0), 0.0, 1.0), 2.0);
+    }
+    if (int(light.position.w) == LIGHT_KIND_SPOT) {
+        // eased from full strength at the inner cone to nothing at the outer one
+        float cos_angle = dot(light.direction.xyz, normalize(position - light.position.xyz));
+        float cone_attenuation = smoothstep(light.cone.y, max(light.cone.x, light.cone.y + 0.0001), cos_angle);
+        attenuation *= cone_attenuation * cone_attenuation;
+    }
+    return radiance * attenuation;
+}
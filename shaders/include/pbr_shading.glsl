@@ -0,0 +1,251 @@
+// shared pbr shading for the pbr fragment shaders, which must define the sample_* functions
+#include "global_uniform.glsl"
+
+layout(set = 1, binding = 0) uniform MaterialProps {
+    vec4 base_color;
+    float base_roughness;
+    float base_metallic;
+    uint enabled_features;
+    vec4 emissive;
+    uvec4 texture_indices; // x: base color, y: normal, z: occlusion roughness metal, only used by bindless shaders
+    vec4 uv_transforms[6]; // two rows per texture in the order base color, normal, occlusion roughness metal
+} material_props;
+// MUST KEEP IN SYNC WITH MaterialAnimationUniforms, multiplied into the material props
+layout(set = 1, binding = 4) uniform MaterialAnimation {
+    vec4 base_color_scale;
+    vec4 emissive_scale; // rgb: scale
+} material_animation;
+
+#include "lights.glsl"
+
+layout(set = 3, binding = 0) uniform samplerCube irradiance_map;
+layout(set = 3, binding = 1) uniform samplerCube prefilter_map;
+layout(set = 3, binding = 2) uniform sampler2D brdf_lut;
+
+layout(location = 0) in VS_OUT {
+    vec3 position;
+    vec2 tex_coord;
+    mat3 tbn;
+} vs_out;
+// transforms object space normals into world space
+layout(location = 5) in mat3 vs_normal_matrix;
+
+layout(location = 0) out vec4 out_color;
+
+const float PI = 3.14159265359;
+
+float distribution_ggx(vec3 normal, vec3 half_vector, float a);
+float geometry_schlick_ggx(float normal_dot_view, float k);
+float geometry_smith(vec3 normal, vec3 view_direction, vec3 light_direction, float k);
+vec3 fresnel_schlick(float cos_theta, vec3 f0);
+vec3 fresnel_schlick_with_roughness(float cos_theta, vec3 f0, float roughness);
+
+// defined by the including shader depending on how the material textures are bound
+vec4 sample_base_color(vec2 tex_coord);
+vec4 sample_normal(vec2 tex_coord);
+vec4 sample_occlusion_roughness_metal(vec2 tex_coord);
+
+// MUST KEEP IN SYNC WITH PbrMaterialFeatureFlags
+const uint ALBEDO_TEXTURE_FLAG = 1 << 0;
+const uint NORMAL_TEXTURE_FLAG = 1 << 1;
+const uint ROUGHNESS_TEXTURE_FLAG = 1 << 2;
+const uint METALLIC_TEXTURE_FLAG = 1 << 3;
+const uint OCCLUSION_TEXTURE_FLAG = 1 << 4;
+// not a texture, the normal texture holds object space rather than tangent space normals
+const uint OBJECT_SPACE_NORMAL_MAP_FLAG = 1u << 31;
+
+// MUST KEEP IN SYNC WITH DebugRenderMode
+const int DEBUG_MODE_FINAL = 0;
+const int DEBUG_MODE_NORMALS = 1;
+const int DEBUG_MODE_ALBEDO = 2;
+const int DEBUG_MODE_ROUGHNESS = 3;
+const int DEBUG_MODE_METALLIC = 4;
+const int DEBUG_MODE_AMBIENT_OCCLUSION = 5;
+
+const int DEBUG_MODE_EMISSIVE = 6;
+const int DEBUG_MODE_VERTEX_NORMALS = 7;
+const int DEBUG_MODE_TANGENTS = 8;
+const int DEBUG_MODE_TEX_COORDS = 9;
+
+struct SurfaceInputs {
+    vec3 normal;
+    vec3 albedo;
+    float roughness;
+    float metallic;
+    float occlusion;
+    vec3 emissive;
+};
+
+// the selected input with no lighting, stored values are encoded back from linear so they show as written
+vec3 debug_color(int debug_mode, SurfaceInputs surface) {
+    switch (debug_mode) {
+        case DEBUG_MODE_NORMALS: return linear_from_srgb(surface.normal * 0.5 + 0.5);
+        case DEBUG_MODE_ALBEDO: return surface.albedo;
+        case DEBUG_MODE_ROUGHNESS: return linear_from_srgb(vec3(surface.roughness));
+        case DEBUG_MODE_METALLIC: return linear_from_srgb(vec3(surface.metallic));
+        case DEBUG_MODE_AMBIENT_OCCLUSION: return linear_from_srgb(vec3(surface.occlusion));
+        case DEBUG_MODE_EMISSIVE: return surface.emissive;
+        // the unmapped mesh data, to check the tangent frame a normal map is applied with
+        case DEBUG_MODE_VERTEX_NORMALS: return linear_from_srgb(normalize(vs_out.tbn[2]) * 0.5 + 0.5);
+        case DEBUG_MODE_TANGENTS: return linear_from_srgb(normalize(vs_out.tbn[0]) * 0.5 + 0.5);
+        case DEBUG_MODE_TEX_COORDS: return linear_from_srgb(vec3(fract(vs_out.tex_coord), 0.0));
+    }
+    return vec3(0.0);
+}
+
+void main() {
+    float occlusion = 1;
+    float roughness = material_props.base_roughness;
+    float metallic = material_props.base_metallic;
+    vec3 albedo = material_props.base_color.rgb * material_animation.base_color_scale.rgb;
+    vec3 emissive = material_props.emissive.rgb * material_animation.emissive_scale.rgb;
+    vec3 normal = normalize(vs_out.tbn[2]);
+    vec2 base_color_uv = transform_uv(material_props.uv_transforms[0], material_props.uv_transforms[1], vs_out.tex_coord);
+    vec2 normal_uv = transform_uv(material_props.uv_transforms[2], material_props.uv_transforms[3], vs_out.tex_coord);
+    vec2 occlusion_roughness_metal_uv = transform_uv(material_props.uv_transforms[4], material_props.uv_transforms[5], vs_out.tex_coord);
+
+    if (bool(material_props.enabled_features & ALBEDO_TEXTURE_FLAG)) {
+        albedo *= sample_base_color(base_color_uv).rgb;
+    }
+    bool normal_mapping_enabled = transforms.debug_params.y > 0.5;
+    if (normal_mapping_enabled && bool(material_props.enabled_features & NORMAL_TEXTURE_FLAG)) {
+        normal = sample_normal(normal_uv).rgb;
+        normal = normal * 2.0 - 1.0;
+        if (bool(material_props.enabled_features & OBJECT_SPACE_NORMAL_MAP_FLAG)) {
+            normal = normalize(vs_normal_matrix * normal);
+        } else {
+            normal = normalize(vs_out.tbn * normal);
+        }
+    }
+    // back faces of double sided materials are lit from their own side
+    if (!gl_FrontFacing) {
+        normal = -normal;
+    }
+    if (bool(material_props.enabled_features & ROUGHNESS_TEXTURE_FLAG)) {
+        roughness *= sample_occlusion_roughness_metal(occlusion_roughness_metal_uv).g;
+    }
+    if (bool(material_props.enabled_features & METALLIC_TEXTURE_FLAG)) {
+        metallic *= sample_occlusion_roughness_metal(occlusion_roughness_metal_uv).b;
+    }
+    if (bool(material_props.enabled_features & OCCLUSION_TEXTURE_FLAG)) {
+        occlusion *= sample_occlusion_roughness_metal(occlusion_roughness_metal_uv).r;
+    }
+
+    int debug_mode = int(transforms.debug_params.x);
+    if (debug_mode != DEBUG_MODE_FINAL) {
+        SurfaceInputs surface = SurfaceInputs(normal, albedo, roughness, metallic, occlusion, emissive);
+        out_color = vec4(encode_output_color(debug_color(debug_mode, surface), transforms.output_params), 1.0);
+        write_fragment_depth(transforms.depth_params, 0.0);
+        return;
+    }
+
+    vec3 view_direction = normalize(transforms.camera_position.xyz - vs_out.position);
+    vec3 reflection_direction = reflect(-view_direction, normal);
+    vec3 f0 = vec3(0.04);
+    f0 = mix(f0, albedo, metallic);
+
+    vec3 accumulated_lighting = vec3(0.0);
+
+    // ------------------------ start per light calculations ------------------------
+    for (uint light_index = 0; light_index < used_light_count(); light_index++) {
+        Light light = lights.lights[light_index];
+        vec3 light_direction = direction_to_light(light, vs_out.position);
+        float normal_dot_light = max(dot(normal, light_direction), 0.0);
+        float normal_dot_view = max(dot(normal, view_direction), 0.0);
+        vec3 half_vector = normalize(view_direction + light_direction);
+        vec3 radiance = light_radiance(light, vs_out.position);
+
+        // cook-torrance brdf
+        float normal_distribution_function = distribution_ggx(normal, half_vector, roughness);
+        float geometry = geometry_smith(normal, view_direction, light_direction, roughness);
+        vec3 fresnel = fresnel_schlick(max(dot(half_vector, view_direction), 0.0), f0);
+
+        vec3 numerator = normal_distribution_function * geometry * fresnel;
+        float denominator = 4.0 * normal_dot_view * normal_dot_light + 0.0001;
+        vec3 specular = numerator / denominator;
+
+        vec3 k_specular = fresnel;
+        vec3 k_diffuse = vec3(1.0) - k_specular;
+        k_diffuse *= 1.0 - metallic;
+        accumulated_lighting += (k_diffuse * albedo / PI + specular) * radiance * normal_dot_light;
+    }
+
+    // ------------------------ end per light calculations ------------------------
+
+    // ambient lighting, image based when an environment is loaded and a constant color otherwise
+    vec3 ambient = transforms.ambient_params.rgb * albedo * occlusion;
+    if (transforms.ambient_params.w > 0.5) {
+        vec3 fresnel = fresnel_schlick_with_roughness(max(dot(normal, view_direction), 0.0), f0, roughness);
+
+        vec3 k_specular = fresnel;
+        vec3 k_diffuse = 1.0 - k_specular;
+        k_diffuse *= 1.0 - metallic;
+
+        // rotate the sample directions to match the orientation of the environment
+        float environment_yaw = transforms.environment_params.y;
+        vec3 irradiance = texture(irradiance_map, rotate_y(normal, environment_yaw)).rgb;
+        vec3 diffuse = irradiance * albedo;
+
+        // sample both the pre-filter map and the BRDF lut and combine them together as per the Split-Sum approximation to get the IBL specular part.
+        // the roughness range is spread over the pre-filter map's mips, however many the environment was created with
+        float max_reflection_lod = float(textureQueryLevels(prefilter_map) - 1);
+        vec3 prefiltered_color = textureLod(prefilter_map, rotate_y(reflection_direction, environment_yaw), roughness * max_reflection_lod).rgb;
+        vec2 brdf = texture(brdf_lut, vec2(max(dot(normal, view_direction), 0.0), roughness)).rg;
+        vec3 specular = prefiltered_color * (fresnel * brdf.x + brdf.y);
+
+        ambient = (k_diffuse * diffuse + specular) * occlusion * transforms.environment_params.x;
+    }
+
+    vec3 color = ambient + accumulated_lighting + emissive;
+
+    color = tonemap_scene_color(color, transforms.output_params);
+
+    // fog is blended after tone mapping so the fog color shows as picked
+    float view_distance = length(transforms.camera_position.xyz - vs_out.position);
+    color = mix(color, transforms.fog_color.rgb, fog_amount(view_distance, transforms.fog_color, transforms.fog_params));
+
+    out_color = vec4(encode_output_color(color, transforms.output_params), 1.0);
+    write_fragment_depth(transforms.depth_params, 0.0);
+}
+
+float distribution_ggx(vec3 normal, vec3 half_vector, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float NdotH = max(dot(normal, half_vector), 0.0);
+    float NdotH2 = NdotH*NdotH;
+    float nom = a2;
+    float denom = (NdotH2 * (a2 - 1.0) + 1.0);
+    denom = PI * denom * denom;
+    return nom / denom;
+}
+
+float geometry_schlick_ggx(float normal_dot_view, float roughness)
+{
+    float r = (roughness + 1.0);
+    float k = (r * r) / 8.0;
+    float nom   = normal_dot_view;
+    float denom = normal_dot_view * (1.0 - k) + k;
+
+    return nom / denom;
+}
+
+float geometry_smith(vec3 normal, vec3 view_direction, vec3 light_direction, float k)
+{
+    float normal_dot_view = max(dot(normal, view_direction), 0.0);
+    float normal_dot_light = max(dot(normal, light_direction), 0.0);
+    float ggx1 = geometry_schlick_ggx(normal_dot_view, k);
+    float ggx2 = geometry_schlick_ggx(normal_dot_light, k);
+
+    return ggx1 * ggx2;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0)
+{
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// use the roughness for ambient lighting where there is not half vector for simulating the micro facet model
+vec3 fresnel_schlick_with_roughness(float cos_theta, vec3 f0, float roughness)
+{
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
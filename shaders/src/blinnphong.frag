@@ -13,11 +13,7 @@ layout(set = 1, binding = 3) uniform MaterialProps {
     vec4 base_color;
 } material_props;
 
-layout(set = 2, binding = 0) uniform PointLight {
-    vec3 position;
-    vec3 color;
-    float emissivity;
-} point_light;
+#include "lights.glsl"
 
 layout(location = 0) in VS_OUT {
     vec3 position;
@@ -45,12 +41,13 @@ void main() {
 
     // ambient lighting
     float ambient_strength = 0.1;
-    vec3 ambient = ambient_strength * point_light.color;
+    vec3 ambient = ambient_strength * lights.lights[0].color.rgb;
 
     // diffuse lighting
-    vec3 light_direction = normalize(point_light.position - vs_out.position);
+    // only lit by the first light
+    vec3 light_direction = direction_to_light(lights.lights[0], vs_out.position);
     float diff = max(dot(normal, light_direction), 0.0);
-    vec3 diffuse = diff * point_light.color;
+    vec3 diffuse = diff * lights.lights[0].color.rgb;
 
     // specular (Blinn-Phong specular)
     float specular_strength = 0.5;
@@ -62,7 +59,7 @@ void main() {
     blinn_term = clamp(blinn_term, 0, 1);
     blinn_term = incidence_angle != 0.0 ? blinn_term : 0.0;
     blinn_term = pow(blinn_term, 256);
-    vec3 specular = specular_strength * blinn_term * point_light.color;
+    vec3 specular = specular_strength * blinn_term * lights.lights[0].color.rgb;
 
 
     vec3 result = (ambient + diffuse + specular) * albedo;
@@ -0,0 +1,15 @@
+#version 460
+#include "common.glsl"
+
+layout(set = 0, binding = 0) uniform TransformationMatrices {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 environment_params;
+    vec4 depth_params; // x: logarithmic depth enabled, y: far plane, z: reverse z enabled
+} transforms;
+
+// no color is written, only the depth test matters for the occlusion query
+void main() {
+    write_fragment_depth(transforms.depth_params, 0.0);
+}
@@ -0,0 +1,21 @@
+#version 460
+
+layout(set = 0, binding = 0) uniform TransformationMatrices {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+} transforms;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    vec4 bounds_min;
+    vec4 bounds_max;
+} constants;
+
+layout(location = 0) in vec3 in_position;
+
+void main() {
+    // the cube's corners are at -1 and 1, stretch them out to the bounds
+    vec3 local_position = mix(constants.bounds_min.xyz, constants.bounds_max.xyz, in_position * 0.5 + 0.5);
+    gl_Position = transforms.projection * transforms.view * constants.model * vec4(local_position, 1.0);
+}
@@ -1,13 +1,26 @@
 #version 460
+#include "common.glsl"
 
+// egui vertex colors are premultiplied sRGB, read through a UNORM attribute so they arrive still encoded
 layout(location = 0) in vec2 frag_tex_coord;
 layout(location = 1) in vec4 fragColor;
 
 layout(set = 0, binding = 0) uniform sampler2D tex_sampler;
 
+layout(push_constant) uniform PushConstants {
+    vec2 screen_size;
+    float output_encoding;
+    float paper_white_nits;
+} constants;
+
 layout(location = 0) out vec4 out_color;
 
 void main() {
+    // the ui textures are _SRGB formats so sampling decodes them, egui expects to multiply in gamma space
     vec4 tex_linear = texture(tex_sampler, frag_tex_coord);
-    out_color = fragColor * tex_linear;
+    vec4 tex_gamma = vec4(srgb_from_linear(tex_linear.rgb), tex_linear.a);
+    vec4 color_gamma = fragColor * tex_gamma;
+    // decoded so the swapchain's encoding doesn't apply the gamma curve twice
+    vec3 color_linear = linear_from_srgb(color_gamma.rgb);
+    out_color = vec4(encode_output_color(color_linear, vec4(constants.output_encoding, constants.paper_white_nits, 0.0, 0.0)), color_gamma.a);
 }
@@ -6,6 +6,8 @@ layout(location = 2) in vec4 inColor;
 
 layout(push_constant) uniform PushConstants {
     vec2 screen_size;
+    float output_encoding;
+    float paper_white_nits;
 } constants;
 
 layout(location = 0) out vec2 frag_tex_coord;
@@ -0,0 +1,12 @@
+#version 460
+#include "common.glsl"
+#include "global_uniform.glsl"
+
+layout(location = 0) in vec4 in_color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    out_color = vec4(encode_output_color(in_color.rgb, transforms.output_params), in_color.a);
+    write_fragment_depth(transforms.depth_params, 0.0);
+}
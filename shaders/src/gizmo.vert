@@ -0,0 +1,16 @@
+#version 460
+
+layout(set = 0, binding = 0) uniform TransformationMatrices {
+    mat4 view;
+    mat4 projection;
+} transforms;
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec4 in_color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    out_color = in_color;
+    gl_Position = transforms.projection * transforms.view * vec4(in_position, 1.0);
+}
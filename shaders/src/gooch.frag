@@ -11,11 +11,7 @@ layout(set = 1, binding = 1) uniform MaterialProps {
     vec4 base_color;
 } material_props;
 
-layout(set = 2, binding = 0) uniform PointLight {
-    vec3 position;
-    vec3 color;
-    float emissivity;
-} point_light;
+#include "lights.glsl"
 
 layout(location = 0) in vec3 frag_position;
 layout(location = 1) in vec3 frag_normal;
@@ -27,7 +23,8 @@ void main() {
     vec4 surface_color = texture(tex_sampler, frag_tex_coord) * material_props.base_color;
     vec4 cool_color = vec4(0.0, 0.0, 0.55, 1.0) * 0.1 + 0.9 * surface_color;
     vec4 warm_color = vec4(0.3, 0.3, 0.0, 1.0) * 0.1 + 0.9 * surface_color;
-    vec3 light_dir = normalize(point_light.position - frag_position);
+    // only lit by the first light
+    vec3 light_dir = direction_to_light(lights.lights[0], frag_position);
     vec3 normal = normalize(frag_normal);
     float t = (dot(light_dir, normal) + 1) / 2;
     vec4 kfinal = mix(cool_color, warm_color, t);
@@ -0,0 +1,18 @@
+#version 460
+#include "common.glsl"
+#include "global_uniform.glsl"
+
+// egui's font atlas, the glyph coverage is in the alpha channel
+layout(set = 1, binding = 0) uniform sampler2D font_atlas;
+
+layout(location = 0) in vec2 in_uv;
+layout(location = 1) in vec4 in_color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    float alpha = in_color.a * texture(font_atlas, in_uv).a;
+    // blended as premultiplied alpha
+    out_color = vec4(encode_output_color(in_color.rgb, transforms.output_params) * alpha, alpha);
+    write_fragment_depth(transforms.depth_params, 0.0);
+}
@@ -0,0 +1,14 @@
+#version 460
+#include "common.glsl"
+#include "global_uniform.glsl"
+
+const vec3 OUTLINE_COLOR = vec3(1.0, 0.8, 0.1);
+// as in wireframe.frag, the mask pipeline's depth bias is lost when the depth is written here
+const float LOGARITHMIC_DEPTH_OFFSET = 0.00002;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    out_color = vec4(encode_output_color(OUTLINE_COLOR, transforms.output_params), 1.0);
+    write_fragment_depth(transforms.depth_params, LOGARITHMIC_DEPTH_OFFSET);
+}
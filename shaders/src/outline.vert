@@ -0,0 +1,25 @@
+#version 460
+
+#include "global_uniform.glsl"
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat4 normal_matrix;
+} constants;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+
+// the same width whatever the distance to the object
+const float OUTLINE_WIDTH_PIXELS = 3.0;
+
+void main() {
+    vec4 clip_position = transforms.projection * transforms.view * constants.model * vec4(inPosition, 1.0);
+    vec3 normal = vec3(constants.normal_matrix * vec4(inNormal, 0));
+    vec2 clip_normal = (transforms.projection * transforms.view * vec4(normal, 0.0)).xy;
+    if (dot(clip_normal, clip_normal) > 0.0) {
+        // scaled by w so the offset survives the perspective divide, a pixel is 2 / size in normalized coordinates
+        clip_position.xy += normalize(clip_normal) * OUTLINE_WIDTH_PIXELS * 2.0 / transforms.frame_params.yz * clip_position.w;
+    }
+    gl_Position = clip_position;
+}
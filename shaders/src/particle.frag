@@ -0,0 +1,16 @@
+#version 460
+#include "common.glsl"
+#include "global_uniform.glsl"
+
+layout(location = 0) in vec2 in_corner;
+layout(location = 1) in vec4 in_color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    // soft round particle that fades out towards the edge of the quad
+    float falloff = 1.0 - smoothstep(0.0, 1.0, length(in_corner));
+    // additively blended, so alpha is applied by scaling the color
+    out_color = vec4(encode_output_color(in_color.rgb, transforms.output_params) * in_color.a * falloff, 0.0);
+    write_fragment_depth(transforms.depth_params, 0.0);
+}
@@ -0,0 +1,34 @@
+#version 460
+
+layout(set = 0, binding = 0) uniform TransformationMatrices {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 environment_params;
+    vec4 depth_params;
+} transforms;
+
+// per instance
+layout(location = 0) in vec4 in_position_size;
+layout(location = 1) in vec4 in_color;
+
+layout(location = 0) out vec2 out_corner;
+layout(location = 1) out vec4 out_color;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    // the camera's right and up axes are the first two rows of the view rotation, keeping the quad facing the camera
+    vec3 camera_right = vec3(transforms.view[0][0], transforms.view[1][0], transforms.view[2][0]);
+    vec3 camera_up = vec3(transforms.view[0][1], transforms.view[1][1], transforms.view[2][1]);
+    float half_size = in_position_size.w * 0.5;
+    vec3 world_position = in_position_size.xyz + (camera_right * corner.x + camera_up * corner.y) * half_size;
+
+    out_corner = corner;
+    out_color = in_color;
+    gl_Position = transforms.projection * transforms.view * vec4(world_position, 1.0);
+}
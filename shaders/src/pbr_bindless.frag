@@ -0,0 +1,19 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#include "common.glsl"
+#include "pbr_shading.glsl"
+
+// every registered texture, indexed by the texture indices in the material props
+layout(set = 4, binding = 0) uniform sampler2D bindless_textures[];
+
+vec4 sample_base_color(vec2 tex_coord) {
+    return texture(bindless_textures[nonuniformEXT(material_props.texture_indices.x)], tex_coord);
+}
+
+vec4 sample_normal(vec2 tex_coord) {
+    return texture(bindless_textures[nonuniformEXT(material_props.texture_indices.y)], tex_coord);
+}
+
+vec4 sample_occlusion_roughness_metal(vec2 tex_coord) {
+    return texture(bindless_textures[nonuniformEXT(material_props.texture_indices.z)], tex_coord);
+}
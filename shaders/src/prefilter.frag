@@ -3,8 +3,10 @@ layout(location = 0) in vec3 in_position;
 
 layout(location = 0) out vec4 out_color;
 layout(set = 0, binding = 0) uniform samplerCube environment_map;
+// MUST KEEP IN SYNC WITH PrefilterParams
 layout(set = 1, binding = 0) uniform PrefilterProps {
     float roughness;
+    uint sample_count;
 } props;
 
 const float PI = 3.14159265359;
@@ -23,11 +25,17 @@ void main() {
     vec3 r = normal;
     vec3 v = r;
 
-    const uint SAMPLE_COUNT = 1024u;
+    // a perfectly smooth surface reflects just the one direction, so every sample would land on the same texel
+    if (props.roughness == 0.0) {
+        out_color = vec4(texture(environment_map, normal).rgb, 1.0);
+        return;
+    }
+
+    uint sample_count = max(props.sample_count, 1u);
     float total_weight = 0.0;
     vec3 prefiltered_color = vec3(0.0);
-    for (uint i = 0u; i < SAMPLE_COUNT; ++i) {
-        vec2 xi = hammersley(i, SAMPLE_COUNT);
+    for (uint i = 0u; i < sample_count; ++i) {
+        vec2 xi = hammersley(i, sample_count);
         vec3 h = importance_sample_ggx(xi, normal, props.roughness);
         vec3 l = normalize(2.0 * dot(v, h) * h - v);
 
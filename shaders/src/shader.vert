@@ -21,9 +21,13 @@ layout(location = 0) out VS_OUT {
     vec2 tex_coord;
     mat3 tbn;
 } vs_out;
+// outside of the block so the shaders that don't use it needn't declare it
+layout(location = 5) out mat3 vs_normal_matrix;
 
 void main() {
     gl_Position = transforms.projection * transforms.view * constants.model * vec4(inPosition, 1.0);
+    // only used when drawing points, larger points need the large points feature
+    gl_PointSize = 1.0;
     vs_out.tex_coord = inTexCoord;
     vec3 normal = vec3(constants.normal_matrix * vec4(inNormal, 0));
     vs_out.position = (constants.model * vec4(inPosition, 1.0)).xyz;
@@ -33,4 +37,5 @@ void main() {
     t = normalize(t - dot(t, n) * n);
     vec3 b = cross(n, t);
     vs_out.tbn = mat3(t, b, n);
+    vs_normal_matrix = mat3(constants.normal_matrix);
 }
\ No newline at end of file
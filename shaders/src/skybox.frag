@@ -1,4 +1,6 @@
 #version 460
+#include "common.glsl"
+#include "global_uniform.glsl"
 layout(location = 0) in vec3 in_position;
 
 layout(location = 0) out vec4 out_color;
@@ -6,7 +8,12 @@ layout(location = 0) out vec4 out_color;
 layout(set = 1, binding = 0) uniform samplerCube cube_map;
 
 void main() {
-    vec3 color = texture(cube_map, in_position).rgb;
-    color = color / (color + vec3(1.0));
-    out_color = vec4(color, 1.0);
+    vec3 color = texture(cube_map, rotate_y(in_position, transforms.environment_params.y)).rgb;
+    color *= transforms.environment_params.x;
+    color = tonemap_scene_color(color, transforms.output_params);
+    // the sky is treated as being at the far plane, fading into the fog towards and below the horizon
+    float horizon_fog = fog_amount(transforms.depth_params.y, transforms.fog_color, transforms.fog_params);
+    horizon_fog *= 1.0 - smoothstep(0.0, 0.3, normalize(in_position).y);
+    color = mix(color, transforms.fog_color.rgb, horizon_fog);
+    out_color = vec4(encode_output_color(color, transforms.output_params), 1.0);
 }
\ No newline at end of file
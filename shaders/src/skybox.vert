@@ -5,6 +5,8 @@ layout(set = 0, binding = 0) uniform TransformationMatrices {
     mat4 view;
     mat4 projection;
     vec4 camera_position;
+    vec4 environment_params;
+    vec4 depth_params; // x: logarithmic depth enabled, y: far plane, z: reverse z enabled
 } transforms;
 
 layout(location = 0) out vec3 out_position;
@@ -13,6 +15,7 @@ void main() {
     out_position = in_position;
     mat4 rot_view = mat4(mat3(transforms.view)); // remove translation from the view matrix
     vec4 clip_position = transforms.projection * rot_view * vec4(out_position, 1.0);
-    // ensure the depth of the skybox is 1.0 so it's always rendered at the back
-    gl_Position = clip_position.xyww;
+    // put the skybox exactly on the far plane so it's always rendered at the back, which is a depth of 0 with reverse z
+    float far_depth = transforms.depth_params.z > 0.5 ? 0.0 : clip_position.w;
+    gl_Position = vec4(clip_position.xy, far_depth, clip_position.w);
 }
\ No newline at end of file
@@ -0,0 +1,16 @@
+#version 460
+
+// the default material thumbnails are drawn with, a light grey lit from over the camera's shoulder
+const vec3 BASE_COLOR = vec3(0.7, 0.7, 0.72);
+const vec3 LIGHT_DIRECTION = normalize(vec3(0.4, 1.0, 0.6));
+const float AMBIENT = 0.25;
+
+layout(location = 0) in vec3 in_normal;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    // lit from both sides so inside faces of open meshes aren't black
+    float diffuse = abs(dot(normalize(in_normal), LIGHT_DIRECTION));
+    out_color = vec4(BASE_COLOR * (AMBIENT + (1.0 - AMBIENT) * diffuse), 1.0);
+}
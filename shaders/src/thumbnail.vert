@@ -0,0 +1,17 @@
+#version 460
+
+// MUST KEEP IN SYNC WITH ThumbnailPushConstants
+layout(push_constant) uniform PushConstants {
+    mat4 model_view_projection;
+    mat4 normal_matrix;
+} constants;
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec3 in_normal;
+
+layout(location = 0) out vec3 out_normal;
+
+void main() {
+    gl_Position = constants.model_view_projection * vec4(in_position, 1.0);
+    out_normal = vec3(constants.normal_matrix * vec4(in_normal, 0.0));
+}
@@ -1,21 +1,24 @@
 #version 460
-
-layout(set = 0, binding = 0) uniform TransformationMatrices {
-    mat4 view;
-    mat4 projection;
-    vec4 camera_position;
-} transforms;
-
+#include "common.glsl"
+#include "global_uniform.glsl"
 
 layout(set = 1, binding = 0) uniform MaterialProps {
     vec4 base_color;
     float base_roughness;
     float base_metallic;
-    int use_textures;
+    uint enabled_features;
+    vec4 emissive;
+    uvec4 texture_indices;
+    vec4 uv_transforms[6]; // two rows per texture in the order base color, normal, occlusion roughness metal
 } material_props;
 layout(set = 1, binding = 1) uniform sampler2D base_color_sampler;
 layout(set = 1, binding = 2) uniform sampler2D normal_sampler;
 layout(set = 1, binding = 3) uniform sampler2D occlusion_roughness_metal_sampler;
+// MUST KEEP IN SYNC WITH MaterialAnimationUniforms
+layout(set = 1, binding = 4) uniform MaterialAnimation {
+    vec4 base_color_scale;
+    vec4 emissive_scale; // rgb: scale
+} material_animation;
 
 layout(location = 0) in VS_OUT {
     vec3 position;
@@ -26,5 +29,8 @@ layout(location = 0) in VS_OUT {
 layout(location = 0) out vec4 out_color;
 
 void main() {
-    out_color = texture(base_color_sampler, vs_out.tex_coord) * material_props.base_color;
+    vec2 base_color_uv = transform_uv(material_props.uv_transforms[0], material_props.uv_transforms[1], vs_out.tex_coord);
+    vec4 color = texture(base_color_sampler, base_color_uv) * material_props.base_color * material_animation.base_color_scale;
+    out_color = vec4(encode_output_color(color.rgb, transforms.output_params), color.a);
+    write_fragment_depth(transforms.depth_params, 0.0);
 }
@@ -0,0 +1,15 @@
+#version 460
+#include "common.glsl"
+#include "global_uniform.glsl"
+
+// bright orange stands out against most materials and the sky
+const vec3 WIREFRAME_COLOR = vec3(1.0, 0.45, 0.0);
+// the pipeline's depth bias is lost when the depth is written here, so logarithmic depth is pulled forward instead
+const float LOGARITHMIC_DEPTH_OFFSET = 0.00002;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    out_color = vec4(encode_output_color(WIREFRAME_COLOR, transforms.output_params), 1.0);
+    write_fragment_depth(transforms.depth_params, LOGARITHMIC_DEPTH_OFFSET);
+}
@@ -1,46 +1,112 @@
+use std::path::PathBuf;
+
 use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::EventLoop;
+use winit::window::{Icon, Window};
+
+use log::error;
 
+use crate::benchmark::BenchmarkReport;
 use crate::ecs_engine::EcsEngine;
+use crate::renderer::{InitialScene, RenderError, Renderer, RendererSettings};
 
 const WINDOW_TITLE: &str = "Fast Rehnda";
 const WINDOW_WIDTH: u32 = 1600;
 const WINDOW_HEIGHT: u32 = 1200;
 
+pub struct WindowConfig {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    pub icon: Option<PathBuf>,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            title: WINDOW_TITLE.to_string(),
+            width: WINDOW_WIDTH,
+            height: WINDOW_HEIGHT,
+            icon: None,
+        }
+    }
+}
+
+impl WindowConfig {
+    fn load_icon(&self) -> Option<Icon> {
+        self.icon.as_ref().map(|icon_path| {
+            let icon_image = image::open(icon_path)
+                .unwrap_or_else(|_| panic!("Failed to load window icon {}", icon_path.display()))
+                .into_rgba8();
+            let (width, height) = icon_image.dimensions();
+            Icon::from_rgba(icon_image.into_raw(), width, height).expect("Failed to create window icon")
+        })
+    }
+}
+
 pub struct Application {
-    etna_engine: EcsEngine,
+    renderer: Renderer,
 }
 
 // https://github.com/unknownue/vulkan-tutorial-rust/blob/master/src/tutorials/00_base_code.rs
 impl Application {
-    pub fn new(event_loop: &EventLoop<()>) -> Application {
-        let window = winit::window::WindowBuilder::new()
-            .with_title(WINDOW_TITLE)
-            .with_inner_size(winit::dpi::LogicalSize::new(WINDOW_WIDTH, WINDOW_HEIGHT))
-            .build(event_loop)
-            .expect("Failed to create window.");
-        let engine = EcsEngine::new(window, event_loop);
+    // shows the scene file if one is given, otherwise the demo scene. Hdr output is used when the display supports it
+    pub fn new(event_loop: &EventLoop<()>, window_config: WindowConfig, scene_path: Option<PathBuf>, hdr_output: bool) -> Application {
+        let window = Self::build_window(event_loop, &window_config, true);
+        let renderer = Renderer::new(window, event_loop, RendererSettings {
+            scene: scene_path.map_or(InitialScene::Demo, InitialScene::File),
+            hdr_output,
+            show_ui: true,
+        });
 
         Application {
-            etna_engine: engine,
+            renderer,
         }
     }
 
+    // renders the demo scene offscreen behind a hidden window and reports the frame timings
+    pub fn benchmark(event_loop: &EventLoop<()>, frame_count: usize) -> BenchmarkReport {
+        let window = Self::build_window(event_loop, &WindowConfig::default(), false);
+        let mut engine = EcsEngine::new_benchmark(window, event_loop);
+        engine.run_benchmark(frame_count)
+    }
+
+    // adds another window viewing the same scene with its own camera
+    pub fn add_window(&mut self, event_loop: &EventLoop<()>, window_config: WindowConfig) {
+        let window = Self::build_window(event_loop, &window_config, true);
+        self.renderer.add_window(window);
+    }
+
+    // hidden windows are only rendered to offscreen
+    fn build_window(event_loop: &EventLoop<()>, window_config: &WindowConfig, visible: bool) -> Window {
+        winit::window::WindowBuilder::new()
+            .with_title(&window_config.title)
+            .with_inner_size(winit::dpi::LogicalSize::new(window_config.width, window_config.height))
+            .with_window_icon(window_config.load_icon())
+            .with_visible(visible)
+            .build(event_loop)
+            .expect("Failed to create window.")
+    }
+
     pub fn main_loop(mut self, event_loop: EventLoop<()>) {
         event_loop.run(move |event, _, control_flow| {
             if let Event::WindowEvent {
                 event,
-                ..
+                window_id,
             } = &event {
-                self.etna_engine.handle_window_event(event);
+                self.renderer.handle_window_event(*window_id, event);
             };
             match event {
                 Event::WindowEvent {
                     event: WindowEvent::CloseRequested,
-                    ..
+                    window_id,
                 } => {
-                    println!("Close requested, stopping");
-                    control_flow.set_exit();
+                    if self.renderer.is_main_window(window_id) {
+                        println!("Close requested, stopping");
+                        control_flow.set_exit();
+                    } else {
+                        self.renderer.close_window(window_id);
+                    }
                 }
                 Event::WindowEvent {
                     event: WindowEvent::KeyboardInput {
@@ -58,8 +124,10 @@ impl Application {
                     }
                 }
                 Event::MainEventsCleared => {
-                    // application update code
-                    self.etna_engine.render();
+                    match self.renderer.render_frame() {
+                        Ok(()) | Err(RenderError::WindowMinimized) => {}
+                        Err(err) => error!("{}", err),
+                    }
                 }
                 _ => (),
             }
@@ -1,17 +1,22 @@
 use std::hash::{Hash, Hasher};
 use std::path::Path;
+use std::sync::Arc;
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
+use ash::vk;
 use bevy_ecs::system::adapter::new;
 use bevy_ecs::system::Resource;
+use log::{error, warn};
 
-use crate::etna::{CommandPool, Device, Image, PhysicalDevice};
+use crate::etna::{CommandPool, Device, GraphicsSettings, Image, PhysicalDevice};
 use crate::etna::material_pipeline::{DescriptorManager};
 use crate::rehnda_core::ConstPtr;
 use crate::assets::gltf_loader;
-use crate::assets::material_server::MaterialPipelineHandle;
-use crate::assets::render_object::{MaterialHandle, Mesh, PbrMaterial, PbrMaterialOptions, PbrMaterialUniforms, RenderObject};
-use crate::etna::cube_map::{CubeMap, CubeMapManager, CubeMapTexture, EnvironmentMaps};
+use crate::assets::gltf_loader::{CoordinateSystem, GltfSource, LoadProgress, PivotMode};
+use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer};
+use crate::assets::render_object::{LoadedMesh, MaterialHandle, Mesh, MeshLod, PbrMaterial, PbrMaterialFeatureFlags, PbrMaterialOptions, PbrMaterialTextures, RenderObject};
+use crate::assets::thumbnail::ThumbnailRenderer;
+use crate::etna::cube_map::{BakedEnvironmentFiles, CubeMapManager, EnvironmentMaps, IblSettings};
 
 pub struct LoadedGltfMesh {
     pub mesh_handle: MeshHandle,
@@ -25,31 +30,96 @@ pub struct AssetManager {
     resource_command_pool: CommandPool,
     meshes: AHashMap<MeshHandle, Mesh>,
     materials: AHashMap<MaterialHandle, PbrMaterial>,
+    // (source material, override options, overridden material), options aren't hashable so this is searched linearly
+    material_overrides: Vec<(MaterialHandle, PbrMaterialOptions, MaterialHandle)>,
     pub cube_map_manager: CubeMapManager,
     pub global_light_map: Option<(EnvironmentMaps, MaterialPipelineHandle)>,
+    // bound for lit materials while there is no global light map
+    default_environment: EnvironmentMaps,
+    // used for environments rendered after it is set, baked environments keep the resolutions they were baked at
+    pub ibl_settings: IblSettings,
+    thumbnail_renderer: ThumbnailRenderer,
 }
 
 impl AssetManager {
-    pub fn create(device: ConstPtr<Device>, physical_device: ConstPtr<PhysicalDevice>, descriptor_manager: &mut DescriptorManager, resource_command_pool: CommandPool) -> Self {
+    pub fn create(device: ConstPtr<Device>, physical_device: ConstPtr<PhysicalDevice>, graphics_settings: &GraphicsSettings, descriptor_manager: &mut DescriptorManager, resource_command_pool: CommandPool) -> Self {
         let cube_map_manager = CubeMapManager::create(device, descriptor_manager, &resource_command_pool);
+        let default_environment = cube_map_manager.create_default_environment(&physical_device, graphics_settings, &resource_command_pool, descriptor_manager);
         AssetManager {
             device,
             physical_device,
             resource_command_pool,
             meshes: AHashMap::new(),
             materials: AHashMap::new(),
+            material_overrides: Vec::new(),
             cube_map_manager,
             global_light_map: None,
+            default_environment,
+            ibl_settings: IblSettings::default(),
+            thumbnail_renderer: ThumbnailRenderer::create(device),
         }
     }
 
-    pub fn load_global_light_map(&mut self, light_map_path: &Path, descriptor_manager: &mut DescriptorManager, pipeline: MaterialPipelineHandle) {
-        let img = self.cube_map_manager.create_environment_maps(&self.physical_device, &self.resource_command_pool, descriptor_manager, light_map_path);
+    // a directory is an environment baked offline, otherwise the maps are rendered from an equirectangular image
+    pub fn load_global_light_map(&mut self, light_map_path: &Path, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, pipeline: MaterialPipelineHandle) {
+        if !light_map_path.is_dir() {
+            let img = self.cube_map_manager.create_environment_maps(&self.physical_device, graphics_settings, &self.resource_command_pool, descriptor_manager, light_map_path, &self.ibl_settings);
+            self.global_light_map = Some((img, pipeline));
+            return;
+        }
+        // a baked directory that can't be loaded is rendered from its source image instead, when it has one
+        let files = BakedEnvironmentFiles::in_dir(light_map_path);
+        let img = match self.cube_map_manager.load_baked_environment(&self.physical_device, graphics_settings, &self.resource_command_pool, descriptor_manager, &files, &self.ibl_settings) {
+            Ok(img) => img,
+            Err(err) => match &files.source {
+                Some(source) => {
+                    warn!("Failed to load the baked environment, rendering it from {} instead: {}", source.display(), err);
+                    self.cube_map_manager.create_environment_maps(&self.physical_device, graphics_settings, &self.resource_command_pool, descriptor_manager, source, &self.ibl_settings)
+                }
+                None => {
+                    error!("Failed to load the baked environment in {} and it has no source image to render it from: {}", light_map_path.display(), err);
+                    self.global_light_map = None;
+                    return;
+                }
+            },
+        };
         self.global_light_map = Some((img, pipeline));
     }
 
-    pub fn load_gltf(&mut self, gltf_path: &Path, descriptor_manager: &mut DescriptorManager, pipeline: MaterialPipelineHandle) -> Vec<RenderObject> {
-        let (meshes, materials, mesh_material_indices) = gltf_loader::load_gltf(self.device, &self.physical_device, &self.resource_command_pool, descriptor_manager, gltf_path);
+    // the loaded environment, or black maps when there isn't one
+    pub fn environment_maps(&self) -> &EnvironmentMaps {
+        self.global_light_map.as_ref().map_or(&self.default_environment, |(environment_maps, _)| environment_maps)
+    }
+
+    // writes the current environment maps out so they can be loaded without rendering them again
+    pub fn export_global_light_map(&self, dir: &Path) -> Result<(), String> {
+        let (environment_maps, _) = self.global_light_map.as_ref().ok_or("No environment is loaded")?;
+        environment_maps.export(&self.resource_command_pool, dir)
+    }
+
+    pub fn load_global_light_map_from_faces(&mut self, face_paths: [&Path; 6], descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, pipeline: MaterialPipelineHandle) {
+        let img = self.cube_map_manager.create_environment_maps_from_faces(&self.physical_device, graphics_settings, &self.resource_command_pool, descriptor_manager, face_paths, &self.ibl_settings);
+        self.global_light_map = Some((img, pipeline));
+    }
+
+    // the pivot mode moves the file's meshes so their bounds sit around the origin as it says
+    pub fn load_gltf(&mut self, gltf_path: &Path, pivot_mode: PivotMode, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, material_server: &MaterialServer, pipeline: MaterialPipelineHandle) -> Vec<LoadedMesh> {
+        self.load_gltf_with_progress(gltf_path, CoordinateSystem::default(), pivot_mode, descriptor_manager, graphics_settings, material_server, pipeline, &mut |_| {})
+    }
+
+    // the progress is reported as each texture is decoded, which is most of the loading time. The file's content is
+    // converted from the given coordinate system before the pivot is placed
+    pub fn load_gltf_with_progress(&mut self, gltf_path: &Path, coordinate_system: CoordinateSystem, pivot_mode: PivotMode, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, material_server: &MaterialServer, pipeline: MaterialPipelineHandle, progress: &mut dyn FnMut(LoadProgress)) -> Vec<LoadedMesh> {
+        let source = GltfSource::read(gltf_path, progress)
+            .with_coordinate_system(coordinate_system)
+            .with_pivot_mode(pivot_mode);
+        self.upload_gltf(&source, descriptor_manager, graphics_settings, material_server, pipeline)
+    }
+
+    // uploads a glTF file that was already read, possibly on another thread. Meshes with none of their primitives
+    // drawable are left out
+    pub fn upload_gltf(&mut self, source: &GltfSource, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, material_server: &MaterialServer, pipeline: MaterialPipelineHandle) -> Vec<LoadedMesh> {
+        let (meshes, materials) = gltf_loader::upload_gltf(self.device, &self.physical_device, graphics_settings, &self.resource_command_pool, descriptor_manager, source);
 
         let material_handles: Vec<MaterialHandle> = materials.into_iter().map(|material| {
             let material_handle = MaterialHandle::new(self.materials.len() as u32);
@@ -57,16 +127,36 @@ impl AssetManager {
             material_handle
         }).collect();
 
-        std::iter::zip(meshes.into_iter(), mesh_material_indices.into_iter()).into_iter().map(|(mesh, mesh_material_index)| {
-            let mesh_handle = MeshHandle::new(self.meshes.len() as u32);
-            self.meshes.insert(mesh_handle, mesh);
-            let material_handle = material_handles[mesh_material_index];
-            RenderObject {
-                mesh_handle,
-                material_instance_handle: material_handle,
-                material_pipeline_handle: pipeline,
-            }
-        }).collect()
+        meshes.into_iter()
+            .map(|gltf_mesh| LoadedMesh {
+                name: gltf_mesh.name,
+                primitives: gltf_mesh.primitives.into_iter()
+                    .filter_map(|(mesh, material_index)| self.add_primitive(mesh, material_handles[material_index], material_server, pipeline))
+                    .collect(),
+            })
+            .filter(|loaded_mesh| !loaded_mesh.primitives.is_empty())
+            .collect()
+    }
+
+    fn add_primitive(&mut self, mesh: Mesh, material_handle: MaterialHandle, material_server: &MaterialServer, pipeline: MaterialPipelineHandle) -> Option<RenderObject> {
+        let material_pipeline_handle = if mesh.topology != vk::PrimitiveTopology::TRIANGLE_LIST {
+            let Some(variant) = material_server.topology_variant(&pipeline, mesh.topology) else {
+                warn!("Skipping a mesh of {:?} as its material has no pipeline for them", mesh.topology);
+                return None;
+            };
+            variant
+        } else if self.materials[&material_handle].is_double_sided() {
+            material_server.double_sided_variant(&pipeline)
+        } else {
+            pipeline
+        };
+        let mesh_handle = MeshHandle::new(self.meshes.len() as u32);
+        self.meshes.insert(mesh_handle, mesh);
+        Some(RenderObject {
+            mesh_handle,
+            material_instance_handle: material_handle,
+            material_pipeline_handle,
+        })
     }
 
     pub fn duplicate_material_with_uniforms(&mut self, material: &MaterialHandle, descriptor_manager: &mut DescriptorManager, new_options: &PbrMaterialOptions) -> MaterialHandle {
@@ -77,6 +167,94 @@ impl AssetManager {
         handle
     }
 
+    // returns a copy of the render object using the source material's textures with the given options.
+    // Identical overrides of the same material share a single material
+    pub fn override_material(&mut self, render_object: &RenderObject, descriptor_manager: &mut DescriptorManager, options: &PbrMaterialOptions) -> RenderObject {
+        let source_material = render_object.material_instance_handle;
+        let existing_override = self.material_overrides.iter()
+            .find(|(source, override_options, _)| *source == source_material && override_options == options)
+            .map(|(_, _, overridden_material)| *overridden_material);
+        let material_instance_handle = existing_override.unwrap_or_else(|| {
+            let overridden_material = self.duplicate_material_with_uniforms(&source_material, descriptor_manager, options);
+            self.material_overrides.push((source_material, *options, overridden_material));
+            overridden_material
+        });
+        RenderObject {
+            material_instance_handle,
+            ..*render_object
+        }
+    }
+
+    // edits a material's uniforms live, everything drawn with it changes from the next frame
+    pub fn update_material_options(&mut self, material_handle: &MaterialHandle, options: &PbrMaterialOptions) {
+        self.materials.get_mut(material_handle).unwrap().set_options(&self.resource_command_pool, options);
+        // the edited material no longer matches the options it was overridden with
+        self.material_overrides.retain(|(_, _, overridden_material)| overridden_material != material_handle);
+    }
+
+    // flips a single feature of a material live, e.g. to compare it with and without normal mapping
+    pub fn set_material_feature(&mut self, material_handle: &MaterialHandle, feature: PbrMaterialFeatureFlags, enabled: bool) {
+        self.materials.get_mut(material_handle).unwrap().set_feature(&self.resource_command_pool, feature, enabled);
+        self.material_overrides.retain(|(_, _, overridden_material)| overridden_material != material_handle);
+    }
+
+    // frees every loaded mesh, material and the environment, nothing may be using them on the gpu
+    pub fn clear(&mut self) {
+        self.meshes.clear();
+        self.materials.clear();
+        self.material_overrides.clear();
+        self.global_light_map = None;
+    }
+
+    // replaces the simpler meshes drawn in place of the mesh as the camera moves away. They are drawn with the mesh's
+    // material and pipeline, so lods of another topology are left out
+    pub fn set_mesh_lods(&mut self, mesh_handle: &MeshHandle, lods: &[MeshLod]) {
+        let topology = self.mesh_ref(mesh_handle).topology;
+        let mut lods: Vec<MeshLod> = lods.iter()
+            .filter(|lod| {
+                let lod_topology = self.meshes[&lod.mesh_handle].topology;
+                if lod.mesh_handle == *mesh_handle || lod_topology != topology {
+                    warn!("Ignoring a lod of {:?} for a mesh of {:?}", lod_topology, topology);
+                    return false;
+                }
+                true
+            })
+            .copied()
+            .collect();
+        lods.sort_by(|a, b| a.min_distance.total_cmp(&b.min_distance));
+        self.meshes.get_mut(mesh_handle).unwrap().lods = lods;
+    }
+
+    pub fn has_mesh_lods(&self) -> bool {
+        self.meshes.values().any(|mesh| !mesh.lods.is_empty())
+    }
+
+    pub fn mesh_count(&self) -> usize {
+        self.meshes.len()
+    }
+
+    pub fn material_count(&self) -> usize {
+        self.materials.len()
+    }
+
+    // overridden materials share their source's textures, so those are only counted once
+    pub fn texture_count(&self) -> usize {
+        let texture_sets: AHashSet<*const PbrMaterialTextures> = self.materials.values().map(|material| Arc::as_ptr(material.textures())).collect();
+        texture_sets.len() * PbrMaterialTextures::TEXTURES_PER_MATERIAL
+    }
+
+    // uploads the full size mips of the material's streamed textures, returning how many were streamed in
+    pub fn stream_in_material_textures(&self, material_handle: &MaterialHandle) -> usize {
+        self.material_ref(material_handle).textures().all().into_iter()
+            .filter(|texture| texture.stream_in(&self.physical_device, &self.resource_command_pool))
+            .count()
+    }
+
+    // the mesh drawn with a default material as size by size RGBA pixels, waits for the gpu to finish drawing it
+    pub fn render_thumbnail(&self, mesh_handle: &MeshHandle, size: u32) -> Vec<u8> {
+        self.thumbnail_renderer.render(&self.resource_command_pool, self.mesh_ref(mesh_handle), size)
+    }
+
     pub fn mesh_ref(&self, mesh_handle: &MeshHandle) -> &Mesh {
         unsafe { self.meshes.get(mesh_handle).unwrap_unchecked() }
     }
@@ -133,6 +311,19 @@ impl<T> PartialEq for AssetHandle<T> {
     }
 }
 
+// handles are ordered so draws can be sorted to group the same assets together
+impl<T> Ord for AssetHandle<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.handle.cmp(&other.handle)
+    }
+}
+
+impl<T> PartialOrd for AssetHandle<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl<T> Hash for AssetHandle<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         state.write_u32(self.handle)
@@ -1,21 +1,30 @@
 use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use bevy_hierarchy::Children;
 use bevy_time::Time;
 use bytemuck_derive::{Pod, Zeroable};
 use glam::Vec4;
-use winit::event::{VirtualKeyCode};
+use log::info;
+use winit::event::MouseButton;
 
-use crate::rehnda_core::{Mat4, Vec3};
+use crate::assets::AssetManager;
+use crate::assets::demo_scenes::Actor;
+use crate::assets::render_object::{actor_render_object_entities, Aabb, MeshGroup, RenderObject, Transform};
+use crate::etna::{CommandPool, GraphicsSettings, Swapchain};
+use crate::rehnda_core::{Mat4, Vec2, Vec3};
 use crate::rehnda_core::input::{InputState};
+use crate::rehnda_core::input_bindings::InputAction;
 
 #[repr(C)]
 #[derive(Zeroable, Pod, Debug, Copy, Clone)]
 pub struct ViewProjectionMatrices {
     pub view: Mat4,
     pub projection: Mat4,
+    // Camera::position with w as 1, the lit shaders take their view vectors from it rather than the inverse view
     pub camera_position: Vec4,
 }
 
-#[derive(Resource)]
+#[derive(Resource, Clone)]
 pub struct Camera {
     pub position: Vec3,
     pub front: Vec3,
@@ -27,6 +36,7 @@ pub struct Camera {
     fov_y: f32,
     z_near: f32,
     z_far: f32,
+    reverse_z: bool,
 }
 
 const OPENGL_TO_VULKAN_MATRIX: Mat4 = Mat4::from_cols_array(&[
@@ -42,10 +52,16 @@ pub fn vulkan_projection_matrix(fov_y: f32, aspect_ratio: f32, z_near: f32, z_fa
     projection
 }
 
+pub fn vulkan_reverse_z_projection_matrix(fov_y: f32, aspect_ratio: f32, z_near: f32, z_far: f32) -> Mat4 {
+    // swapping the planes maps the near plane to a depth of 1 and the far plane to 0
+    let mut projection = Mat4::perspective_rh(fov_y, aspect_ratio, z_far, z_near);
+    projection.y_axis[1] *= -1.0;
+    projection
+}
+
 impl Camera {
     pub fn new(fov_y_degrees: f32, aspect_ratio: f32, z_near: f32, z_far: f32) -> Camera {
-        let mut projection = Mat4::perspective_rh_gl(fov_y_degrees.to_radians(), aspect_ratio, z_near, z_far);
-        projection.y_axis[1] *= -1.0;
+        let projection = vulkan_projection_matrix(fov_y_degrees.to_radians(), aspect_ratio, z_near, z_far);
         Camera {
             up: (0.0, 1.0, 0.0).into(),
             front: (0.0, 0.0, 1.0).into(),
@@ -57,11 +73,55 @@ impl Camera {
             z_near,
             z_far,
             aspect_ratio,
+            reverse_z: false,
         }
     }
 
+    pub fn fov_y(&self) -> f32 {
+        self.fov_y
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+
+    pub fn z_near(&self) -> f32 {
+        self.z_near
+    }
+
+    pub fn z_far(&self) -> f32 {
+        self.z_far
+    }
+
+    pub fn set_near(&mut self, z_near: f32) {
+        assert!(z_near > 0.0 && z_near < self.z_far, "Near plane must be positive and closer than the far plane");
+        self.z_near = z_near;
+        self.update_projection();
+    }
+
+    pub fn set_far(&mut self, z_far: f32) {
+        assert!(z_far > self.z_near, "Far plane must be further than the near plane");
+        self.z_far = z_far;
+        self.update_projection();
+    }
+
     pub fn update_aspect_ratio(&mut self, aspect_ratio: f32) {
-        self.projection = vulkan_projection_matrix(self.fov_y, aspect_ratio, self.z_near, self.z_far);
+        self.aspect_ratio = aspect_ratio;
+        self.update_projection();
+    }
+
+    // must match GraphicsSettings::reverse_z
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+        self.update_projection();
+    }
+
+    fn update_projection(&mut self) {
+        self.projection = if self.reverse_z {
+            vulkan_reverse_z_projection_matrix(self.fov_y, self.aspect_ratio, self.z_near, self.z_far)
+        } else {
+            vulkan_projection_matrix(self.fov_y, self.aspect_ratio, self.z_near, self.z_far)
+        };
     }
 
     pub fn to_view_proj(&self) -> ViewProjectionMatrices {
@@ -71,6 +131,25 @@ impl Camera {
             camera_position: (self.position, 1.0).into(),
         }
     }
+
+    // ndc has x and y in [-1, 1] with y down, depth is the value read from the depth buffer. Returns None where
+    // nothing was drawn
+    pub fn world_position_from_depth(&self, ndc: Vec2, depth: f32, logarithmic_depth: bool) -> Option<Vec3> {
+        let far_depth = if self.reverse_z { 0.0 } else { 1.0 };
+        if depth == far_depth {
+            return None;
+        }
+        let view = Mat4::look_at_rh(self.position, self.position + self.front, self.up);
+        if !logarithmic_depth {
+            return Some((self.projection * view).inverse().project_point3(ndc.extend(depth)));
+        }
+        // undo the log depth written by logarithmic_fragment_depth in common.glsl to get the distance along the view direction
+        let depth = if self.reverse_z { 1.0 - depth } else { depth };
+        let view_depth = (depth * (1.0 + self.z_far).log2()).exp2() - 1.0;
+        let view_ray = self.projection.inverse().project_point3(ndc.extend(far_depth));
+        let view_position = view_ray * (view_depth / -view_ray.z);
+        Some(view.inverse().transform_point3(view_position))
+    }
 }
 
 enum CameraMovementType {
@@ -80,6 +159,8 @@ enum CameraMovementType {
 
 pub struct CameraMovementState {
     movement_type: CameraMovementType,
+    // the point the orbit camera circles and looks at, the origin until it is panned or focused elsewhere
+    orbit_target: Vec3,
     orbit_rotation: f32,
     orbit_elevation: f32,
     orbit_target_distance: f32,
@@ -89,6 +170,7 @@ impl Default for CameraMovementState {
     fn default() -> Self {
         Self {
             movement_type: CameraMovementType::Orbit,
+            orbit_target: Vec3::ZERO,
             orbit_rotation: 0.0,
             orbit_elevation: 0.0,
             orbit_target_distance: 15.0,
@@ -96,8 +178,8 @@ impl Default for CameraMovementState {
     }
 }
 
-pub fn camera_input_system(time: Res<Time>, mut camera_movement_state: Local<CameraMovementState>, mut camera: ResMut<Camera>, input_state: Res<InputState>) {
-    if input_state.is_just_down(VirtualKeyCode::T) {
+pub fn camera_input_system(time: Res<Time>, mut camera_movement_state: Local<CameraMovementState>, mut camera: ResMut<Camera>, input_state: Res<InputState>, depth_pick: Res<DepthPick>, render_object_bounds: RenderObjectBounds) {
+    if input_state.is_action_just_down(InputAction::ToggleCameraMode) {
         match camera_movement_state.movement_type {
             CameraMovementType::Orbit => {
                 camera_movement_state.movement_type = CameraMovementType::Fps;
@@ -107,6 +189,14 @@ pub fn camera_input_system(time: Res<Time>, mut camera_movement_state: Local<Cam
             }
         }
     }
+    if input_state.is_action_just_down(InputAction::FocusSelected) {
+        let selected_bounds = depth_pick.entity.and_then(|entity| render_object_bounds.world_bounds()
+            .into_iter()
+            .find(|(render_object, _)| *render_object == entity));
+        if let Some((_, bounds)) = selected_bounds {
+            frame_bounds(&camera, &mut camera_movement_state, bounds);
+        }
+    }
     match camera_movement_state.movement_type {
         CameraMovementType::Orbit => {
             handle_orbit_movement(&time, &mut camera, &mut camera_movement_state, &input_state);
@@ -117,68 +207,163 @@ pub fn camera_input_system(time: Res<Time>, mut camera_movement_state: Local<Cam
     }
 }
 
+// moves the orbit camera to look at the bounds from far enough away that its bounding sphere fits in view
+fn frame_bounds(camera: &Camera, camera_movement_state: &mut CameraMovementState, bounds: Aabb) {
+    let radius = ((bounds.max - bounds.min).length() * 0.5).max(0.01);
+    let fov_x = 2.0 * ((camera.fov_y() * 0.5).tan() * camera.aspect_ratio()).atan();
+    let half_fov = camera.fov_y().min(fov_x) * 0.5;
+    camera_movement_state.movement_type = CameraMovementType::Orbit;
+    camera_movement_state.orbit_target = bounds.center();
+    camera_movement_state.orbit_target_distance = radius / half_fov.sin();
+}
+
+/// What is needed to find where the drawn objects are in the world
+#[derive(SystemParam)]
+pub struct RenderObjectBounds<'w, 's> {
+    actors: Query<'w, 's, (&'static Transform, &'static Children), With<Actor>>,
+    render_objects: Query<'w, 's, &'static RenderObject>,
+    mesh_groups: Query<'w, 's, &'static Children, With<MeshGroup>>,
+    asset_manager: Res<'w, AssetManager>,
+}
+
+impl<'w, 's> RenderObjectBounds<'w, 's> {
+    // every drawn object's bounds placed by its actor's transform, the same way the renderer draws them
+    pub fn world_bounds(&self) -> Vec<(Entity, Aabb)> {
+        let mut world_bounds = Vec::new();
+        for (actor_transform, children) in self.actors.iter() {
+            for entity in actor_render_object_entities(children, &self.mesh_groups) {
+                if let Ok(render_object) = self.render_objects.get(entity) {
+                    let mesh = self.asset_manager.mesh_ref(&render_object.mesh_handle);
+                    world_bounds.push((entity, mesh.bounds.transformed(actor_transform.matrix() * mesh.relative_transform)));
+                }
+            }
+        }
+        world_bounds
+    }
+}
+
+/// The world position under the cursor when [InputAction::PickDepth] was last pressed, found by reading back the
+/// depth buffer, and the object picked there
+#[derive(Resource, Default)]
+pub struct DepthPick {
+    pub world_position: Option<Vec3>,
+    // the smallest object whose bounds contain the picked position, framed by [InputAction::FocusSelected]
+    pub entity: Option<Entity>,
+}
+
+// runs after drawing so the depth buffer holds the frame that was just drawn
+pub fn depth_pick_system(input_state: Res<InputState>, camera: Res<Camera>, swapchain: Res<Swapchain>, command_pool: Res<CommandPool>, graphics_settings: Res<GraphicsSettings>, mut depth_pick: ResMut<DepthPick>, render_object_bounds: RenderObjectBounds) {
+    if !input_state.is_action_just_down(InputAction::PickDepth) {
+        return;
+    }
+    let Some(cursor_position) = input_state.cursor_position() else {
+        return;
+    };
+    // the cursor is in window pixels but the scene is rendered at the render scale
+    let (extent, render_extent) = (swapchain.extent(), swapchain.render_extent());
+    let render_scale = Vec2::new(render_extent.width as f32 / extent.width as f32, render_extent.height as f32 / extent.height as f32);
+    let pixel = (cursor_position * render_scale).floor();
+    let Some(depth) = swapchain.read_depth(&command_pool, pixel.as_uvec2()) else {
+        return;
+    };
+    let ndc = (pixel + 0.5) / Vec2::new(render_extent.width as f32, render_extent.height as f32) * 2.0 - 1.0;
+    depth_pick.world_position = camera.world_position_from_depth(ndc, depth, graphics_settings.logarithmic_depth);
+    depth_pick.entity = depth_pick.world_position.and_then(|position| render_object_bounds.world_bounds()
+        .into_iter()
+        .filter(|(_, bounds)| bounds.contains(position))
+        .min_by(|(_, a), (_, b)| a.volume().total_cmp(&b.volume()))
+        .map(|(entity, _)| entity));
+    match depth_pick.world_position {
+        Some(position) => info!("Picked depth {} at x: {:.2}, y: {:.2}, z: {:.2}", depth, position.x, position.y, position.z),
+        None => info!("Nothing drawn under the cursor to pick"),
+    }
+}
+
+const ORBIT_DRAG_DEGREES_PER_PIXEL: f32 = 0.3;
+// how far the target moves per pixel dragged while panning, scaled by the orbit distance
+const ORBIT_PAN_PER_PIXEL: f32 = 0.0015;
+// how much the orbit distance is scaled by per line scrolled
+const ORBIT_SCROLL_ZOOM_FACTOR: f32 = 0.9;
+
 fn handle_orbit_movement(time: &Time, camera: &mut Camera, camera_movement_state: &mut CameraMovementState, input_state: &InputState) {
     let rotate_speed = time.delta_seconds() * 100.0;
     let zoom_speed = time.delta_seconds() * 10.0;
-    if input_state.is_down(VirtualKeyCode::W) {
+    if input_state.is_action_down(InputAction::MoveForward) {
         camera_movement_state.orbit_elevation += rotate_speed;
     }
-    if input_state.is_down(VirtualKeyCode::S) {
+    if input_state.is_action_down(InputAction::MoveBackward) {
         camera_movement_state.orbit_elevation -= rotate_speed;
     }
-    if input_state.is_down(VirtualKeyCode::A) {
+    if input_state.is_action_down(InputAction::MoveLeft) {
         camera_movement_state.orbit_rotation -= rotate_speed;
     }
-    if input_state.is_down(VirtualKeyCode::D) {
+    if input_state.is_action_down(InputAction::MoveRight) {
         camera_movement_state.orbit_rotation += rotate_speed;
     }
-    if input_state.is_down(VirtualKeyCode::Q) {
+    if input_state.is_action_down(InputAction::ZoomOut) {
         camera_movement_state.orbit_target_distance += zoom_speed;
     }
-    if input_state.is_down(VirtualKeyCode::E) {
+    if input_state.is_action_down(InputAction::ZoomIn) {
         camera_movement_state.orbit_target_distance -= zoom_speed;
     }
+    // dragging with the right or middle mouse button orbits, or pans the target while the pan key is held. Scrolling
+    // zooms
+    if input_state.is_mouse_down(MouseButton::Right) || input_state.is_mouse_down(MouseButton::Middle) {
+        let cursor_delta = input_state.cursor_delta();
+        if input_state.is_action_down(InputAction::PanCamera) {
+            let right = camera.front.cross(camera.up).normalize();
+            let up = right.cross(camera.front);
+            let pan_scale = ORBIT_PAN_PER_PIXEL * camera_movement_state.orbit_target_distance;
+            camera_movement_state.orbit_target += (up * cursor_delta.y - right * cursor_delta.x) * pan_scale;
+        } else {
+            camera_movement_state.orbit_rotation += cursor_delta.x * ORBIT_DRAG_DEGREES_PER_PIXEL;
+            camera_movement_state.orbit_elevation += cursor_delta.y * ORBIT_DRAG_DEGREES_PER_PIXEL;
+        }
+    }
+    camera_movement_state.orbit_target_distance *= ORBIT_SCROLL_ZOOM_FACTOR.powf(input_state.scroll_delta());
+    // stop short of the poles, where the camera's up vector would be parallel to its front
+    camera_movement_state.orbit_elevation = camera_movement_state.orbit_elevation.clamp(-89.0, 89.0);
     camera_movement_state.orbit_target_distance = camera_movement_state.orbit_target_distance.clamp(0.5, 100.0);
 
     let target_distance = camera_movement_state.orbit_target_distance;
     let x = target_distance * camera_movement_state.orbit_rotation.to_radians().sin() * camera_movement_state.orbit_elevation.to_radians().cos();
     let y = target_distance * camera_movement_state.orbit_elevation.to_radians().sin();
     let z = target_distance * camera_movement_state.orbit_rotation.to_radians().cos() * camera_movement_state.orbit_elevation.to_radians().cos();
-    camera.position = (x, y, z).into();
-    camera.front = (-camera.position).normalize();
+    camera.position = camera_movement_state.orbit_target + Vec3::new(x, y, z);
+    camera.front = (camera_movement_state.orbit_target - camera.position).normalize();
 }
 
 fn handle_fps_movement(time: &Time, camera: &mut Camera, input_state: &InputState) {
     let mut speed_modifier = time.delta_seconds();
-    if input_state.is_down(VirtualKeyCode::LShift) {
+    if input_state.is_action_down(InputAction::SlowMovement) {
         speed_modifier *= 0.1;
     }
     let movement_speed = speed_modifier * 20.0;
     let rotation_speed = speed_modifier * 80.0;
     let facing_direction = camera.front;
     let up = camera.up;
-    if input_state.is_down(VirtualKeyCode::W) {
+    if input_state.is_action_down(InputAction::MoveForward) {
         camera.position += facing_direction * movement_speed;
     }
-    if input_state.is_down(VirtualKeyCode::S) {
+    if input_state.is_action_down(InputAction::MoveBackward) {
         camera.position -= facing_direction * movement_speed;
     }
-    if input_state.is_down(VirtualKeyCode::A) {
+    if input_state.is_action_down(InputAction::MoveLeft) {
         camera.position -= facing_direction.cross(up).normalize() * movement_speed;
     }
-    if input_state.is_down(VirtualKeyCode::D) {
+    if input_state.is_action_down(InputAction::MoveRight) {
         camera.position += facing_direction.cross(up) * movement_speed;
     }
-    if input_state.is_down(VirtualKeyCode::Space) {
+    if input_state.is_action_down(InputAction::MoveUp) {
         camera.position += up * movement_speed;
     }
-    if input_state.is_down(VirtualKeyCode::LControl) {
+    if input_state.is_action_down(InputAction::MoveDown) {
         camera.position -= up * movement_speed;
     }
-    if input_state.is_down(VirtualKeyCode::Q) {
+    if input_state.is_action_down(InputAction::TurnLeft) {
         camera.yaw -= rotation_speed;
     }
-    if input_state.is_down(VirtualKeyCode::E) {
+    if input_state.is_action_down(InputAction::TurnRight) {
         camera.yaw += rotation_speed;
     }
 
@@ -187,4 +372,36 @@ fn handle_fps_movement(time: &Time, camera: &mut Camera, input_state: &InputStat
     let y = camera.pitch.to_radians().sin();
     let z = camera.yaw.to_radians().sin() * camera.pitch.to_radians().cos();
     camera.front = Vec3::new(x, y, z).normalize();
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moved_camera() -> Camera {
+        let mut camera = Camera::new(60.0, 16.0 / 9.0, 0.1, 100.0);
+        camera.position = Vec3::new(3.0, -2.5, 7.25);
+        camera.front = Vec3::new(-0.5, 0.25, -1.0).normalize();
+        camera
+    }
+
+    fn assert_uploads_position(camera: &Camera) {
+        let view_proj = camera.to_view_proj();
+        assert_eq!(view_proj.camera_position, Vec4::new(camera.position.x, camera.position.y, camera.position.z, 1.0));
+        // the same position the shaders would get from inverting the view
+        let view_position = view_proj.view.inverse().w_axis.truncate();
+        assert!(view_position.abs_diff_eq(camera.position, 1e-4), "{} != {}", view_position, camera.position);
+    }
+
+    #[test]
+    fn test_camera_position_matches_camera() {
+        assert_uploads_position(&moved_camera());
+    }
+
+    #[test]
+    fn test_camera_position_matches_camera_with_reverse_z() {
+        let mut camera = moved_camera();
+        camera.set_reverse_z(true);
+        assert_uploads_position(&camera);
+    }
+}
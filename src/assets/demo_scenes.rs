@@ -1,19 +1,28 @@
 use std::path::Path;
 
+use ash::vk;
 use bevy_ecs::prelude::*;
-use bevy_ecs::system::EntityCommands;
+use bevy_ecs::system::{BoxedSystem, EntityCommands};
 use bevy_hierarchy::{BuildChildren};
 use enumflags2::BitFlag;
 use glam::{EulerRot, Quat};
 
-use crate::etna::{material_pipeline, Swapchain};
+use log::info;
+
+use crate::etna::{Device, GraphicsSettings, material_pipeline, Swapchain};
 use crate::etna::material_pipeline::DescriptorManager;
-use crate::rehnda_core::{ColorRgbaF, Vec3};
+use crate::rehnda_core::{ColorRgbaF, LongLivedObject, Vec3};
+use crate::rehnda_core::input::InputState;
+use crate::rehnda_core::input_bindings::InputAction;
 use crate::assets::{AssetManager, Camera, skybox};
+use crate::assets::gltf_loader::{GltfLight, ImportedLight, PivotMode};
 use crate::assets::light_source::PointLight;
-use crate::assets::material_server::{MaterialServer, Shader};
-use crate::assets::render_object::{PbrMaterialFeatureFlags, PbrMaterialOptions, PbrMaterialUniforms, RenderObject, Transform};
+use crate::assets::particles::Emitter;
+use crate::assets::model_loader::PendingModels;
+use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer, Shader};
+use crate::assets::render_object::{PbrMaterialFeatureFlags, PbrMaterialOptions, LoadedMesh, MeshGroup, Transform};
 use crate::assets::skybox::SkyBox;
+use crate::assets::world_labels::WorldLabel;
 
 #[derive(Component)]
 pub struct Actor {
@@ -23,102 +32,126 @@ pub struct Actor {
 #[derive(Component)]
 pub struct ShouldDrawDebug;
 
-pub fn spheres_scene(mut commands: Commands, swapchain: Res<Swapchain>, mut asset_manager: ResMut<AssetManager>, mut material_server: ResMut<MaterialServer>, mut descriptor_manager: ResMut<DescriptorManager>) {
+/// The startup system that built the demo scene, run again to rebuild the scene from scratch
+#[derive(Resource)]
+pub struct ReloadableScene {
+    startup_system: BoxedSystem,
+}
+
+impl ReloadableScene {
+    pub fn new<Params>(startup_system: impl IntoSystem<(), (), Params>) -> Self {
+        Self {
+            startup_system: Box::new(IntoSystem::into_system(startup_system)),
+        }
+    }
+}
+
+// tears down every entity and loaded asset then runs the scene's startup system again. The demo scenes own every
+// entity in the world, so all of them are removed
+pub fn scene_reload_system(world: &mut World) {
+    if !world.resource::<InputState>().is_action_just_down(InputAction::ReloadScene) {
+        return;
+    }
+    info!("Reloading the demo scene");
+    // frames in flight may still be drawing the assets about to be freed
+    unsafe { world.resource::<LongLivedObject<Device>>().device_wait_idle() }
+        .expect("Failed to wait for the device to be idle");
+    world.clear_entities();
+    world.resource_mut::<PendingModels>().clear();
+    world.resource_mut::<AssetManager>().clear();
+    world.resource_scope(|world, mut scene: Mut<ReloadableScene>| {
+        scene.startup_system.initialize(world);
+        scene.startup_system.run((), world);
+        scene.startup_system.apply_buffers(world);
+    });
+}
+
+pub fn spheres_scene(mut commands: Commands, swapchain: Res<Swapchain>, graphics_settings: Res<GraphicsSettings>, mut asset_manager: ResMut<AssetManager>, mut material_server: ResMut<MaterialServer>, mut descriptor_manager: ResMut<DescriptorManager>, mut pending_models: ResMut<PendingModels>) {
     let mut camera = Camera::new(45.0, swapchain.aspect_ratio(), 0.1, 1000.0);
+    camera.set_reverse_z(graphics_settings.reverse_z);
     camera.position = (1.5, -0.6, 9.7).into();
     camera.yaw = -97.0;
     commands.insert_resource(camera);
 
-    let pbr_material = material_server.load_material(material_pipeline::textured_pipeline, Shader::Pbr);
-    let unlit_material = material_server.load_material(material_pipeline::textured_pipeline, Shader::Unlit);
+    let pbr_material = load_pbr_pipeline(&mut material_server, &graphics_settings);
+    let unlit_material = material_server.load_material(material_pipeline::unlit_pipeline, Shader::Unlit);
     let skybox_material = material_server.load_material(skybox::skybox_pipeline, Shader::SkyBox);
-    let sphere_model = asset_manager.load_gltf(Path::new("assets/models/Sphere/UvSphere.glb"), &mut descriptor_manager, pbr_material)[0];
-    asset_manager.load_global_light_map(Path::new("assets/drakensberg_solitary_mountain_8k.hdr"), &mut descriptor_manager, skybox_material);
+    let sphere_model = asset_manager.load_gltf(Path::new("assets/models/Sphere/UvSphere.glb"), PivotMode::Keep, &mut descriptor_manager, &graphics_settings, &material_server, pbr_material)[0].primitives[0];
+    asset_manager.load_global_light_map(Path::new("assets/drakensberg_solitary_mountain_8k.hdr"), &mut descriptor_manager, &graphics_settings, skybox_material);
 
     for x_index in 0..5 {
         for y_index in 0..2 {
             let roughness = 0.25 * x_index as f32;
             let metallic = 1.0 * y_index as f32;
-            let new_material = asset_manager.duplicate_material_with_uniforms(&sphere_model.material_instance_handle, &mut descriptor_manager, &PbrMaterialOptions {
+            let sphere_object = asset_manager.override_material(&sphere_model, &mut descriptor_manager, &PbrMaterialOptions {
                 base_color: ColorRgbaF::new(0.7, 0.1, 0.1, 1.0),
                 roughness,
                 metallic,
                 features: PbrMaterialFeatureFlags::empty(),
+                ..Default::default()
             });
-            let mut sphere_object = sphere_model;
-            sphere_object.material_instance_handle = new_material;
-            let sphere = commands.spawn((
-                Actor {
-                    name: format!("Sphere [R: {:.1}][M: {:.1}]", roughness, metallic),
-                },
-                Transform {
-                    translation: ((x_index as f32 - 2.0), -(y_index as f32 - 0.5), 0.0).into(),
-                    rotation: Quat::IDENTITY,
-                    scale: Vec3::splat(0.3),
-                },
-            ));
-            add_model_to_parent(sphere, std::slice::from_ref(&sphere_object));
+            commands.spawn_model(&[LoadedMesh::from(sphere_object)], Transform {
+                translation: ((x_index as f32 - 2.0), -(y_index as f32 - 0.5), 0.0).into(),
+                rotation: Quat::IDENTITY,
+                scale: Vec3::splat(0.3),
+            }, format!("Sphere [R: {:.1}][M: {:.1}]", roughness, metallic));
+            commands.spawn((Transform {
+                translation: ((x_index as f32 - 2.0), -(y_index as f32 - 0.5) + 0.45, 0.0).into(),
+                ..Default::default()
+            }, WorldLabel::new(format!("R {:.2} M {:.1}", roughness, metallic))));
         }
     }
 
-    let flight_helmet = asset_manager.load_gltf(Path::new("../glTF-Sample-Models/2.0/FlightHelmet/glTF/FlightHelmet.glb"), &mut descriptor_manager, pbr_material);
-    add_model_to_parent(commands.spawn((
-        Actor {
-            name: "FlightHelmet".into(),
-        },
-        Transform {
-            translation: (3.5, -1.15, 0.0).into(),
-            rotation: Quat::IDENTITY,
-            scale: Vec3::splat(4.0),
-        },
-        ShouldDrawDebug,
-    )), flight_helmet.as_slice(),
-    );
-
-    let floor = asset_manager.load_gltf(Path::new("../assets/Floor/floor_material.glb"), &mut descriptor_manager, pbr_material);
-    add_model_to_parent(commands.spawn((
-        Actor {
-            name: "Floor".into(),
-        },
-        Transform {
-            translation: (0.0, -1.75, 0.0).into(),
-            rotation: Quat::IDENTITY,
-            scale: Vec3::splat(4.0),
-        },
-        ShouldDrawDebug,
-    )), floor.as_slice(),
-    );
-
-    let water_bottle = asset_manager.load_gltf(Path::new("../glTF-Sample-Models/2.0/WaterBottle/glTF-Binary/WaterBottle.glb"), &mut descriptor_manager, pbr_material);
-    add_model_to_parent(commands.spawn((
-        Actor {
-            name: "WaterBottle".into(),
-        },
+    // the helmet has the most textures, so it's decoded in the background while the rest of the scene shows
+    pending_models.load(Path::new("../glTF-Sample-Models/2.0/FlightHelmet/glTF/FlightHelmet.glb"), pbr_material, Transform {
+        translation: (3.5, -1.15, 0.0).into(),
+        rotation: Quat::IDENTITY,
+        scale: Vec3::splat(4.0),
+    }, "FlightHelmet");
+
+    let floor = asset_manager.load_gltf(Path::new("../assets/Floor/floor_material.glb"), PivotMode::Keep, &mut descriptor_manager, &graphics_settings, &material_server, pbr_material);
+    commands.spawn_model(floor.as_slice(), Transform {
+        translation: (0.0, -1.75, 0.0).into(),
+        rotation: Quat::IDENTITY,
+        scale: Vec3::splat(4.0),
+    }, "Floor").insert(ShouldDrawDebug);
+
+    let water_bottle = asset_manager.load_gltf(Path::new("../glTF-Sample-Models/2.0/WaterBottle/glTF-Binary/WaterBottle.glb"), PivotMode::Keep, &mut descriptor_manager, &graphics_settings, &material_server, pbr_material);
+    commands.spawn_model(water_bottle.as_slice(), Transform {
+        translation: (-3.5, 0.15, 0.0).into(),
+        rotation: Quat::IDENTITY,
+        scale: Vec3::splat(10.0),
+    }, "WaterBottle").insert(ShouldDrawDebug);
+
+    let light_bulb_model = asset_manager.load_gltf(Path::new("../glTF-Sample-Models/2.0/WaterBottle/glTF-Binary/WaterBottle.glb"), PivotMode::Keep, &mut descriptor_manager, &graphics_settings, &material_server, unlit_material);
+    commands.spawn_model(light_bulb_model.as_slice(), Transform {
+        translation: (10.0, 10.0, 10.0).into(),
+        rotation: Quat::IDENTITY,
+        scale: Vec3::splat(1.0),
+    }, "Light").insert((PointLight {
+        light_color: (1.0, 1.0, 1.0).into(),
+        emissivity: 100.0,
+        ..Default::default()
+    }, ShouldDrawDebug));
+
+    commands.spawn((
         Transform {
-            translation: (-3.5, 0.15, 0.0).into(),
-            rotation: Quat::IDENTITY,
-            scale: Vec3::splat(10.0),
-        },
-        ShouldDrawDebug,
-    )), water_bottle.as_slice(),
-    );
-
-    let light_bulb_model = asset_manager.load_gltf(Path::new("../glTF-Sample-Models/2.0/WaterBottle/glTF-Binary/WaterBottle.glb"), &mut descriptor_manager, unlit_material);
-    let light_bulb_entity = commands.spawn((
-        Actor {
-            name: "Light".into(),
-        }, Transform {
-            translation: (10.0, 10.0, 10.0).into(),
-            rotation: Quat::IDENTITY,
-            scale: Vec3::splat(1.0),
+            translation: (0.0, -1.7, 2.0).into(),
+            ..Default::default()
         },
-        PointLight {
-            light_color: (1.0, 1.0, 1.0).into(),
-            emissivity: 100.0,
+        Emitter {
+            emission_rate: 200.0,
+            lifetime: 1.5,
+            start_color: ColorRgbaF::new(1.0, 0.6, 0.2, 1.0),
+            end_color: ColorRgbaF::new(0.8, 0.1, 0.0, 0.0),
+            start_size: 0.08,
+            end_size: 0.02,
+            initial_velocity: (0.0, 3.0, 0.0).into(),
+            velocity_randomness: 0.8,
+            acceleration: (0.0, -3.0, 0.0).into(),
+            ..Default::default()
         },
-        ShouldDrawDebug,
     ));
-    add_model_to_parent(light_bulb_entity, light_bulb_model.as_slice());
 
     // commands.spawn(SkyBox {
     //     pipeline: skybox_material,
@@ -126,52 +159,100 @@ pub fn spheres_scene(mut commands: Commands, swapchain: Res<Swapchain>, mut asse
     // })
 }
 
-pub fn shader_development_scene(mut commands: Commands, swapchain: Res<Swapchain>, mut asset_manager: ResMut<AssetManager>, mut material_server: ResMut<MaterialServer>, mut descriptor_manager: ResMut<DescriptorManager>) {
+pub fn shader_development_scene(mut commands: Commands, swapchain: Res<Swapchain>, graphics_settings: Res<GraphicsSettings>, mut asset_manager: ResMut<AssetManager>, mut material_server: ResMut<MaterialServer>, mut descriptor_manager: ResMut<DescriptorManager>) {
     let mut camera = Camera::new(45.0, swapchain.aspect_ratio(), 0.1, 1000.0);
+    camera.set_reverse_z(graphics_settings.reverse_z);
     camera.position = (1.5, -0.6, 9.7).into();
     camera.yaw = -97.0;
     commands.insert_resource(camera);
 
-    let pbr_pipeline = material_server.load_material(material_pipeline::textured_pipeline, Shader::Pbr);
-    let unlit_material = material_server.load_material(material_pipeline::textured_pipeline, Shader::Unlit);
+    let pbr_pipeline = material_server.load_material(material_pipeline::textured_pipeline, pbr_shader(&graphics_settings));
+    material_server.load_double_sided_variant(pbr_pipeline, material_pipeline::double_sided_textured_pipeline);
+    material_server.load_topology_variant(pbr_pipeline, vk::PrimitiveTopology::POINT_LIST, material_pipeline::point_textured_pipeline);
+    material_server.load_topology_variant(pbr_pipeline, vk::PrimitiveTopology::LINE_LIST, material_pipeline::line_textured_pipeline);
+    let unlit_material = material_server.load_material(material_pipeline::unlit_pipeline, Shader::Unlit);
 
-    let cannon_model = asset_manager.load_gltf(Path::new("../glTF-Sample-Models/2.0/SciFiHelmet/glTF/SciFiHelmet.gltf"), &mut descriptor_manager, pbr_pipeline);
-    let light_bulb_model = asset_manager.load_gltf(Path::new("../glTF-Sample-Models/2.0/WaterBottle/glTF-Binary/WaterBottle.glb"), &mut descriptor_manager, unlit_material);
+    let cannon_model = asset_manager.load_gltf(Path::new("../glTF-Sample-Models/2.0/SciFiHelmet/glTF/SciFiHelmet.gltf"), PivotMode::Keep, &mut descriptor_manager, &graphics_settings, &material_server, pbr_pipeline);
+    let light_bulb_model = asset_manager.load_gltf(Path::new("../glTF-Sample-Models/2.0/WaterBottle/glTF-Binary/WaterBottle.glb"), PivotMode::Keep, &mut descriptor_manager, &graphics_settings, &material_server, unlit_material);
 
-    let cannon_entity = commands.spawn((
-        Actor {
-            name: "Flight Helmet".into(),
-        },
-        Transform {
-            translation: (0.0, -1.5, 0.0).into(),
-            rotation: Quat::from_euler(EulerRot::XYZ, 0.0, 0.0, 0.0),
-            scale: Vec3::splat(10.0),
-        },
-        ShouldDrawDebug,
-    ));
-    add_model_to_parent(cannon_entity, cannon_model.as_slice());
-
-    let light_bulb_entity = commands.spawn((
-        Actor {
-            name: "Light".into(),
-        }, Transform {
-            translation: (2.0, 2.0, 2.0).into(),
-            rotation: Quat::IDENTITY,
-            scale: Vec3::splat(6.0),
-        },
-        PointLight {
-            light_color: (1.0, 1.0, 1.0).into(),
-            emissivity: 100.0,
-        },
-        ShouldDrawDebug,
-    ));
-    add_model_to_parent(light_bulb_entity, light_bulb_model.as_slice());
+    commands.spawn_model(cannon_model.as_slice(), Transform {
+        translation: (0.0, -1.5, 0.0).into(),
+        rotation: Quat::from_euler(EulerRot::XYZ, 0.0, 0.0, 0.0),
+        scale: Vec3::splat(10.0),
+    }, "Flight Helmet").insert(ShouldDrawDebug);
+
+    commands.spawn_model(light_bulb_model.as_slice(), Transform {
+        translation: (2.0, 2.0, 2.0).into(),
+        rotation: Quat::IDENTITY,
+        scale: Vec3::splat(6.0),
+    }, "Light").insert((PointLight {
+        light_color: (1.0, 1.0, 1.0).into(),
+        emissivity: 100.0,
+        ..Default::default()
+    }, ShouldDrawDebug));
+}
+
+// the lit pipeline models are drawn with, with its variants for double sided materials and point and line primitives
+pub fn load_pbr_pipeline(material_server: &mut MaterialServer, graphics_settings: &GraphicsSettings) -> MaterialPipelineHandle {
+    let pbr_pipeline = material_server.load_material(material_pipeline::textured_pipeline, pbr_shader(graphics_settings));
+    material_server.load_double_sided_variant(pbr_pipeline, material_pipeline::double_sided_textured_pipeline);
+    material_server.load_topology_variant(pbr_pipeline, vk::PrimitiveTopology::POINT_LIST, material_pipeline::point_textured_pipeline);
+    material_server.load_topology_variant(pbr_pipeline, vk::PrimitiveTopology::LINE_LIST, material_pipeline::line_textured_pipeline);
+    pbr_pipeline
 }
 
-fn add_model_to_parent(mut commands1: EntityCommands, cannon_model: &[RenderObject]) {
-    commands1.with_children(|parent| {
-        for mesh in cannon_model {
-            parent.spawn((*mesh, Transform::default()));
+// falls back to the per material descriptor sets when the device can't do bindless textures
+pub fn pbr_shader(graphics_settings: &GraphicsSettings) -> Shader {
+    if graphics_settings.bindless_textures {
+        Shader::PbrBindless
+    } else {
+        Shader::Pbr
+    }
+}
+
+// each mesh becomes a MeshGroup child of the entity, with its primitives as the group's children
+pub fn add_model_to_parent(entity: &mut EntityCommands, model: &[LoadedMesh]) {
+    entity.with_children(|parent| {
+        for mesh in model {
+            parent.spawn(MeshGroup { name: mesh.name.clone() }).with_children(|mesh_group| {
+                for primitive in &mesh.primitives {
+                    mesh_group.spawn((*primitive, Transform::default()));
+                }
+            });
+        }
+    });
+}
+
+// the lights move with the parent, as the model's meshes do
+pub fn add_lights_to_parent(entity: &mut EntityCommands, lights: &[GltfLight]) {
+    entity.with_children(|parent| {
+        for gltf_light in lights {
+            let (scale, rotation, translation) = gltf_light.relative_transform.to_scale_rotation_translation();
+            let transform = Transform { translation, rotation, scale };
+            match gltf_light.light {
+                ImportedLight::Point(light) => parent.spawn((light, transform)),
+                ImportedLight::Directional(light) => parent.spawn((light, transform)),
+                ImportedLight::Spot(light) => parent.spawn((light, transform)),
+            };
         }
     });
+}
+
+/// Places a loaded model in the world as a named [Actor] with the meshes as its children, see [add_model_to_parent]
+pub trait SpawnModel<'w, 's> {
+    // returns the parent so more components can be inserted, or it can be parented to another model to compose them
+    fn spawn_model<'a>(&'a mut self, model: &[LoadedMesh], transform: Transform, name: impl Into<String>) -> EntityCommands<'w, 's, 'a>;
+}
+
+impl<'w, 's> SpawnModel<'w, 's> for Commands<'w, 's> {
+    fn spawn_model<'a>(&'a mut self, model: &[LoadedMesh], transform: Transform, name: impl Into<String>) -> EntityCommands<'w, 's, 'a> {
+        let mut entity = self.spawn((
+            Actor {
+                name: name.into(),
+            },
+            transform,
+        ));
+        add_model_to_parent(&mut entity, model);
+        entity
+    }
 }
\ No newline at end of file
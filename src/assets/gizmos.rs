@@ -0,0 +1,168 @@
+use std::ffi::CString;
+use std::mem::size_of;
+use std::path::Path;
+
+use ash::vk;
+use bevy_ecs::prelude::*;
+use bytemuck_derive::{Pod, Zeroable};
+
+use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer, Shader};
+use crate::etna::{Device, DeviceRes, GraphicsSettings, HostMappedBuffer, HostMappedBufferCreateInfo, MAX_FRAMES_IN_FLIGHT, Swapchain};
+use crate::etna::material_pipeline::{DescriptorManager, MaterialPipeline, PipelineCreateInfo, PipelineMultisamplingInfo, PipelineVertexInputDescription, RasterizationOptions};
+use crate::etna::shader::ShaderModule;
+use crate::rehnda_core::{ColorRgbaF, ConstPtr, Vec3};
+
+// the most line vertices drawn in a frame, any more are skipped
+pub const MAX_GIZMO_VERTICES: usize = 16384;
+// how many straight lines make up a circle
+const CIRCLE_SEGMENTS: usize = 32;
+// lines drawn from the apex of a cone to its base
+const CONE_EDGES: usize = 4;
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Debug, Copy, Clone)]
+struct GizmoVertex {
+    position: Vec3,
+    color: ColorRgbaF,
+}
+
+/// Debug lines drawn over the scene in world space. The lines are cleared at the start of every frame, so systems
+/// add the ones they want each frame
+#[derive(Resource)]
+pub struct GizmoRenderer {
+    vertex_buffers: [HostMappedBuffer; MAX_FRAMES_IN_FLIGHT],
+    pub pipeline: MaterialPipelineHandle,
+    vertices: Vec<GizmoVertex>,
+}
+
+impl GizmoRenderer {
+    pub fn create(device: ConstPtr<Device>, material_server: &mut MaterialServer) -> Self {
+        let vertex_buffers = [(); MAX_FRAMES_IN_FLIGHT].map(|_| HostMappedBuffer::create(device, HostMappedBufferCreateInfo {
+            size: (size_of::<GizmoVertex>() * MAX_GIZMO_VERTICES) as u64,
+            usage: vk::BufferUsageFlags::VERTEX_BUFFER,
+        }));
+        Self {
+            vertex_buffers,
+            pipeline: material_server.load_material(gizmo_pipeline, Shader::Gizmo),
+            vertices: Vec::new(),
+        }
+    }
+
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: ColorRgbaF) {
+        if self.vertices.len() + 2 > MAX_GIZMO_VERTICES {
+            return;
+        }
+        self.vertices.push(GizmoVertex { position: start, color });
+        self.vertices.push(GizmoVertex { position: end, color });
+    }
+
+    pub fn circle(&mut self, center: Vec3, normal: Vec3, radius: f32, color: ColorRgbaF) {
+        let (tangent, bitangent) = normal.any_orthonormal_pair();
+        let point = |index: usize| {
+            let angle = index as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+            center + (tangent * angle.cos() + bitangent * angle.sin()) * radius
+        };
+        for index in 0..CIRCLE_SEGMENTS {
+            self.line(point(index), point(index + 1), color);
+        }
+    }
+
+    // a cone opening from the apex along the direction, the angle is from the direction to the cone's side and the
+    // side is the given length
+    pub fn cone(&mut self, apex: Vec3, direction: Vec3, angle: f32, length: f32, color: ColorRgbaF) {
+        let base_center = apex + direction * length * angle.cos();
+        let base_radius = length * angle.sin();
+        self.circle(base_center, direction, base_radius, color);
+        let (tangent, bitangent) = direction.any_orthonormal_pair();
+        for edge in 0..CONE_EDGES {
+            let edge_angle = edge as f32 / CONE_EDGES as f32 * std::f32::consts::TAU;
+            self.line(apex, base_center + (tangent * edge_angle.cos() + bitangent * edge_angle.sin()) * base_radius, color);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    // writes this frame's lines, returning the buffer to draw from and the number of vertices in it
+    pub fn prepare_vertices(&self, frame_index: usize) -> (vk::Buffer, u32) {
+        let vertex_buffer = &self.vertex_buffers[frame_index % MAX_FRAMES_IN_FLIGHT];
+        vertex_buffer.write_data(bytemuck::cast_slice(self.vertices.as_slice()));
+        (vertex_buffer.vk_buffer(), self.vertices.len() as u32)
+    }
+}
+
+pub fn gizmo_renderer_startup_system(mut commands: Commands, device: DeviceRes, mut material_server: ResMut<MaterialServer>) {
+    commands.insert_resource(GizmoRenderer::create(device.ptr(), &mut material_server));
+}
+
+pub fn clear_gizmos_system(mut gizmo_renderer: ResMut<GizmoRenderer>) {
+    gizmo_renderer.vertices.clear();
+}
+
+pub fn gizmo_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    let vert_shader_module = ShaderModule::load_from_file(device, vert_shader_path);
+    let frag_shader_module = ShaderModule::load_from_file(device, frag_shader_path);
+    let main_function_name = CString::new("main").unwrap();
+    let vertex_shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module.handle())
+        .name(main_function_name.as_c_str())
+        .build();
+    let frag_shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module.handle())
+        .name(main_function_name.as_c_str())
+        .build();
+
+    let vertex_binding = vk::VertexInputBindingDescription::builder()
+        .binding(0)
+        .stride(size_of::<GizmoVertex>() as u32)
+        .input_rate(vk::VertexInputRate::VERTEX)
+        .build();
+    let vertex_attributes = [
+        vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build(),
+        vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(size_of::<Vec3>() as u32)
+            .build(),
+    ];
+    let vertex_input = PipelineVertexInputDescription {
+        bindings: std::slice::from_ref(&vertex_binding),
+        attributes: &vertex_attributes,
+    };
+
+    let multisampling = PipelineMultisamplingInfo {
+        msaa_samples: graphics_settings.msaa_samples,
+        enable_sample_rate_shading: graphics_settings.sample_rate_shading_enabled,
+    };
+
+    let create_info = PipelineCreateInfo {
+        global_set_layouts: &[descriptor_manager.global_descriptor_layout],
+        additional_descriptor_set_layouts: &[],
+        shader_stages: &[vertex_shader_stage_ci, frag_shader_stage_ci],
+        push_constants: &[],
+        extent: swapchain.extent,
+        image_format: swapchain.image_format,
+        vertex_input,
+        multisampling,
+        // hidden by the geometry in front of them, but don't hide anything themselves
+        rasterization_options: &RasterizationOptions {
+            cull_mode: vk::CullModeFlags::NONE,
+            depth_write_enabled: false,
+            topology: vk::PrimitiveTopology::LINE_LIST,
+            ..RasterizationOptions::default()
+        },
+        depth_compare_op: graphics_settings.depth_compare_op(),
+        depth_format: swapchain.depth_buffer.format,
+    };
+
+    MaterialPipeline::create(device, &create_info)
+}
@@ -1,6 +1,6 @@
-use std::{fs, io, mem};
+use std::fs;
 use std::io::Read;
-use std::mem::MaybeUninit;
+use std::mem::size_of;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -9,60 +9,262 @@ use ash::vk;
 use bytemuck::{Pod, Zeroable};
 use enumflags2::BitFlag;
 use glam::{Mat4, Quat};
-use gltf::{Accessor, Gltf, Node, Semantic};
+use gltf::{Accessor, Glb, Gltf, Node, Semantic};
 use gltf::buffer;
+use gltf::khr_lights_punctual::Kind;
+use gltf::mesh::Mode;
 use gltf::json::accessor::ComponentType;
 use gltf::scene::Transform;
-use image::{DynamicImage, EncodableLayout, RgbaImage};
+use image::{EncodableLayout, RgbaImage};
 use lazy_static::lazy_static;
+use log::{error, warn};
 
-use crate::etna::{Buffer, BufferCreateInfo, CommandPool, Device, PhysicalDevice, SamplerOptions, TexSamplerOptions, Texture, TextureCreateInfo};
+use crate::etna::{Buffer, BufferCreateInfo, CommandPool, Device, GraphicsSettings, PhysicalDevice, SamplerOptions, TexSamplerOptions, Texture, TextureCreateInfo};
 use crate::etna::material_pipeline::DescriptorManager;
 use crate::rehnda_core::{ColorRgbaF, ConstPtr, Vec2, Vec3, Vec4};
-use crate::assets::render_object::{Mesh, PbrMaterial, PbrMaterialFeatureFlags, PbrMaterialOptions, PbrMaterialTextures, PbrMaterialUniforms};
+use crate::assets::render_object::{Aabb, ClearcoatOptions, Mesh, PbrMaterial, PbrMaterialFeatureFlags, PbrMaterialOptions, PbrMaterialTextures, PbrTextureTransforms, UvTransform};
 use crate::assets::Vertex;
+use crate::assets::light_source::{DirectionalLight, PointLight, SpotLight};
 
 lazy_static! {
     static ref MISSING_TEXTURE_IMG: RgbaImage = missing_texture();
 }
 
-pub type MeshesAndMaterials = (Vec<Mesh>, Vec<PbrMaterial>, Vec<usize>);
+pub type MeshesAndMaterials = (Vec<GltfMesh>, Vec<PbrMaterial>);
 
-pub fn load_gltf(device: ConstPtr<Device>, physical_device: &PhysicalDevice, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, gltf_path: &Path) -> MeshesAndMaterials {
-    let working_dir = gltf_path.parent().unwrap();
-    let gltf = read_gltf_file(gltf_path);
-    let sources_data = SourcesData::load_data_into_memory(&gltf, working_dir);
-    let mut materials: Vec<PbrMaterial> = gltf.materials()
-        .map(|gltf_material| load_gltf_material(device, physical_device, command_pool, descriptor_manager, &sources_data, &gltf_material))
-        .collect();
-    let mut meshes: Vec<Mesh> = Vec::new();
-    let mut mesh_material_indices: Vec<usize> = Vec::new();
-    for gltf_mesh in gltf.meshes() {
-        for primitive in gltf_mesh.primitives() {
-            if let Some(index) = primitive.material().index() {
-                mesh_material_indices.push(index);
-            } else {
-                let index = materials.len();
-                materials.push(create_textureless_material(device, physical_device, command_pool, descriptor_manager));
-                mesh_material_indices.push(index);
+/// The uploaded primitives of one of the file's meshes, in the file's mesh order
+pub struct GltfMesh {
+    pub name: Option<String>,
+    // each primitive that could be built and the index of its material
+    pub primitives: Vec<(Mesh, usize)>,
+}
+
+/// A light from the KHR_lights_punctual extension, placed relative to the model it was loaded with
+#[derive(Debug, Copy, Clone)]
+pub struct GltfLight {
+    pub relative_transform: Mat4,
+    pub light: ImportedLight,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum ImportedLight {
+    Point(PointLight),
+    Directional(DirectionalLight),
+    Spot(SpotLight),
+}
+
+/// The axes a glTF file's content was authored in. glTF itself is right handed with Y up, but files exported from other
+/// tools sometimes keep their own axes, which are converted to the engine's when the file is uploaded
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum CoordinateSystem {
+    #[default]
+    YUpRightHanded,
+    YUpLeftHanded,
+    ZUpRightHanded,
+    ZUpLeftHanded,
+    // any other conversion, applied before all of the file's node transforms
+    Custom(Mat4),
+}
+
+impl CoordinateSystem {
+    // the transform from this coordinate system into the engine's right handed Y up one
+    pub fn import_transform(&self) -> Mat4 {
+        match *self {
+            CoordinateSystem::YUpRightHanded => Mat4::IDENTITY,
+            CoordinateSystem::YUpLeftHanded => Mat4::from_scale(Vec3::new(1.0, 1.0, -1.0)),
+            // rotates +Z up onto +Y, taking +Y to -Z
+            CoordinateSystem::ZUpRightHanded => Mat4::from_rotation_x(-std::f32::consts::FRAC_PI_2),
+            CoordinateSystem::ZUpLeftHanded => Mat4::from_rotation_x(-std::f32::consts::FRAC_PI_2) * Mat4::from_scale(Vec3::new(1.0, -1.0, 1.0)),
+            CoordinateSystem::Custom(transform) => transform,
+        }
+    }
+}
+
+/// Where a glTF file's origin is placed, moving its meshes and lights so models authored far from their origin rotate
+/// and are placed around their geometry
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum PivotMode {
+    // the origin the file was authored with
+    #[default]
+    Keep,
+    // the center of the bounds around all of the file's meshes
+    Center,
+    // the center of the bottom of the bounds, so the model sits on the ground at its origin
+    BottomCenter,
+}
+
+impl PivotMode {
+    // the point in the bounds that is moved to the origin
+    fn pivot(&self, bounds: &Aabb) -> Vec3 {
+        match self {
+            PivotMode::Keep => Vec3::ZERO,
+            PivotMode::Center => bounds.center(),
+            PivotMode::BottomCenter => Vec3::new(bounds.center().x, bounds.min.y, bounds.center().z),
+        }
+    }
+}
+
+/// How far through decoding its textures a glTF file is
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LoadProgress {
+    pub loaded_textures: usize,
+    pub total_textures: usize,
+}
+
+impl LoadProgress {
+    pub fn fraction(&self) -> f32 {
+        if self.total_textures == 0 {
+            1.0
+        } else {
+            self.loaded_textures as f32 / self.total_textures as f32
+        }
+    }
+}
+
+/// A glTF file with its buffers read and images decoded, everything needed before anything is uploaded. Reading is
+/// the slow part of loading so it can happen on any thread, the upload must stay on the thread owning the queue
+pub struct GltfSource {
+    gltf: Gltf,
+    material_extensions: Vec<MaterialExtensions>,
+    sources_data: SourcesData,
+    // baked into the relative transforms of the meshes and lights
+    import_transform: Mat4,
+    pivot_mode: PivotMode,
+}
+
+impl GltfSource {
+    pub fn read(gltf_path: &Path, progress: &mut dyn FnMut(LoadProgress)) -> Self {
+        Self::try_read(gltf_path, progress).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // fails if the file can't be opened or isn't glTF, missing buffers and images still panic while they are loaded
+    pub fn try_read(gltf_path: &Path, progress: &mut dyn FnMut(LoadProgress)) -> Result<Self, String> {
+        let working_dir = gltf_path.parent().ok_or_else(|| format!("{} has no parent directory", gltf_path.display()))?;
+        let gltf_bytes = fs::read(gltf_path).map_err(|err| format!("Failed to open {}: {}", gltf_path.display(), err))?;
+        let mut gltf = Gltf::from_slice(&gltf_bytes).map_err(|err| format!("Failed to read {}: {}", gltf_path.display(), err))?;
+        let material_extensions = read_material_extensions(&gltf_bytes);
+        let sources_data = SourcesData::load_data_into_memory(&mut gltf, working_dir, progress);
+        Ok(Self {
+            gltf,
+            material_extensions,
+            sources_data,
+            import_transform: Mat4::IDENTITY,
+            pivot_mode: PivotMode::Keep,
+        })
+    }
+
+    // converts the file's content from the coordinate system it was authored in. Mirroring conversions flip the
+    // winding, which is handled when drawing as for any other mirrored transform
+    pub fn with_coordinate_system(self, coordinate_system: CoordinateSystem) -> Self {
+        Self {
+            import_transform: coordinate_system.import_transform(),
+            ..self
+        }
+    }
+
+    // moves the file's content so the pivot sits at its origin, after it is converted from its coordinate system
+    pub fn with_pivot_mode(self, pivot_mode: PivotMode) -> Self {
+        Self {
+            pivot_mode,
+            ..self
+        }
+    }
+
+    // the lights in the first scene, which needs nothing uploading so can be read on any thread
+    pub fn lights(&self) -> Vec<GltfLight> {
+        let mut lights = Vec::new();
+        let import_transform = self.pivoted_import_transform();
+        if let Some(scene) = self.gltf.scenes().next() {
+            for scene_node in scene.nodes() {
+                collect_lights(&mut lights, &scene_node, import_transform);
             }
-            meshes.push(build_mesh_from_primitives(device, command_pool, &sources_data, primitive));
         }
+        lights
     }
 
-    if let Some(scene) = gltf.scenes().next() {
-        for scene_node in scene.nodes() {
-            update_transforms(&mut meshes, &scene_node, Mat4::IDENTITY);
+    // the import transform followed by the move of the pivot to the origin. The bounds come from the min and max glTF
+    // requires on position accessors, so are known before anything is uploaded
+    fn pivoted_import_transform(&self) -> Mat4 {
+        if self.pivot_mode == PivotMode::Keep {
+            return self.import_transform;
         }
+        let meshes: Vec<gltf::Mesh> = self.gltf.meshes().collect();
+        let mesh_bounds: Vec<Aabb> = mesh_instances(&self.gltf, self.import_transform).into_iter()
+            .flat_map(|instance| meshes[instance.mesh_index].primitives()
+                .zip(instance.primitive_transforms)
+                .filter_map(|(primitive, transform)| primitive.get(&Semantic::Positions)
+                    .and_then(|positions| accessor_bounds(&positions))
+                    .map(|bounds| bounds.transformed(transform)))
+                .collect::<Vec<Aabb>>())
+            .collect();
+        if mesh_bounds.is_empty() {
+            warn!("No mesh bounds to place the pivot with, keeping the file's origin");
+            return self.import_transform;
+        }
+        let bounds = Aabb::from_points(mesh_bounds.iter().flat_map(|bounds| [bounds.min, bounds.max]));
+        Mat4::from_translation(-self.pivot_mode.pivot(&bounds)) * self.import_transform
     }
+}
 
-    (meshes, materials, mesh_material_indices)
+// the min and max of a vec3 accessor
+fn accessor_bounds(accessor: &Accessor) -> Option<Aabb> {
+    let to_vec3 = |value: gltf::json::Value| -> Option<Vec3> {
+        let components: Vec<f32> = value.as_array()?.iter().map(|component| component.as_f64().map(|component| component as f32)).collect::<Option<_>>()?;
+        (components.len() == 3).then(|| Vec3::from_slice(&components))
+    };
+    Some(Aabb {
+        min: to_vec3(accessor.min()?)?,
+        max: to_vec3(accessor.max()?)?,
+    })
 }
 
-fn create_textureless_material(device: ConstPtr<Device>, physical_device: &PhysicalDevice, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager) -> PbrMaterial {
-    let base_color_texture = default_texture(device, physical_device, command_pool, descriptor_manager);
-    let normal_texture = default_texture(device, physical_device, command_pool, descriptor_manager);
-    let occlusion_roughness_metallic_texture = default_texture(device, physical_device, command_pool, descriptor_manager);
+pub fn upload_gltf(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, source: &GltfSource) -> MeshesAndMaterials {
+    let import_transform = source.pivoted_import_transform();
+    let GltfSource { gltf, material_extensions, sources_data, .. } = source;
+    let mut materials: Vec<PbrMaterial> = gltf.materials()
+        .map(|gltf_material| {
+            let extensions = gltf_material.index()
+                .and_then(|index| material_extensions.get(index).copied())
+                .unwrap_or_default();
+            load_gltf_material(device, physical_device, graphics_settings, command_pool, descriptor_manager, sources_data, &gltf_material, &extensions)
+        })
+        .collect();
+    // meshes and primitives are numbered differently, so the nodes are matched to meshes before placing primitives
+    let gltf_meshes: Vec<gltf::Mesh> = gltf.meshes().collect();
+    // every mesh instance is kept even if all its primitives are skipped, so the meshes stay in the file's order
+    let mut meshes: Vec<GltfMesh> = Vec::new();
+    for instance in mesh_instances(gltf, import_transform) {
+        let gltf_mesh = &gltf_meshes[instance.mesh_index];
+        let mut primitives = Vec::new();
+        for (primitive, primitive_transform) in gltf_mesh.primitives().zip(instance.primitive_transforms) {
+            let primitive_index = primitive.index();
+            let mut mesh = match build_mesh_from_primitives(device, command_pool, sources_data, primitive.clone()) {
+                Ok(mesh) => mesh,
+                Err(err) => {
+                    error!("Skipping primitive {} of mesh {}: {}", primitive_index, gltf_mesh.index(), err);
+                    continue;
+                }
+            };
+            mesh.relative_transform = primitive_transform;
+            let material_index = primitive.material().index().unwrap_or_else(|| {
+                materials.push(create_textureless_material(device, physical_device, graphics_settings, command_pool, descriptor_manager));
+                materials.len() - 1
+            });
+            primitives.push((mesh, material_index));
+        }
+        meshes.push(GltfMesh {
+            name: gltf_mesh.name().map(str::to_string),
+            primitives,
+        });
+    }
+
+    (meshes, materials)
+}
+
+fn create_textureless_material(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager) -> PbrMaterial {
+    let base_color_texture = default_texture(device, physical_device, graphics_settings, command_pool, descriptor_manager);
+    let normal_texture = default_texture(device, physical_device, graphics_settings, command_pool, descriptor_manager);
+    let occlusion_roughness_metallic_texture = default_texture(device, physical_device, graphics_settings, command_pool, descriptor_manager);
 
     PbrMaterial::create(device, command_pool, descriptor_manager, Arc::new(PbrMaterialTextures {
         base_color_texture,
@@ -74,13 +276,84 @@ fn create_textureless_material(device: ConstPtr<Device>, physical_device: &Physi
 }
 
 
-fn update_transforms(meshes: &mut Vec<Mesh>, node: &Node, parent_transform: Mat4) {
+// one placement of a glTF mesh by a node, with the relative transform of each of the mesh's primitives
+#[derive(Debug, PartialEq)]
+struct MeshInstance {
+    mesh_index: usize,
+    primitive_transforms: Vec<Mat4>,
+}
+
+// every placement of the file's meshes, by glTF mesh index then node order. A mesh used by several nodes has its
+// primitives uploaded once per node, as each mesh carries its own transform
+fn mesh_instances(gltf: &Gltf, import_transform: Mat4) -> Vec<MeshInstance> {
+    gltf.meshes()
+        .zip(mesh_node_transforms(gltf, import_transform))
+        .flat_map(|(gltf_mesh, node_transforms)| node_transforms.into_iter()
+            .map(move |transform| MeshInstance {
+                mesh_index: gltf_mesh.index(),
+                primitive_transforms: vec![transform; gltf_mesh.primitives().len()],
+            }))
+        .collect()
+}
+
+// the transforms of the nodes placing each of the file's meshes, by glTF mesh index. Meshes no node in the first scene
+// places are only converted by the import transform
+fn mesh_node_transforms(gltf: &Gltf, import_transform: Mat4) -> Vec<Vec<Mat4>> {
+    let mut transforms: Vec<Vec<Mat4>> = vec![Vec::new(); gltf.meshes().len()];
+    if let Some(scene) = gltf.scenes().next() {
+        for scene_node in scene.nodes() {
+            collect_mesh_transforms(&mut transforms, &scene_node, import_transform);
+        }
+    }
+    for mesh_transforms in transforms.iter_mut().filter(|mesh_transforms| mesh_transforms.is_empty()) {
+        mesh_transforms.push(import_transform);
+    }
+    transforms
+}
+
+fn collect_mesh_transforms(transforms: &mut [Vec<Mat4>], node: &Node, parent_transform: Mat4) {
     let transform = parent_transform * gltf_transform_to_mat4(node.transform());
     if let Some(mesh) = node.mesh() {
-        meshes[mesh.index()].relative_transform = transform;
+        transforms[mesh.index()].push(transform);
     }
     for child_node in node.children() {
-        update_transforms(meshes, &child_node, transform);
+        collect_mesh_transforms(transforms, &child_node, transform);
+    }
+}
+
+fn collect_lights(lights: &mut Vec<GltfLight>, node: &Node, parent_transform: Mat4) {
+    let transform = parent_transform * gltf_transform_to_mat4(node.transform());
+    if let Some(light) = node.light() {
+        // point and spot intensities are in candela and directional ones in lux, both are already in the inverse square
+        // and constant forms the engine's emissivity takes so are used as they are
+        let light_color = Vec3::from(light.color());
+        let imported_light = match light.kind() {
+            Kind::Point => ImportedLight::Point(PointLight {
+                light_color,
+                emissivity: light.intensity(),
+                range: light.range(),
+            }),
+            Kind::Directional => ImportedLight::Directional(DirectionalLight {
+                light_color,
+                emissivity: light.intensity(),
+            }),
+            Kind::Spot { inner_cone_angle, outer_cone_angle } => ImportedLight::Spot(SpotLight {
+                light_color,
+                emissivity: light.intensity(),
+                // glTF spot lights shine along the node's -z
+                direction: Vec3::NEG_Z,
+                inner_cone: inner_cone_angle,
+                outer_cone: outer_cone_angle,
+                range: light.range(),
+            }),
+        };
+        lights.push(GltfLight {
+            relative_transform: transform,
+            light: imported_light,
+        });
+    }
+    for child_node in node.children() {
+        collect_lights(lights, &child_node, transform);
     }
 }
 
@@ -96,8 +369,8 @@ fn missing_texture() -> RgbaImage {
     image::open(img_path).expect("Failed to open gltf image").to_rgba8()
 }
 
-fn default_texture(device: ConstPtr<Device>, physical_device: &PhysicalDevice, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager) -> Texture {
-    Texture::create(device, physical_device, command_pool, descriptor_manager, &TextureCreateInfo {
+fn default_texture(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager) -> Texture {
+    Texture::create(device, physical_device, graphics_settings, command_pool, descriptor_manager, &TextureCreateInfo {
         width: MISSING_TEXTURE_IMG.width(),
         height: MISSING_TEXTURE_IMG.height(),
         mip_levels: Some((MISSING_TEXTURE_IMG.width().max(MISSING_TEXTURE_IMG.height())).ilog2() + 1),
@@ -108,12 +381,15 @@ fn default_texture(device: ConstPtr<Device>, physical_device: &PhysicalDevice, c
             mip_map_mode: None,
             address_mode_u: Default::default(),
             address_mode_v: Default::default(),
+            anisotropy_level: None,
+            mip_lod_bias: None,
+            max_lod: None,
         }),
         format: vk::Format::R8G8B8A8_SRGB,
     })
 }
 
-fn load_gltf_material(device: ConstPtr<Device>, physical_device: &PhysicalDevice, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, data_buffers: &SourcesData, gltf_material: &gltf::material::Material) -> PbrMaterial {
+fn load_gltf_material(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, data_buffers: &SourcesData, gltf_material: &gltf::material::Material, extensions: &MaterialExtensions) -> PbrMaterial {
     let base_color_texture = gltf_material.pbr_metallic_roughness().base_color_texture();
     let base_color_tex_coord_index = base_color_texture.as_ref().map(|base_color_texture| base_color_texture.tex_coord());
     assert_eq!(base_color_tex_coord_index.unwrap(), 0, "Currently only support loading gltf models with the attribute TEXCOORD_0");
@@ -123,16 +399,16 @@ fn load_gltf_material(device: ConstPtr<Device>, physical_device: &PhysicalDevice
 
     let base_color_texture = base_color_texture.as_ref().map(|texture| {
         material_features |= PbrMaterialFeatureFlags::AlbedoTexture;
-        load_gltf_texture(device, physical_device, command_pool, descriptor_manager, data_buffers, &texture.texture(), vk::Format::R8G8B8A8_SRGB)
+        load_gltf_texture(device, physical_device, graphics_settings, command_pool, descriptor_manager, data_buffers, &texture.texture(), vk::Format::R8G8B8A8_SRGB)
     }).unwrap_or_else(|| {
-        default_texture(device, physical_device, command_pool, descriptor_manager)
+        default_texture(device, physical_device, graphics_settings, command_pool, descriptor_manager)
     });
 
     let normal_texture = gltf_material.normal_texture().map(|texture| {
         material_features |= PbrMaterialFeatureFlags::NormalTexture;
-        load_gltf_texture(device, physical_device, command_pool, descriptor_manager, data_buffers, &texture.texture(), vk::Format::R8G8B8A8_UNORM)
+        load_gltf_texture(device, physical_device, graphics_settings, command_pool, descriptor_manager, data_buffers, &texture.texture(), vk::Format::R8G8B8A8_UNORM)
     }).unwrap_or_else(|| {
-        default_texture(device, physical_device, command_pool, descriptor_manager)
+        default_texture(device, physical_device, graphics_settings, command_pool, descriptor_manager)
     });
 
     // TODO this assumes that occlusion always uses the R channel, metal B and roughness G. Metal and
@@ -142,11 +418,14 @@ fn load_gltf_material(device: ConstPtr<Device>, physical_device: &PhysicalDevice
         if gltf_material.occlusion_texture().is_some() {
             material_features |= PbrMaterialFeatureFlags::OcclusionTexture;
         }
-        load_gltf_texture(device, physical_device, command_pool, descriptor_manager, data_buffers, &texture.texture(), vk::Format::R8G8B8A8_UNORM)
+        load_gltf_texture(device, physical_device, graphics_settings, command_pool, descriptor_manager, data_buffers, &texture.texture(), vk::Format::R8G8B8A8_UNORM)
     }).unwrap_or_else(|| {
-        default_texture(device, physical_device, command_pool, descriptor_manager)
+        default_texture(device, physical_device, graphics_settings, command_pool, descriptor_manager)
     });
 
+    // TODO support emissive textures
+    let emissive = Vec3::from(gltf_material.emissive_factor()) * extensions.emissive_strength.unwrap_or(1.0);
+
     PbrMaterial::create(
         device,
         command_pool,
@@ -158,106 +437,260 @@ fn load_gltf_material(device: ConstPtr<Device>, physical_device: &PhysicalDevice
         }),
         &PbrMaterialOptions {
             base_color,
+            emissive,
+            clearcoat: extensions.clearcoat,
+            double_sided: gltf_material.double_sided(),
             features: material_features,
+            uv_transforms: extensions.uv_transforms,
             ..Default::default()
         },
     )
 }
 
-fn build_mesh_from_primitives(device: ConstPtr<Device>, command_pool: &CommandPool, data_buffers: &SourcesData, primitive: gltf::Primitive) -> Mesh {
-    let primitive_attributes = PrimitiveAttributes::new(&primitive, data_buffers);
+#[derive(Default, Copy, Clone)]
+struct MaterialExtensions {
+    emissive_strength: Option<f32>,
+    clearcoat: Option<ClearcoatOptions>,
+    uv_transforms: PbrTextureTransforms,
+}
+
+impl MaterialExtensions {
+    fn from_json(material: &gltf::json::Value) -> Self {
+        let pbr_metallic_roughness = material.get("pbrMetallicRoughness");
+        let uv_transforms = PbrTextureTransforms {
+            base_color: texture_transform(pbr_metallic_roughness.and_then(|pbr| pbr.get("baseColorTexture"))),
+            normal: texture_transform(material.get("normalTexture")),
+            occlusion_roughness_metallic: texture_transform(pbr_metallic_roughness.and_then(|pbr| pbr.get("metallicRoughnessTexture"))),
+        };
+        let Some(extensions) = material.get("extensions") else {
+            return Self {
+                uv_transforms,
+                ..Self::default()
+            };
+        };
+        let emissive_strength = extensions.get("KHR_materials_emissive_strength")
+            .and_then(|extension| json_f32(extension, "emissiveStrength"));
+        let clearcoat = extensions.get("KHR_materials_clearcoat").map(|extension| ClearcoatOptions {
+            factor: json_f32(extension, "clearcoatFactor").unwrap_or(0.0),
+            roughness: json_f32(extension, "clearcoatRoughnessFactor").unwrap_or(0.0),
+        });
+        Self {
+            emissive_strength,
+            clearcoat,
+            uv_transforms,
+        }
+    }
+}
+
+// reads KHR_texture_transform from a texture info, identity when it isn't present
+fn texture_transform(texture_info: Option<&gltf::json::Value>) -> UvTransform {
+    let Some(extension) = texture_info
+        .and_then(|texture_info| texture_info.get("extensions"))
+        .and_then(|extensions| extensions.get("KHR_texture_transform")) else {
+        return UvTransform::default();
+    };
+    let identity = UvTransform::default();
+    UvTransform {
+        offset: json_vec2(extension, "offset").unwrap_or(identity.offset),
+        rotation: json_f32(extension, "rotation").unwrap_or(identity.rotation),
+        scale: json_vec2(extension, "scale").unwrap_or(identity.scale),
+    }
+}
+
+fn json_vec2(value: &gltf::json::Value, key: &str) -> Option<Vec2> {
+    let array = value.get(key)?.as_array()?;
+    match array.as_slice() {
+        [x, y] => Some(Vec2::new(x.as_f64()? as f32, y.as_f64()? as f32)),
+        _ => None,
+    }
+}
+
+fn json_f32(value: &gltf::json::Value, key: &str) -> Option<f32> {
+    value.get(key).and_then(|value| value.as_f64()).map(|value| value as f32)
+}
+
+// the gltf crate drops material and texture info extensions it has no feature for, so pull them out of the raw json, indexed by material
+fn read_material_extensions(gltf_bytes: &[u8]) -> Vec<MaterialExtensions> {
+    let json_bytes = if gltf_bytes.starts_with(b"glTF") {
+        Glb::from_slice(gltf_bytes).expect("Failed to read glb").json
+    } else {
+        gltf_bytes.into()
+    };
+    let document: gltf::json::Value = gltf::json::deserialize::from_slice(&json_bytes).expect("Failed to parse gltf json");
+    document.get("materials")
+        .and_then(|materials| materials.as_array())
+        .map(|materials| materials.iter().map(MaterialExtensions::from_json).collect())
+        .unwrap_or_default()
+}
+
+// fails on accessors that would read outside of their buffers rather than reading out of bounds
+fn build_mesh_from_primitives(device: ConstPtr<Device>, command_pool: &CommandPool, data_buffers: &SourcesData, primitive: gltf::Primitive) -> Result<Mesh, String> {
+    let primitive_attributes = PrimitiveAttributes::new(&primitive, data_buffers)?;
 
-    let position_accessor: BufferAccessor<Vec3> = primitive_attributes.attribute_accessor(Semantic::Positions).unwrap();
+    let mode = primitive.mode();
+    // points and lines have no surface to shade, so they may leave out the attributes that describe one
+    let has_surface = matches!(mode, Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan);
+    let position_accessor: BufferAccessor<Vec3> = primitive_attributes.attribute_accessor(Semantic::Positions)?;
     // TODO handle when no tangents exist on a model
-    let tangent_accessor: BufferAccessor<[f32; 4]> = primitive_attributes.attribute_accessor(Semantic::Tangents).unwrap();
-    let normal_accessor: BufferAccessor<Vec3> = primitive_attributes.attribute_accessor(Semantic::Normals).unwrap();
-    let base_color_tex_coord_accessor: BufferAccessor<Vec2> = primitive_attributes.attribute_accessor(Semantic::TexCoords(0)).unwrap();
+    let tangent_accessor: Option<BufferAccessor<[f32; 4]>> = primitive_attributes.surface_attribute_accessor(Semantic::Tangents, has_surface)?;
+    let normal_accessor: Option<BufferAccessor<Vec3>> = primitive_attributes.surface_attribute_accessor(Semantic::Normals, has_surface)?;
+    let base_color_tex_coord_accessor: Option<BufferAccessor<Vec2>> = primitive_attributes.surface_attribute_accessor(Semantic::TexCoords(0), has_surface)?;
 
     let vertices: Vec<Vertex> = (0..primitive_attributes.vertex_count)
         .map(|i| {
             let position = position_accessor.data_at_index(i);
-            let tangent = tangent_accessor.data_at_index(i);
-            let normal = normal_accessor.data_at_index(i);
+            let tangent = tangent_accessor.as_ref().map_or([1.0, 0.0, 0.0, 1.0], |accessor| accessor.data_at_index(i));
+            let normal = normal_accessor.as_ref().map_or(Vec3::Y, |accessor| accessor.data_at_index(i));
             Vertex {
                 position,
                 normal,
-                texture_coord: base_color_tex_coord_accessor.data_at_index(i),
+                texture_coord: base_color_tex_coord_accessor.as_ref().map_or(Vec2::ZERO, |accessor| accessor.data_at_index(i)),
                 tangent: Vec4::new(tangent[0], tangent[1], tangent[2], tangent[3]),
             }
         })
         .collect();
 
-    let indices: Vec<u32> = (0..primitive_attributes.index_count)
-        .map(|i| match &primitive_attributes.indices_accessor {
-            IndexAccessor::U8(accessor) => { accessor.data_at_index(i) as u32 }
-            IndexAccessor::U16(accessor) => { accessor.data_at_index(i) as u32 }
-            IndexAccessor::U32(accessor) => { accessor.data_at_index(i) }
-        })
-        .collect();
+    let indices = mesh_indices(&primitive_attributes, mode);
 
+    // the vertex and index buffers are uploaded in one submit
+    let mut command_buffer = command_pool.one_time_command_buffer();
     let buffer_data: &[u8] = bytemuck::cast_slice(vertices.as_slice());
-    let vertex_buffer = Buffer::create_and_initialize_buffer_with_staging_buffer(device, command_pool, BufferCreateInfo {
+    let vertex_buffer = Buffer::record_staged_upload(device, &mut command_buffer, BufferCreateInfo {
         data: buffer_data,
         usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
     });
 
-    let index_buffer_data: &[u8] = bytemuck::cast_slice(indices.as_slice());
-    let index_buffer = Buffer::create_and_initialize_buffer_with_staging_buffer(device, command_pool, BufferCreateInfo {
+    let index_buffer_data: &[u8] = match &indices {
+        MeshIndices::U16(indices) => bytemuck::cast_slice(indices.as_slice()),
+        MeshIndices::U32(indices) => bytemuck::cast_slice(indices.as_slice()),
+    };
+    let index_buffer = Buffer::record_staged_upload(device, &mut command_buffer, BufferCreateInfo {
         data: index_buffer_data,
         usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
     });
+    command_buffer.submit_and_wait().expect("Failed to upload mesh buffers");
 
-    Mesh {
+    Ok(Mesh {
         vertex_buffer,
         index_buffer,
         index_count: indices.len() as u32,
+        index_type: indices.index_type(),
+        topology: list_topology(mode),
         relative_transform: Mat4::IDENTITY,
+        bounds: Aabb::from_points(vertices.iter().map(|vertex| vertex.position)),
+        lods: Vec::new(),
+    })
+}
+
+fn list_topology(mode: Mode) -> vk::PrimitiveTopology {
+    match mode {
+        Mode::Points => vk::PrimitiveTopology::POINT_LIST,
+        Mode::Lines | Mode::LineLoop | Mode::LineStrip => vk::PrimitiveTopology::LINE_LIST,
+        Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan => vk::PrimitiveTopology::TRIANGLE_LIST,
+    }
+}
+
+// converts the indices of strips, fans and loops into lists of the same primitives, so every mesh of a kind can be drawn
+// with the same pipeline
+fn list_indices<T: Copy>(mode: Mode, indices: Vec<T>) -> Vec<T> {
+    match mode {
+        Mode::Points | Mode::Lines | Mode::Triangles => indices,
+        Mode::LineStrip => indices.windows(2).flatten().copied().collect(),
+        Mode::LineLoop => {
+            let mut line_list: Vec<T> = indices.windows(2).flatten().copied().collect();
+            if indices.len() > 2 {
+                line_list.extend([indices[indices.len() - 1], indices[0]]);
+            }
+            line_list
+        }
+        // every other triangle of a strip has its first two vertices swapped to keep the winding the same
+        Mode::TriangleStrip => indices.windows(3).enumerate()
+            .flat_map(|(i, triangle)| if i % 2 == 0 { [triangle[0], triangle[1], triangle[2]] } else { [triangle[1], triangle[0], triangle[2]] })
+            .collect(),
+        Mode::TriangleFan => indices.windows(2).skip(1)
+            .flat_map(|edge| [indices[0], edge[0], edge[1]])
+            .collect(),
     }
 }
 
-fn load_gltf_texture(device: ConstPtr<Device>, physical_device: &PhysicalDevice, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, data_buffers: &SourcesData, texture: &gltf::Texture, format: vk::Format) -> Texture {
-    let image = data_buffers.images[texture.index()].to_rgba8();
-    let sampler_options = TexSamplerOptions::from_gltf(&texture.sampler());
+fn load_gltf_texture(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, data_buffers: &SourcesData, texture: &gltf::Texture, format: vk::Format) -> Texture {
+    let image = &data_buffers.images[texture.index()];
+    let sampler_options = TexSamplerOptions::from_gltf(&texture.sampler(), graphics_settings.default_sampler);
 
-    Texture::create(device, physical_device, command_pool, descriptor_manager, &TextureCreateInfo {
+    let create_info = TextureCreateInfo {
         width: image.width(),
         height: image.height(),
         mip_levels: Some((image.width().max(image.height())).ilog2() + 1),
         data: image.as_bytes(),
         sampler_info: SamplerOptions::FilterOptions(&sampler_options),
         format,
-    })
+    };
+    if graphics_settings.texture_streaming {
+        Texture::create_streamed(device, physical_device, graphics_settings, command_pool, descriptor_manager, &create_info)
+    } else {
+        Texture::create(device, physical_device, graphics_settings, command_pool, descriptor_manager, &create_info)
+    }
+}
+
+// u8 indices need an extension to bind, so they are widened to u16 instead. Primitives without indices use their
+// vertices in order, so they're given sequential ones and drawn like any other mesh
+fn mesh_indices(primitive_attributes: &PrimitiveAttributes, mode: Mode) -> MeshIndices {
+    let index_count = primitive_attributes.index_count;
+    match &primitive_attributes.indices_accessor {
+        Some(IndexAccessor::U8(accessor)) => MeshIndices::U16(list_indices(mode, (0..index_count).map(|i| accessor.data_at_index(i) as u16).collect())),
+        Some(IndexAccessor::U16(accessor)) => MeshIndices::U16(list_indices(mode, (0..index_count).map(|i| accessor.data_at_index(i)).collect())),
+        Some(IndexAccessor::U32(accessor)) => MeshIndices::U32(list_indices(mode, (0..index_count).map(|i| accessor.data_at_index(i)).collect())),
+        None if index_count <= u16::MAX as usize => MeshIndices::U16(list_indices(mode, (0..index_count as u16).collect())),
+        None => MeshIndices::U32(list_indices(mode, (0..index_count as u32).collect())),
+    }
 }
 
 struct PrimitiveAttributes<'a> {
-    data_buffers: &'a SourcesData<'a>,
+    data_buffers: &'a SourcesData,
     semantic_accessors: AHashMap<Semantic, Accessor<'a>>,
-    indices_accessor: IndexAccessor<'a>,
+    // None for non-indexed primitives
+    indices_accessor: Option<IndexAccessor<'a>>,
     vertex_count: usize,
+    // the vertex count when there are no indices
     index_count: usize,
 }
 
 impl<'a> PrimitiveAttributes<'a> {
-    fn new(primitive: &gltf::Primitive<'a>, data_buffers: &'a SourcesData) -> Self {
+    fn new(primitive: &gltf::Primitive<'a>, data_buffers: &'a SourcesData) -> Result<Self, String> {
         let semantic_accessors: AHashMap<Semantic, Accessor<'a>> = primitive.attributes().map(|attribute| (attribute.0, attribute.1)).collect();
-        let vertex_count = semantic_accessors.get(&Semantic::Positions).unwrap().count();
-        let indices_accessor = match primitive.indices().unwrap().data_type() {
-            ComponentType::U8 => { IndexAccessor::U8(BufferAccessor::new(data_buffers, &primitive.indices().unwrap())) }
-            ComponentType::U16 => { IndexAccessor::U16(BufferAccessor::new(data_buffers, &primitive.indices().unwrap())) }
-            ComponentType::U32 => { IndexAccessor::U32(BufferAccessor::new(data_buffers, &primitive.indices().unwrap())) }
-            _ => { panic!("Index type other than u8, u16 and u32 are not supported") }
+        let vertex_count = semantic_accessors.get(&Semantic::Positions)
+            .ok_or("Missing Positions attribute")?
+            .count();
+        let indices_accessor = match primitive.indices() {
+            Some(indices) => Some(match indices.data_type() {
+                ComponentType::U8 => { IndexAccessor::U8(BufferAccessor::new(data_buffers, &indices)?) }
+                ComponentType::U16 => { IndexAccessor::U16(BufferAccessor::new(data_buffers, &indices)?) }
+                ComponentType::U32 => { IndexAccessor::U32(BufferAccessor::new(data_buffers, &indices)?) }
+                _ => { panic!("Index type other than u8, u16 and u32 are not supported") }
+            }),
+            None => None,
         };
-        PrimitiveAttributes {
+        Ok(PrimitiveAttributes {
             semantic_accessors,
             data_buffers,
             indices_accessor,
             vertex_count,
-            index_count: primitive.indices().unwrap().count(),
-        }
+            index_count: primitive.indices().map_or(vertex_count, |indices| indices.count()),
+        })
     }
 
-    fn attribute_accessor<T>(&self, semantic: Semantic) -> Option<BufferAccessor<'a, T>> where T: Pod, T: Zeroable {
-        self.semantic_accessors.get(&semantic).map(|accessor| BufferAccessor::new(self.data_buffers, accessor))
+    fn attribute_accessor<T>(&self, semantic: Semantic) -> Result<BufferAccessor<'a, T>, String> where T: Pod, T: Zeroable {
+        let accessor = self.semantic_accessors.get(&semantic)
+            .ok_or_else(|| format!("Missing {:?} attribute", semantic))?;
+        BufferAccessor::new(self.data_buffers, accessor)
+    }
+
+    // the attribute is only required when the primitive has a surface, otherwise a missing attribute is None
+    fn surface_attribute_accessor<T>(&self, semantic: Semantic, has_surface: bool) -> Result<Option<BufferAccessor<'a, T>>, String> where T: Pod, T: Zeroable {
+        if !has_surface && !self.semantic_accessors.contains_key(&semantic) {
+            return Ok(None);
+        }
+        self.attribute_accessor(semantic).map(Some)
     }
 }
 
@@ -267,9 +700,31 @@ enum IndexAccessor<'a> {
     U32(BufferAccessor<'a, u32>),
 }
 
-enum BufferData<'a> {
+#[derive(Debug, PartialEq)]
+enum MeshIndices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl MeshIndices {
+    fn len(&self) -> usize {
+        match self {
+            MeshIndices::U16(indices) => indices.len(),
+            MeshIndices::U32(indices) => indices.len(),
+        }
+    }
+
+    fn index_type(&self) -> vk::IndexType {
+        match self {
+            MeshIndices::U16(_) => vk::IndexType::UINT16,
+            MeshIndices::U32(_) => vk::IndexType::UINT32,
+        }
+    }
+}
+
+enum BufferData {
     Source(SourceBuffers),
-    Bin(&'a Vec<u8>),
+    Bin(Vec<u8>),
 }
 
 struct SourceBuffers {
@@ -277,18 +732,19 @@ struct SourceBuffers {
     buffer_offsets: Vec<usize>,
 }
 
-struct SourcesData<'a> {
-    buffer_data: BufferData<'a>,
-    images: Vec<DynamicImage>,
+struct SourcesData {
+    buffer_data: BufferData,
+    // decoded to rgba up front so none of the decoding is left for the upload
+    images: Vec<RgbaImage>,
 }
 
-impl<'a> SourcesData<'a> {
-    fn load_data_into_memory(gltf: &'a Gltf, working_dir: &Path) -> Self {
-        let buffer_data_temp: Vec<MaybeUninit<u8>> = vec![MaybeUninit::<u8>::uninit(); gltf.buffers().map(|buffer| buffer.length()).sum()];
-        let buffer_data = if let Some(bin) = &gltf.blob {
+impl SourcesData {
+    // takes the glb's binary chunk so the data can outlive the borrow of the gltf
+    fn load_data_into_memory(gltf: &mut Gltf, working_dir: &Path, progress: &mut dyn FnMut(LoadProgress)) -> Self {
+        let buffer_data = if let Some(bin) = gltf.blob.take() {
             BufferData::Bin(bin)
         } else {
-            let mut buffer_data: Vec<u8> = unsafe { mem::transmute(buffer_data_temp) };
+            let mut buffer_data = vec![0u8; gltf.buffers().map(|buffer| buffer.length()).sum()];
             let mut buffer_offsets: Vec<usize> = Vec::with_capacity(gltf.buffers().len());
             for buffer in gltf.buffers() {
                 let offset = buffer_offsets.iter().sum();
@@ -311,7 +767,9 @@ impl<'a> SourcesData<'a> {
             })
         };
 
-        let mut images: Vec<DynamicImage> = Vec::with_capacity(gltf.images().len());
+        let total_textures = gltf.images().len();
+        let mut images: Vec<RgbaImage> = Vec::with_capacity(total_textures);
+        progress(LoadProgress { loaded_textures: 0, total_textures });
         for image in gltf.images() {
             let image = match image.source() {
                 gltf::image::Source::View {
@@ -337,7 +795,8 @@ impl<'a> SourcesData<'a> {
                     image::open(img_path).expect("Failed to open gltf image")
                 }
             };
-            images.push(image);
+            images.push(image.into_rgba8());
+            progress(LoadProgress { loaded_textures: images.len(), total_textures });
         }
 
         SourcesData {
@@ -372,26 +831,154 @@ struct BufferAccessor<'a, T> where T: Zeroable, T: Pod {
 }
 
 impl<'a, T> BufferAccessor<'a, T> where T: Zeroable, T: Pod {
-    fn new(buffers: &'a SourcesData, accessor: &Accessor) -> Self {
-        let view = accessor.view().unwrap();
+    // checks every element the accessor covers is within its view and the view is within its buffer, so reading any
+    // index below the accessor's count stays in bounds
+    fn new(buffers: &'a SourcesData, accessor: &Accessor) -> Result<Self, String> {
+        let view = accessor.view().ok_or_else(|| format!("Accessor {} has no buffer view", accessor.index()))?;
+        if accessor.size() != size_of::<T>() {
+            return Err(format!("Accessor {} has {} byte elements, expected {}", accessor.index(), accessor.size(), size_of::<T>()));
+        }
+        // interleaved attributes share a view with a stride, tightly packed ones are the size of their element apart
         let stride = view.stride().unwrap_or_else(|| accessor.size());
         let offset = accessor.offset() + view.offset();
-        BufferAccessor {
-            buffer_data: buffers.buffer_ref(view.buffer().index()),
+        let buffer_data = buffers.buffer_ref(view.buffer().index());
+        let view_end = view.offset() + view.length();
+        let accessor_end = match accessor.count() {
+            0 => offset,
+            count => offset + stride * (count - 1) + size_of::<T>(),
+        };
+        if view_end > buffer_data.len() || accessor_end > view_end {
+            return Err(format!("Accessor {} reads past the end of its buffer view", accessor.index()));
+        }
+        Ok(BufferAccessor {
+            buffer_data,
             stride,
             offset,
             marker: std::marker::PhantomData,
-        }
+        })
     }
 
     fn data_at_index(&self, index: usize) -> T {
-        let data = unsafe { self.buffer_data.as_ptr().add(self.offset + index * self.stride) };
-        unsafe { *(data as *const T) }
+        let start = self.offset + index * self.stride;
+        // glTF only aligns elements to their component size, so they can't be read in place as T
+        bytemuck::pod_read_unaligned(&self.buffer_data[start..start + size_of::<T>()])
     }
 }
 
-fn read_gltf_file(path: &Path) -> Gltf {
-    let file = fs::File::open(path).expect("failed to open gltf file");
-    let reader = io::BufReader::new(file);
-    Gltf::from_reader(reader).expect("Failed to read gltf")
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a single triangle's positions start the buffer, any index data follows them
+    const TRIANGLE_POSITIONS: [f32; 9] = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+    const POSITIONS_LENGTH: usize = 36;
+
+    const FLOAT: u32 = 5126;
+    const UNSIGNED_BYTE: u32 = 5121;
+    const UNSIGNED_SHORT: u32 = 5123;
+
+    fn sources_data(index_data: &[u8]) -> SourcesData {
+        let mut data: Vec<u8> = bytemuck::cast_slice(&TRIANGLE_POSITIONS).to_vec();
+        data.extend_from_slice(index_data);
+        SourcesData {
+            buffer_data: BufferData::Bin(data),
+            images: Vec::new(),
+        }
+    }
+
+    // one mesh with a single triangle, indexed with the component type when there is one
+    fn triangle_json(index_component_type: Option<u32>, index_data_length: usize) -> String {
+        let (indices, index_view, index_accessor) = match index_component_type {
+            Some(component_type) => (
+                r#","indices":1"#.to_string(),
+                format!(r#",{{"buffer":0,"byteOffset":{},"byteLength":{}}}"#, POSITIONS_LENGTH, index_data_length),
+                format!(r#",{{"bufferView":1,"componentType":{},"count":3,"type":"SCALAR"}}"#, component_type),
+            ),
+            None => (String::new(), String::new(), String::new()),
+        };
+        format!(r#"{{
+            "asset":{{"version":"2.0"}},
+            "buffers":[{{"byteLength":{}}}],
+            "bufferViews":[{{"buffer":0,"byteOffset":0,"byteLength":{}}}{}],
+            "accessors":[{{"bufferView":0,"componentType":{},"count":3,"type":"VEC3","min":[0,0,0],"max":[1,1,0]}}{}],
+            "meshes":[{{"primitives":[{{"attributes":{{"POSITION":0}}{}}}]}}]
+        }}"#, POSITIONS_LENGTH + index_data_length, POSITIONS_LENGTH, index_view, FLOAT, index_accessor, indices)
+    }
+
+    fn triangle_indices(index_component_type: Option<u32>, index_data: &[u8]) -> MeshIndices {
+        let gltf = Gltf::from_slice(triangle_json(index_component_type, index_data.len()).as_bytes()).unwrap();
+        let sources_data = sources_data(index_data);
+        let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+        let primitive_attributes = PrimitiveAttributes::new(&primitive, &sources_data).unwrap();
+        mesh_indices(&primitive_attributes, primitive.mode())
+    }
+
+    #[test]
+    fn test_u16_indices_stay_u16() {
+        let indices = triangle_indices(Some(UNSIGNED_SHORT), bytemuck::cast_slice(&[2u16, 1, 0]));
+        assert_eq!(indices.index_type(), vk::IndexType::UINT16);
+        assert_eq!(indices, MeshIndices::U16(vec![2, 1, 0]));
+    }
+
+    #[test]
+    fn test_u8_indices_widen_to_u16() {
+        let indices = triangle_indices(Some(UNSIGNED_BYTE), &[2u8, 1, 0]);
+        assert_eq!(indices.index_type(), vk::IndexType::UINT16);
+        assert_eq!(indices, MeshIndices::U16(vec![2, 1, 0]));
+    }
+
+    #[test]
+    fn test_unindexed_primitive_gets_sequential_indices() {
+        let indices = triangle_indices(None, &[]);
+        assert_eq!(indices.index_type(), vk::IndexType::UINT16);
+        assert_eq!(indices, MeshIndices::U16(vec![0, 1, 2]));
+    }
+
+    // the triangle's positions shared by every primitive, placed by the nodes of the first scene
+    fn placed_meshes_json(scene_nodes: &str, nodes: &str, meshes: &str) -> String {
+        format!(r#"{{
+            "asset":{{"version":"2.0"}},
+            "scene":0,
+            "scenes":[{{"nodes":{}}}],
+            "nodes":{},
+            "buffers":[{{"byteLength":{}}}],
+            "bufferViews":[{{"buffer":0,"byteOffset":0,"byteLength":{}}}],
+            "accessors":[{{"bufferView":0,"componentType":{},"count":3,"type":"VEC3","min":[0,0,0],"max":[1,1,0]}}],
+            "meshes":{}
+        }}"#, scene_nodes, nodes, POSITIONS_LENGTH, POSITIONS_LENGTH, FLOAT, meshes)
+    }
+
+    fn flattened_primitive_transforms(gltf: &Gltf) -> Vec<(usize, Mat4)> {
+        mesh_instances(gltf, Mat4::IDENTITY).into_iter()
+            .flat_map(|instance| instance.primitive_transforms.into_iter().map(move |transform| (instance.mesh_index, transform)))
+            .collect()
+    }
+
+    #[test]
+    fn test_primitives_get_their_own_nodes_transform() {
+        // mesh 0 has two primitives under a scaled parent node, mesh 1 has one under its own rotated node
+        let json = placed_meshes_json(
+            "[0,2]",
+            r#"[{"children":[1],"scale":[2,2,2]},{"mesh":0,"translation":[1,2,3]},{"mesh":1,"rotation":[0,0.7071068,0,0.7071068],"translation":[-4,0,5]}]"#,
+            r#"[{"primitives":[{"attributes":{"POSITION":0}},{"attributes":{"POSITION":0}}]},{"primitives":[{"attributes":{"POSITION":0}}]}]"#,
+        );
+        let gltf = Gltf::from_slice(json.as_bytes()).unwrap();
+        let first_transform = Mat4::from_scale(Vec3::splat(2.0)) * Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0));
+        let second_transform = gltf_transform_to_mat4(gltf.nodes().nth(2).unwrap().transform());
+        assert_ne!(second_transform, Mat4::IDENTITY);
+        assert_eq!(flattened_primitive_transforms(&gltf), vec![(0, first_transform), (0, first_transform), (1, second_transform)]);
+    }
+
+    #[test]
+    fn test_mesh_used_by_several_nodes_is_placed_at_each() {
+        let json = placed_meshes_json(
+            "[0,1]",
+            r#"[{"mesh":0,"translation":[1,0,0]},{"mesh":0,"translation":[0,0,-1]}]"#,
+            r#"[{"primitives":[{"attributes":{"POSITION":0}},{"attributes":{"POSITION":0}}]}]"#,
+        );
+        let gltf = Gltf::from_slice(json.as_bytes()).unwrap();
+        let first_transform = Mat4::from_translation(Vec3::new(1.0, 0.0, 0.0));
+        let second_transform = Mat4::from_translation(Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(flattened_primitive_transforms(&gltf), vec![(0, first_transform), (0, first_transform), (0, second_transform), (0, second_transform)]);
+    }
+}
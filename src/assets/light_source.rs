@@ -1,70 +1,283 @@
+use std::mem::size_of;
+
 use ash::vk;
 use bevy_ecs::prelude::*;
-use crevice::std140::AsStd140;
-use glam::Vec4Swizzles;
-use crate::assets::demo_scenes::Actor;
+use bevy_hierarchy::Parent;
+use bytemuck_derive::{Pod, Zeroable};
+use log::warn;
+
+use crate::assets::gizmos::GizmoRenderer;
 use crate::assets::render_object::Transform;
 use crate::etna::{Device, HostMappedBuffer, HostMappedBufferCreateInfo};
 use crate::etna::material_pipeline::DescriptorManager;
-use crate::rehnda_core::{ConstPtr, Vec3};
+use crate::rehnda_core::{ColorRgbaF, ConstPtr, Mat4, Vec3, Vec4};
+
+// MUST KEEP IN SYNC WITH MAX_LIGHTS in lights.glsl
+pub const MAX_LIGHTS: usize = 16;
 
-#[derive(Component)]
+/// Light radiating in every direction from its entity's translation, falling off with the square of the distance
+#[derive(Component, Copy, Clone, Debug)]
 pub struct PointLight {
     pub light_color: Vec3,
     pub emissivity: f32,
+    // the light is faded out to nothing at this distance, without one it falls off forever
+    pub range: Option<f32>,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        Self {
+            light_color: (1.0, 1.0, 1.0).into(),
+            emissivity: 1.0,
+            range: None,
+        }
+    }
 }
 
-#[derive(AsStd140)]
-struct PointLightUniform {
-    pub position: Vec3,
+/// Light arriving from infinitely far away along its entity's forward (-z) axis, like the sun. The emissivity is the
+/// light reaching a surface facing it, wherever the surface is
+#[derive(Component, Copy, Clone, Debug)]
+pub struct DirectionalLight {
     pub light_color: Vec3,
     pub emissivity: f32,
 }
 
-impl Default for PointLight {
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            light_color: (1.0, 1.0, 1.0).into(),
+            emissivity: 3.0,
+        }
+    }
+}
+
+/// A point light limited to a cone around its direction, fading out between the inner and outer cone angles
+#[derive(Component, Copy, Clone, Debug)]
+pub struct SpotLight {
+    pub light_color: Vec3,
+    pub emissivity: f32,
+    // in the entity's space, so the light turns with its transform
+    pub direction: Vec3,
+    // angles in radians from the direction to the edges of the cone. Inside the inner cone the light is at full
+    // strength, past the outer one there is none
+    pub inner_cone: f32,
+    pub outer_cone: f32,
+    // the light is faded out to nothing at this distance, without one it falls off forever
+    pub range: Option<f32>,
+}
+
+impl Default for SpotLight {
     fn default() -> Self {
         Self {
             light_color: (1.0, 1.0, 1.0).into(),
             emissivity: 1.0,
+            direction: Vec3::NEG_Z,
+            inner_cone: 0.0,
+            outer_cone: std::f32::consts::FRAC_PI_4,
+            range: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum LightKind {
+    Point,
+    Directional,
+    Spot,
+}
+
+impl LightKind {
+    // MUST KEEP IN SYNC WITH the light kinds in lights.glsl
+    fn shader_value(&self) -> f32 {
+        match self {
+            LightKind::Point => 0.0,
+            LightKind::Directional => 1.0,
+            LightKind::Spot => 2.0,
+        }
+    }
+}
+
+// MUST KEEP IN SYNC WITH Light in lights.glsl
+#[repr(C)]
+#[derive(Pod, Zeroable, Debug, Copy, Clone)]
+struct LightUniform {
+    // xyz: world position, w: kind
+    position: Vec4,
+    // xyz: the direction the light shines in, w: range, 0 for unlimited
+    direction: Vec4,
+    // rgb: color, w: emissivity
+    color: Vec4,
+    // x: cosine of the inner cone angle, y: cosine of the outer cone angle, only used by spot lights
+    cone: Vec4,
+}
+
+impl LightUniform {
+    // the direction is in the light's space, lights without one shine along their forward axis
+    fn new(kind: LightKind, world_matrix: Mat4, direction: Vec3, light_color: Vec3, emissivity: f32, range: Option<f32>) -> Self {
+        let direction = world_matrix.transform_vector3(direction).normalize_or_zero();
+        Self {
+            position: world_matrix.w_axis.truncate().extend(kind.shader_value()),
+            direction: direction.extend(range.unwrap_or(0.0)),
+            color: light_color.extend(emissivity),
+            cone: Vec4::ZERO,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Debug, Copy, Clone)]
+struct LightsUniform {
+    // x: the number of lights used
+    light_count: [u32; 4],
+    lights: [LightUniform; MAX_LIGHTS],
+}
+
+/// Adjustments applied to the skybox and image based lighting at draw time, so they don't require re-capturing the environment maps
+#[derive(Resource)]
+pub struct EnvironmentSettings {
+    pub intensity: f32,
+    pub rotation_yaw_degrees: f32,
+    // constant light used in place of the image based lighting while no environment is loaded
+    pub ambient_color: Vec3,
+    pub ambient_intensity: f32,
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            rotation_yaw_degrees: 0.0,
+            ambient_color: Vec3::ONE,
+            ambient_intensity: 0.03,
+        }
+    }
+}
+
+/// Distance fog blended over the scene in the forward shaders, the skybox is fogged towards the horizon
+#[derive(Resource)]
+pub struct Fog {
+    pub color: Vec3,
+    pub mode: FogMode,
+    // used by the exponential modes
+    pub density: f32,
+    // view distances the linear fog ramps between
+    pub start: f32,
+    pub end: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FogMode {
+    Off,
+    Linear,
+    Exponential,
+    ExponentialSquared,
+}
+
+impl FogMode {
+    pub const ALL: [FogMode; 4] = [FogMode::Off, FogMode::Linear, FogMode::Exponential, FogMode::ExponentialSquared];
+
+    // MUST KEEP IN SYNC WITH the fog modes in common.glsl
+    pub fn shader_value(&self) -> f32 {
+        match self {
+            FogMode::Off => 0.0,
+            FogMode::Linear => 1.0,
+            FogMode::Exponential => 2.0,
+            FogMode::ExponentialSquared => 3.0,
         }
     }
 }
 
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            color: Vec3::new(0.6, 0.65, 0.7),
+            mode: FogMode::Off,
+            density: 0.05,
+            start: 10.0,
+            end: 100.0,
+        }
+    }
+}
 
 #[derive(Resource)]
 pub struct LightingDataManager {
-    pub point_light_buffer: HostMappedBuffer,
+    pub light_buffer: HostMappedBuffer,
     pub descriptor_set: vk::DescriptorSet,
+    // the number of lights written to the buffer last update
+    pub light_count: u32,
 }
 
 impl LightingDataManager {
     pub fn new(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager) -> Self {
         let buffer = HostMappedBuffer::create(device, HostMappedBufferCreateInfo {
-           size: PointLightUniform::std140_size_static() as u64,
+           size: size_of::<LightsUniform>() as u64,
             usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
         });
         let descriptor_buffer_info = vk::DescriptorBufferInfo::builder()
             .buffer(buffer.vk_buffer())
             .offset(0)
-            .range(PointLightUniform::std140_size_static() as u64);
+            .range(size_of::<LightsUniform>() as u64);
         let (descriptor_set, _) = descriptor_manager.descriptor_builder()
             .bind_buffer(0, descriptor_buffer_info, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::FRAGMENT)
             .build()
             .expect("Failed to build light buffer");
         Self {
-            point_light_buffer: buffer,
+            light_buffer: buffer,
             descriptor_set,
+            light_count: 0,
         }
     }
 }
 
-pub fn update_lights_system(mut lighting_data_manager: ResMut<LightingDataManager>, lights: Query<(&Transform, &PointLight)>) {
-    if let Some((transform, light)) = lights.iter().nth(0) {
-        let light_uniform = PointLightUniform {
-            position: transform.translation,
-            light_color: light.light_color,
-            emissivity: light.emissivity,
-        }.as_std140();
-        lighting_data_manager.point_light_buffer.write_data(light_uniform.as_bytes());
+// lights spawned as children of a model, such as those imported from glTF, are placed relative to it
+fn light_world_matrix(transform: &Transform, parent: Option<&Parent>, parent_transforms: &Query<&Transform>) -> Mat4 {
+    let parent_matrix = parent
+        .and_then(|parent| parent_transforms.get(parent.get()).ok())
+        .map_or(Mat4::IDENTITY, Transform::matrix);
+    parent_matrix * transform.matrix()
+}
+
+pub fn update_lights_system(mut lighting_data_manager: ResMut<LightingDataManager>, point_lights: Query<(&Transform, &PointLight, Option<&Parent>)>, directional_lights: Query<(&Transform, &DirectionalLight, Option<&Parent>)>, spot_lights: Query<(&Transform, &SpotLight, Option<&Parent>)>, parent_transforms: Query<&Transform>, mut warned_about_limit: Local<bool>) {
+    let point_light_uniforms = point_lights.iter().map(|(transform, light, parent)| {
+        LightUniform::new(LightKind::Point, light_world_matrix(transform, parent, &parent_transforms), Vec3::NEG_Z, light.light_color, light.emissivity, light.range)
+    });
+    let directional_light_uniforms = directional_lights.iter().map(|(transform, light, parent)| {
+        LightUniform::new(LightKind::Directional, light_world_matrix(transform, parent, &parent_transforms), Vec3::NEG_Z, light.light_color, light.emissivity, None)
+    });
+    let spot_light_uniforms = spot_lights.iter().map(|(transform, light, parent)| {
+        // the inner cone can't be wider than the outer one, or there'd be no edge to fade across
+        let inner_cone = light.inner_cone.min(light.outer_cone);
+        LightUniform {
+            cone: Vec4::new(inner_cone.cos(), light.outer_cone.cos(), 0.0, 0.0),
+            ..LightUniform::new(LightKind::Spot, light_world_matrix(transform, parent, &parent_transforms), light.direction, light.light_color, light.emissivity, light.range)
+        }
+    });
+    let light_uniforms: Vec<LightUniform> = point_light_uniforms.chain(directional_light_uniforms).chain(spot_light_uniforms).collect();
+    if light_uniforms.len() > MAX_LIGHTS && !*warned_about_limit {
+        *warned_about_limit = true;
+        warn!("Only the first {} of {} lights are used", MAX_LIGHTS, light_uniforms.len());
     }
-}
\ No newline at end of file
+
+    let mut lights_uniform: LightsUniform = bytemuck::Zeroable::zeroed();
+    let light_count = light_uniforms.len().min(MAX_LIGHTS);
+    lights_uniform.light_count[0] = light_count as u32;
+    lights_uniform.lights[..light_count].copy_from_slice(&light_uniforms[..light_count]);
+    lighting_data_manager.light_buffer.write_data(bytemuck::bytes_of(&lights_uniform));
+    lighting_data_manager.light_count = light_count as u32;
+}
+
+// how far the cone is drawn along a spot light without a range
+const SPOT_LIGHT_GIZMO_LENGTH: f32 = 2.0;
+
+// outlines each spot light's outer cone, with the inner cone in a dimmer color inside it
+pub fn spot_light_gizmo_system(mut gizmo_renderer: ResMut<GizmoRenderer>, spot_lights: Query<(&Transform, &SpotLight, Option<&Parent>)>, parent_transforms: Query<&Transform>) {
+    for (transform, light, parent) in spot_lights.iter() {
+        let world_matrix = light_world_matrix(transform, parent, &parent_transforms);
+        let apex = world_matrix.w_axis.truncate();
+        let direction = world_matrix.transform_vector3(light.direction).normalize_or_zero();
+        let length = light.range.unwrap_or(SPOT_LIGHT_GIZMO_LENGTH);
+        let color = ColorRgbaF::new(light.light_color.x, light.light_color.y, light.light_color.z, 1.0);
+        gizmo_renderer.cone(apex, direction, light.outer_cone, length, color);
+        gizmo_renderer.cone(apex, direction, light.inner_cone.min(light.outer_cone), length, ColorRgbaF::new(color.r * 0.5, color.g * 0.5, color.b * 0.5, 1.0));
+    }
+}
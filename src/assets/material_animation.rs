@@ -0,0 +1,98 @@
+use ahash::AHashMap;
+use bevy_ecs::prelude::*;
+use bevy_hierarchy::Children;
+use bevy_time::Time;
+
+use crate::assets::AssetManager;
+use crate::assets::render_object::{actor_render_object_entities, MaterialAnimationUniforms, MaterialHandle, MeshGroup, RenderObject};
+use crate::etna::MAX_FRAMES_IN_FLIGHT;
+use crate::rehnda_core::Vec4;
+
+/// How an animated material's values change over time
+#[derive(Copy, Clone, Debug)]
+pub enum MaterialAnimation {
+    // scales the emissive from min up to max and back down again every period
+    PulseEmissive { min: f32, max: f32, period_seconds: f32 },
+    // scales the base color alpha from one value to the other over the duration, then holds it
+    FadeAlpha { from: f32, to: f32, duration_seconds: f32 },
+}
+
+impl MaterialAnimation {
+    fn uniforms(&self, elapsed_seconds: f32) -> MaterialAnimationUniforms {
+        match *self {
+            MaterialAnimation::PulseEmissive { min, max, period_seconds } => {
+                let phase = elapsed_seconds / period_seconds.max(f32::EPSILON) * std::f32::consts::TAU;
+                let scale = min + (max - min) * (0.5 - 0.5 * phase.cos());
+                MaterialAnimationUniforms {
+                    emissive_scale: Vec4::splat(scale),
+                    ..Default::default()
+                }
+            }
+            MaterialAnimation::FadeAlpha { from, to, duration_seconds } => {
+                let progress = (elapsed_seconds / duration_seconds.max(f32::EPSILON)).min(1.0);
+                MaterialAnimationUniforms {
+                    base_color_scale: Vec4::new(1.0, 1.0, 1.0, from + (to - from) * progress),
+                    ..Default::default()
+                }
+            }
+        }
+    }
+}
+
+/// Animates the material of the render object it's added to, or the materials of all of a model's render objects when
+/// added to the model. Materials are shared by every object drawn with them, so the animation shows on all of them, and
+/// only one animator per material is used
+#[derive(Component, Clone, Debug)]
+pub struct MaterialAnimator {
+    pub animation: MaterialAnimation,
+    elapsed_seconds: f32,
+}
+
+impl MaterialAnimator {
+    pub fn new(animation: MaterialAnimation) -> Self {
+        Self {
+            animation,
+            elapsed_seconds: 0.0,
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.elapsed_seconds = 0.0;
+    }
+}
+
+/// The animated values of each animated material, written to the material as each frame is drawn. Once a material
+/// stops being animated its defaults are written for every frame in flight before it is forgotten
+#[derive(Resource, Default)]
+pub struct AnimatedMaterials {
+    values: AHashMap<MaterialHandle, (MaterialAnimationUniforms, usize)>,
+}
+
+impl AnimatedMaterials {
+    // must be called after the frame's fence has been waited on
+    pub fn write_frame(&self, asset_manager: &AssetManager, frame_index: usize) {
+        for (material_handle, (uniforms, _)) in &self.values {
+            asset_manager.material_ref(material_handle).write_animation(frame_index, uniforms);
+        }
+    }
+}
+
+pub fn animate_materials_system(time: Res<Time>, mut animated_materials: ResMut<AnimatedMaterials>, mut animators: Query<(&mut MaterialAnimator, Option<&RenderObject>, Option<&Children>)>, render_objects: Query<&RenderObject>, mesh_groups: Query<&'static Children, With<MeshGroup>>) {
+    // anything animated this frame is overwritten below, leaving the defaults for the ones that have stopped
+    animated_materials.values.retain(|_, (uniforms, frames_left)| {
+        *uniforms = MaterialAnimationUniforms::default();
+        let keep = *frames_left > 0;
+        *frames_left = frames_left.saturating_sub(1);
+        keep
+    });
+    for (mut animator, render_object, children) in &mut animators {
+        animator.elapsed_seconds += time.delta_seconds();
+        let uniforms = animator.animation.uniforms(animator.elapsed_seconds);
+        let child_render_objects = children.into_iter()
+            .flat_map(|children| actor_render_object_entities(children, &mesh_groups))
+            .filter_map(|child| render_objects.get(child).ok());
+        for render_object in render_object.into_iter().chain(child_render_objects) {
+            animated_materials.values.insert(render_object.material_instance_handle, (uniforms, MAX_FRAMES_IN_FLIGHT));
+        }
+    }
+}
@@ -1,24 +1,40 @@
 use std::path::Path;
 
 use ahash::AHashMap;
+use ash::vk;
 use bevy_ecs::prelude::*;
-use winit::event::VirtualKeyCode;
 
 use crate::assets::{AssetHandle, shader_compiler};
-use crate::etna::{Device, DeviceRes, GraphicsSettings, PhysicalDeviceRes, Swapchain};
+use crate::etna::{DeletionQueue, Device, DeviceRes, GraphicsSettings, Swapchain};
 use crate::etna::material_pipeline::{DescriptorManager, MaterialPipeline};
 use crate::rehnda_core::ConstPtr;
 use crate::rehnda_core::input::InputState;
+use crate::rehnda_core::input_bindings::InputAction;
 
 pub type MaterialPipelineHandle = AssetHandle<MaterialPipeline>;
 
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub enum Shader {
     Default,
     Gooch,
     Unlit,
     Pbr,
+    // samples material textures from the bindless texture array, requires GraphicsSettings::bindless_textures
+    PbrBindless,
     BlinnPhong,
     SkyBox,
+    Particle,
+    // depth tested bounding boxes for occlusion queries
+    BoundingBox,
+    // billboarded text sampled from egui's font atlas
+    Label,
+    // colored debug lines
+    Gizmo,
+    // a constant color, for the edges drawn over meshes
+    Wireframe,
+    // the selected mesh marking the stencil, then its outline drawn around it
+    OutlineMask,
+    Outline,
 }
 
 impl Shader {
@@ -36,20 +52,42 @@ impl Shader {
             Shader::Pbr => {
                 ("shaders/spirv/shader.vert_spv", "shaders/spirv/pbr.frag_spv")
             }
+            Shader::PbrBindless => {
+                ("shaders/spirv/shader.vert_spv", "shaders/spirv/pbr_bindless.frag_spv")
+            }
             Shader::BlinnPhong => {
                 ("shaders/spirv/shader.vert_spv", "shaders/spirv/blinnphong.frag_spv")
             }
             Shader::SkyBox => {
                 ("shaders/spirv/skybox.vert_spv", "shaders/spirv/skybox.frag_spv")
             }
+            Shader::Particle => {
+                ("shaders/spirv/particle.vert_spv", "shaders/spirv/particle.frag_spv")
+            }
+            Shader::BoundingBox => {
+                ("shaders/spirv/bounding_box.vert_spv", "shaders/spirv/bounding_box.frag_spv")
+            }
+            Shader::Label => {
+                ("shaders/spirv/label.vert_spv", "shaders/spirv/label.frag_spv")
+            }
+            Shader::Gizmo => {
+                ("shaders/spirv/gizmo.vert_spv", "shaders/spirv/gizmo.frag_spv")
+            }
+            Shader::Wireframe => {
+                ("shaders/spirv/shader.vert_spv", "shaders/spirv/wireframe.frag_spv")
+            }
+            Shader::OutlineMask => {
+                ("shaders/spirv/shader.vert_spv", "shaders/spirv/outline.frag_spv")
+            }
+            Shader::Outline => {
+                ("shaders/spirv/outline.vert_spv", "shaders/spirv/outline.frag_spv")
+            }
         }
     }
 }
 
 struct MaterialAsset {
-    materials: [Option<MaterialPipeline>; 2],
-    current_material: usize,
-    frames_since_pending_deletion: usize,
+    material: Option<MaterialPipeline>,
     material_creation_function: fn(ConstPtr<Device>, &mut DescriptorManager, &GraphicsSettings, &Swapchain, &Path, &Path) -> MaterialPipeline,
     shader: Shader,
 }
@@ -57,55 +95,121 @@ struct MaterialAsset {
 #[derive(Default, Resource)]
 pub struct MaterialServer {
     materials: AHashMap<MaterialPipelineHandle, MaterialAsset>,
+    double_sided_variants: AHashMap<MaterialPipelineHandle, MaterialPipelineHandle>,
+    topology_variants: AHashMap<(MaterialPipelineHandle, vk::PrimitiveTopology), MaterialPipelineHandle>,
+    // the settings the loaded pipelines were built with
+    sample_rate_shading_enabled: bool,
+    logarithmic_depth: bool,
+    // counts the pipelines built, so commands recorded with replaced pipelines can tell they're stale
+    generation: u64,
 }
 
 impl MaterialServer {
-    pub fn reload_materials(&mut self) {
-        shader_compiler::compile_all_files();
-        for (material_handle, material_asset) in self.materials.iter_mut() {
-            material_asset.current_material = (material_asset.current_material + 1) % 2;
-            material_asset.frames_since_pending_deletion = 1;
+    // replaced pipelines are handed to the deletion queue as in flight frames may still be using them
+    pub fn reload_materials(&mut self, deletion_queue: &mut DeletionQueue) {
+        let changed_shaders = shader_compiler::compile_all_files();
+        for material_asset in self.materials.values_mut() {
+            let (vert_path, frag_path) = material_asset.shader.shader_paths();
+            if !changed_shaders.contains(Path::new(vert_path)) && !changed_shaders.contains(Path::new(frag_path)) {
+                continue;
+            }
+            if let Some(old_material) = material_asset.material.take() {
+                deletion_queue.queue_deletion(old_material);
+            }
+        }
+    }
+
+    // rebuilds every pipeline on the next update, for settings that are baked into all of them
+    pub fn rebuild_materials(&mut self, deletion_queue: &mut DeletionQueue) {
+        for material_asset in self.materials.values_mut() {
+            if let Some(old_material) = material_asset.material.take() {
+                deletion_queue.queue_deletion(old_material);
+            }
         }
     }
 
+    // loading the same material again shares the existing pipeline, so reloaded scenes don't duplicate them
     pub fn load_material(&mut self, material_creation_function: fn(ConstPtr<Device>, &mut DescriptorManager, &GraphicsSettings, &Swapchain, &Path, &Path) -> MaterialPipeline, shader: Shader) -> MaterialPipelineHandle {
+        let existing_material = self.materials.iter()
+            .find(|(_, asset)| asset.shader == shader && asset.material_creation_function as usize == material_creation_function as usize)
+            .map(|(handle, _)| *handle);
+        if let Some(material_handle) = existing_material {
+            return material_handle;
+        }
         let material_handle = MaterialPipelineHandle::new(self.materials.len() as u32);
         self.materials.insert(material_handle, MaterialAsset {
-            materials: [None, None],
-            current_material: 0,
+            material: None,
             material_creation_function,
-            frames_since_pending_deletion: 0,
             shader,
         });
         material_handle
     }
 
+    // registers a variant of the material using the same shaders, used for double sided materials
+    pub fn load_double_sided_variant(&mut self, material_handle: MaterialPipelineHandle, material_creation_function: fn(ConstPtr<Device>, &mut DescriptorManager, &GraphicsSettings, &Swapchain, &Path, &Path) -> MaterialPipeline) -> MaterialPipelineHandle {
+        let shader = self.materials.get(&material_handle).expect("Material must be loaded before its double sided variant").shader;
+        let variant_handle = self.load_material(material_creation_function, shader);
+        self.double_sided_variants.insert(material_handle, variant_handle);
+        variant_handle
+    }
+
+    // falls back to the given material if it has no double sided variant
+    pub fn double_sided_variant(&self, material_handle: &MaterialPipelineHandle) -> MaterialPipelineHandle {
+        self.double_sided_variants.get(material_handle).copied().unwrap_or(*material_handle)
+    }
+
+    // registers a variant of the material using the same shaders for meshes of points or lines
+    pub fn load_topology_variant(&mut self, material_handle: MaterialPipelineHandle, topology: vk::PrimitiveTopology, material_creation_function: fn(ConstPtr<Device>, &mut DescriptorManager, &GraphicsSettings, &Swapchain, &Path, &Path) -> MaterialPipeline) -> MaterialPipelineHandle {
+        let shader = self.materials.get(&material_handle).expect("Material must be loaded before its topology variants").shader;
+        let variant_handle = self.load_material(material_creation_function, shader);
+        self.topology_variants.insert((material_handle, topology), variant_handle);
+        variant_handle
+    }
+
+    // materials are drawn as triangle lists unless they have a variant for the topology
+    pub fn topology_variant(&self, material_handle: &MaterialPipelineHandle, topology: vk::PrimitiveTopology) -> Option<MaterialPipelineHandle> {
+        if topology == vk::PrimitiveTopology::TRIANGLE_LIST {
+            return Some(*material_handle);
+        }
+        self.topology_variants.get(&(*material_handle, topology)).copied()
+    }
+
     pub fn material_ref(&self, handle: &MaterialPipelineHandle) -> Option<&MaterialPipeline> {
-        self.materials.get(handle).and_then(|asset| asset.materials[asset.current_material].as_ref())
+        self.materials.get(handle).and_then(|asset| asset.material.as_ref())
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
     }
 }
 
-pub fn material_server_system(mut material_server: ResMut<MaterialServer>, input_state: Res<InputState>, device: DeviceRes, mut descriptor_manager: ResMut<DescriptorManager>, physical_device: PhysicalDeviceRes, swapchain: Res<Swapchain>) {
-    for (material_handle, material_asset) in material_server.materials.iter_mut() {
-        if material_asset.materials[material_asset.current_material].is_none() {
+pub fn material_server_system(mut material_server: ResMut<MaterialServer>, input_state: Res<InputState>, device: DeviceRes, mut descriptor_manager: ResMut<DescriptorManager>, graphics_settings: Res<GraphicsSettings>, swapchain: Res<Swapchain>, mut deletion_queue: ResMut<DeletionQueue>) {
+    if input_state.is_action_just_down(InputAction::ReloadShaders) {
+        material_server.reload_materials(&mut deletion_queue);
+    }
+    if material_server.sample_rate_shading_enabled != graphics_settings.sample_rate_shading_enabled {
+        material_server.sample_rate_shading_enabled = graphics_settings.sample_rate_shading_enabled;
+        material_server.rebuild_materials(&mut deletion_queue);
+    }
+    // only the logarithmic depth variants write their depth, so early depth testing stays on without it
+    if material_server.logarithmic_depth != graphics_settings.logarithmic_depth {
+        material_server.logarithmic_depth = graphics_settings.logarithmic_depth;
+        material_server.rebuild_materials(&mut deletion_queue);
+    }
+    let mut built_pipelines = 0;
+    for material_asset in material_server.materials.values_mut() {
+        if material_asset.material.is_none() {
             let shader_files = material_asset.shader.shader_paths();
             let vert_path = Path::new(shader_files.0);
             let frag_path = Path::new(shader_files.1);
-            let loaded_material = (material_asset.material_creation_function)(device.ptr(), &mut descriptor_manager, &physical_device.graphics_settings, &swapchain, &vert_path, &frag_path);
-            material_asset.materials[material_asset.current_material] = Some(loaded_material);
-        }
-        // drop the inactive material
-        if material_asset.materials[(material_asset.current_material + 1) % 2].is_some() {
-            if material_asset.frames_since_pending_deletion == 0 {
-                material_asset.materials[(material_asset.current_material + 1) % 2] = None;
-            } else {
-                material_asset.frames_since_pending_deletion -= 1;
-            }
+            let logarithmic_depth_frag_path = graphics_settings.logarithmic_depth.then(|| shader_compiler::logarithmic_depth_variant(frag_path)).flatten();
+            let frag_path = logarithmic_depth_frag_path.as_deref().unwrap_or(frag_path);
+            let loaded_material = (material_asset.material_creation_function)(device.ptr(), &mut descriptor_manager, &graphics_settings, &swapchain, &vert_path, &frag_path);
+            material_asset.material = Some(loaded_material);
+            built_pipelines += 1;
         }
     }
-    if input_state.is_just_down(VirtualKeyCode::Semicolon) {
-        material_server.reload_materials();
-    }
+    material_server.generation += built_pipelines;
 }
 
 pub fn material_startup_system() {
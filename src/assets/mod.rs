@@ -5,10 +5,20 @@ pub use camera::*;
 mod vertex;
 pub use vertex::*;
 pub mod demo_scenes;
+pub mod scene_loader;
 pub mod gltf_loader;
+pub mod model_loader;
 pub mod render_object;
 pub mod material_server;
 pub mod shader_compiler;
 pub mod light_source;
 pub mod skybox;
-pub mod cube;
\ No newline at end of file
+pub mod cube;
+pub mod particles;
+pub mod world_labels;
+pub mod gizmos;
+pub mod material_animation;
+pub mod texture_streaming;
+pub mod thumbnail;
+pub mod wireframe;
+pub mod outline;
@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+
+use bevy_ecs::prelude::*;
+use log::error;
+
+use crate::assets::AssetManager;
+use crate::assets::demo_scenes::{add_lights_to_parent, ShouldDrawDebug, SpawnModel};
+use crate::assets::gltf_loader::{GltfSource, LoadProgress};
+use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer};
+use crate::assets::render_object::Transform;
+use crate::etna::GraphicsSettings;
+use crate::etna::material_pipeline::DescriptorManager;
+
+/// glTF files being read and decoded on worker threads so the window stays responsive. Once read each is uploaded on
+/// the main thread and spawned like [SpawnModel::spawn_model] by [pending_models_system]
+#[derive(Resource, Default)]
+pub struct PendingModels {
+    models: Vec<PendingModel>,
+}
+
+struct PendingModel {
+    path: PathBuf,
+    name: String,
+    transform: Transform,
+    pipeline: MaterialPipelineHandle,
+    progress: Arc<ProgressCounter>,
+    reader: JoinHandle<GltfSource>,
+}
+
+// written by the reading thread, read by the ui
+#[derive(Default)]
+struct ProgressCounter {
+    loaded_textures: AtomicUsize,
+    total_textures: AtomicUsize,
+}
+
+impl PendingModels {
+    pub fn load(&mut self, gltf_path: &Path, pipeline: MaterialPipelineHandle, transform: Transform, name: impl Into<String>) {
+        let progress = Arc::new(ProgressCounter::default());
+        let reader = {
+            let progress = progress.clone();
+            let gltf_path = gltf_path.to_path_buf();
+            std::thread::spawn(move || GltfSource::read(&gltf_path, &mut |update| {
+                progress.total_textures.store(update.total_textures, Ordering::Relaxed);
+                progress.loaded_textures.store(update.loaded_textures, Ordering::Relaxed);
+            }))
+        };
+        self.models.push(PendingModel {
+            path: gltf_path.to_path_buf(),
+            name: name.into(),
+            transform,
+            pipeline,
+            progress,
+            reader,
+        });
+    }
+
+    // the reads carry on in the background but nothing is spawned from them
+    pub fn clear(&mut self) {
+        self.models.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+
+    pub fn progress(&self) -> impl Iterator<Item=(&str, LoadProgress)> {
+        self.models.iter().map(|model| (model.name.as_str(), LoadProgress {
+            loaded_textures: model.progress.loaded_textures.load(Ordering::Relaxed),
+            total_textures: model.progress.total_textures.load(Ordering::Relaxed),
+        }))
+    }
+}
+
+// uploads and spawns the models whose reads have finished, the rest are left reading in the background
+pub fn pending_models_system(mut commands: Commands, mut pending_models: ResMut<PendingModels>, mut asset_manager: ResMut<AssetManager>, mut descriptor_manager: ResMut<DescriptorManager>, graphics_settings: Res<GraphicsSettings>, material_server: Res<MaterialServer>) {
+    let (finished, reading): (Vec<PendingModel>, Vec<PendingModel>) = std::mem::take(&mut pending_models.models)
+        .into_iter()
+        .partition(|model| model.reader.is_finished());
+    pending_models.models = reading;
+    for model in finished {
+        let Ok(source) = model.reader.join() else {
+            error!("Failed to read {}", model.path.display());
+            continue;
+        };
+        let meshes = asset_manager.upload_gltf(&source, &mut descriptor_manager, &graphics_settings, &material_server, model.pipeline);
+        let mut entity = commands.spawn_model(meshes.as_slice(), model.transform, model.name);
+        entity.insert(ShouldDrawDebug);
+        add_lights_to_parent(&mut entity, &source.lights());
+    }
+}
+
+pub fn all_models_loaded(pending_models: Res<PendingModels>) -> bool {
+    pending_models.is_empty()
+}
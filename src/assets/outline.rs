@@ -0,0 +1,19 @@
+use bevy_ecs::prelude::*;
+
+use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer, Shader};
+use crate::etna::material_pipeline;
+
+/// The pipelines outlining the render object picked with [crate::assets::DepthPick]. The outline is only drawn when
+/// the depth buffer has a stencil
+#[derive(Resource)]
+pub struct OutlineRenderer {
+    pub mask_pipeline: MaterialPipelineHandle,
+    pub outline_pipeline: MaterialPipelineHandle,
+}
+
+pub fn outline_renderer_startup_system(mut commands: Commands, mut material_server: ResMut<MaterialServer>) {
+    commands.insert_resource(OutlineRenderer {
+        mask_pipeline: material_server.load_material(material_pipeline::outline_mask_pipeline, Shader::OutlineMask),
+        outline_pipeline: material_server.load_material(material_pipeline::outline_pipeline, Shader::Outline),
+    });
+}
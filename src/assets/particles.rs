@@ -0,0 +1,244 @@
+use std::ffi::CString;
+use std::mem::size_of;
+use std::path::Path;
+
+use ash::vk;
+use bevy_ecs::prelude::*;
+use bevy_time::Time;
+use bytemuck_derive::{Pod, Zeroable};
+
+use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer, Shader};
+use crate::assets::render_object::Transform;
+use crate::etna::{Device, DeviceRes, GraphicsSettings, HostMappedBuffer, HostMappedBufferCreateInfo, MAX_FRAMES_IN_FLIGHT, Swapchain};
+use crate::etna::material_pipeline::{BlendMode, DescriptorManager, MaterialPipeline, PipelineCreateInfo, PipelineMultisamplingInfo, PipelineVertexInputDescription, RasterizationOptions};
+use crate::etna::shader::ShaderModule;
+use crate::rehnda_core::{ColorRgbaF, ConstPtr, Vec3};
+
+// the most particles drawn in a frame across all emitters, any more are skipped
+pub const MAX_PARTICLES: usize = 8192;
+
+/// Spawns particles at its entity's translation, which are simulated on the cpu and drawn as additive billboards
+#[derive(Component)]
+pub struct Emitter {
+    // particles per second
+    pub emission_rate: f32,
+    // seconds each particle lives for
+    pub lifetime: f32,
+    pub start_color: ColorRgbaF,
+    pub end_color: ColorRgbaF,
+    pub start_size: f32,
+    pub end_size: f32,
+    pub initial_velocity: Vec3,
+    // how far each particle's starting velocity can randomly deviate from the initial velocity
+    pub velocity_randomness: f32,
+    pub acceleration: Vec3,
+    pub max_particles: usize,
+    pub state: EmitterState,
+}
+
+// the live particles of an emitter, updated by update_emitters_system
+pub struct EmitterState {
+    particles: Vec<Particle>,
+    emission_accumulator: f32,
+    random_state: u32,
+}
+
+impl Default for EmitterState {
+    fn default() -> Self {
+        Self {
+            particles: Vec::new(),
+            emission_accumulator: 0.0,
+            random_state: 0x9E3779B9,
+        }
+    }
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            emission_rate: 50.0,
+            lifetime: 2.0,
+            start_color: ColorRgbaF::WHITE,
+            end_color: ColorRgbaF::new(1.0, 1.0, 1.0, 0.0),
+            start_size: 0.1,
+            end_size: 0.0,
+            initial_velocity: Vec3::Y,
+            velocity_randomness: 0.5,
+            acceleration: Vec3::ZERO,
+            max_particles: 1000,
+            state: EmitterState::default(),
+        }
+    }
+}
+
+struct Particle {
+    position: Vec3,
+    velocity: Vec3,
+    age: f32,
+}
+
+impl Emitter {
+    fn update(&mut self, emitter_position: Vec3, delta_seconds: f32) {
+        let lifetime = self.lifetime;
+        self.state.particles.retain(|particle| particle.age + delta_seconds < lifetime);
+        for particle in self.state.particles.iter_mut() {
+            particle.age += delta_seconds;
+            particle.velocity += self.acceleration * delta_seconds;
+            particle.position += particle.velocity * delta_seconds;
+        }
+
+        self.state.emission_accumulator += self.emission_rate * delta_seconds;
+        while self.state.emission_accumulator >= 1.0 {
+            self.state.emission_accumulator -= 1.0;
+            if self.state.particles.len() >= self.max_particles {
+                continue;
+            }
+            let random_direction = Vec3::new(self.state.next_random(), self.state.next_random(), self.state.next_random());
+            self.state.particles.push(Particle {
+                position: emitter_position,
+                velocity: self.initial_velocity + random_direction * self.velocity_randomness,
+                age: 0.0,
+            });
+        }
+    }
+
+    fn write_instances(&self, instances: &mut Vec<ParticleInstance>) {
+        for particle in self.state.particles.iter() {
+            let t = particle.age / self.lifetime;
+            instances.push(ParticleInstance {
+                position: particle.position,
+                size: self.start_size + (self.end_size - self.start_size) * t,
+                color: self.start_color.lerp(&self.end_color, t),
+            });
+        }
+    }
+}
+
+impl EmitterState {
+    // xorshift, returning a value in [-1, 1]
+    fn next_random(&mut self) -> f32 {
+        self.random_state ^= self.random_state << 13;
+        self.random_state ^= self.random_state >> 17;
+        self.random_state ^= self.random_state << 5;
+        (self.random_state as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Debug, Copy, Clone)]
+struct ParticleInstance {
+    position: Vec3,
+    size: f32,
+    color: ColorRgbaF,
+}
+
+#[derive(Resource)]
+pub struct ParticleRenderer {
+    instance_buffers: [HostMappedBuffer; MAX_FRAMES_IN_FLIGHT],
+    pub pipeline: MaterialPipelineHandle,
+}
+
+impl ParticleRenderer {
+    pub fn create(device: ConstPtr<Device>, material_server: &mut MaterialServer) -> Self {
+        let instance_buffers = [(); MAX_FRAMES_IN_FLIGHT].map(|_| HostMappedBuffer::create(device, HostMappedBufferCreateInfo {
+            size: (size_of::<ParticleInstance>() * MAX_PARTICLES) as u64,
+            usage: vk::BufferUsageFlags::VERTEX_BUFFER,
+        }));
+        Self {
+            instance_buffers,
+            pipeline: material_server.load_material(particle_pipeline, Shader::Particle),
+        }
+    }
+
+    // writes the instances for this frame, returning the buffer to draw from and the number of particles in it
+    pub fn prepare_instances<'a>(&self, frame_index: usize, emitters: impl Iterator<Item=&'a Emitter>) -> (vk::Buffer, u32) {
+        let mut instances = Vec::new();
+        for emitter in emitters {
+            emitter.write_instances(&mut instances);
+        }
+        instances.truncate(MAX_PARTICLES);
+        let instance_buffer = &self.instance_buffers[frame_index % MAX_FRAMES_IN_FLIGHT];
+        instance_buffer.write_data(bytemuck::cast_slice(instances.as_slice()));
+        (instance_buffer.vk_buffer(), instances.len() as u32)
+    }
+}
+
+pub fn particle_renderer_startup_system(mut commands: Commands, device: DeviceRes, mut material_server: ResMut<MaterialServer>) {
+    commands.insert_resource(ParticleRenderer::create(device.ptr(), &mut material_server));
+}
+
+pub fn update_emitters_system(time: Res<Time>, mut emitters: Query<(&Transform, &mut Emitter)>) {
+    for (transform, mut emitter) in emitters.iter_mut() {
+        emitter.update(transform.translation, time.delta_seconds());
+    }
+}
+
+pub fn particle_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    let vert_shader_module = ShaderModule::load_from_file(device, Path::new(vert_shader_path));
+    let frag_shader_module = ShaderModule::load_from_file(device, Path::new(frag_shader_path));
+    let main_function_name = CString::new("main").unwrap();
+    let vertex_shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module.handle())
+        .name(main_function_name.as_c_str())
+        .build();
+    let frag_shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module.handle())
+        .name(main_function_name.as_c_str())
+        .build();
+
+    // each particle is an instance, the quad corners are generated from the vertex index
+    let instance_binding = vk::VertexInputBindingDescription::builder()
+        .binding(0)
+        .stride(size_of::<ParticleInstance>() as u32)
+        .input_rate(vk::VertexInputRate::INSTANCE)
+        .build();
+    let instance_attributes = [
+        // position and size
+        vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(0)
+            .build(),
+        // color
+        vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset((size_of::<f32>() * 4) as u32)
+            .build(),
+    ];
+    let vertex_input = PipelineVertexInputDescription {
+        bindings: std::slice::from_ref(&instance_binding),
+        attributes: &instance_attributes,
+    };
+
+    let multisampling = PipelineMultisamplingInfo {
+        msaa_samples: graphics_settings.msaa_samples,
+        enable_sample_rate_shading: graphics_settings.sample_rate_shading_enabled,
+    };
+
+    let create_info = PipelineCreateInfo {
+        global_set_layouts: &[descriptor_manager.global_descriptor_layout],
+        additional_descriptor_set_layouts: &[],
+        shader_stages: &[vertex_shader_stage_ci, frag_shader_stage_ci],
+        push_constants: &[],
+        extent: swapchain.extent,
+        image_format: swapchain.image_format,
+        vertex_input,
+        multisampling,
+        // particles are depth tested against the opaque geometry but don't occlude each other
+        rasterization_options: &RasterizationOptions {
+            cull_mode: vk::CullModeFlags::NONE,
+            depth_write_enabled: false,
+            blend_mode: BlendMode::Additive,
+            ..RasterizationOptions::default()
+        },
+        depth_compare_op: graphics_settings.depth_compare_op(),
+        depth_format: swapchain.depth_buffer.format,
+    };
+
+    MaterialPipeline::create(device, &create_info)
+}
@@ -3,12 +3,15 @@ use std::sync::Arc;
 
 use ash::vk;
 use bevy_ecs::prelude::*;
+use bevy_hierarchy::Children;
 use bytemuck_derive::{Pod, Zeroable};
 use enumflags2::{BitFlag, bitflags, BitFlags};
+use log::error;
+use serde::Deserialize;
 
-use crate::etna::{Buffer, BufferCreateInfo, CommandPool, Device, Texture};
+use crate::etna::{Buffer, BufferCreateInfo, CommandPool, Device, HostMappedBuffer, HostMappedBufferCreateInfo, MAX_FRAMES_IN_FLIGHT, Texture};
 use crate::etna::material_pipeline::DescriptorManager;
-use crate::rehnda_core::{ColorRgbaF, ConstPtr, Mat4, Quat, Vec3};
+use crate::rehnda_core::{ColorRgbaF, ConstPtr, Mat4, Quat, Vec2, Vec3, Vec4};
 use crate::assets::{AssetHandle, MeshHandle};
 use crate::assets::material_server::MaterialPipelineHandle;
 
@@ -42,11 +45,102 @@ pub struct RenderObject {
     pub material_pipeline_handle: MaterialPipelineHandle,
 }
 
+/// One of a loaded model's meshes. Each of its primitives is its own render object so it keeps its own material, but
+/// they are placed together as the mesh's node places them in the file
+#[derive(Clone)]
+pub struct LoadedMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<RenderObject>,
+}
+
+impl From<RenderObject> for LoadedMesh {
+    fn from(render_object: RenderObject) -> Self {
+        Self {
+            name: None,
+            primitives: vec![render_object],
+        }
+    }
+}
+
+/// A model's mesh placed in the world, its primitives are the render objects among its children
+#[derive(Component, Clone, Debug)]
+pub struct MeshGroup {
+    pub name: Option<String>,
+}
+
+// the entities that may be an actor's render objects, its children and the children of its mesh groups
+pub fn actor_render_object_entities<'a>(children: &'a Children, mesh_groups: &'a Query<'a, 'a, &'static Children, With<MeshGroup>>) -> impl Iterator<Item = Entity> + 'a {
+    children.iter().flat_map(move |child| {
+        let primitives = mesh_groups.get(*child).into_iter().flat_map(|primitives| primitives.iter().copied());
+        std::iter::once(*child).chain(primitives)
+    })
+}
+
 pub struct Mesh {
     pub vertex_buffer: Buffer,
     pub index_buffer: Buffer,
     pub index_count: u32,
+    // u16 when the source indices fit, to halve the index memory
+    pub index_type: vk::IndexType,
+    // strips, fans and loops are converted to lists when loaded, so this is a point, line or triangle list
+    pub topology: vk::PrimitiveTopology,
     pub relative_transform: Mat4,
+    // in the mesh's own space, before the relative transform
+    pub bounds: Aabb,
+    // simpler meshes drawn in its place further from the camera, ordered by their min distance
+    pub lods: Vec<MeshLod>,
+}
+
+impl Mesh {
+    // the mesh to draw for an object this far from the camera, the mesh itself while it's closer than every lod
+    pub fn lod_mesh_handle(&self, mesh_handle: MeshHandle, distance: f32) -> MeshHandle {
+        self.lods.iter().rev()
+            .find(|lod| distance >= lod.min_distance)
+            .map_or(mesh_handle, |lod| lod.mesh_handle)
+    }
+}
+
+/// A simpler version of a mesh, drawn in its place once the camera is at least min_distance from the mesh's bounds
+#[derive(Copy, Clone, PartialEq)]
+pub struct MeshLod {
+    pub mesh_handle: MeshHandle,
+    pub min_distance: f32,
+}
+
+/// Axis aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_points(points: impl Iterator<Item=Vec3>) -> Aabb {
+        points.fold(Aabb { min: Vec3::splat(f32::MAX), max: Vec3::splat(f32::MIN) }, |bounds, point| Aabb {
+            min: bounds.min.min(point),
+            max: bounds.max.max(point),
+        })
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn volume(&self) -> f32 {
+        (self.max - self.min).max(Vec3::ZERO).to_array().iter().product()
+    }
+
+    // the box around all eight transformed corners, which can be larger than the transformed contents
+    pub fn transformed(&self, matrix: Mat4) -> Aabb {
+        Aabb::from_points((0..8).map(|corner| {
+            let select = |axis: usize| if corner & (1 << axis) == 0 { self.min[axis] } else { self.max[axis] };
+            matrix.transform_point3(Vec3::new(select(0), select(1), select(2)))
+        }))
+    }
 }
 
 pub type MaterialHandle = AssetHandle<PbrMaterial>;
@@ -56,8 +150,17 @@ pub struct PbrMaterial {
     textures: Arc<PbrMaterialTextures>,
     descriptor_set: vk::DescriptorSet,
     uniform_buffer: Buffer,
+    // one MaterialAnimationUniforms slot per frame in flight, so animating doesn't touch the static uniforms
+    animation_buffer: HostMappedBuffer,
+    // indices into the bindless texture array, zeroed when bindless textures are disabled
+    texture_indices: [u32; 4],
+    // the features the material was loaded with, i.e. the textures it actually has
+    loaded_features: BitFlags<PbrMaterialFeatureFlags>,
 }
 
+// the largest minUniformBufferOffsetAlignment the spec allows, so every device can bind each animation slot
+const ANIMATION_SLOT_STRIDE: u64 = 256;
+
 
 #[bitflags]
 #[repr(u32)]
@@ -70,12 +173,36 @@ pub enum PbrMaterialFeatureFlags {
     OcclusionTexture = 1 << 4,
 }
 
+// set in the uniform's feature flags next to the PbrMaterialFeatureFlags, MUST KEEP IN SYNC with pbr_shading.glsl
+const OBJECT_SPACE_NORMAL_MAP_FLAG: u32 = 1 << 31;
+
+/// The space a material's normal map is stored in, both encode each axis from [-1, 1] into [0, 1] of the rgb channels.
+/// Tangent space maps are relative to the surface and are bent by the mesh's normals and tangents, +z points out of
+/// the surface so flat areas are (0.5, 0.5, 1.0). Object space maps hold the normal in the model's own axes and ignore
+/// the mesh's normals and tangents, so they only suit the one mesh they were baked for. glTF normal maps are always
+/// tangent space
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum NormalMapSpace {
+    #[default]
+    Tangent,
+    Object,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct PbrMaterialOptions {
     pub base_color: ColorRgbaF,
     pub roughness: f32,
     pub metallic: f32,
+    // linear emitted radiance, may go above 1.0 when an emissive strength is applied
+    pub emissive: Vec3,
+    // not yet used by the shaders
+    pub clearcoat: Option<ClearcoatOptions>,
+    // double sided materials are drawn without back face culling
+    pub double_sided: bool,
     pub features: BitFlags<PbrMaterialFeatureFlags>,
+    pub uv_transforms: PbrTextureTransforms,
+    // only used when the material has a normal texture
+    pub normal_map_space: NormalMapSpace,
 }
 
 impl Default for PbrMaterialOptions {
@@ -84,11 +211,59 @@ impl Default for PbrMaterialOptions {
             base_color: ColorRgbaF::WHITE,
             roughness: 1.0,
             metallic: 1.0,
+            emissive: Vec3::ZERO,
+            clearcoat: None,
+            double_sided: false,
             features: PbrMaterialFeatureFlags::empty(),
+            uv_transforms: PbrTextureTransforms::default(),
+            normal_map_space: NormalMapSpace::Tangent,
+        }
+    }
+}
+
+// transforms applied to the mesh uvs before sampling each material texture, from KHR_texture_transform
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PbrTextureTransforms {
+    pub base_color: UvTransform,
+    pub normal: UvTransform,
+    pub occlusion_roughness_metallic: UvTransform,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UvTransform {
+    pub offset: Vec2,
+    // counter clockwise, in radians
+    pub rotation: f32,
+    pub scale: Vec2,
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::ZERO,
+            rotation: 0.0,
+            scale: Vec2::ONE,
         }
     }
 }
 
+impl UvTransform {
+    // the rows of the 2x3 matrix translation * rotation * scale, as defined by KHR_texture_transform
+    fn to_uniform_rows(self) -> [Vec4; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        [
+            Vec4::new(cos * self.scale.x, sin * self.scale.y, self.offset.x, 0.0),
+            Vec4::new(-sin * self.scale.x, cos * self.scale.y, self.offset.y, 0.0),
+        ]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClearcoatOptions {
+    pub factor: f32,
+    pub roughness: f32,
+}
+
 #[repr(C)]
 #[derive(Pod, Zeroable, Debug, PartialEq, Copy, Clone)]
 pub struct PbrMaterialUniforms {
@@ -96,19 +271,54 @@ pub struct PbrMaterialUniforms {
     pub roughness: f32,
     pub metallic: f32,
     pub enabled_feature_flags: u32,
+    // matches std140 alignment of the emissive vec4
+    _padding: u32,
+    pub emissive: Vec4,
+    pub texture_indices: [u32; 4],
+    // two rows per texture, in the order base color, normal, occlusion roughness metallic
+    pub uv_transforms: [Vec4; 6],
 }
 
 impl PbrMaterialUniforms {
-    fn from_options(options: &PbrMaterialOptions) -> Self {
+    fn from_options(options: &PbrMaterialOptions, texture_indices: [u32; 4]) -> Self {
         Self {
             base_color: options.base_color,
             roughness: options.roughness,
             metallic: options.metallic,
-            enabled_feature_flags: options.features.bits(),
+            enabled_feature_flags: match options.normal_map_space {
+                NormalMapSpace::Tangent => options.features.bits(),
+                NormalMapSpace::Object => options.features.bits() | OBJECT_SPACE_NORMAL_MAP_FLAG,
+            },
+            _padding: 0,
+            emissive: options.emissive.extend(0.0),
+            texture_indices,
+            uv_transforms: bytemuck::cast([
+                options.uv_transforms.base_color.to_uniform_rows(),
+                options.uv_transforms.normal.to_uniform_rows(),
+                options.uv_transforms.occlusion_roughness_metallic.to_uniform_rows(),
+            ]),
         }
     }
 }
 
+/// Multiplied into a material's uniforms by the shaders, for values that change every frame such as a pulsing emissive
+/// or fading alpha. Each frame in flight has its own copy, bound with a dynamic offset
+#[repr(C)]
+#[derive(Pod, Zeroable, Debug, PartialEq, Copy, Clone)]
+pub struct MaterialAnimationUniforms {
+    pub base_color_scale: Vec4,
+    // only rgb is used
+    pub emissive_scale: Vec4,
+}
+
+impl Default for MaterialAnimationUniforms {
+    fn default() -> Self {
+        Self {
+            base_color_scale: Vec4::ONE,
+            emissive_scale: Vec4::ONE,
+        }
+    }
+}
 
 pub struct PbrMaterialTextures {
     pub base_color_texture: Texture,
@@ -116,13 +326,107 @@ pub struct PbrMaterialTextures {
     pub occlusion_roughness_metallic_texture: Texture,
 }
 
+impl PbrMaterialTextures {
+    pub const TEXTURES_PER_MATERIAL: usize = 3;
+
+    pub fn all(&self) -> [&Texture; Self::TEXTURES_PER_MATERIAL] {
+        [&self.base_color_texture, &self.normal_texture, &self.occlusion_roughness_metallic_texture]
+    }
+}
+
 impl PbrMaterial {
     pub fn descriptor_set(&self) -> vk::DescriptorSet {
         self.descriptor_set
     }
 
+    pub fn is_double_sided(&self) -> bool {
+        self.options.double_sided
+    }
+
+    pub fn options(&self) -> &PbrMaterialOptions {
+        &self.options
+    }
+
+    pub fn textures(&self) -> &Arc<PbrMaterialTextures> {
+        &self.textures
+    }
+
+    // rewrites the uniforms in place, so the descriptor set and anything drawn with the material pick up the change.
+    // Whether the material is double sided is chosen by its pipeline, so changing it here has no effect
+    pub fn set_options(&mut self, command_pool: &CommandPool, options: &PbrMaterialOptions) {
+        let uniform = PbrMaterialUniforms::from_options(options, self.texture_indices);
+        self.uniform_buffer.update_with_staging_buffer(command_pool, bytemuck::bytes_of(&uniform));
+        self.options = *options;
+    }
+
+    pub fn loaded_features(&self) -> BitFlags<PbrMaterialFeatureFlags> {
+        self.loaded_features
+    }
+
+    // turns one of the material's shading inputs on or off for comparing its contribution, only rewriting the uniforms.
+    // Features the material wasn't loaded with have no texture of their own, so they can only be turned off
+    pub fn set_feature(&mut self, command_pool: &CommandPool, feature: PbrMaterialFeatureFlags, enabled: bool) {
+        let mut options = self.options;
+        if enabled && self.loaded_features.contains(feature) {
+            options.features.insert(feature);
+        } else {
+            options.features.remove(feature);
+        }
+        if options != self.options {
+            self.set_options(command_pool, &options);
+        }
+    }
+
+    // the dynamic offset of the animation slot read by the frame
+    pub fn animation_offset(frame_index: usize) -> u32 {
+        ((frame_index % MAX_FRAMES_IN_FLIGHT) as u64 * ANIMATION_SLOT_STRIDE) as u32
+    }
+
+    // must only be called once the frame's previous use of its slot has finished executing
+    pub fn write_animation(&self, frame_index: usize, uniforms: &MaterialAnimationUniforms) {
+        self.animation_buffer.write_data_at(Self::animation_offset(frame_index) as u64, bytemuck::bytes_of(uniforms));
+    }
+
+    fn create_animation_buffer(device: ConstPtr<Device>) -> HostMappedBuffer {
+        let animation_buffer = HostMappedBuffer::create(device, HostMappedBufferCreateInfo {
+            size: ANIMATION_SLOT_STRIDE * MAX_FRAMES_IN_FLIGHT as u64,
+            usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
+        });
+        for frame_index in 0..MAX_FRAMES_IN_FLIGHT {
+            animation_buffer.write_data_at(Self::animation_offset(frame_index) as u64, bytemuck::bytes_of(&MaterialAnimationUniforms::default()));
+        }
+        animation_buffer
+    }
+
     pub fn create(device: ConstPtr<Device>, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, textures: Arc<PbrMaterialTextures>, options: &PbrMaterialOptions) -> Self {
-        let uniform = [PbrMaterialUniforms::from_options(options)];
+        // the per material texture bindings are still written so non bindless shaders can use the material
+        let texture_indices = match descriptor_manager.bindless_textures.as_mut() {
+            Some(bindless_textures) => {
+                // a texture that doesn't fit samples the first registered one rather than failing the whole load
+                let mut register = |texture: &Texture| bindless_textures.register_texture(texture).unwrap_or_else(|err| {
+                    error!("Failed to register bindless texture: {:?}", err);
+                    0
+                });
+                [
+                    register(&textures.base_color_texture),
+                    register(&textures.normal_texture),
+                    register(&textures.occlusion_roughness_metallic_texture),
+                    0,
+                ]
+            }
+            None => [0; 4],
+        };
+        Self::create_with_texture_indices(device, command_pool, descriptor_manager, textures, options, texture_indices, options.features)
+    }
+
+    pub fn copy_with_new_uniforms(&self, device: ConstPtr<Device>, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, options: &PbrMaterialOptions) -> Self {
+        Self::create_with_texture_indices(device, command_pool, descriptor_manager, self.textures.clone(), options, self.texture_indices, self.loaded_features)
+    }
+
+    // the uniform and animation buffers and the descriptor set binding them with the textures, which are shared by
+    // copies of the material
+    fn create_with_texture_indices(device: ConstPtr<Device>, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, textures: Arc<PbrMaterialTextures>, options: &PbrMaterialOptions, texture_indices: [u32; 4], loaded_features: BitFlags<PbrMaterialFeatureFlags>) -> Self {
+        let uniform = [PbrMaterialUniforms::from_options(options, texture_indices)];
         let uniform_data: &[u8] = bytemuck::cast_slice(&uniform);
         let uniform_buffer = Buffer::create_and_initialize_buffer_with_staging_buffer(device, command_pool, BufferCreateInfo {
             data: uniform_data,
@@ -132,6 +436,11 @@ impl PbrMaterial {
             .buffer(uniform_buffer.buffer)
             .offset(0)
             .range(size_of::<PbrMaterialUniforms>() as u64);
+        let animation_buffer = Self::create_animation_buffer(device);
+        let animation_buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(animation_buffer.vk_buffer())
+            .offset(0)
+            .range(size_of::<MaterialAnimationUniforms>() as u64);
         let base_color_image_info = vk::DescriptorImageInfo::builder()
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
             .image_view(textures.base_color_texture.image.image_view)
@@ -150,6 +459,7 @@ impl PbrMaterial {
             .bind_image(1, base_color_image_info, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)
             .bind_image(2, normal_image_info, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)
             .bind_image(3, occlusion_roughness_metal_image_info, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)
+            .bind_buffer(4, animation_buffer_info, vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, vk::ShaderStageFlags::FRAGMENT)
             .build()
             .expect("Failed to allocate bindings");
         Self {
@@ -157,45 +467,9 @@ impl PbrMaterial {
             options: *options,
             descriptor_set,
             uniform_buffer,
-        }
-    }
-
-    pub fn copy_with_new_uniforms(&self, device: ConstPtr<Device>, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, options: &PbrMaterialOptions) -> Self {
-        let uniform = [PbrMaterialUniforms::from_options(options)];
-        let uniform_data: &[u8] = bytemuck::cast_slice(&uniform);
-        let uniform_buffer = Buffer::create_and_initialize_buffer_with_staging_buffer(device, command_pool, BufferCreateInfo {
-            data: uniform_data,
-            usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
-        });
-        let material_props_buffer = vk::DescriptorBufferInfo::builder()
-            .buffer(uniform_buffer.buffer)
-            .offset(0)
-            .range(size_of::<PbrMaterialUniforms>() as u64);
-        let base_color_image_info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(self.textures.base_color_texture.image.image_view)
-            .sampler(self.textures.base_color_texture.sampler);
-        let normal_image_info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(self.textures.normal_texture.image.image_view)
-            .sampler(self.textures.normal_texture.sampler);
-        let occlusion_roughness_metal_image_info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(self.textures.occlusion_roughness_metallic_texture.image.image_view)
-            .sampler(self.textures.occlusion_roughness_metallic_texture.sampler);
-
-        let (descriptor_set, _descriptor_set_layout) = descriptor_manager.descriptor_builder()
-            .bind_buffer(0, material_props_buffer, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::FRAGMENT)
-            .bind_image(1, base_color_image_info, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)
-            .bind_image(2, normal_image_info, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)
-            .bind_image(3, occlusion_roughness_metal_image_info, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)
-            .build()
-            .expect("Failed to allocate bindings");
-        Self {
-            textures: self.textures.clone(),
-            options: *options,
-            descriptor_set,
-            uniform_buffer,
+            animation_buffer,
+            texture_indices,
+            loaded_features,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,407 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use ahash::AHashMap;
+use bevy_ecs::prelude::*;
+use bevy_hierarchy::DespawnRecursiveExt;
+use bevy_time::Time;
+use glam::{EulerRot, Quat};
+use log::{error, info};
+use ron::extensions::Extensions;
+use serde::Deserialize;
+
+use crate::assets::{AssetManager, Camera, MeshHandle, skybox};
+use crate::assets::demo_scenes::{Actor, add_lights_to_parent, add_model_to_parent, load_pbr_pipeline, ShouldDrawDebug, SpawnModel};
+use crate::assets::gltf_loader::{GltfLight, GltfSource};
+use crate::assets::light_source::PointLight;
+use crate::assets::model_loader::PendingModels;
+use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer, Shader};
+use crate::assets::render_object::{LoadedMesh, MeshLod, NormalMapSpace, Transform};
+use crate::etna::{DeviceRes, GraphicsSettings, material_pipeline, Swapchain};
+use crate::etna::cube_map::{IblQuality, IblSettings};
+use crate::etna::material_pipeline::DescriptorManager;
+use crate::rehnda_core::{ColorRgbaF, Vec3};
+use crate::rehnda_core::input::InputState;
+use crate::rehnda_core::input_bindings::InputAction;
+
+// how often the scene file is checked for changes
+const SCENE_POLL_INTERVAL_SECONDS: f32 = 0.5;
+
+/// A scene read from a RON file, fields left out of the file use their defaults
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SceneDescription {
+    pub camera: Option<CameraDescription>,
+    // equirectangular HDR used for the skybox and image based lighting, or a directory of maps baked offline
+    pub environment: Option<PathBuf>,
+    // how detailed the maps rendered from an equirectangular environment are, lower is faster to load
+    pub ibl_quality: IblQuality,
+    pub models: Vec<ModelDescription>,
+    pub lights: Vec<LightDescription>,
+}
+
+impl SceneDescription {
+    // the environment maps are rendered again when the quality changes
+    fn environment_changed(&self, loaded_environment: Option<&PathBuf>, asset_manager: &AssetManager) -> bool {
+        self.environment.as_ref() != loaded_environment || IblSettings::preset(self.ibl_quality) != asset_manager.ibl_settings
+    }
+}
+
+#[derive(Deserialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct CameraDescription {
+    pub position: (f32, f32, f32),
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov_y_degrees: f32,
+}
+
+impl Default for CameraDescription {
+    fn default() -> Self {
+        Self {
+            position: (0.0, 0.0, 5.0),
+            yaw: -90.0,
+            pitch: 0.0,
+            fov_y_degrees: 45.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ModelDescription {
+    pub name: Option<String>,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub transform: TransformDescription,
+    #[serde(default)]
+    pub unlit: bool,
+    #[serde(default)]
+    pub material: MaterialOverride,
+    // simpler versions of the model drawn as the camera moves away, each must have the same primitives in the same order
+    #[serde(default)]
+    pub lods: Vec<LodDescription>,
+}
+
+#[derive(Deserialize)]
+pub struct LodDescription {
+    pub path: PathBuf,
+    // the lod is drawn once the camera is at least this far from a primitive's bounds
+    pub min_distance: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TransformDescription {
+    pub translation: (f32, f32, f32),
+    // euler angles applied in XYZ order
+    pub rotation_degrees: (f32, f32, f32),
+    pub scale: (f32, f32, f32),
+}
+
+impl Default for TransformDescription {
+    fn default() -> Self {
+        Self {
+            translation: (0.0, 0.0, 0.0),
+            rotation_degrees: (0.0, 0.0, 0.0),
+            scale: (1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl TransformDescription {
+    fn to_transform(&self) -> Transform {
+        let (x, y, z) = self.rotation_degrees;
+        Transform {
+            translation: self.translation.into(),
+            rotation: Quat::from_euler(EulerRot::XYZ, x.to_radians(), y.to_radians(), z.to_radians()),
+            scale: self.scale.into(),
+        }
+    }
+}
+
+/// Replaces the model's material values, anything not set keeps the value from the gltf file
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct MaterialOverride {
+    pub base_color: Option<(f32, f32, f32, f32)>,
+    pub roughness: Option<f32>,
+    pub metallic: Option<f32>,
+    pub emissive: Option<(f32, f32, f32)>,
+    pub normal_map_space: Option<NormalMapSpace>,
+}
+
+impl MaterialOverride {
+    fn is_empty(&self) -> bool {
+        self.base_color.is_none() && self.roughness.is_none() && self.metallic.is_none() && self.emissive.is_none() && self.normal_map_space.is_none()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LightDescription {
+    pub name: Option<String>,
+    pub position: (f32, f32, f32),
+    #[serde(default = "default_light_color")]
+    pub color: (f32, f32, f32),
+    #[serde(default = "default_light_emissivity")]
+    pub emissivity: f32,
+    // drawn unlit at the light's position
+    pub model: Option<PathBuf>,
+    #[serde(default = "default_light_model_scale")]
+    pub model_scale: f32,
+}
+
+fn default_light_color() -> (f32, f32, f32) {
+    (1.0, 1.0, 1.0)
+}
+
+fn default_light_emissivity() -> f32 {
+    100.0
+}
+
+fn default_light_model_scale() -> f32 {
+    1.0
+}
+
+impl SceneDescription {
+    // reads and validates the scene, the error names the scene file and whatever was wrong with it
+    pub fn load(scene_path: &Path) -> Result<SceneDescription, String> {
+        let scene_source = fs::read_to_string(scene_path)
+            .map_err(|err| format!("Failed to read scene file {}: {}", scene_path.display(), err))?;
+        let scene: SceneDescription = ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_str(&scene_source)
+            .map_err(|err| format!("Failed to parse scene file {}: {}", scene_path.display(), err))?;
+        let missing_assets: Vec<String> = scene.asset_paths()
+            .filter(|asset_path| !asset_path.exists())
+            .map(|asset_path| asset_path.display().to_string())
+            .collect();
+        if !missing_assets.is_empty() {
+            return Err(format!("Scene file {} references missing assets: {}", scene_path.display(), missing_assets.join(", ")));
+        }
+        Ok(scene)
+    }
+
+    fn asset_paths(&self) -> impl Iterator<Item=&Path> {
+        self.environment.iter()
+            .chain(self.models.iter().flat_map(|model| std::iter::once(&model.path).chain(model.lods.iter().map(|lod| &lod.path))))
+            .chain(self.lights.iter().filter_map(|light| light.model.as_ref()))
+            .map(PathBuf::as_path)
+    }
+}
+
+/// Marks the root entities spawned from the scene file, so they can be removed when it is reloaded
+#[derive(Component)]
+pub struct SceneEntity;
+
+#[derive(Clone, Copy)]
+struct SceneMaterials {
+    pbr: MaterialPipelineHandle,
+    unlit: MaterialPipelineHandle,
+    skybox: MaterialPipelineHandle,
+}
+
+/// The scene file being displayed, it is reloaded whenever the file is modified
+#[derive(Resource)]
+pub struct SceneFile {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+    seconds_since_poll: f32,
+    materials: Option<SceneMaterials>,
+    environment: Option<PathBuf>,
+    camera: Option<CameraDescription>,
+    // gltf files are only loaded once and reused when the scene is reloaded, keyed by path and whether they are unlit
+    loaded_models: AHashMap<(PathBuf, bool), (Vec<LoadedMesh>, Vec<GltfLight>)>,
+}
+
+impl SceneFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            last_modified: None,
+            seconds_since_poll: 0.0,
+            materials: None,
+            environment: None,
+            camera: None,
+            loaded_models: AHashMap::new(),
+        }
+    }
+
+    fn modified_time(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    // the model's meshes and the lights it brings with it
+    fn load_model(&mut self, path: &Path, unlit: bool, asset_manager: &mut AssetManager, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, material_server: &MaterialServer) -> (Vec<LoadedMesh>, Vec<GltfLight>) {
+        let materials = self.materials.expect("Scene materials must be loaded before models");
+        self.loaded_models.entry((path.to_path_buf(), unlit))
+            .or_insert_with(|| {
+                let pipeline = if unlit { materials.unlit } else { materials.pbr };
+                let source = GltfSource::read(path, &mut |_| {});
+                (asset_manager.upload_gltf(&source, descriptor_manager, graphics_settings, material_server, pipeline), source.lights())
+            })
+            .clone()
+    }
+}
+
+// pairs each of the model's primitives with the primitive at the same position in each lod's file
+fn set_model_lods(model: &ModelDescription, meshes: &[LoadedMesh], scene_file: &mut SceneFile, asset_manager: &mut AssetManager, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, material_server: &MaterialServer) {
+    let lod_primitives: Vec<(Vec<MeshHandle>, f32)> = model.lods.iter()
+        .map(|lod| {
+            let (lod_meshes, _) = scene_file.load_model(&lod.path, model.unlit, asset_manager, descriptor_manager, graphics_settings, material_server);
+            let primitives = lod_meshes.iter().flat_map(|mesh| mesh.primitives.iter().map(|primitive| primitive.mesh_handle)).collect();
+            (primitives, lod.min_distance)
+        })
+        .collect();
+    let primitives = meshes.iter().flat_map(|mesh| mesh.primitives.iter());
+    for (primitive_index, primitive) in primitives.enumerate() {
+        let lods: Vec<MeshLod> = lod_primitives.iter()
+            .filter_map(|(lod_meshes, min_distance)| lod_meshes.get(primitive_index).map(|mesh_handle| MeshLod { mesh_handle: *mesh_handle, min_distance: *min_distance }))
+            .collect();
+        asset_manager.set_mesh_lods(&primitive.mesh_handle, &lods);
+    }
+}
+
+pub fn scene_startup_system(mut commands: Commands, mut scene_file: ResMut<SceneFile>, swapchain: Res<Swapchain>, graphics_settings: Res<GraphicsSettings>, mut asset_manager: ResMut<AssetManager>, mut material_server: ResMut<MaterialServer>, mut descriptor_manager: ResMut<DescriptorManager>) {
+    let pbr = load_pbr_pipeline(&mut material_server, &graphics_settings);
+    scene_file.materials = Some(SceneMaterials {
+        pbr,
+        unlit: material_server.load_material(material_pipeline::unlit_pipeline, Shader::Unlit),
+        skybox: material_server.load_material(skybox::skybox_pipeline, Shader::SkyBox),
+    });
+
+    let mut camera = Camera::new(45.0, swapchain.aspect_ratio(), 0.1, 1000.0);
+    camera.set_reverse_z(graphics_settings.reverse_z);
+    commands.insert_resource(camera);
+
+    scene_file.last_modified = scene_file.modified_time();
+    let scene = SceneDescription::load(&scene_file.path).unwrap_or_else(|err| panic!("{}", err));
+    info!("Loading scene {}", scene_file.path.display());
+    spawn_scene(&mut commands, &scene, &mut scene_file, &swapchain, &graphics_settings, &mut asset_manager, &material_server, &mut descriptor_manager);
+}
+
+// while running a bad edit to the scene file is logged and the current scene is kept
+pub fn scene_hot_reload_system(
+    mut commands: Commands,
+    mut scene_file: ResMut<SceneFile>,
+    time: Res<Time>,
+    scene_entities: Query<Entity, With<SceneEntity>>,
+    device: DeviceRes,
+    swapchain: Res<Swapchain>,
+    graphics_settings: Res<GraphicsSettings>,
+    mut asset_manager: ResMut<AssetManager>,
+    material_server: Res<MaterialServer>,
+    mut descriptor_manager: ResMut<DescriptorManager>,
+    mut pending_models: ResMut<PendingModels>,
+    input_state: Res<InputState>,
+) {
+    // the reload key rebuilds the scene and reloads its assets even if the file hasn't changed
+    let reload_requested = input_state.is_action_just_down(InputAction::ReloadScene);
+    scene_file.seconds_since_poll += time.delta_seconds();
+    if scene_file.seconds_since_poll < SCENE_POLL_INTERVAL_SECONDS && !reload_requested {
+        return;
+    }
+    scene_file.seconds_since_poll = 0.0;
+    let modified_time = scene_file.modified_time();
+    let file_changed = modified_time.is_some() && modified_time != scene_file.last_modified;
+    if !file_changed && !reload_requested {
+        return;
+    }
+    scene_file.last_modified = modified_time;
+
+    let scene = match SceneDescription::load(&scene_file.path) {
+        Ok(scene) => scene,
+        Err(err) => {
+            error!("{}, keeping the current scene", err);
+            return;
+        }
+    };
+    info!("Reloading scene {}", scene_file.path.display());
+    if reload_requested {
+        // frames in flight may still be drawing the assets about to be freed
+        unsafe { device.device_wait_idle() }.expect("Failed to wait for the device to be idle");
+        pending_models.clear();
+        asset_manager.clear();
+        scene_file.loaded_models.clear();
+        scene_file.environment = None;
+    } else if scene.environment_changed(scene_file.environment.as_ref(), &asset_manager) {
+        // the previous environment maps may still be in use by frames in flight
+        unsafe { device.device_wait_idle() }.expect("Failed to wait for the device to be idle");
+    }
+    for entity in scene_entities.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    spawn_scene(&mut commands, &scene, &mut scene_file, &swapchain, &graphics_settings, &mut asset_manager, &material_server, &mut descriptor_manager);
+}
+
+fn spawn_scene(commands: &mut Commands, scene: &SceneDescription, scene_file: &mut SceneFile, swapchain: &Swapchain, graphics_settings: &GraphicsSettings, asset_manager: &mut AssetManager, material_server: &MaterialServer, descriptor_manager: &mut DescriptorManager) {
+    let materials = scene_file.materials.expect("Scene materials must be loaded before spawning the scene");
+
+    // the camera is only moved when its description changes, so editing the rest of the scene doesn't reset the view
+    if scene.camera != scene_file.camera {
+        let camera_description = scene.camera.clone().unwrap_or_default();
+        let mut camera = Camera::new(camera_description.fov_y_degrees, swapchain.aspect_ratio(), 0.1, 1000.0);
+        camera.set_reverse_z(graphics_settings.reverse_z);
+        camera.position = camera_description.position.into();
+        camera.yaw = camera_description.yaw;
+        camera.pitch = camera_description.pitch;
+        commands.insert_resource(camera);
+        scene_file.camera = scene.camera.clone();
+    }
+
+    if scene.environment_changed(scene_file.environment.as_ref(), asset_manager) {
+        asset_manager.ibl_settings = IblSettings::preset(scene.ibl_quality);
+        match &scene.environment {
+            Some(environment) => asset_manager.load_global_light_map(environment, descriptor_manager, graphics_settings, materials.skybox),
+            None => asset_manager.global_light_map = None,
+        }
+        scene_file.environment = scene.environment.clone();
+    }
+
+    for model in &scene.models {
+        let (mut meshes, lights) = scene_file.load_model(&model.path, model.unlit, asset_manager, descriptor_manager, graphics_settings, material_server);
+        set_model_lods(model, &meshes, scene_file, asset_manager, descriptor_manager, graphics_settings, material_server);
+        if !model.material.is_empty() {
+            for render_object in meshes.iter_mut().flat_map(|mesh| mesh.primitives.iter_mut()) {
+                let mut options = *asset_manager.material_ref(&render_object.material_instance_handle).options();
+                if let Some((r, g, b, a)) = model.material.base_color {
+                    options.base_color = ColorRgbaF::new(r, g, b, a);
+                }
+                options.roughness = model.material.roughness.unwrap_or(options.roughness);
+                options.metallic = model.material.metallic.unwrap_or(options.metallic);
+                options.emissive = model.material.emissive.map_or(options.emissive, Vec3::from);
+                options.normal_map_space = model.material.normal_map_space.unwrap_or(options.normal_map_space);
+                *render_object = asset_manager.override_material(render_object, descriptor_manager, &options);
+            }
+        }
+        let name = model.name.clone().unwrap_or_else(|| model.path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()));
+        let mut entity = commands.spawn_model(meshes.as_slice(), model.transform.to_transform(), name);
+        entity.insert((SceneEntity, ShouldDrawDebug));
+        add_lights_to_parent(&mut entity, &lights);
+    }
+
+    for (light_index, light) in scene.lights.iter().enumerate() {
+        let mut light_entity = commands.spawn((
+            Actor {
+                name: light.name.clone().unwrap_or_else(|| format!("Light {}", light_index)),
+            },
+            Transform {
+                translation: light.position.into(),
+                scale: Vec3::splat(light.model_scale),
+                ..Default::default()
+            },
+            PointLight {
+                light_color: light.color.into(),
+                emissivity: light.emissivity,
+                ..Default::default()
+            },
+            SceneEntity,
+            ShouldDrawDebug,
+        ));
+        if let Some(model_path) = &light.model {
+            // the light is the model's, any lights in its file are left out
+            let (light_model, _) = scene_file.load_model(model_path, true, asset_manager, descriptor_manager, graphics_settings, material_server);
+            add_model_to_parent(&mut light_entity, light_model.as_slice());
+        }
+    }
+}
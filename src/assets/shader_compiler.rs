@@ -1,34 +1,184 @@
 use std::fs::File;
+use std::hash::Hasher;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use ahash::{AHashMap, AHashSet};
 use glob::glob;
-use shaderc::{CompileOptions, Compiler, ShaderKind};
+use log::info;
+use shaderc::{CompileOptions, Compiler, IncludeType, ResolvedInclude, ShaderKind};
 
-pub fn compile_all_files() {
+const SPIRV_DIRECTORY: &str = "shaders/spirv";
+const MANIFEST_PATH: &str = "shaders/spirv/manifest";
+const INCLUDE_DIRECTORY: &str = "shaders/include";
+// defined for the variant of each fragment shader that writes its depth, see write_fragment_depth in common.glsl
+const LOGARITHMIC_DEPTH_DEFINE: &str = "LOGARITHMIC_DEPTH";
+
+/// Compiles any shaders whose source has changed since the last compile, returning the paths of the spirv files that were rewritten
+pub fn compile_all_files() -> AHashSet<PathBuf> {
     let files_to_compile = files_to_compile();
-    let compiler = Compiler::new().expect("Failed to build compiler");
-    files_to_compile.iter().for_each(|to_compile| compile_to_spirv(&compiler, to_compile));
+    std::fs::create_dir_all(SPIRV_DIRECTORY).expect("Failed to create spirv output directory");
+    let mut manifest = read_manifest();
+    let mut changed_shaders = AHashSet::new();
+    let mut compiler: Option<Compiler> = None;
+    for to_compile in files_to_compile.iter() {
+        let source = read_source(to_compile.path_buf.as_path());
+        let source_hash = hash_source_with_includes(&source);
+        let out_path = to_compile.out_path();
+        let logarithmic_depth_out_path = (to_compile.kind == ShaderKind::Fragment && writes_fragment_depth(&source, &mut AHashSet::new()))
+            .then(|| to_compile.logarithmic_depth_out_path());
+        let shader_name = to_compile.shader_name();
+        let outputs_exist = std::iter::once(&out_path).chain(&logarithmic_depth_out_path).all(|path| path.exists());
+        if outputs_exist && manifest.get(&shader_name) == Some(&source_hash) {
+            continue;
+        }
+        let compiler = compiler.get_or_insert_with(|| Compiler::new().expect("Failed to build compiler"));
+        compile_to_spirv(compiler, to_compile, &source, None, &out_path);
+        if let Some(logarithmic_depth_out_path) = logarithmic_depth_out_path {
+            compile_to_spirv(compiler, to_compile, &source, Some(LOGARITHMIC_DEPTH_DEFINE), &logarithmic_depth_out_path);
+            changed_shaders.insert(logarithmic_depth_out_path);
+        }
+        manifest.insert(shader_name, source_hash);
+        changed_shaders.insert(out_path);
+    }
+    if !changed_shaders.is_empty() {
+        info!("Compiled {} changed shaders", changed_shaders.len());
+        write_manifest(&manifest);
+    }
+    changed_shaders
+}
+
+// the spirv of the shader's variant that writes its logarithmic depth, if the shader has one
+pub fn logarithmic_depth_variant(spirv_path: &Path) -> Option<PathBuf> {
+    let spirv_path = spirv_path.to_str()?;
+    let variant_path = PathBuf::from(format!("{}_log_depth_spv", spirv_path.strip_suffix("_spv")?));
+    variant_path.exists().then_some(variant_path)
 }
 
-fn compile_to_spirv(compiler: &Compiler, to_compile: &ToCompile) {
+fn compile_to_spirv(compiler: &Compiler, to_compile: &ToCompile, source: &str, define: Option<&str>, out_path: &Path) {
     let file_path = to_compile.path_buf.as_path();
-    let mut file = File::open(to_compile.path_buf.as_path()).unwrap();
-    let mut file_data = String::new();
-    file.read_to_string(&mut file_data).unwrap();
     let mut compile_options = CompileOptions::new().unwrap();
     compile_options.set_generate_debug_info();
+    compile_options.set_include_callback(resolve_include);
+    if let Some(define) = define {
+        compile_options.add_macro_definition(define, None);
+    }
     let binary_result = compiler.compile_into_spirv(
-        file_data.as_str(),
+        source,
         to_compile.kind,
         file_path.file_name().unwrap().to_str().unwrap(),
         "main",
         Some(&compile_options),
     ).unwrap();
-    let out_file_name = format!("shaders/spirv/{}_spv", to_compile.path_buf.file_name().unwrap().to_str().unwrap());
-    let mut out_file = File::create(out_file_name).unwrap();
+    let mut out_file = File::create(out_path).unwrap();
     out_file.write_all(binary_result.as_binary_u8()).unwrap();
 }
 
+fn read_source(path: &Path) -> String {
+    let mut file = File::open(path).unwrap();
+    let mut file_data = String::new();
+    file.read_to_string(&mut file_data).unwrap();
+    file_data
+}
+
+fn resolve_include(requested_source: &str, _include_type: IncludeType, _requesting_source: &str, _include_depth: usize) -> Result<ResolvedInclude, String> {
+    let include_path = Path::new(INCLUDE_DIRECTORY).join(requested_source);
+    let content = std::fs::read_to_string(&include_path)
+        .map_err(|err| format!("Failed to read include {}: {}", include_path.display(), err))?;
+    Ok(ResolvedInclude {
+        resolved_name: include_path.to_str().unwrap().to_string(),
+        content,
+    })
+}
+
+// FNV-1a, unlike DefaultHasher its output doesn't change between rust versions, which the manifest on disk relies on
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+// includes are hashed along with the source so editing a header recompiles all of its dependents
+fn hash_source_with_includes(source: &str) -> u64 {
+    let mut hasher = StableHasher::default();
+    let mut visited_includes = AHashSet::new();
+    hash_source(&mut hasher, source, &mut visited_includes);
+    hasher.finish()
+}
+
+fn hash_source(hasher: &mut StableHasher, source: &str, visited_includes: &mut AHashSet<String>) {
+    hasher.write(source.as_bytes());
+    for include in included_files(source) {
+        if !visited_includes.insert(include.clone()) {
+            continue;
+        }
+        if let Ok(include_source) = std::fs::read_to_string(Path::new(INCLUDE_DIRECTORY).join(&include)) {
+            hash_source(hasher, &include_source, visited_includes);
+        }
+    }
+}
+
+// whether the shader or one of its includes calls write_fragment_depth, rather than only including its definition
+fn writes_fragment_depth(source: &str, visited_includes: &mut AHashSet<String>) -> bool {
+    if source.lines().any(|line| line.trim_start().starts_with("write_fragment_depth(")) {
+        return true;
+    }
+    included_files(source).into_iter().any(|include| {
+        if !visited_includes.insert(include.clone()) {
+            return false;
+        }
+        let Ok(include_source) = std::fs::read_to_string(Path::new(INCLUDE_DIRECTORY).join(&include)) else {
+            return false;
+        };
+        writes_fragment_depth(&include_source, visited_includes)
+    })
+}
+
+fn included_files(source: &str) -> Vec<String> {
+    source.lines()
+        .filter_map(|line| line.trim().strip_prefix("#include"))
+        .filter_map(|include| {
+            let include = include.trim();
+            include.strip_prefix('"').and_then(|name| name.strip_suffix('"'))
+                .or_else(|| include.strip_prefix('<').and_then(|name| name.strip_suffix('>')))
+        })
+        .map(|include| include.to_string())
+        .collect()
+}
+
+// the manifest is a line per shader of "<shader file name> <source hash>"
+fn read_manifest() -> AHashMap<String, u64> {
+    let Ok(manifest) = std::fs::read_to_string(MANIFEST_PATH) else {
+        return AHashMap::new();
+    };
+    manifest.lines()
+        .filter_map(|line| {
+            let (shader_name, hash) = line.split_once(' ')?;
+            Some((shader_name.to_string(), hash.parse().ok()?))
+        })
+        .collect()
+}
+
+fn write_manifest(manifest: &AHashMap<String, u64>) {
+    let mut lines: Vec<String> = manifest.iter().map(|(shader_name, hash)| format!("{} {}", shader_name, hash)).collect();
+    lines.sort();
+    std::fs::write(MANIFEST_PATH, lines.join("\n")).expect("Failed to write shader manifest");
+}
+
 fn files_to_compile() -> Vec<ToCompile> {
     let mut to_compiles: Vec<ToCompile> = Vec::new();
     for entry in glob("shaders/src/**/*").unwrap() {
@@ -43,6 +193,10 @@ fn files_to_compile() -> Vec<ToCompile> {
                 path_buf: a,
                 kind: ShaderKind::Fragment,
             }),
+            "comp" => to_compiles.push(ToCompile {
+                path_buf: a,
+                kind: ShaderKind::Compute,
+            }),
             _ => panic!("Unsupported extension in shaders")
         }
     }
@@ -52,4 +206,36 @@ fn files_to_compile() -> Vec<ToCompile> {
 struct ToCompile {
     path_buf: PathBuf,
     kind: ShaderKind,
-}
\ No newline at end of file
+}
+
+impl ToCompile {
+    fn shader_name(&self) -> String {
+        self.path_buf.file_name().unwrap().to_str().unwrap().to_string()
+    }
+
+    fn out_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}/{}_spv", SPIRV_DIRECTORY, self.shader_name()))
+    }
+
+    fn logarithmic_depth_out_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}/{}_log_depth_spv", SPIRV_DIRECTORY, self.shader_name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(bytes: &[u8]) -> u64 {
+        let mut hasher = StableHasher::default();
+        hasher.write(bytes);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_stable_hasher_matches_fnv1a() {
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -55,7 +55,13 @@ pub fn skybox_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut Descri
         image_format: swapchain.image_format,
         vertex_input,
         multisampling,
-        rasterization_options: &RasterizationOptions::default(),
+        // the sky box sits on the far plane, so doesn't write depth to stay behind everything in either depth direction
+        rasterization_options: &RasterizationOptions {
+            depth_write_enabled: false,
+            ..RasterizationOptions::default()
+        },
+        depth_compare_op: graphics_settings.depth_compare_op(),
+        depth_format: swapchain.depth_buffer.format,
     };
 
     MaterialPipeline::create(device, &create_info)
@@ -0,0 +1,32 @@
+use bevy_ecs::prelude::*;
+use bevy_hierarchy::Children;
+
+use crate::assets::{AssetManager, Camera};
+use crate::assets::demo_scenes::Actor;
+use crate::assets::render_object::{actor_render_object_entities, MeshGroup, RenderObject, Transform};
+
+// an object's textures are streamed in once its bounds are at least this large relative to their distance from the
+// camera, roughly a fifth of the view across
+const STREAM_IN_SIZE_TO_DISTANCE: f32 = 0.2;
+// each texture is uploaded with a blocking submit, so only a few are streamed in a frame to avoid long hitches
+const MAX_TEXTURES_STREAMED_PER_FRAME: usize = 3;
+
+// streams in the full size mips of textures loaded with texture streaming as the objects using them come close.
+// Textures are never streamed back out
+pub fn texture_streaming_system(asset_manager: Res<AssetManager>, camera: Res<Camera>, actors: Query<(&Transform, &Children), With<Actor>>, render_objects: Query<&RenderObject>, mesh_groups: Query<&'static Children, With<MeshGroup>>) {
+    let mut streamed_textures = 0;
+    for (actor_transform, children) in &actors {
+        for render_object in actor_render_object_entities(children, &mesh_groups).filter_map(|entity| render_objects.get(entity).ok()) {
+            if streamed_textures >= MAX_TEXTURES_STREAMED_PER_FRAME {
+                return;
+            }
+            let mesh = asset_manager.mesh_ref(&render_object.mesh_handle);
+            let bounds = mesh.bounds.transformed(actor_transform.matrix() * mesh.relative_transform);
+            let distance = camera.position.distance(camera.position.clamp(bounds.min, bounds.max));
+            let size = bounds.min.distance(bounds.max);
+            if size >= distance * STREAM_IN_SIZE_TO_DISTANCE {
+                streamed_textures += asset_manager.stream_in_material_textures(&render_object.material_instance_handle);
+            }
+        }
+    }
+}
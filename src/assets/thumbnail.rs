@@ -0,0 +1,207 @@
+use std::ffi::CString;
+use std::mem::size_of;
+use std::path::Path;
+
+use ash::vk;
+use bytemuck_derive::{Pod, Zeroable};
+use log::warn;
+
+use crate::assets::{vulkan_projection_matrix, Vertex};
+use crate::assets::render_object::Mesh;
+use crate::etna::{Buffer, CommandPool, Device, Image, ImageCreateInfo, ImageType, MsaaSamples};
+use crate::etna::material_pipeline::{MaterialPipeline, PipelineCreateInfo, PipelineMultisamplingInfo, PipelineVertexInputDescription, RasterizationOptions};
+use crate::etna::shader::ShaderModule;
+use crate::rehnda_core::{ConstPtr, Mat4, Vec3};
+
+// srgb so the pixels read back are ready to be shown or saved without converting them
+const THUMBNAIL_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+const THUMBNAIL_DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+const THUMBNAIL_FOV_Y: f32 = std::f32::consts::FRAC_PI_4;
+// the camera looks down at the mesh from the front right, the usual three-quarter view of a product shot
+const THUMBNAIL_VIEW_DIRECTION: Vec3 = Vec3::new(1.0, 0.75, 1.0);
+
+// MUST KEEP IN SYNC WITH thumbnail.vert
+#[repr(C)]
+#[derive(Pod, Zeroable, Debug, Copy, Clone)]
+struct ThumbnailPushConstants {
+    model_view_projection: Mat4,
+    normal_matrix: Mat4,
+}
+
+/// Renders single meshes to small offscreen images, for previews like an asset browser. Meshes are drawn with a
+/// default material and their own lighting, so they look the same whatever scene they were loaded into
+pub struct ThumbnailRenderer {
+    device: ConstPtr<Device>,
+    pipeline: MaterialPipeline,
+}
+
+impl ThumbnailRenderer {
+    pub fn create(device: ConstPtr<Device>) -> Self {
+        Self {
+            device,
+            pipeline: thumbnail_pipeline(device),
+        }
+    }
+
+    /// The mesh framed by a three-quarter camera as `size` by `size` RGBA pixels, with a transparent background
+    pub fn render(&self, command_pool: &CommandPool, mesh: &Mesh, size: u32) -> Vec<u8> {
+        let size = size.max(1);
+        let color_image = Image::create_image(self.device, &ImageCreateInfo {
+            image_type: ImageType::SingleImage,
+            width: size,
+            height: size,
+            format: THUMBNAIL_FORMAT,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            mip_levels: 1,
+            memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            image_aspect_flags: vk::ImageAspectFlags::COLOR,
+            num_samples: vk::SampleCountFlags::TYPE_1,
+            create_flags: vk::ImageCreateFlags::empty(),
+        });
+        let depth_image = Image::create_image(self.device, &ImageCreateInfo {
+            image_type: ImageType::SingleImage,
+            width: size,
+            height: size,
+            format: THUMBNAIL_DEPTH_FORMAT,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            mip_levels: 1,
+            memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            image_aspect_flags: vk::ImageAspectFlags::DEPTH,
+            num_samples: vk::SampleCountFlags::TYPE_1,
+            create_flags: vk::ImageCreateFlags::empty(),
+        });
+        let readback_buffer = Buffer::create_readback_buffer(self.device, size as u64 * size as u64 * 4);
+        let extent = vk::Extent2D { width: size, height: size };
+
+        let one_time_command_buffer = command_pool.one_time_command_buffer();
+        let command_buffer = *one_time_command_buffer;
+        color_image.transition_to(command_buffer, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags2::COLOR_ATTACHMENT_WRITE);
+        depth_image.transition_to(command_buffer, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL, vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS, vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE);
+
+        let color_attachment_info = vk::RenderingAttachmentInfo::builder()
+            .image_view(color_image.image_view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue { color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 0.0] } });
+        let depth_attachment_info = vk::RenderingAttachmentInfo::builder()
+            .image_view(depth_image.image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .clear_value(vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } });
+        let rendering_info = vk::RenderingInfo::builder()
+            .render_area(vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent })
+            .layer_count(1)
+            .color_attachments(std::slice::from_ref(&color_attachment_info))
+            .depth_attachment(&depth_attachment_info);
+        unsafe { self.device.cmd_begin_rendering(command_buffer, &rendering_info) };
+
+        if mesh.topology == vk::PrimitiveTopology::TRIANGLE_LIST {
+            self.record_mesh(command_buffer, mesh, extent);
+        } else {
+            warn!("Can't draw a thumbnail of a mesh of {:?}, leaving it empty", mesh.topology);
+        }
+
+        unsafe { self.device.cmd_end_rendering(command_buffer) };
+
+        color_image.transition_to(command_buffer, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ);
+        let copy_region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build()
+            )
+            .image_extent(vk::Extent3D { width: size, height: size, depth: 1 })
+            .build();
+        unsafe { self.device.cmd_copy_image_to_buffer(command_buffer, color_image.vk_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, readback_buffer.buffer, std::slice::from_ref(&copy_region)) };
+        one_time_command_buffer.submit_and_wait().expect("Failed to render thumbnail");
+
+        readback_buffer.read_data()[..(size * size * 4) as usize].to_vec()
+    }
+
+    fn record_mesh(&self, command_buffer: vk::CommandBuffer, mesh: &Mesh, extent: vk::Extent2D) {
+        // a sphere around the bounds fits in view from any direction, so the camera distance only depends on its size
+        let bounds = mesh.bounds.transformed(mesh.relative_transform);
+        let center = bounds.center();
+        let radius = ((bounds.max - bounds.min).length() * 0.5).max(f32::EPSILON);
+        let distance = radius / (THUMBNAIL_FOV_Y * 0.5).sin();
+        let eye = center + THUMBNAIL_VIEW_DIRECTION.normalize() * distance;
+        let view = Mat4::look_at_rh(eye, center, Vec3::Y);
+        let projection = vulkan_projection_matrix(THUMBNAIL_FOV_Y, 1.0, (distance - radius).max(distance * 0.001), distance + radius);
+
+        let push_constants = ThumbnailPushConstants {
+            model_view_projection: projection * view * mesh.relative_transform,
+            normal_matrix: mesh.relative_transform.inverse().transpose(),
+        };
+
+        self.pipeline.cmd_bind(command_buffer);
+        self.pipeline.cmd_set_mirrored(command_buffer, mesh.relative_transform.determinant() < 0.0);
+        let viewport = vk::Viewport::builder()
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build();
+        let scissor = vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent };
+        unsafe {
+            self.device.cmd_set_viewport(command_buffer, 0, std::slice::from_ref(&viewport));
+            self.device.cmd_set_scissor(command_buffer, 0, std::slice::from_ref(&scissor));
+            self.device.cmd_push_constants(command_buffer, self.pipeline.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, bytemuck::bytes_of(&push_constants));
+            self.device.cmd_bind_vertex_buffers(command_buffer, 0, std::slice::from_ref(&mesh.vertex_buffer.buffer), &[0]);
+            self.device.cmd_bind_index_buffer(command_buffer, mesh.index_buffer.buffer, 0, mesh.index_type);
+            self.device.cmd_draw_indexed(command_buffer, mesh.index_count, 1, 0, 0, 0);
+        }
+    }
+}
+
+fn thumbnail_pipeline(device: ConstPtr<Device>) -> MaterialPipeline {
+    let vert_shader_module = ShaderModule::load_from_file(device, Path::new("shaders/spirv/thumbnail.vert_spv"));
+    let frag_shader_module = ShaderModule::load_from_file(device, Path::new("shaders/spirv/thumbnail.frag_spv"));
+    let main_function_name = CString::new("main").unwrap();
+    let vertex_shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module.handle())
+        .name(main_function_name.as_c_str())
+        .build();
+    let frag_shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module.handle())
+        .name(main_function_name.as_c_str())
+        .build();
+
+    let push_constant = vk::PushConstantRange::builder()
+        .offset(0)
+        .size(size_of::<ThumbnailPushConstants>() as u32)
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .build();
+
+    let vertex_attributes = Vertex::attribute_descriptions();
+    let vertex_input = PipelineVertexInputDescription {
+        bindings: &[Vertex::binding_description()],
+        attributes: vertex_attributes.as_slice(),
+    };
+
+    let create_info = PipelineCreateInfo {
+        global_set_layouts: &[],
+        additional_descriptor_set_layouts: &[],
+        shader_stages: &[vertex_shader_stage_ci, frag_shader_stage_ci],
+        push_constants: &[push_constant],
+        extent: vk::Extent2D { width: 128, height: 128 },
+        image_format: THUMBNAIL_FORMAT,
+        vertex_input,
+        multisampling: PipelineMultisamplingInfo {
+            msaa_samples: MsaaSamples::X1,
+            enable_sample_rate_shading: false,
+        },
+        rasterization_options: &RasterizationOptions::default(),
+        depth_compare_op: vk::CompareOp::LESS,
+        depth_format: vk::Format::D32_SFLOAT,
+    };
+
+    MaterialPipeline::create(device, &create_info)
+}
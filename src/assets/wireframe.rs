@@ -0,0 +1,39 @@
+use bevy_ecs::prelude::*;
+
+use crate::assets::DepthPick;
+use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer, Shader};
+use crate::assets::render_object::RenderObject;
+use crate::etna::material_pipeline;
+use crate::rehnda_core::input::InputState;
+use crate::rehnda_core::input_bindings::InputAction;
+
+/// Draws the edges of the render object's triangles over it, as GraphicsSettings::wireframe_overlay does for every
+/// object
+#[derive(Component)]
+pub struct ShowWireframe;
+
+/// The pipeline drawing the edges of meshes over the filled meshes
+#[derive(Resource)]
+pub struct WireframeRenderer {
+    pub pipeline: MaterialPipelineHandle,
+}
+
+pub fn wireframe_renderer_startup_system(mut commands: Commands, mut material_server: ResMut<MaterialServer>) {
+    commands.insert_resource(WireframeRenderer {
+        pipeline: material_server.load_material(material_pipeline::wireframe_pipeline, Shader::Wireframe),
+    });
+}
+
+pub fn toggle_selected_wireframe_system(mut commands: Commands, input_state: Res<InputState>, depth_pick: Res<DepthPick>, render_objects: Query<Option<&ShowWireframe>, With<RenderObject>>) {
+    if !input_state.is_action_just_down(InputAction::ToggleWireframe) {
+        return;
+    }
+    let Some(entity) = depth_pick.entity else {
+        return;
+    };
+    match render_objects.get(entity) {
+        Ok(Some(_)) => { commands.entity(entity).remove::<ShowWireframe>(); }
+        Ok(None) => { commands.entity(entity).insert(ShowWireframe); }
+        Err(_) => {}
+    }
+}
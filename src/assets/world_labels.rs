@@ -0,0 +1,239 @@
+use std::ffi::CString;
+use std::mem::size_of;
+use std::path::Path;
+
+use ash::vk;
+use bevy_ecs::prelude::*;
+use bytemuck_derive::{Pod, Zeroable};
+use egui::{Color32, FontId};
+
+use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer, Shader};
+use crate::assets::render_object::Transform;
+use crate::etna::{Device, DeviceRes, GraphicsSettings, HostMappedBuffer, HostMappedBufferCreateInfo, MAX_FRAMES_IN_FLIGHT, Swapchain};
+use crate::etna::material_pipeline::{BlendMode, DescriptorManager, layout_binding, MaterialPipeline, PipelineCreateInfo, PipelineMultisamplingInfo, PipelineVertexInputDescription, RasterizationOptions};
+use crate::etna::shader::ShaderModule;
+use crate::rehnda_core::{ColorRgbaF, ConstPtr, Vec3, Vec4};
+use crate::ui::UiPainter;
+
+// the most glyphs drawn in a frame across all labels, any more are skipped
+pub const MAX_LABEL_GLYPHS: usize = 8192;
+// the size the labels are laid out at, larger sizes give sharper glyphs in the atlas when viewed up close
+const LABEL_FONT_POINTS: f32 = 32.0;
+
+/// Text drawn facing the camera at its entity's translation, with glyphs from egui's font atlas
+#[derive(Component)]
+pub struct WorldLabel {
+    pub text: String,
+    // the height of a line of text in world units
+    pub height: f32,
+    pub color: ColorRgbaF,
+    // drawn over the scene instead of being hidden by the geometry in front of it
+    pub always_on_top: bool,
+}
+
+impl WorldLabel {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            height: 0.15,
+            color: ColorRgbaF::WHITE,
+            always_on_top: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Debug, Copy, Clone)]
+struct GlyphInstance {
+    position: Vec3,
+    // world units per point
+    scale: f32,
+    // min and max corners in points relative to the label's center
+    glyph_rect: Vec4,
+    // min and max uvs in the font atlas
+    uv_rect: Vec4,
+    color: ColorRgbaF,
+}
+
+#[derive(Resource)]
+pub struct LabelRenderer {
+    instance_buffers: [HostMappedBuffer; MAX_FRAMES_IN_FLIGHT],
+    pub pipeline: MaterialPipelineHandle,
+    pub always_on_top_pipeline: MaterialPipelineHandle,
+    // the depth tested glyphs come first, followed by those drawn on top
+    glyphs: Vec<GlyphInstance>,
+    depth_tested_glyph_count: usize,
+    // the atlas the descriptor set was written for, rebuilt when egui replaces it
+    font_atlas: Option<(vk::ImageView, vk::DescriptorSet)>,
+}
+
+impl LabelRenderer {
+    pub fn create(device: ConstPtr<Device>, material_server: &mut MaterialServer) -> Self {
+        let instance_buffers = [(); MAX_FRAMES_IN_FLIGHT].map(|_| HostMappedBuffer::create(device, HostMappedBufferCreateInfo {
+            size: (size_of::<GlyphInstance>() * MAX_LABEL_GLYPHS) as u64,
+            usage: vk::BufferUsageFlags::VERTEX_BUFFER,
+        }));
+        Self {
+            instance_buffers,
+            pipeline: material_server.load_material(label_pipeline, Shader::Label),
+            always_on_top_pipeline: material_server.load_material(always_on_top_label_pipeline, Shader::Label),
+            glyphs: Vec::new(),
+            depth_tested_glyph_count: 0,
+            font_atlas: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    pub fn font_atlas_descriptor_set(&self) -> Option<vk::DescriptorSet> {
+        self.font_atlas.map(|(_, descriptor_set)| descriptor_set)
+    }
+
+    // writes the glyphs laid out this frame, returning the buffer to draw from and the number of depth tested and on
+    // top glyphs in it
+    pub fn prepare_instances(&self, frame_index: usize) -> (vk::Buffer, u32, u32) {
+        let instance_buffer = &self.instance_buffers[frame_index % MAX_FRAMES_IN_FLIGHT];
+        instance_buffer.write_data(bytemuck::cast_slice(self.glyphs.as_slice()));
+        (instance_buffer.vk_buffer(), self.depth_tested_glyph_count as u32, (self.glyphs.len() - self.depth_tested_glyph_count) as u32)
+    }
+
+    fn update_font_atlas(&mut self, descriptor_manager: &mut DescriptorManager, ui_painter: &UiPainter) -> bool {
+        let Some(font_texture) = ui_painter.font_texture() else {
+            return false;
+        };
+        if self.font_atlas.map(|(image_view, _)| image_view) != Some(font_texture.image.image_view) {
+            let image_info = vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(font_texture.image.image_view)
+                .sampler(font_texture.sampler);
+            let (descriptor_set, _) = descriptor_manager.descriptor_builder()
+                .bind_image(0, image_info, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)
+                .build()
+                .expect("Failed to build font atlas descriptor");
+            self.font_atlas = Some((font_texture.image.image_view, descriptor_set));
+        }
+        true
+    }
+}
+
+pub fn label_renderer_startup_system(mut commands: Commands, device: DeviceRes, mut material_server: ResMut<MaterialServer>) {
+    commands.insert_resource(LabelRenderer::create(device.ptr(), &mut material_server));
+}
+
+// lays out every label's glyphs. The font atlas only gains the glyphs that are new to it when the ui runs, so text
+// first shown while the ui is hidden appears once it is shown again
+pub fn world_label_layout_system(mut label_renderer: ResMut<LabelRenderer>, mut descriptor_manager: ResMut<DescriptorManager>, ui_painter: Res<UiPainter>, egui_ctx: NonSend<egui::Context>, labels: Query<(&Transform, &WorldLabel)>) {
+    label_renderer.glyphs.clear();
+    label_renderer.depth_tested_glyph_count = 0;
+    // the atlas exists once egui has run, before then it has no fonts to lay out with
+    if labels.is_empty() || !label_renderer.update_font_atlas(&mut descriptor_manager, &ui_painter) {
+        return;
+    }
+    let [atlas_width, atlas_height] = egui_ctx.fonts(|fonts| fonts.font_image_size());
+    let uv_normalizer = egui::vec2(1.0 / atlas_width as f32, 1.0 / atlas_height as f32);
+
+    let mut depth_tested = Vec::new();
+    let mut on_top = Vec::new();
+    for (transform, label) in labels.iter() {
+        let galley = egui_ctx.fonts(|fonts| fonts.layout_no_wrap(label.text.clone(), FontId::proportional(LABEL_FONT_POINTS), Color32::WHITE));
+        let center = galley.rect.center().to_vec2();
+        let scale = label.height / galley.rect.height().max(1.0);
+        let glyphs = if label.always_on_top { &mut on_top } else { &mut depth_tested };
+        for glyph in galley.rows.iter().flat_map(|row| row.glyphs.iter()) {
+            let uv_rect = glyph.uv_rect;
+            if uv_rect.is_nothing() {
+                continue;
+            }
+            let min = glyph.pos + uv_rect.offset - center;
+            let max = min + uv_rect.size;
+            glyphs.push(GlyphInstance {
+                position: transform.translation,
+                scale,
+                glyph_rect: Vec4::new(min.x, min.y, max.x, max.y),
+                uv_rect: Vec4::new(uv_rect.min[0] as f32 * uv_normalizer.x, uv_rect.min[1] as f32 * uv_normalizer.y, uv_rect.max[0] as f32 * uv_normalizer.x, uv_rect.max[1] as f32 * uv_normalizer.y),
+                color: label.color,
+            });
+        }
+    }
+    depth_tested.truncate(MAX_LABEL_GLYPHS);
+    on_top.truncate(MAX_LABEL_GLYPHS - depth_tested.len());
+    label_renderer.depth_tested_glyph_count = depth_tested.len();
+    label_renderer.glyphs = depth_tested;
+    label_renderer.glyphs.append(&mut on_top);
+}
+
+pub fn label_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    create_label_pipeline(device, descriptor_manager, graphics_settings, swapchain, vert_shader_path, frag_shader_path, graphics_settings.depth_compare_op())
+}
+
+pub fn always_on_top_label_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    create_label_pipeline(device, descriptor_manager, graphics_settings, swapchain, vert_shader_path, frag_shader_path, vk::CompareOp::ALWAYS)
+}
+
+fn create_label_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path, depth_compare_op: vk::CompareOp) -> MaterialPipeline {
+    let vert_shader_module = ShaderModule::load_from_file(device, vert_shader_path);
+    let frag_shader_module = ShaderModule::load_from_file(device, frag_shader_path);
+    let main_function_name = CString::new("main").unwrap();
+    let vertex_shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module.handle())
+        .name(main_function_name.as_c_str())
+        .build();
+    let frag_shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module.handle())
+        .name(main_function_name.as_c_str())
+        .build();
+
+    // each glyph is an instance, the quad corners are generated from the vertex index
+    let instance_binding = vk::VertexInputBindingDescription::builder()
+        .binding(0)
+        .stride(size_of::<GlyphInstance>() as u32)
+        .input_rate(vk::VertexInputRate::INSTANCE)
+        .build();
+    // position and scale, glyph rect, uv rect and color
+    let instance_attributes: Vec<vk::VertexInputAttributeDescription> = (0..4).map(|location| {
+        vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(location)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(location * (size_of::<f32>() * 4) as u32)
+            .build()
+    }).collect();
+    let vertex_input = PipelineVertexInputDescription {
+        bindings: std::slice::from_ref(&instance_binding),
+        attributes: &instance_attributes,
+    };
+
+    let multisampling = PipelineMultisamplingInfo {
+        msaa_samples: graphics_settings.msaa_samples,
+        enable_sample_rate_shading: graphics_settings.sample_rate_shading_enabled,
+    };
+    let font_atlas_layout = descriptor_manager.layout_cache.create_descriptor_layout_for_binding(&[
+        layout_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT),
+    ]);
+
+    let create_info = PipelineCreateInfo {
+        global_set_layouts: &[descriptor_manager.global_descriptor_layout],
+        additional_descriptor_set_layouts: &[font_atlas_layout],
+        shader_stages: &[vertex_shader_stage_ci, frag_shader_stage_ci],
+        push_constants: &[],
+        extent: swapchain.extent,
+        image_format: swapchain.image_format,
+        vertex_input,
+        multisampling,
+        // glyphs overlap their neighbours' quads, so they don't write depth
+        rasterization_options: &RasterizationOptions {
+            cull_mode: vk::CullModeFlags::NONE,
+            depth_write_enabled: false,
+            blend_mode: BlendMode::PremultipliedAlpha,
+            ..RasterizationOptions::default()
+        },
+        depth_compare_op,
+        depth_format: swapchain.depth_buffer.format,
+    };
+
+    MaterialPipeline::create(device, &create_info)
+}
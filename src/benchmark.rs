@@ -0,0 +1,109 @@
+use std::fmt::{Display, Formatter};
+
+use bevy_ecs::prelude::*;
+use bevy_time::Time;
+
+use crate::assets::Camera;
+use crate::etna::{draw_scene_offscreen, FrameRenderContext, GpuTimer, GraphicsSettings, SceneRenderData, SharedFrameResources, Swapchain};
+
+// frames skipped before recording, while pipelines compile and caches warm up
+pub const BENCHMARK_WARMUP_FRAMES: usize = 10;
+
+#[derive(Resource, Default)]
+pub struct BenchmarkRecorder {
+    frames_rendered: usize,
+    cpu_frame_times_ms: Vec<f64>,
+    gpu_frame_times_ms: Vec<f64>,
+}
+
+impl BenchmarkRecorder {
+    pub fn record_gpu_time(&mut self, gpu_time_ms: f64) {
+        if self.frames_rendered > BENCHMARK_WARMUP_FRAMES {
+            self.gpu_frame_times_ms.push(gpu_time_ms);
+        }
+    }
+
+    pub fn report(&self) -> BenchmarkReport {
+        BenchmarkReport {
+            frames: self.cpu_frame_times_ms.len(),
+            cpu: FrameTimeStats::from_samples(&self.cpu_frame_times_ms),
+            gpu: FrameTimeStats::from_samples(&self.gpu_frame_times_ms),
+        }
+    }
+}
+
+pub struct BenchmarkReport {
+    pub frames: usize,
+    pub cpu: Option<FrameTimeStats>,
+    pub gpu: Option<FrameTimeStats>,
+}
+
+impl Display for BenchmarkReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Benchmark of {} frames", self.frames)?;
+        match &self.cpu {
+            Some(cpu) => writeln!(f, "CPU frame time: {}", cpu)?,
+            None => writeln!(f, "CPU frame time: no frames recorded")?,
+        }
+        match &self.gpu {
+            Some(gpu) => write!(f, "GPU frame time: {}", gpu),
+            None => write!(f, "GPU frame time: unavailable, timestamp queries not supported"),
+        }
+    }
+}
+
+/// Summary of frame times in milliseconds
+pub struct FrameTimeStats {
+    pub average: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl FrameTimeStats {
+    pub fn from_samples(samples: &[f64]) -> Option<FrameTimeStats> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+        Some(FrameTimeStats {
+            average: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+impl Display for FrameTimeStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "avg {:.3}ms, p50 {:.3}ms, p95 {:.3}ms, p99 {:.3}ms, max {:.3}ms", self.average, self.p50, self.p95, self.p99, self.max)
+    }
+}
+
+// replaces the draw system when benchmarking, rendering offscreen so no swapchain images are presented
+pub fn benchmark_draw_system(
+    mut frame_renderer: ResMut<FrameRenderContext>,
+    graphics_settings: Res<GraphicsSettings>,
+    swapchain: Res<Swapchain>,
+    camera: Res<Camera>,
+    scene: SceneRenderData,
+    mut shared: SharedFrameResources,
+    mut gpu_timer: Option<ResMut<GpuTimer>>,
+    time: Res<Time>,
+    mut recorder: ResMut<BenchmarkRecorder>,
+) {
+    // the time since the last update covers the whole previous frame, including waiting on the gpu
+    if recorder.frames_rendered > BENCHMARK_WARMUP_FRAMES {
+        recorder.cpu_frame_times_ms.push(time.raw_delta_seconds_f64() * 1000.0);
+    }
+    let gpu_time = draw_scene_offscreen(&mut frame_renderer, &mut shared, &swapchain, &camera, &scene, &graphics_settings, gpu_timer.as_deref_mut());
+    if let Some(gpu_time) = gpu_time {
+        recorder.record_gpu_time(gpu_time);
+    }
+    recorder.frames_rendered += 1;
+}
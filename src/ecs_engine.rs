@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use bevy_app::App;
 use bevy_ecs::prelude::*;
 use bevy_time::TimePlugin;
@@ -5,20 +7,32 @@ use egui::epaint::Shadow;
 use egui::Visuals;
 use log::info;
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
-use winit::event::{KeyboardInput, WindowEvent};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::event::{ElementState, MouseScrollDelta, WindowEvent};
 use winit::event_loop::EventLoopWindowTarget;
-use winit::window::Window;
+use winit::monitor::{MonitorHandle, VideoMode};
+use winit::window::{Fullscreen, Window, WindowId};
 
-use crate::etna::{CommandPool, Device, draw_system, FrameRenderContext, Instance, PhysicalDevice, Surface, Swapchain, swapchain_systems};
+use crate::benchmark::{benchmark_draw_system, BENCHMARK_WARMUP_FRAMES, BenchmarkRecorder, BenchmarkReport};
+use crate::etna::{auto_exposure_startup_system, AutoExposure, CommandPool, DeletionQueue, Device, draw_system, frame_pacing_system, FrameRenderContext, FrameSlots, GpuTimer, GraphicsSettings, hdr_screenshot_system, Instance, MAX_FRAMES_IN_FLIGHT, occlusion_culler_startup_system, OcclusionCuller, PhysicalDevice, renderdoc_capture_system, RenderStats, screenshot_system, SecondaryWindow, SecondaryWindows, secondary_windows_draw_system, Surface, Swapchain, swapchain_systems, ValidationSettings};
 use crate::etna::material_pipeline::DescriptorManager;
-use crate::rehnda_core::input::{input_systems, InputState};
-use crate::rehnda_core::LongLivedObject;
-use crate::assets::{AssetManager, camera_input_system, light_source, material_server};
+use crate::rehnda_core::input::{CursorMovedInput, input_systems, InputState, MouseButtonInput, MouseWheelInput};
+use crate::rehnda_core::input_bindings::{INPUT_BINDINGS_PATH, InputAction, InputBindings};
+use crate::rehnda_core::{LongLivedObject, Vec2};
+use crate::assets::{AssetManager, Camera, camera_input_system, depth_pick_system, DepthPick, gizmos, light_source, material_animation, material_server, outline, particles, texture_streaming, wireframe, world_labels};
 use crate::assets::demo_scenes;
-use crate::assets::light_source::LightingDataManager;
+use crate::assets::demo_scenes::{ReloadableScene, scene_reload_system};
+use crate::assets::model_loader::{all_models_loaded, pending_models_system, PendingModels};
+use crate::assets::scene_loader::{scene_hot_reload_system, scene_startup_system, SceneFile};
+use crate::assets::light_source::{EnvironmentSettings, Fog, LightingDataManager};
 use crate::assets::material_server::MaterialServer;
+use crate::assets::particles::ParticleRenderer;
 use crate::assets::shader_compiler::compile_all_files;
-use crate::ui::{EguiOutput, ui_builder_system, UiPainter};
+use crate::renderer::InitialScene;
+use crate::ui::{EguiOutput, environment_only_toggle_system, MaterialEditor, ui_builder_system, UiPainter, ui_visibility_toggle_system, ui_visible, UiVisibility};
+
+// touchpads scroll in pixels, this converts them to roughly the same speed as a mouse wheel
+const PIXELS_PER_SCROLL_LINE: f32 = 40.0;
 
 pub struct EcsEngine {
     // sync objects above here
@@ -33,12 +47,55 @@ struct EtnaContext {
 #[derive(Resource)]
 pub struct EtnaWindow {
     pub winit_window: winit::window::Window,
+    pub fullscreen_mode: FullscreenMode,
+    // where the window was before going fullscreen, so it can be restored
+    windowed_placement: Option<(PhysicalPosition<i32>, PhysicalSize<u32>)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FullscreenMode {
+    Borderless,
+    Exclusive,
 }
 
 impl EtnaWindow {
     fn is_minimized(&self) -> bool {
         self.winit_window.inner_size().height == 0 || self.winit_window.inner_size().width == 0
     }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.winit_window.fullscreen().is_some()
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        if self.is_fullscreen() {
+            self.winit_window.set_fullscreen(None);
+            if let Some((position, size)) = self.windowed_placement.take() {
+                self.winit_window.set_outer_position(position);
+                self.winit_window.set_inner_size(size);
+            }
+            return;
+        }
+        let position = self.winit_window.outer_position().unwrap_or_default();
+        self.windowed_placement = Some((position, self.winit_window.inner_size()));
+        let monitor = self.winit_window.current_monitor();
+        let fullscreen = match self.fullscreen_mode {
+            FullscreenMode::Exclusive => monitor.as_ref()
+                .and_then(Self::best_video_mode)
+                .map_or_else(|| Fullscreen::Borderless(monitor), Fullscreen::Exclusive),
+            FullscreenMode::Borderless => Fullscreen::Borderless(monitor),
+        };
+        self.winit_window.set_fullscreen(Some(fullscreen));
+    }
+
+    // prefer the monitor's native resolution at the highest refresh rate available
+    fn best_video_mode(monitor: &MonitorHandle) -> Option<VideoMode> {
+        let native_size = monitor.size();
+        monitor.video_modes()
+            .filter(|video_mode| video_mode.size() == native_size)
+            .max_by_key(|video_mode| (video_mode.bit_depth(), video_mode.refresh_rate_millihertz()))
+            .or_else(|| monitor.video_modes().max_by_key(|video_mode| (video_mode.size().width * video_mode.size().height, video_mode.refresh_rate_millihertz())))
+    }
 }
 
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
@@ -46,31 +103,115 @@ enum RehndaSet {
     PreUpdate,
     Update,
     Render,
+    // waits out the rest of the frame once everything has been presented
+    FramePacing,
 }
 
 impl EcsEngine {
-    pub fn new(window: Window, event_loop: &EventLoopWindowTarget<()>) -> EcsEngine {
+    // a scene file is reloaded whenever it changes
+    pub fn new(window: Window, event_loop: &EventLoopWindowTarget<()>, scene: InitialScene, hdr_output: bool) -> EcsEngine {
+        let mut app = Self::create_app(window, event_loop, hdr_output);
+        // inserted up front so the camera can be placed before the first frame, the scenes replace it with their own
+        let mut camera = Camera::new(45.0, app.world.resource::<Swapchain>().aspect_ratio(), 0.1, 1000.0);
+        camera.set_reverse_z(app.world.resource::<GraphicsSettings>().reverse_z);
+        app.insert_resource(camera);
+        match scene {
+            InitialScene::Empty => {}
+            InitialScene::Demo => {
+                app.add_startup_system(demo_scenes::spheres_scene);
+                app.insert_resource(ReloadableScene::new(demo_scenes::spheres_scene));
+                app.add_system(scene_reload_system.in_set(RehndaSet::Update));
+            }
+            InitialScene::File(scene_path) => {
+                app.insert_resource(SceneFile::new(scene_path));
+                app.add_startup_system(scene_startup_system);
+                app.add_system(scene_hot_reload_system.in_set(RehndaSet::Update));
+            }
+        }
+        app.init_resource::<SecondaryWindows>();
+        app.init_resource::<FrameSlots>();
+        app.init_resource::<RenderStats>();
+        app.add_startup_system(occlusion_culler_startup_system);
+        app.add_startup_system(auto_exposure_startup_system);
+        app.add_systems((
+            ui_visibility_toggle_system.in_set(RehndaSet::Update),
+            environment_only_toggle_system.in_set(RehndaSet::Update),
+            renderdoc_capture_system.in_set(RehndaSet::Update),
+            ui_builder_system.run_if(should_render).run_if(ui_visible).in_set(RehndaSet::Render),
+            world_labels::world_label_layout_system.after(ui_builder_system).before(draw_system).in_set(RehndaSet::Render),
+            draw_system.after(ui_builder_system).run_if(should_render).in_set(RehndaSet::Render),
+            swapchain_systems::swap_chain_recreation_system.run_if(swapchain_systems::swap_chain_needs_recreation).after(draw_system).in_set(RehndaSet::Render),
+            secondary_windows_draw_system.after(draw_system).in_set(RehndaSet::Render),
+            hdr_screenshot_system.after(draw_system).before(swapchain_systems::swap_chain_recreation_system).run_if(should_render).in_set(RehndaSet::Render),
+            screenshot_system.after(draw_system).before(swapchain_systems::swap_chain_recreation_system).run_if(should_render).in_set(RehndaSet::Render),
+            depth_pick_system.after(draw_system).before(swapchain_systems::swap_chain_recreation_system).run_if(should_render).in_set(RehndaSet::Render),
+            frame_pacing_system.in_set(RehndaSet::FramePacing),
+        ));
+        app.configure_set(
+            RehndaSet::Render.before(RehndaSet::FramePacing)
+        );
+        EcsEngine {
+            app,
+        }
+    }
+
+    // renders offscreen without presenting or drawing the ui, the window is only needed to create the device
+    pub fn new_benchmark(window: Window, event_loop: &EventLoopWindowTarget<()>) -> EcsEngine {
+        let mut app = Self::create_app(window, event_loop, false);
+        app.add_startup_system(demo_scenes::spheres_scene);
+        let gpu_timer = GpuTimer::create(app.world.resource::<LongLivedObject<Device>>().ptr(), &app.world.resource::<LongLivedObject<PhysicalDevice>>());
+        if let Some(gpu_timer) = gpu_timer {
+            app.insert_resource(gpu_timer);
+        }
+        app.init_resource::<BenchmarkRecorder>();
+        app.add_system(benchmark_draw_system.run_if(all_models_loaded).in_set(RehndaSet::Render));
+        EcsEngine {
+            app,
+        }
+    }
+
+    // the resources and update systems shared by every way of running the engine
+    fn create_app(window: Window, event_loop: &EventLoopWindowTarget<()>, hdr_output: bool) -> App {
         compile_all_files();
         let mut app = App::new();
         app.add_plugin(TimePlugin::default());
-        Self::initialise_rendering_resources(&mut app, window, event_loop);
+        Self::initialise_rendering_resources(&mut app, window, event_loop, hdr_output);
         app.init_resource::<InputState>();
+        app.insert_resource(InputBindings::load_or_default(Path::new(INPUT_BINDINGS_PATH)));
         app.init_resource::<MaterialServer>();
+        app.init_resource::<EnvironmentSettings>();
+        app.init_resource::<Fog>();
+        app.init_resource::<UiVisibility>();
+        app.init_resource::<MaterialEditor>();
+        app.init_resource::<DepthPick>();
+        app.init_resource::<PendingModels>();
+        app.init_resource::<DeletionQueue>();
+        app.init_resource::<material_animation::AnimatedMaterials>();
         app.add_event::<winit::event::KeyboardInput>();
+        app.add_event::<MouseButtonInput>();
+        app.add_event::<MouseWheelInput>();
+        app.add_event::<CursorMovedInput>();
         app.add_startup_system(material_server::material_startup_system);
-        app.add_startup_system(demo_scenes::spheres_scene);
+        app.add_startup_system(particles::particle_renderer_startup_system);
+        app.add_startup_system(world_labels::label_renderer_startup_system);
+        app.add_startup_system(gizmos::gizmo_renderer_startup_system);
+        app.add_startup_system(wireframe::wireframe_renderer_startup_system);
+        app.add_startup_system(outline::outline_renderer_startup_system);
         app.add_systems((
             input_systems::input_system.in_set(RehndaSet::PreUpdate),
+            fullscreen_toggle_system.after(input_systems::input_system).in_set(RehndaSet::PreUpdate),
+            gizmos::clear_gizmos_system.in_set(RehndaSet::PreUpdate),
         ));
         app.add_system(material_server::material_server_system.in_set(RehndaSet::Render));
         app.add_systems((
             camera_input_system.in_set(RehndaSet::Update),
             light_source::update_lights_system.in_set(RehndaSet::Update),
-            ui_builder_system.run_if(should_render).in_set(RehndaSet::Render),
-        ));
-        app.add_systems((
-            draw_system.after(ui_builder_system).run_if(should_render).in_set(RehndaSet::Render),
-            swapchain_systems::swap_chain_recreation_system.run_if(swapchain_systems::swap_chain_needs_recreation).after(draw_system).in_set(RehndaSet::Render),
+            light_source::spot_light_gizmo_system.run_if(ui_visible).in_set(RehndaSet::Update),
+            particles::update_emitters_system.in_set(RehndaSet::Update),
+            material_animation::animate_materials_system.in_set(RehndaSet::Update),
+            texture_streaming::texture_streaming_system.after(camera_input_system).in_set(RehndaSet::Update),
+            wireframe::toggle_selected_wireframe_system.in_set(RehndaSet::Update),
+            pending_models_system.in_set(RehndaSet::Update),
         ));
         app.configure_set(
             RehndaSet::PreUpdate.before(RehndaSet::Update)
@@ -78,31 +219,32 @@ impl EcsEngine {
         app.configure_set(
             RehndaSet::Update.before(RehndaSet::Render)
         );
-        EcsEngine {
-            app,
-        }
+        app
     }
 
-    fn initialise_rendering_resources(app: &mut App, window: Window, event_loop: &EventLoopWindowTarget<()>) {
+    fn initialise_rendering_resources(app: &mut App, window: Window, event_loop: &EventLoopWindowTarget<()>, hdr_output: bool) {
         let entry = ash::Entry::linked();
-        let instance = LongLivedObject::new(Instance::new(&entry));
+        let instance = LongLivedObject::new(Instance::new(&entry, ValidationSettings::default()));
         let surface = Surface::new(&entry, &instance, window.raw_display_handle(), window.raw_window_handle()).expect("Failed to create surface");
         let physical_device = LongLivedObject::new(PhysicalDevice::pick_physical_device(instance.ptr(), &surface));
-        info!("Graphics Settings: {:?}", physical_device.graphics_settings);
-        let device = LongLivedObject::new(Device::create(&instance, &surface, &physical_device));
+        let mut graphics_settings = physical_device.default_graphics_settings();
+        graphics_settings.hdr_output = hdr_output;
+        info!("Graphics Settings: {:?}", graphics_settings);
+        let device = LongLivedObject::new(Device::create(&instance, &surface, &physical_device, &graphics_settings));
         let command_pool = CommandPool::create(device.ptr(), physical_device.queue_families().graphics_family);
         let swapchain = Swapchain::create(
             &instance,
             device.ptr(),
             &physical_device,
+            &graphics_settings,
             &surface,
             &command_pool,
             &physical_device.queue_families(),
-            surface.query_best_swapchain_creation_details(window.inner_size(), physical_device.handle()),
+            surface.query_best_swapchain_creation_details(window.inner_size(), physical_device.handle(), graphics_settings.hdr_output),
         );
-        let mut descriptor_manager = DescriptorManager::create(device.ptr());
-        let asset_manager = AssetManager::create(device.ptr(), physical_device.ptr(), &mut descriptor_manager, CommandPool::create(device.ptr(), physical_device.queue_families().graphics_family));
-        let frame_renderer = FrameRenderContext::create(device.ptr(), &command_pool, &mut descriptor_manager);
+        let mut descriptor_manager = DescriptorManager::create(device.ptr(), graphics_settings.bindless_textures);
+        let asset_manager = AssetManager::create(device.ptr(), physical_device.ptr(), &graphics_settings, &mut descriptor_manager, CommandPool::create(device.ptr(), physical_device.queue_families().graphics_family));
+        let frame_renderer = FrameRenderContext::create(device.ptr(), &command_pool, &mut descriptor_manager, graphics_settings.recording_threads);
 
         // ui resources
         let egui_ctx = egui::Context::default();
@@ -112,18 +254,21 @@ impl EcsEngine {
         app.insert_non_send_resource(egui::Context::default());
         app.insert_non_send_resource(egui_winit::State::new(event_loop));
         app.insert_resource(EguiOutput::default());
-        app.insert_resource(UiPainter::create(device.ptr(), &physical_device.graphics_settings, &swapchain));
+        app.insert_resource(UiPainter::create(device.ptr(), &graphics_settings, &swapchain));
         app.insert_resource(LightingDataManager::new(device.ptr(), &mut descriptor_manager));
         let etna_context = EtnaContext {
             entry,
         };
         app.insert_resource(EtnaWindow {
-            winit_window: window
+            winit_window: window,
+            fullscreen_mode: FullscreenMode::Borderless,
+            windowed_placement: None,
         });
         app.insert_resource(etna_context);
         app.insert_resource(instance);
         app.insert_resource(surface);
         app.insert_resource(physical_device);
+        app.insert_resource(graphics_settings);
         app.insert_resource(device);
         app.insert_resource(command_pool);
         app.insert_resource(swapchain);
@@ -136,14 +281,95 @@ impl EcsEngine {
         self.app.update();
     }
 
-    pub fn handle_window_event(&mut self, window_event: &WindowEvent) {
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.app.world
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        self.app.world.resource::<EtnaWindow>().is_minimized()
+    }
+
+    // renders the given number of frames after the warmup and reports their timings
+    pub fn run_benchmark(&mut self, frame_count: usize) -> BenchmarkReport {
+        // nothing is drawn until the models loading in the background are in the scene
+        self.app.update();
+        while !self.app.world.resource::<PendingModels>().is_empty() {
+            self.app.update();
+        }
+        for _ in 0..frame_count + BENCHMARK_WARMUP_FRAMES + 1 {
+            self.app.update();
+        }
+        unsafe { self.app.world.resource::<LongLivedObject<Device>>().device_wait_idle() }
+            .expect("Failed to wait for the device to be idle");
+        // collect the frames still in flight when the loop ended
+        let gpu_times: Vec<f64> = match self.app.world.get_resource_mut::<GpuTimer>() {
+            Some(mut gpu_timer) => (0..MAX_FRAMES_IN_FLIGHT).filter_map(|frame_index| gpu_timer.take_milliseconds(frame_index)).collect(),
+            None => Vec::new(),
+        };
+        let mut recorder = self.app.world.resource_mut::<BenchmarkRecorder>();
+        for gpu_time in gpu_times {
+            recorder.record_gpu_time(gpu_time);
+        }
+        recorder.report()
+    }
+
+    // opens another window rendering the scene from its own camera
+    pub fn add_secondary_window(&mut self, window: Window) {
+        let world = self.app.world.cell();
+        let secondary_window = SecondaryWindow::create(
+            &world.resource::<EtnaContext>().entry,
+            &world.resource::<LongLivedObject<Instance>>(),
+            world.resource::<LongLivedObject<Device>>().ptr(),
+            &world.resource::<LongLivedObject<PhysicalDevice>>(),
+            &world.resource::<GraphicsSettings>(),
+            &world.resource::<CommandPool>(),
+            &mut world.resource_mut::<DescriptorManager>(),
+            &world.resource::<Swapchain>(),
+            window,
+        );
+        world.resource_mut::<SecondaryWindows>().add(secondary_window);
+    }
+
+    pub fn is_main_window(&self, window_id: WindowId) -> bool {
+        self.app.world.resource::<EtnaWindow>().winit_window.id() == window_id
+    }
+
+    pub fn close_secondary_window(&mut self, window_id: WindowId) {
+        let world = self.app.world.cell();
+        world.resource_mut::<SecondaryWindows>().remove(&world.resource::<LongLivedObject<Device>>(), &mut world.resource_mut::<FrameSlots>(), window_id);
+    }
+
+    pub fn handle_window_event(&mut self, window_id: WindowId, window_event: &WindowEvent) {
+        if !self.is_main_window(window_id) {
+            if let WindowEvent::Resized(_) = window_event {
+                if let Some(window) = self.app.world.resource_mut::<SecondaryWindows>().find_mut(window_id) {
+                    window.request_swapchain_recreation();
+                }
+            }
+            return;
+        }
         let world = self.app.world.cell();
         let winit_state = &mut world.non_send_resource_mut::<egui_winit::State>();
-        if let WindowEvent::KeyboardInput { input, .. } = window_event {
-            world.send_event(*input);
+        // a hidden ui gets no events, so it can't consume clicks meant for the scene
+        let egui_consumed = world.resource::<UiVisibility>().show_ui && winit_state.on_event(&world.non_send_resource::<egui::Context>(), window_event).consumed;
+        match window_event {
+            WindowEvent::KeyboardInput { input, .. } => world.send_event(*input),
+            // releases are always forwarded so buttons pressed over the scene can't get stuck down
+            WindowEvent::MouseInput { state, button, .. } if !egui_consumed || *state == ElementState::Released => {
+                world.send_event(MouseButtonInput { button: *button, state: *state });
+            }
+            WindowEvent::MouseWheel { delta, .. } if !egui_consumed => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_SCROLL_LINE,
+                };
+                world.send_event(MouseWheelInput { delta });
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                world.send_event(CursorMovedInput { position: Vec2::new(position.x as f32, position.y as f32) });
+            }
+            _ => {}
         }
-
-        let _ = winit_state.on_event(&world.non_send_resource::<egui::Context>(), window_event);
     }
 }
 
@@ -151,13 +377,31 @@ fn should_render(window: Res<EtnaWindow>) -> bool {
     !window.is_minimized()
 }
 
+fn fullscreen_toggle_system(input_state: Res<InputState>, mut window: ResMut<EtnaWindow>, mut swapchain: ResMut<Swapchain>) {
+    if input_state.is_action_down(InputAction::FullscreenModifier) && input_state.is_action_just_down(InputAction::ToggleFullscreen) {
+        // alt+shift+enter by default selects exclusive fullscreen, otherwise borderless
+        if !window.is_fullscreen() {
+            let exclusive = input_state.is_action_down(InputAction::ExclusiveFullscreen);
+            window.fullscreen_mode = if exclusive { FullscreenMode::Exclusive } else { FullscreenMode::Borderless };
+        }
+        window.toggle_fullscreen();
+        swapchain.needs_recreation = true;
+    }
+}
+
 impl Drop for EcsEngine {
     fn drop(&mut self) {
         unsafe { self.app.world.resource::<LongLivedObject<Device>>().device_wait_idle().expect("Failed to wait for the device to be idle") };
         self.app.world.remove_resource::<EguiOutput>();
         self.app.world.remove_resource::<UiPainter>();
         self.app.world.remove_resource::<LightingDataManager>();
+        self.app.world.remove_resource::<ParticleRenderer>();
+        self.app.world.remove_resource::<SecondaryWindows>();
+        self.app.world.remove_resource::<GpuTimer>();
+        self.app.world.remove_resource::<OcclusionCuller>();
+        self.app.world.remove_resource::<AutoExposure>();
         self.app.world.remove_resource::<MaterialServer>();
+        self.app.world.remove_resource::<DeletionQueue>();
         self.app.world.remove_resource::<AssetManager>();
         self.app.world.remove_resource::<CommandPool>();
         self.app.world.remove_resource::<FrameRenderContext>();
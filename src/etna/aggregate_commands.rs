@@ -19,23 +19,6 @@ pub mod image_transitions {
         pub layer_count: u32,
     }
 
-    impl TransitionProps {
-        pub const fn undefined_to_transfer_dst(mip_levels: u32) -> TransitionProps {
-            TransitionProps {
-                old_layout: vk::ImageLayout::UNDEFINED,
-                src_access_mask: vk::AccessFlags2::empty(),
-                src_stage_mask: vk::PipelineStageFlags2::TOP_OF_PIPE,
-                new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                dst_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
-                dst_stage_mask: vk::PipelineStageFlags2::TRANSFER,
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                level_count: mip_levels,
-                base_mip_level: 0,
-                layer_count: 1,
-            }
-        }
-    }
-
     pub fn transition_image_layout(device: &etna::Device, command_buffer: &vk::CommandBuffer, image: vk::Image, transition: &TransitionProps) {
         let image_memory_barrier = vk::ImageMemoryBarrier2::builder()
             .src_access_mask(transition.src_access_mask)
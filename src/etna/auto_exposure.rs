@@ -0,0 +1,180 @@
+use std::mem::size_of;
+use std::path::Path;
+
+use ash::vk;
+use bevy_ecs::prelude::*;
+use bytemuck_derive::{Pod, Zeroable};
+
+use crate::etna::{ComputePipeline, ComputePipelineCreateInfo, Device, DeviceRes, GraphicsSettings, HostMappedBuffer, HostMappedBufferCreateInfo, MAX_FRAMES_IN_FLIGHT, Swapchain};
+use crate::etna::material_pipeline::{DescriptorManager, layout_binding};
+use crate::rehnda_core::{ConstPtr, Vec4};
+
+// MUST KEEP IN SYNC WITH luminance_histogram.comp
+const HISTOGRAM_BINS: usize = 256;
+const HISTOGRAM_GROUP_SIZE: u32 = 16;
+// the average luminance of the scene is exposed to show as middle gray
+const MIDDLE_GRAY: f32 = 0.18;
+
+// MUST KEEP IN SYNC WITH luminance_histogram.comp
+#[repr(C)]
+#[derive(Zeroable, Pod, Debug, Copy, Clone)]
+struct HistogramPushConstants {
+    // x: log2 luminance of the darkest bin, y: 1 / the log2 luminance range of the bins
+    histogram_params: Vec4,
+    output_params: Vec4,
+}
+
+/// Measures the brightness of each rendered frame with a luminance histogram and eases the exposure towards it, like
+/// eyes adjusting to a change in light. The histograms are read once the frame's fence has signaled, so the exposure
+/// follows a couple of frames behind what is drawn
+#[derive(Resource)]
+pub struct AutoExposure {
+    device: ConstPtr<Device>,
+    pipeline: ComputePipeline,
+    histograms: [HostMappedBuffer; MAX_FRAMES_IN_FLIGHT],
+    // the min and max EV each frame slot's histogram covers, None when nothing was measured with the slot
+    measured_ranges: [Option<(f32, f32)>; MAX_FRAMES_IN_FLIGHT],
+    // None until the first histogram is read, which is then used as is rather than adapted to
+    adapted_ev: Option<f32>,
+}
+
+impl AutoExposure {
+    pub fn create(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager) -> Self {
+        let set_layout = descriptor_manager.layout_cache.create_descriptor_layout_for_binding(&[
+            layout_binding(0, vk::DescriptorType::SAMPLED_IMAGE, vk::ShaderStageFlags::COMPUTE),
+            layout_binding(1, vk::DescriptorType::STORAGE_BUFFER, vk::ShaderStageFlags::COMPUTE),
+        ]);
+        let push_constant = vk::PushConstantRange::builder()
+            .offset(0)
+            .size(size_of::<HistogramPushConstants>() as u32)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build();
+        let pipeline = ComputePipeline::create(device, &ComputePipelineCreateInfo {
+            shader_path: Path::new("shaders/spirv/luminance_histogram.comp_spv"),
+            set_layouts: &[set_layout],
+            push_constants: &[push_constant],
+        });
+        let histograms = std::array::from_fn(|_| HostMappedBuffer::create(device, HostMappedBufferCreateInfo {
+            size: (HISTOGRAM_BINS * size_of::<u32>()) as u64,
+            usage: vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        }));
+        Self {
+            device,
+            pipeline,
+            histograms,
+            measured_ranges: [None; MAX_FRAMES_IN_FLIGHT],
+            adapted_ev: None,
+        }
+    }
+
+    // what the scene's radiance is scaled by before tone mapping, 1.0 when auto exposure is off
+    pub fn exposure(&self, graphics_settings: &GraphicsSettings) -> f32 {
+        match self.adapted_ev {
+            Some(ev) if graphics_settings.auto_exposure => MIDDLE_GRAY / luminance_from_ev100(ev),
+            _ => 1.0,
+        }
+    }
+
+    // adapts to the histogram last measured with the frame's slot, must be called after the slot's fence has been
+    // waited on
+    pub fn begin_frame(&mut self, frame_index: usize, delta_seconds: f32, graphics_settings: &GraphicsSettings) {
+        let slot = frame_index % MAX_FRAMES_IN_FLIGHT;
+        if !graphics_settings.auto_exposure {
+            // starts from the scene as it is when turned back on rather than adapting from a stale exposure
+            self.adapted_ev = None;
+            self.measured_ranges = [None; MAX_FRAMES_IN_FLIGHT];
+            return;
+        }
+        let Some((min_ev, max_ev)) = self.measured_ranges[slot].take() else {
+            return;
+        };
+        let Some(average_ev) = self.average_ev(slot, min_ev, max_ev) else {
+            return;
+        };
+        let target_ev = average_ev.clamp(graphics_settings.auto_exposure_min_ev, graphics_settings.auto_exposure_max_ev);
+        let adapted_ev = match self.adapted_ev {
+            Some(adapted_ev) => adapted_ev + (target_ev - adapted_ev) * (1.0 - (-delta_seconds * graphics_settings.auto_exposure_adaptation_speed).exp()),
+            None => target_ev,
+        };
+        self.adapted_ev = Some(adapted_ev);
+    }
+
+    // builds the histogram of the scene image, which must be sampleable by compute shaders. output_params must be
+    // what the scene was drawn with, so its radiance can be recovered from the tone mapped colors
+    pub fn cmd_measure(&mut self, command_buffer: vk::CommandBuffer, frame_index: usize, descriptor_manager: &mut DescriptorManager, swapchain: &Swapchain, graphics_settings: &GraphicsSettings, output_params: Vec4) {
+        let slot = frame_index % MAX_FRAMES_IN_FLIGHT;
+        let histogram = &self.histograms[slot];
+        let scene_image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(swapchain.scene_image.image_view);
+        let histogram_info = vk::DescriptorBufferInfo::builder()
+            .buffer(histogram.vk_buffer())
+            .offset(0)
+            .range(histogram.size());
+        let (descriptor_set, _) = descriptor_manager.frame_descriptor_builder(frame_index)
+            .bind_image(0, scene_image_info, vk::DescriptorType::SAMPLED_IMAGE, vk::ShaderStageFlags::COMPUTE)
+            .bind_buffer(1, histogram_info, vk::DescriptorType::STORAGE_BUFFER, vk::ShaderStageFlags::COMPUTE)
+            .build()
+            .expect("Failed to allocate luminance histogram bindings");
+
+        let (min_ev, max_ev) = (graphics_settings.auto_exposure_min_ev, graphics_settings.auto_exposure_max_ev.max(graphics_settings.auto_exposure_min_ev + 1.0));
+        let min_log_luminance = luminance_from_ev100(min_ev).log2();
+        let max_log_luminance = luminance_from_ev100(max_ev).log2();
+        let push_constants = HistogramPushConstants {
+            histogram_params: Vec4::new(min_log_luminance, 1.0 / (max_log_luminance - min_log_luminance), 0.0, 0.0),
+            output_params,
+        };
+        let extent = swapchain.render_extent();
+        unsafe {
+            self.device.cmd_fill_buffer(command_buffer, histogram.vk_buffer(), 0, vk::WHOLE_SIZE, 0);
+            let clear_before_counting = vk::MemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                .build();
+            self.device.cmd_pipeline_barrier2(command_buffer, &vk::DependencyInfo::builder().memory_barriers(std::slice::from_ref(&clear_before_counting)));
+
+            self.pipeline.cmd_bind(command_buffer);
+            self.device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline.pipeline_layout, 0, std::slice::from_ref(&descriptor_set), &[]);
+            self.device.cmd_push_constants(command_buffer, self.pipeline.pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, bytemuck::bytes_of(&push_constants));
+            self.device.cmd_dispatch(command_buffer, extent.width.div_ceil(HISTOGRAM_GROUP_SIZE), extent.height.div_ceil(HISTOGRAM_GROUP_SIZE), 1);
+
+            let counts_before_host_read = vk::MemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+                .dst_access_mask(vk::AccessFlags2::HOST_READ)
+                .build();
+            self.device.cmd_pipeline_barrier2(command_buffer, &vk::DependencyInfo::builder().memory_barriers(std::slice::from_ref(&counts_before_host_read)));
+        }
+        self.measured_ranges[slot] = Some((min_ev, max_ev));
+    }
+
+    // the mean log luminance of the measured pixels as EV100, None if every pixel was too dark to measure
+    fn average_ev(&self, slot: usize, min_ev: f32, max_ev: f32) -> Option<f32> {
+        let bins: &[u32] = bytemuck::cast_slice(self.histograms[slot].read_data());
+        // bin 0 counts the pixels too dark to measure, which would otherwise drag the average down to black
+        let mut pixel_count = 0u64;
+        let mut weighted_position = 0.0f64;
+        for (bin, &count) in bins.iter().enumerate().skip(1) {
+            let position = (bin as f64 - 0.5) / (HISTOGRAM_BINS - 2) as f64;
+            pixel_count += count as u64;
+            weighted_position += position * count as f64;
+        }
+        if pixel_count == 0 {
+            return None;
+        }
+        let average_position = (weighted_position / pixel_count as f64) as f32;
+        Some(min_ev + average_position.min(1.0) * (max_ev - min_ev))
+    }
+}
+
+// the average luminance a light meter calibrated to 12.5 reads as the given exposure value at ISO 100
+fn luminance_from_ev100(ev100: f32) -> f32 {
+    2.0f32.powf(ev100) * 12.5 / 100.0
+}
+
+pub fn auto_exposure_startup_system(mut commands: Commands, device: DeviceRes, mut descriptor_manager: ResMut<DescriptorManager>) {
+    commands.insert_resource(AutoExposure::create(device.ptr(), &mut descriptor_manager));
+}
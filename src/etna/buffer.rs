@@ -7,6 +7,7 @@ use gpu_allocator::MemoryLocation;
 use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
 
 use crate::etna;
+use crate::etna::OneTimeCommandBuffer;
 use crate::rehnda_core::ConstPtr;
 
 pub struct Buffer {
@@ -41,12 +42,53 @@ impl Buffer {
     }
 
     pub fn create_and_initialize_buffer_with_staging_buffer(device: ConstPtr<etna::Device>, command_pool: &etna::CommandPool, create_info: BufferCreateInfo) -> Buffer {
-        let mut buffer = Self::create_empty_buffer(device, create_info.data.len() as u64, create_info.usage | vk::BufferUsageFlags::TRANSFER_DST, MemoryLocation::GpuOnly);
-        buffer.populate_buffer_using_staging_buffer(command_pool, create_info.data);
+        let mut command_buffer = command_pool.one_time_command_buffer();
+        let buffer = Self::record_staged_upload(device, &mut command_buffer, create_info);
+        command_buffer.submit_and_wait().expect("Failed to upload buffer");
         buffer
     }
 
-    fn populate_buffer_using_staging_buffer(&mut self, command_pool: &etna::CommandPool, data: &[u8]) {
+    // records the copy from a staging buffer without submitting, so several uploads can share one submit. The buffer
+    // can't be used until the command buffer has been submitted
+    pub fn record_staged_upload(device: ConstPtr<etna::Device>, command_buffer: &mut OneTimeCommandBuffer, create_info: BufferCreateInfo) -> Buffer {
+        let buffer = Self::create_empty_buffer(device, create_info.data.len() as u64, create_info.usage | vk::BufferUsageFlags::TRANSFER_DST, MemoryLocation::GpuOnly);
+        buffer.record_copy_from_staging_buffer(command_buffer, create_info.data);
+        buffer
+    }
+
+    // overwrites the start of a buffer made with a staging upload while it may still be in use. The copy waits for the
+    // previously submitted work reading it and later submissions see the new data, so descriptors using it needn't change
+    pub fn update_with_staging_buffer(&self, command_pool: &etna::CommandPool, data: &[u8]) {
+        let mut command_buffer = command_pool.one_time_command_buffer();
+        let reads_before_write = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .build();
+        unsafe { self.device.cmd_pipeline_barrier2(*command_buffer, &vk::DependencyInfo::builder().memory_barriers(std::slice::from_ref(&reads_before_write))) };
+        self.record_copy_from_staging_buffer(&mut command_buffer, data);
+        let write_before_reads = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+            .build();
+        unsafe { self.device.cmd_pipeline_barrier2(*command_buffer, &vk::DependencyInfo::builder().memory_barriers(std::slice::from_ref(&write_before_reads))) };
+        command_buffer.submit_and_wait().expect("Failed to update buffer");
+    }
+
+    // a host visible buffer that images and buffers can be copied into to read them on the cpu
+    pub fn create_readback_buffer(device: ConstPtr<etna::Device>, size: u64) -> Buffer {
+        Self::create_empty_buffer(device, size, vk::BufferUsageFlags::TRANSFER_DST, MemoryLocation::GpuToCpu)
+    }
+
+    // only valid for host visible buffers once the writes to them have completed
+    pub fn read_data(&self) -> &[u8] {
+        self.allocation.mapped_slice().expect("Buffer is not host visible")
+    }
+
+    fn record_copy_from_staging_buffer(&self, command_buffer: &mut OneTimeCommandBuffer, data: &[u8]) {
         let staging_buffer = Self::create_empty_buffer(
             self.device,
             self.size,
@@ -57,14 +99,13 @@ impl Buffer {
         let staging_buffer_memory = staging_buffer.allocation.mapped_ptr().unwrap().as_ptr();
         unsafe { staging_buffer_memory.copy_from_nonoverlapping(data.as_ptr() as *const c_void, data.len()); }
 
-        let command_buffer = command_pool.one_time_command_buffer();
-
         let copy_region = [
             vk::BufferCopy::builder()
                 .size(self.size)
                 .build()
         ];
-        unsafe { self.device.cmd_copy_buffer(*command_buffer, staging_buffer.buffer, self.buffer, &copy_region); }
+        unsafe { self.device.cmd_copy_buffer(**command_buffer, staging_buffer.buffer, self.buffer, &copy_region); }
+        command_buffer.keep_alive_until_submitted(staging_buffer);
     }
 
     fn create_empty_buffer(device: ConstPtr<etna::Device>, size: u64, usage: vk::BufferUsageFlags, memory_location: MemoryLocation) -> Buffer {
@@ -126,6 +167,16 @@ impl HostMappedBuffer {
         unsafe { self.mapped_memory.as_ptr().copy_from_nonoverlapping(data.as_ptr() as *const c_void, data.len()); }
     }
 
+    pub fn write_data_at(&self, offset: u64, data: &[u8]) {
+        assert!(offset + data.len() as u64 <= self.buffer.size, "Write past the end of a host mapped buffer");
+        unsafe { self.mapped_memory.as_ptr().add(offset as usize).copy_from_nonoverlapping(data.as_ptr() as *const c_void, data.len()); }
+    }
+
+    // only valid once the gpu's writes to it have completed and been made visible to the host
+    pub fn read_data(&self) -> &[u8] {
+        self.buffer.read_data()
+    }
+
     pub fn size(&self) -> u64 {
         self.buffer.size
     }
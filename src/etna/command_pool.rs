@@ -1,15 +1,21 @@
+use std::any::Any;
 use std::ops::Deref;
 
+use ash::prelude::VkResult;
 use ash::vk;
 use bevy_ecs::system::Resource;
+use log::error;
 
 use crate::rehnda_core::ConstPtr;
 use crate::etna;
 
+/// Command pools can't be used from more than one thread at a time, so each thread that records commands needs its
+/// own. This resource is the main thread's pool
 #[derive(Resource)]
 pub struct CommandPool {
     device: ConstPtr<etna::Device>,
     command_pool: vk::CommandPool,
+    queue_family_index: u32,
 }
 
 impl CommandPool {
@@ -23,9 +29,30 @@ impl CommandPool {
         CommandPool {
             device,
             command_pool,
+            queue_family_index,
         }
     }
 
+    // another pool on the same queue family, for recording on a different thread
+    pub fn create_sibling(&self) -> CommandPool {
+        Self::create(self.device, self.queue_family_index)
+    }
+
+    // resets every command buffer allocated from the pool, none of them can be pending execution
+    pub fn reset(&self) {
+        unsafe { self.device.reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty()) }
+            .expect("Failed to reset command pool");
+    }
+
+    pub fn allocate_secondary_command_buffers(&self, num_command_buffers: u32) -> Vec<vk::CommandBuffer> {
+        let command_buffer_alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .command_buffer_count(num_command_buffers)
+            .level(vk::CommandBufferLevel::SECONDARY);
+        unsafe { self.device.allocate_command_buffers(&command_buffer_alloc_info) }
+            .expect("Failed to allocation secondary command buffer")
+    }
+
     pub fn allocate_command_buffers(&self, num_command_buffers: u32) -> Vec<vk::CommandBuffer> {
         let command_buffer_alloc_info = vk::CommandBufferAllocateInfo::builder()
             .command_pool(self.command_pool)
@@ -48,10 +75,16 @@ impl Drop for CommandPool {
     }
 }
 
+/// A primary command buffer that is submitted once and waited on. Any number of recordings can be batched into it
+/// before [OneTimeCommandBuffer::submit_and_wait], with their staging resources kept alive until the submit completes.
+/// Dropping it without submitting submits it, logging rather than returning any error
 pub struct OneTimeCommandBuffer {
     device: ConstPtr<etna::Device>,
     command_buffer: vk::CommandBuffer,
     owning_command_pool: vk::CommandPool,
+    // resources the recorded commands read from, dropped once the gpu is done with them
+    kept_alive: Vec<Box<dyn Any>>,
+    submitted: bool,
 }
 
 impl Deref for OneTimeCommandBuffer {
@@ -80,28 +113,54 @@ impl OneTimeCommandBuffer {
             device,
             command_buffer,
             owning_command_pool,
+            kept_alive: Vec::new(),
+            submitted: false,
         }
     }
 
-    fn end(&mut self) {
-        unsafe {
+    // holds on to a resource used by the recorded commands, such as a staging buffer, until they have executed
+    pub fn keep_alive_until_submitted(&mut self, resource: impl Any) {
+        self.kept_alive.push(Box::new(resource));
+    }
+
+    // submits everything recorded and waits for it to finish executing, returning the error if the submit or the wait
+    // failed, such as the device being lost or running out of memory
+    pub fn submit_and_wait(mut self) -> VkResult<()> {
+        self.submit()
+    }
+
+    fn submit(&mut self) -> VkResult<()> {
+        self.submitted = true;
+        let command_buffers = [self.command_buffer];
+        let result = unsafe {
             self.device.end_command_buffer(self.command_buffer)
-                .expect("Failed to end one time command buffer");
-            let command_buffers = &[self.command_buffer];
-            let submit_info = [vk::SubmitInfo::builder()
-                .command_buffers(command_buffers)
-                .build()];
-            self.device.queue_submit(self.device.graphics_queue, &submit_info, vk::Fence::null())
-                .expect("Failed to submit one time command buffer to queue");
-            self.device.queue_wait_idle(self.device.graphics_queue)
-                .expect("failed to wait for graphics queue idle");
-            self.device.free_command_buffers(self.owning_command_pool, command_buffers);
-        }
+                .and_then(|_| self.device.create_fence(&vk::FenceCreateInfo::default(), None))
+                .and_then(|fence| {
+                    let submit_info = [vk::SubmitInfo::builder()
+                        .command_buffers(&command_buffers)
+                        .build()];
+                    // waiting on a fence rather than the queue only waits for this submission
+                    let result = self.device.queue_submit(self.device.graphics_queue, &submit_info, fence)
+                        .and_then(|_| self.device.wait_for_fences(&[fence], true, u64::MAX));
+                    self.device.destroy_fence(fence, None);
+                    result
+                })
+        };
+        // if the submit failed the commands never run, and if the wait failed the device is lost, so in either case
+        // nothing is still reading from the command buffer or the resources
+        unsafe { self.device.free_command_buffers(self.owning_command_pool, &command_buffers); }
+        self.kept_alive.clear();
+        result
     }
 }
 
 impl Drop for OneTimeCommandBuffer {
     fn drop(&mut self) {
-        self.end();
+        if self.submitted {
+            return;
+        }
+        if let Err(err) = self.submit() {
+            error!("Failed to submit one time command buffer: {}", err);
+        }
     }
-}
\ No newline at end of file
+}
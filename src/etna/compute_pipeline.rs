@@ -0,0 +1,66 @@
+use std::ffi::CString;
+use std::path::Path;
+
+use ash::vk;
+
+use crate::etna::Device;
+use crate::etna::shader::ShaderModule;
+use crate::rehnda_core::ConstPtr;
+
+pub struct ComputePipelineCreateInfo<'a> {
+    pub shader_path: &'a Path,
+    pub set_layouts: &'a [vk::DescriptorSetLayout],
+    pub push_constants: &'a [vk::PushConstantRange],
+}
+
+/// A compute shader ready to be dispatched, with the layout of the sets and push constants it uses
+pub struct ComputePipeline {
+    device: ConstPtr<Device>,
+    pub pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            // layouts are destroyed by the layout cache
+        }
+    }
+}
+
+impl ComputePipeline {
+    pub fn create(device: ConstPtr<Device>, create_info: &ComputePipelineCreateInfo) -> Self {
+        let shader_module = ShaderModule::load_from_file(device, create_info.shader_path);
+        let main_function_name = CString::new("main").unwrap();
+        let shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module.handle())
+            .name(main_function_name.as_c_str())
+            .build();
+
+        let pipeline_layout_ci = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(create_info.set_layouts)
+            .push_constant_ranges(create_info.push_constants);
+        let pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_ci, None) }
+            .expect("Failed to create compute pipeline layout");
+
+        let pipeline_ci = vk::ComputePipelineCreateInfo::builder()
+            .stage(shader_stage_ci)
+            .layout(pipeline_layout)
+            .build();
+        let pipeline = unsafe { device.create_compute_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&pipeline_ci), None) }
+            .expect("Failed to create compute pipeline")[0];
+
+        Self {
+            device,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn cmd_bind(&self, command_buffer: vk::CommandBuffer) {
+        unsafe { self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline) };
+    }
+}
@@ -1,41 +1,103 @@
 use std::ffi::{c_void, CStr};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use ash::extensions::ext;
 use ash::{Entry, vk};
 use log::{debug, error, info, warn};
 
+static VALIDATION_ERROR_COUNT: AtomicU32 = AtomicU32::new(0);
+static VALIDATION_WARNING_COUNT: AtomicU32 = AtomicU32::new(0);
+static VALIDATION_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub const VALIDATION_LAYER_NAME: &str = "VK_LAYER_KHRONOS_validation";
+// set to 1 to validate a release build, or to 0 to skip validation in a debug build
+pub const VALIDATION_ENV_VAR: &str = "REHNDA_VALIDATION";
+
+// receives every message that passes the filters, with its severity, type and text. Messages can arrive from any
+// thread using the device
+pub type ValidationCallback = Box<dyn Fn(vk::DebugUtilsMessageSeverityFlagsEXT, vk::DebugUtilsMessageTypeFlagsEXT, &str) + Send + Sync>;
+
+/// Whether the validation layers are enabled and which of their messages are reported and how. Without validation
+/// neither the layers, the debug utils extension nor the messenger are created
+pub struct ValidationSettings {
+    pub enabled: bool,
+    pub severities: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+    // replaces logging the messages, they are still counted
+    pub callback: Option<ValidationCallback>,
+}
+
+impl Default for ValidationSettings {
+    // verbose, info and performance messages are mostly noise, so are left out unless asked for
+    fn default() -> Self {
+        Self {
+            enabled: validation_requested(),
+            severities: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            message_types: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            callback: None,
+        }
+    }
+}
+
+// debug builds validate unless the environment variable turns it off, release builds only when it turns it on
+fn validation_requested() -> bool {
+    match std::env::var(VALIDATION_ENV_VAR).as_deref() {
+        Ok("1") | Ok("true") => true,
+        Ok("0") | Ok("false") => false,
+        Ok(value) => {
+            warn!("Ignoring {}={}, expected 1 or 0", VALIDATION_ENV_VAR, value);
+            cfg!(debug_assertions)
+        }
+        Err(_) => cfg!(debug_assertions),
+    }
+}
+
+// true once the messenger is created, until then no messages can be counted
+pub fn is_validation_active() -> bool {
+    VALIDATION_ACTIVE.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ValidationMessageCounts {
+    pub errors: u32,
+    pub warnings: u32,
+}
+
+// the validation errors and warnings reported since startup
+pub fn validation_message_counts() -> ValidationMessageCounts {
+    ValidationMessageCounts {
+        errors: VALIDATION_ERROR_COUNT.load(Ordering::Relaxed),
+        warnings: VALIDATION_WARNING_COUNT.load(Ordering::Relaxed),
+    }
+}
+
 pub struct DebugLayer {
     debug_utils_loader: ext::DebugUtils,
     debug_messenger: vk::DebugUtilsMessengerEXT,
 }
 
 impl DebugLayer {
-    pub fn init(entry: &Entry, instance: &ash::Instance) -> DebugLayer {
+    // the user data is the custom callback from debug_messenger_create_info, which must outlive the layer
+    pub fn init(entry: &Entry, instance: &ash::Instance, validation_settings: &ValidationSettings, user_data: *mut c_void) -> DebugLayer {
         let debug_utils_loader = ext::DebugUtils::new(entry, instance);
-        let messenger_create_info = DebugLayer::debug_messenger_create_info();
+        let messenger_create_info = DebugLayer::debug_messenger_create_info(validation_settings, user_data);
         let debug_messenger = unsafe {
             debug_utils_loader.create_debug_utils_messenger(&messenger_create_info, None)
                 .expect("Failed to create debug utils callback")
         };
+        VALIDATION_ACTIVE.store(true, Ordering::Relaxed);
         DebugLayer {
             debug_utils_loader,
             debug_messenger,
         }
     }
 
-    pub fn debug_messenger_create_info() -> vk::DebugUtilsMessengerCreateInfoEXT {
+    // the user data is null or points to the ValidationCallback that replaces logging
+    pub fn debug_messenger_create_info(validation_settings: &ValidationSettings, user_data: *mut c_void) -> vk::DebugUtilsMessengerCreateInfoEXT {
         vk::DebugUtilsMessengerCreateInfoEXT::builder()
-            .message_severity(
-                // vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE |
-                // vk::DebugUtilsMessageSeverityFlagsEXT::INFO |
-                vk::DebugUtilsMessageSeverityFlagsEXT::WARNING |
-                    vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-            )
-            .message_type(
-                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL |
-                    vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE |
-                    vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-            )
+            .message_severity(validation_settings.severities)
+            .message_type(validation_settings.message_types)
             .pfn_user_callback(Some(vulkan_debug_callback))
+            .user_data(user_data)
             .build()
     }
 }
@@ -45,6 +107,7 @@ impl Drop for DebugLayer {
         unsafe {
             self.debug_utils_loader.destroy_debug_utils_messenger(self.debug_messenger, None);
         }
+        VALIDATION_ACTIVE.store(false, Ordering::Relaxed);
     }
 }
 
@@ -53,8 +116,9 @@ unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut c_void,
+    user_data: *mut c_void,
 ) -> vk::Bool32 {
+    let message_type_flags = message_type;
     let message_type = match message_type {
         vk::DebugUtilsMessageTypeFlagsEXT::GENERAL => "General",
         vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE => "Performance",
@@ -68,6 +132,16 @@ unsafe extern "system" fn vulkan_debug_callback(
     if message.to_str().unwrap().contains("vkGetPhysicalDeviceProperties2KHR: Emulation found unrecognized structure type in pProperties->pNext - this struct will be ignored") {
         return vk::FALSE
     }
+    match message_severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => VALIDATION_WARNING_COUNT.fetch_add(1, Ordering::Relaxed),
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => VALIDATION_ERROR_COUNT.fetch_add(1, Ordering::Relaxed),
+        _ => 0,
+    };
+    if !user_data.is_null() {
+        let callback = &*(user_data as *const ValidationCallback);
+        callback(message_severity, message_type_flags, &message.to_string_lossy());
+        return vk::FALSE;
+    }
     match message_severity {
         vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => debug!("[Vk-{}] {:?}", message_type, message),
         vk::DebugUtilsMessageSeverityFlagsEXT::INFO => info!("[Vk-{}] {:?}", message_type, message),
@@ -0,0 +1,31 @@
+use std::any::Any;
+use std::collections::VecDeque;
+
+use bevy_ecs::system::Resource;
+
+use crate::etna::MAX_FRAMES_IN_FLIGHT;
+
+/// Holds on to resources that may still be referenced by in flight command buffers,
+/// dropping them once every frame that could have used them has finished
+#[derive(Resource, Default)]
+pub struct DeletionQueue {
+    current_frame: usize,
+    pending_deletions: VecDeque<(usize, Box<dyn Any + Send + Sync>)>,
+}
+
+impl DeletionQueue {
+    pub fn queue_deletion<T: Any + Send + Sync>(&mut self, resource: T) {
+        self.pending_deletions.push_back((self.current_frame, Box::new(resource)));
+    }
+
+    // must be called after waiting on the in flight fence for the frame
+    pub fn begin_frame(&mut self, frame: usize) {
+        self.current_frame = frame;
+        while let Some((queued_frame, _)) = self.pending_deletions.front() {
+            if queued_frame + MAX_FRAMES_IN_FLIGHT > frame {
+                break;
+            }
+            self.pending_deletions.pop_front();
+        }
+    }
+}
@@ -10,7 +10,7 @@ use gpu_allocator::AllocatorDebugSettings;
 use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, Allocator, AllocatorCreateDesc};
 
 use crate::etna;
-use crate::etna::{DEVICE_EXTENSIONS, VALIDATION_LAYERS};
+use crate::etna::DEVICE_EXTENSIONS;
 use crate::rehnda_core::LongLivedObject;
 
 pub type DeviceRes<'w> = Res<'w, LongLivedObject<Device>>;
@@ -20,6 +20,8 @@ pub struct Device {
     device: ash::Device,
     pub allocator: ManuallyDrop<UnsafeCell<Allocator>>,
     pub enabled_features: vk::PhysicalDeviceFeatures,
+    // the widths lines can be drawn with, only 1.0 without the wide lines feature
+    pub line_width_range: [f32; 2],
     pub graphics_queue: vk::Queue,
     pub present_queue: vk::Queue,
 }
@@ -33,7 +35,7 @@ impl Deref for Device {
 }
 
 impl Device {
-    pub fn create(instance: &etna::Instance, surface: &etna::Surface, physical_device: &etna::PhysicalDevice) -> Device {
+    pub fn create(instance: &etna::Instance, surface: &etna::Surface, physical_device: &etna::PhysicalDevice, graphics_settings: &etna::GraphicsSettings) -> Device {
         let queue_indices = instance.find_queue_families(surface, physical_device.handle());
         let graphics_family_queue_index = queue_indices.graphics_family.expect("Graphics family must be available");
         let present_family_queue_index = queue_indices.present_family.expect("Present family must be available");
@@ -47,7 +49,6 @@ impl Device {
             .queue_family_index(*unique_queue_family_index)
             .queue_priorities(&[1.0]).build())
             .collect();
-        let validation_layer_names = VALIDATION_LAYERS.map(|layer| layer.as_ptr() as *const c_char);
         let device_extension_names = DEVICE_EXTENSIONS.map(|extension| extension.as_ptr() as *const c_char);
         // enable dynamic rendering
         let mut dynamic_rendering_feature = vk::PhysicalDeviceDynamicRenderingFeatures::builder()
@@ -59,17 +60,27 @@ impl Device {
         let mut buffer_device_address_feature = vk::PhysicalDeviceBufferDeviceAddressFeatures::builder()
             .buffer_device_address(true)
             .build();
+        let bindless_textures = graphics_settings.bindless_textures;
+        let mut descriptor_indexing_feature = vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+            .shader_sampled_image_array_non_uniform_indexing(bindless_textures)
+            .descriptor_binding_sampled_image_update_after_bind(bindless_textures)
+            .descriptor_binding_partially_bound(bindless_textures)
+            .runtime_descriptor_array(bindless_textures)
+            .build();
         let physical_device_features = vk::PhysicalDeviceFeatures::builder()
             .sampler_anisotropy(physical_device.supported_features.sampler_anisotropy == vk::TRUE)
-            .sample_rate_shading(physical_device.graphics_settings.sample_rate_shading_enabled);
+            .sample_rate_shading(physical_device.supported_features.sample_rate_shading == vk::TRUE)
+            .wide_lines(physical_device.supported_features.wide_lines == vk::TRUE)
+            .depth_clamp(physical_device.supported_features.depth_clamp == vk::TRUE)
+            .fill_mode_non_solid(physical_device.supported_features.fill_mode_non_solid == vk::TRUE);
         let device_create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(queue_create_infos.as_slice())
-            .enabled_layer_names(validation_layer_names.as_slice())
             .enabled_extension_names(device_extension_names.as_slice())
             .enabled_features(&physical_device_features)
             .push_next(&mut dynamic_rendering_feature)
             .push_next(&mut synchronization_2_feature)
-            .push_next(&mut buffer_device_address_feature);
+            .push_next(&mut buffer_device_address_feature)
+            .push_next(&mut descriptor_indexing_feature);
 
 
         let device = unsafe { (*instance).create_device(physical_device.handle(), &device_create_info, None) }
@@ -94,15 +105,39 @@ impl Device {
         })
             .expect("Failed to create allocator");
 
+        let line_width_range = if physical_device_features.wide_lines == vk::TRUE {
+            physical_device.device_properties.limits.line_width_range
+        } else {
+            [1.0, 1.0]
+        };
+
         Device {
             device,
             enabled_features: physical_device_features.build(),
+            line_width_range,
             graphics_queue,
             present_queue,
             allocator: ManuallyDrop::new(UnsafeCell::new(allocator)),
         }
     }
 
+    pub fn supports_wide_lines(&self) -> bool {
+        self.enabled_features.wide_lines == vk::TRUE
+    }
+
+    pub fn supports_depth_clamp(&self) -> bool {
+        self.enabled_features.depth_clamp == vk::TRUE
+    }
+
+    // needed to draw polygons as wireframes
+    pub fn supports_fill_mode_non_solid(&self) -> bool {
+        self.enabled_features.fill_mode_non_solid == vk::TRUE
+    }
+
+    pub fn clamp_line_width(&self, line_width: f32) -> f32 {
+        line_width.clamp(self.line_width_range[0], self.line_width_range[1])
+    }
+
     pub fn allocate(&self, allocation_desc: &AllocationCreateDesc) -> gpu_allocator::Result<Allocation> {
          unsafe { (*self.allocator.get()).allocate(allocation_desc) }
     }
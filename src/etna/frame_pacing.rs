@@ -0,0 +1,29 @@
+use std::time::{Duration, Instant};
+
+use bevy_ecs::prelude::*;
+use bevy_time::Time;
+
+use crate::etna::GraphicsSettings;
+
+// sleeping can overshoot by around a millisecond, so the end of the wait spins instead
+const SPIN_DURATION: Duration = Duration::from_millis(1);
+
+// holds the frame until the frame rate cap's frame duration has passed since the frame began, doing nothing when
+// uncapped or when the frame already took longer
+pub fn frame_pacing_system(time: Res<Time>, graphics_settings: Res<GraphicsSettings>) {
+    let frame_rate_cap = graphics_settings.frame_rate_cap;
+    if frame_rate_cap == 0 {
+        return;
+    }
+    let Some(frame_start) = time.last_update() else {
+        return;
+    };
+    let frame_end = frame_start + Duration::from_secs_f64(1.0 / frame_rate_cap as f64);
+    let sleep_duration = frame_end.saturating_duration_since(Instant::now()).saturating_sub(SPIN_DURATION);
+    if !sleep_duration.is_zero() {
+        std::thread::sleep(sleep_duration);
+    }
+    while Instant::now() < frame_end {
+        std::hint::spin_loop();
+    }
+}
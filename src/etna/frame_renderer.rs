@@ -3,26 +3,127 @@ use std::mem::size_of;
 
 use ash::vk;
 use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
 use bevy_hierarchy::Children;
+use bevy_time::Time;
+use bytemuck_derive::{Pod, Zeroable};
+use log::warn;
 
-use crate::etna::{CommandPool, Device, HostMappedBuffer, HostMappedBufferCreateInfo, image_transitions, PhysicalDeviceRes, Swapchain, SwapchainResult, vkinit};
-use crate::etna::material_pipeline::{DescriptorManager, MaterialPipeline, ModelPushConstants};
-use crate::rehnda_core::{ConstPtr, Mat4};
-use crate::assets::{AssetManager, Camera, cube, MeshHandle, ViewProjectionMatrices};
+use crate::etna::{AutoExposure, CommandBufferReuse, CommandPool, DeletionQueue, DepthBuffer, Device, GpuTimer, GraphicsSettings, GraphImage, HostMappedBuffer, HostMappedBufferCreateInfo, ImageAccess, OcclusionCandidate, OcclusionCuller, PassContext, PhysicalDeviceRes, RenderDoc, RenderGraph, Swapchain, SwapchainResult, vkinit};
+use crate::etna::material_pipeline::{DescriptorManager, MaterialBindings, MaterialPipeline, ModelPushConstants};
+use crate::rehnda_core::{ConstPtr, Mat4, Vec3, Vec4};
+use crate::rehnda_core::input::InputState;
+use crate::rehnda_core::input_bindings::InputAction;
+use crate::assets::{AssetManager, Camera, cube, DepthPick, MeshHandle, ViewProjectionMatrices};
 use crate::assets::demo_scenes::Actor;
-use crate::assets::light_source::LightingDataManager;
+use crate::assets::light_source::{EnvironmentSettings, Fog, LightingDataManager};
 use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer};
-use crate::assets::render_object::{MaterialHandle, Mesh, PbrMaterial, RenderObject, Transform};
+use crate::assets::particles::{Emitter, ParticleRenderer};
+use crate::assets::world_labels::LabelRenderer;
+use crate::assets::gizmos::GizmoRenderer;
+use crate::assets::material_animation::AnimatedMaterials;
+use crate::assets::outline::OutlineRenderer;
+use crate::assets::wireframe::{ShowWireframe, WireframeRenderer};
+use crate::assets::render_object::{actor_render_object_entities, MaterialHandle, Mesh, MeshGroup, PbrMaterial, RenderObject, Transform};
 use crate::etna::cube_map::EnvironmentMaps;
-use crate::ui::{EguiOutput, UiPainter};
+use crate::ui::{EguiOutput, UiPainter, UiVisibility};
 
-const MAX_FRAMES_IN_FLIGHT: usize = 2;
+pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
 
 #[derive(Resource)]
 pub struct FrameRenderContext {
     device: ConstPtr<Device>,
     frame_data: [FrameData; MAX_FRAMES_IN_FLIGHT],
     current_frame: usize,
+    renderdoc: Option<RenderDoc>,
+    capture_next_frame: bool,
+    // what each frame data's scene commands were recorded from, None when they can't be reused
+    recorded_scenes: [Option<RecordedScene>; MAX_FRAMES_IN_FLIGHT],
+    // bumped whenever the objects the scene commands are recorded from change
+    scene_version: u64,
+    // what the main window last scaled the scene's radiance by, which the other windows draw with too so they match
+    exposure: f32,
+}
+
+// everything baked into the main window's scene commands, they're stale once any of it differs
+#[derive(PartialEq)]
+struct RecordedScene {
+    scene_version: u64,
+    swapchain_generation: u64,
+    material_generation: u64,
+    graphics_settings: GraphicsSettings,
+    // the animated material offsets are baked into the commands
+    frame_slot: usize,
+}
+
+/// Numbers the frames of every window together, so the per frame buffers they share such as the animated materials,
+/// particles and the deletion queue are only rewritten once the last frame in the same slot has finished
+#[derive(Resource, Default)]
+pub struct FrameSlots {
+    current_frame: usize,
+    // the fence of the last frame submitted in each slot, null until a frame has used it
+    submitted_fences: [vk::Fence; MAX_FRAMES_IN_FLIGHT],
+}
+
+impl FrameSlots {
+    // must be called before the window's own fence is reset, as it may be the one waited on
+    fn wait_for_slot(&self, device: &Device) -> usize {
+        let fence = self.submitted_fences[self.current_frame % MAX_FRAMES_IN_FLIGHT];
+        if fence != vk::Fence::null() {
+            unsafe { device.wait_for_fences(&[fence], true, u64::MAX) }
+                .expect("Failed to wait for the frame slot's fence");
+        }
+        self.current_frame
+    }
+
+    fn end_frame(&mut self, fence: vk::Fence) {
+        self.submitted_fences[self.current_frame % MAX_FRAMES_IN_FLIGHT] = fence;
+        self.current_frame += 1;
+    }
+
+    // the fences may belong to a window that is being destroyed, so the device must be idle first
+    pub fn forget_fences(&mut self) {
+        self.submitted_fences = [vk::Fence::null(); MAX_FRAMES_IN_FLIGHT];
+    }
+}
+
+/// What every window's frames share, indexed by the frame slot rather than the window's own frame
+#[derive(SystemParam)]
+pub struct SharedFrameResources<'w> {
+    frame_slots: ResMut<'w, FrameSlots>,
+    deletion_queue: ResMut<'w, DeletionQueue>,
+    descriptor_manager: ResMut<'w, DescriptorManager>,
+}
+
+impl SharedFrameResources<'_> {
+    // waits for the frame slot to be free and returns it, freeing what the last frame in it was still using
+    fn begin_frame(&mut self, device: &Device) -> usize {
+        let frame_slot = self.frame_slots.wait_for_slot(device);
+        self.deletion_queue.begin_frame(frame_slot);
+        self.descriptor_manager.begin_frame(frame_slot);
+        frame_slot
+    }
+}
+
+/// What the scene pass drew last frame, for judging what a scene costs. Only the main window's frames are counted
+#[derive(Resource, Default, Debug, Copy, Clone)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    // only triangle list meshes count towards this
+    pub triangles: u64,
+    pub visible_objects: u32,
+    // hidden by the occlusion culler, always zero while it's disabled
+    pub culled_objects: u32,
+}
+
+impl RenderStats {
+    fn add_draw(&mut self, mesh: &Mesh) {
+        self.draw_calls += 1;
+        self.visible_objects += 1;
+        if mesh.topology == vk::PrimitiveTopology::TRIANGLE_LIST {
+            self.triangles += (mesh.index_count / 3) as u64;
+        }
+    }
 }
 
 struct FrameData {
@@ -30,9 +131,43 @@ struct FrameData {
     render_finished_semaphore: vk::Semaphore,
     in_flight_fence: vk::Fence,
     command_buffer: vk::CommandBuffer,
+    // the main window records the upscale and ui in here every frame, so the scene's commands can be reused
+    present_command_buffer: vk::CommandBuffer,
 
     global_data: HostMappedBuffer,
     global_descriptor: vk::DescriptorSet,
+    // empty when the scene is recorded inline on the main thread
+    recording_workers: Vec<RecordingWorker>,
+}
+
+// records part of the scene on its own thread, so needs its own command pool
+struct RecordingWorker {
+    command_pool: CommandPool,
+    command_buffer: vk::CommandBuffer,
+}
+
+#[repr(C)]
+#[derive(Zeroable, Pod, Debug, Copy, Clone)]
+pub struct GlobalUniformData {
+    pub view_projection: ViewProjectionMatrices,
+    // x: environment intensity, y: environment yaw rotation in radians
+    pub environment_params: Vec4,
+    // x: logarithmic depth enabled, y: far plane, z: reverse z enabled
+    pub depth_params: Vec4,
+    // rgb: fog color, w: fog mode
+    pub fog_color: Vec4,
+    // x: density, y: linear start distance, z: linear end distance
+    pub fog_params: Vec4,
+    // x: debug render mode, y: normal mapping enabled. Kept here as the model push constants already fill the
+    // minimum guaranteed push constant size
+    pub debug_params: Vec4,
+    // rgb: ambient light used without an environment, w: environment loaded
+    pub ambient_params: Vec4,
+    // x: output encoding, y: paper white in nits, z: peak luminance in nits, both only used by hdr encodings,
+    // w: exposure applied before tone mapping
+    pub output_params: Vec4,
+    // x: seconds since startup, wrapping every hour to keep its precision, yz: viewport size in pixels, w: light count
+    pub frame_params: Vec4,
 }
 
 impl Debug for FrameData {
@@ -41,127 +176,669 @@ impl Debug for FrameData {
     }
 }
 
+/// Everything needed to record the scene, shared by every window that renders it
+#[derive(SystemParam)]
+pub struct SceneRenderData<'w, 's> {
+    asset_manager: Res<'w, AssetManager>,
+    material_server: Res<'w, MaterialServer>,
+    actors_query: Query<'w, 's, (&'static Transform, &'static Children), With<Actor>>,
+    render_objects_query: Query<'w, 's, (&'static Transform, &'static RenderObject)>,
+    mesh_groups: Query<'w, 's, &'static Children, With<MeshGroup>>,
+    lights: Res<'w, LightingDataManager>,
+    environment_settings: Res<'w, EnvironmentSettings>,
+    fog: Res<'w, Fog>,
+    particle_renderer: Res<'w, ParticleRenderer>,
+    label_renderer: Res<'w, LabelRenderer>,
+    gizmo_renderer: Res<'w, GizmoRenderer>,
+    animated_materials: Res<'w, AnimatedMaterials>,
+    wireframe_renderer: Res<'w, WireframeRenderer>,
+    wireframe_objects: Query<'w, 's, (), With<ShowWireframe>>,
+    outline_renderer: Res<'w, OutlineRenderer>,
+    depth_pick: Res<'w, DepthPick>,
+    time: Res<'w, Time>,
+    emitters_query: Query<'w, 's, &'static Emitter>,
+}
+
+type ChangedSceneObject = Or<(Changed<Transform>, Changed<RenderObject>, Changed<Children>, Changed<Actor>, Changed<ShowWireframe>)>;
+
+/// The changes to the scene's objects that stop the main window reusing its recorded scene commands
+#[derive(SystemParam)]
+pub struct SceneChanges<'w, 's> {
+    changed_objects: Query<'w, 's, (), ChangedSceneObject>,
+    removed_transforms: RemovedComponents<'w, 's, Transform>,
+    removed_render_objects: RemovedComponents<'w, 's, RenderObject>,
+    removed_children: RemovedComponents<'w, 's, Children>,
+    removed_actors: RemovedComponents<'w, 's, Actor>,
+    removed_wireframes: RemovedComponents<'w, 's, ShowWireframe>,
+    asset_manager: Res<'w, AssetManager>,
+    // the outlined object is recorded with the scene
+    depth_pick: Res<'w, DepthPick>,
+}
+
+impl SceneChanges<'_, '_> {
+    // whether anything changed since the last call, which must happen every frame so removals aren't seen twice
+    fn take_changed(&mut self) -> bool {
+        let removed = !self.removed_transforms.is_empty() || !self.removed_render_objects.is_empty() || !self.removed_children.is_empty()
+            || !self.removed_actors.is_empty() || !self.removed_wireframes.is_empty();
+        self.removed_transforms.clear();
+        self.removed_render_objects.clear();
+        self.removed_children.clear();
+        self.removed_actors.clear();
+        self.removed_wireframes.clear();
+        removed || !self.changed_objects.is_empty() || self.asset_manager.is_changed() || self.depth_pick.is_changed()
+    }
+}
+
 pub fn draw_system(
     mut frame_renderer: ResMut<FrameRenderContext>,
     physical_device: PhysicalDeviceRes,
+    graphics_settings: Res<GraphicsSettings>,
     command_pool: Res<CommandPool>,
     mut swapchain: ResMut<Swapchain>,
-    asset_manager: Res<AssetManager>,
-    material_server: Res<MaterialServer>,
     camera: Res<Camera>,
-    actors_query: Query<(&Transform, &Children), With<Actor>>,
-    render_objects_query: Query<(&Transform, &RenderObject)>,
+    scene: SceneRenderData,
     mut ui_painter: ResMut<UiPainter>,
     ui_output: Res<EguiOutput>,
-    lights: Res<LightingDataManager>,
+    ui_visibility: Res<UiVisibility>,
+    mut shared: SharedFrameResources,
+    mut occlusion_culler: Option<ResMut<OcclusionCuller>>,
+    mut render_stats: ResMut<RenderStats>,
+    mut scene_changes: SceneChanges,
+    mut auto_exposure: Option<ResMut<AutoExposure>>,
 ) {
-    let frame_data = unsafe { frame_renderer.frame_data.get_unchecked(frame_renderer.current_frame % MAX_FRAMES_IN_FLIGHT) };
+    let frame_data_index = frame_renderer.current_frame % MAX_FRAMES_IN_FLIGHT;
+    let graphics_settings = graphics_settings.as_ref();
+    let frame_index = shared.begin_frame(&frame_renderer.device);
+    if scene_changes.take_changed() {
+        frame_renderer.scene_version += 1;
+    }
+    // particles, gizmos, labels, the occlusion queries and lods change what is drawn every frame
+    let scene_is_dynamic = graphics_settings.occlusion_culling || scene.asset_manager.has_mesh_lods() || !scene.emitters_query.is_empty() || !scene.gizmo_renderer.is_empty() || !scene.label_renderer.is_empty();
+    let scene_to_record = (graphics_settings.command_buffer_reuse == CommandBufferReuse::ReuseWhileStatic && !scene_is_dynamic).then(|| RecordedScene {
+        scene_version: frame_renderer.scene_version,
+        swapchain_generation: swapchain.generation,
+        material_generation: scene.material_server.generation(),
+        graphics_settings: *graphics_settings,
+        frame_slot: frame_index % MAX_FRAMES_IN_FLIGHT,
+    });
+    let reuse_scene = scene_to_record.is_some() && frame_renderer.recorded_scenes[frame_data_index] == scene_to_record;
 
-    update_global_buffer(frame_data, &camera);
+    frame_renderer.exposure = auto_exposure.as_ref().map_or(1.0, |auto_exposure| auto_exposure.exposure(graphics_settings));
+    let exposure = frame_renderer.exposure;
+    let frame_data = unsafe { frame_renderer.frame_data.get_unchecked(frame_data_index) };
+    let Some(image_index) = begin_frame(&frame_renderer.device, &mut swapchain, frame_data, &camera, &scene, graphics_settings, exposure) else {
+        return;
+    };
+    let capture = if frame_renderer.capture_next_frame { frame_renderer.renderdoc.as_ref() } else { None };
+    if let Some(renderdoc) = capture {
+        renderdoc.start_frame_capture();
+    }
+    if let Some(auto_exposure) = auto_exposure.as_deref_mut() {
+        auto_exposure.begin_frame(frame_index, scene.time.delta_seconds(), graphics_settings);
+    }
+
+    let device = &frame_renderer.device;
+    if reuse_scene {
+        // the reused commands read the animated values from the frame's buffers, so they're still written
+        scene.animated_materials.write_frame(&scene.asset_manager, frame_index);
+    } else {
+        begin_recording(device, frame_data.command_buffer);
+        let occlusion_culler = match occlusion_culler.as_deref_mut() {
+            Some(culler) if graphics_settings.occlusion_culling => {
+                culler.cmd_begin_frame(frame_data.command_buffer, frame_index, camera.position);
+                Some(culler)
+            }
+            Some(culler) => {
+                culler.reset();
+                None
+            }
+            None => None,
+        };
+        let mut render_graph = RenderGraph::new();
+        // reused scenes draw the same as when they were recorded, so their stats are kept
+        *render_stats = RenderStats::default();
+        let scene_image = add_scene_passes(&mut render_graph, device, &swapchain, frame_data, frame_index, &scene, graphics_settings, camera.position, occlusion_culler, Some(&mut render_stats));
+        render_graph.finish_image(scene_image, ImageAccess::TransferSrc);
+        render_graph.execute(device, frame_data.command_buffer);
+        end_recording(device, frame_data.command_buffer);
+    }
+    // the ui output is stale while hidden, so it isn't uploaded either
+    if ui_visibility.show_ui {
+        ui_painter.update_resources(&physical_device, graphics_settings, &command_pool, &ui_output, &mut shared.deletion_queue);
+    }
+
+    begin_recording(device, frame_data.present_command_buffer);
+    let mut render_graph = RenderGraph::new();
+    // the scene's commands leave it ready to be blitted
+    let scene_image = render_graph.import_image(swapchain.scene_image.vk_image, vk::ImageAspectFlags::COLOR, ImageAccess::TransferSrc, true);
+    if let Some(auto_exposure) = auto_exposure.as_deref_mut().filter(|_| graphics_settings.auto_exposure) {
+        let output_params = output_params(&swapchain, graphics_settings, exposure);
+        let (descriptor_manager, swapchain) = (&mut shared.descriptor_manager, &swapchain);
+        render_graph.add_pass("luminance histogram", &[(scene_image, ImageAccess::ComputeRead)], move |pass| {
+            auto_exposure.cmd_measure(pass.command_buffer, frame_index, descriptor_manager, swapchain, graphics_settings, output_params);
+        });
+    }
+    let swapchain_image = add_upscale_pass(&mut render_graph, device, &swapchain, scene_image, image_index);
+    // the ui is drawn at native resolution straight into the swapchain image, on top of the upscaled scene
+    render_graph.add_pass("ui", &[(swapchain_image, ImageAccess::ColorAttachment)], |pass| {
+        cmd_begin_ui_rendering(device, &swapchain, pass.command_buffer, image_index);
+        if ui_visibility.show_ui {
+            ui_painter.draw(device, &swapchain, graphics_settings, pass.command_buffer, &ui_output);
+        }
+        unsafe { device.cmd_end_rendering(pass.command_buffer); }
+    });
+    render_graph.finish_image(swapchain_image, ImageAccess::Present);
+    render_graph.execute(device, frame_data.present_command_buffer);
+    end_recording(device, frame_data.present_command_buffer);
+
+    let presented = finish_frame(&frame_renderer.device, &mut swapchain, frame_data, image_index, &[frame_data.command_buffer, frame_data.present_command_buffer]);
+    // submitted even when it couldn't be presented, so the slot's fence will still signal
+    shared.frame_slots.end_frame(frame_data.in_flight_fence);
+    if let Some(renderdoc) = capture {
+        renderdoc.end_frame_capture();
+    }
+    frame_renderer.capture_next_frame = false;
+    frame_renderer.recorded_scenes[frame_data_index] = scene_to_record;
+    if presented {
+        frame_renderer.current_frame += 1;
+    }
+}
+
+pub fn renderdoc_capture_system(input_state: Res<InputState>, mut frame_renderer: ResMut<FrameRenderContext>) {
+    if input_state.is_action_just_down(InputAction::CaptureFrame) {
+        frame_renderer.trigger_renderdoc_capture();
+    }
+}
+
+// draws the scene without any ui, used by windows other than the main one
+pub fn draw_scene_to_window(frame_renderer: &mut FrameRenderContext, shared: &mut SharedFrameResources, swapchain: &mut Swapchain, camera: &Camera, scene: &SceneRenderData, graphics_settings: &GraphicsSettings) {
+    let frame_data_index = frame_renderer.current_frame % MAX_FRAMES_IN_FLIGHT;
+    let frame_index = shared.begin_frame(&frame_renderer.device);
+    let frame_data = unsafe { frame_renderer.frame_data.get_unchecked(frame_data_index) };
+    let Some(image_index) = begin_frame(&frame_renderer.device, swapchain, frame_data, camera, scene, graphics_settings, frame_renderer.exposure) else {
+        return;
+    };
+    let device = &frame_renderer.device;
+    begin_recording(device, frame_data.command_buffer);
+    let mut render_graph = RenderGraph::new();
+    let scene_image = add_scene_passes(&mut render_graph, device, swapchain, frame_data, frame_index, scene, graphics_settings, camera.position, None, None);
+    let swapchain_image = add_upscale_pass(&mut render_graph, device, swapchain, scene_image, image_index);
+    render_graph.finish_image(swapchain_image, ImageAccess::Present);
+    render_graph.execute(device, frame_data.command_buffer);
+    end_recording(device, frame_data.command_buffer);
+
+    let presented = finish_frame(&frame_renderer.device, swapchain, frame_data, image_index, &[frame_data.command_buffer]);
+    shared.frame_slots.end_frame(frame_data.in_flight_fence);
+    frame_renderer.recorded_scenes[frame_data_index] = None;
+    if presented {
+        frame_renderer.current_frame += 1;
+    }
+}
+
+// renders the scene into the scene image without acquiring or presenting a swapchain image, for benchmarking
+// returns the gpu time of the frame that last used this frame data, if it was timed
+pub fn draw_scene_offscreen(frame_renderer: &mut FrameRenderContext, shared: &mut SharedFrameResources, swapchain: &Swapchain, camera: &Camera, scene: &SceneRenderData, graphics_settings: &GraphicsSettings, mut gpu_timer: Option<&mut GpuTimer>) -> Option<f64> {
+    let device = &frame_renderer.device;
+    let frame_data_index = frame_renderer.current_frame % MAX_FRAMES_IN_FLIGHT;
+    let frame_index = shared.begin_frame(device);
+    let frame_data = unsafe { frame_renderer.frame_data.get_unchecked(frame_data_index) };
+    unsafe {
+        device.wait_for_fences(&[frame_data.in_flight_fence], true, u64::MAX)
+            .expect("Failed to wait for in flight fence");
+        device.reset_fences(&[frame_data.in_flight_fence])
+            .expect("Failed to reset fences");
+    }
+    begin_recording(device, frame_data.command_buffer);
+    update_global_buffer(frame_data, camera, scene, graphics_settings, swapchain, frame_renderer.exposure);
+    let previous_gpu_time = gpu_timer.as_mut().and_then(|timer| timer.take_milliseconds(frame_index));
+
+    if let Some(timer) = gpu_timer.as_mut() {
+        timer.cmd_begin(frame_data.command_buffer, frame_index);
+    }
+    let mut render_graph = RenderGraph::new();
+    let scene_image = add_scene_passes(&mut render_graph, device, swapchain, frame_data, frame_index, scene, graphics_settings, camera.position, None, None);
+    // left as the windowed frames leave it, which is how every frame imports it
+    render_graph.finish_image(scene_image, ImageAccess::TransferSrc);
+    render_graph.execute(device, frame_data.command_buffer);
+    if let Some(timer) = gpu_timer.as_mut() {
+        timer.cmd_end(frame_data.command_buffer, frame_index);
+    }
+
+    end_recording(device, frame_data.command_buffer);
+    unsafe {
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(std::slice::from_ref(&frame_data.command_buffer));
+        device.queue_submit(device.graphics_queue, std::slice::from_ref(&submit_info), frame_data.in_flight_fence)
+            .expect("Failed to submit to graphics queue");
+    }
+    shared.frame_slots.end_frame(frame_data.in_flight_fence);
+    frame_renderer.recorded_scenes[frame_data_index] = None;
+    frame_renderer.current_frame += 1;
+    previous_gpu_time
+}
+
+// waits for the frame data to be free and acquires the swapchain image, returning None if the swapchain needs recreating
+fn begin_frame(device: &Device, swapchain: &mut Swapchain, frame_data: &FrameData, camera: &Camera, scene: &SceneRenderData, graphics_settings: &GraphicsSettings, exposure: f32) -> Option<u32> {
+    update_global_buffer(frame_data, camera, scene, graphics_settings, swapchain, exposure);
 
     // acquire the image from the swapcahin to draw to, waiting for the previous usage of this frame data to be free
-    let image_index = match prepare_to_draw(&frame_renderer.device, &swapchain, frame_data) {
+    let image_index = match prepare_to_draw(device, swapchain, frame_data) {
         Ok(index) => index,
         Err(_) => {
-            swapchain.needs_recreation = true;
-            return;
+            swapchain.mark_out_of_date();
+            return None;
         }
     };
+    Some(image_index)
+}
 
-    unsafe { frame_renderer.device.begin_command_buffer(frame_data.command_buffer, &vkinit::COMMAND_BUFFER_BEGIN_INFO) }
-        .expect("Failed to being recording command buffer");
+// the frame's fence must have been waited on, as the command buffer may still be executing until then
+fn begin_recording(device: &Device, command_buffer: vk::CommandBuffer) {
+    unsafe {
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+            .expect("Failed to reset command buffer");
+        device.begin_command_buffer(command_buffer, &vkinit::COMMAND_BUFFER_BEGIN_INFO)
+            .expect("Failed to being recording command buffer");
+    }
+}
 
-    cmd_begin_rendering(&frame_renderer.device, &swapchain, frame_data.command_buffer, image_index);
-    draw_sky_box(&frame_renderer.device, &swapchain, frame_data, &asset_manager, &material_server);
-    let mut last_material_pipeline_handle = MaterialPipelineHandle::null();
-    let mut last_material_pipeline: Option<&MaterialPipeline> = None;
-    let mut last_material_handle = MaterialHandle::null();
-    let mut last_mesh_handle = MeshHandle::null();
-    let mut last_mesh: Option<&Mesh> = None;
+fn end_recording(device: &Device, command_buffer: vk::CommandBuffer) {
+    unsafe { device.end_command_buffer(command_buffer) }
+        .expect("Failed to record command buffer");
+}
+
+// submits the recorded command buffers in order, returning false if the frame couldn't be presented
+fn finish_frame(device: &Device, swapchain: &mut Swapchain, frame_data: &FrameData, image_index: u32, command_buffers: &[vk::CommandBuffer]) -> bool {
+    if submit_draw(device, swapchain, image_index, frame_data, command_buffers).is_err() {
+        swapchain.mark_out_of_date();
+        return false;
+    }
+    swapchain.mark_presented();
+    true
+}
+
+// imports the images the scene renders to and adds the passes drawing the scene into the scene image, which is
+// returned for the passes that use the finished scene
+fn add_scene_passes<'a>(render_graph: &mut RenderGraph<'a>, device: &'a Device, swapchain: &'a Swapchain, frame_data: &'a FrameData, frame_index: usize, scene: &'a SceneRenderData, graphics_settings: &'a GraphicsSettings, camera_position: Vec3, occlusion_culler: Option<&'a mut OcclusionCuller>, render_stats: Option<&'a mut RenderStats>) -> GraphImage {
+    scene.animated_materials.write_frame(&scene.asset_manager, frame_index);
+    // the scene image is shared between frames, so the previous frame's blit has to finish reading it first
+    let scene_image = render_graph.import_image(swapchain.scene_image.vk_image, vk::ImageAspectFlags::COLOR, ImageAccess::TransferSrc, false);
+    let mut attachments = vec![(scene_image, ImageAccess::ColorAttachment)];
+    if swapchain.msaa_enabled {
+        let color_image = render_graph.import_image(swapchain.color_image.vk_image, vk::ImageAspectFlags::COLOR, ImageAccess::ColorAttachment, false);
+        attachments.push((color_image, ImageAccess::ColorAttachment));
+    }
+    // the depth stays in the attachment layout between frames, reading it back returns it to that layout
+    let depth_aspect_mask = DepthBuffer::aspect_mask(swapchain.depth_buffer.format);
+    for depth_image in std::iter::once(&swapchain.depth_buffer.image).chain(&swapchain.depth_buffer.resolved_image) {
+        let depth_image = render_graph.import_image(depth_image.vk_image, depth_aspect_mask, ImageAccess::DepthAttachment, true);
+        attachments.push((depth_image, ImageAccess::DepthAttachment));
+    }
+
+    // the passes share their attachments, so they are recorded as a single render pass instance that the sky box
+    // clears when beginning
+    render_graph.add_pass("sky box", &attachments, move |pass| {
+        cmd_begin_scene_rendering(device, swapchain, pass.command_buffer, graphics_settings, pass.rendering_flags());
+        // the clear is left showing where nothing is drawn so the scene can be composited over something else
+        if !graphics_settings.transparent_background {
+            draw_sky_box(device, swapchain, pass.command_buffer, frame_data.global_descriptor, &scene.asset_manager, &scene.material_server);
+        }
+        unsafe { device.cmd_end_rendering(pass.command_buffer); }
+    });
+    if graphics_settings.environment_only {
+        return scene_image;
+    }
+    render_graph.add_pass("opaque", &attachments, move |pass| {
+        record_opaque_pass(device, swapchain, frame_data, frame_index, scene, graphics_settings, camera_position, occlusion_culler, render_stats, pass);
+    });
+    // particles, gizmos and labels blend over the opaque geometry so are drawn after it
+    render_graph.add_pass("transparent", &attachments, move |pass| {
+        cmd_begin_scene_rendering(device, swapchain, pass.command_buffer, graphics_settings, pass.rendering_flags());
+        draw_particles(device, swapchain, pass.command_buffer, frame_data.global_descriptor, frame_index, scene);
+        draw_gizmos(device, swapchain, pass.command_buffer, frame_data.global_descriptor, frame_index, &scene.gizmo_renderer, &scene.material_server);
+        draw_labels(device, swapchain, pass.command_buffer, frame_data.global_descriptor, frame_index, &scene.label_renderer, &scene.material_server);
+        unsafe { device.cmd_end_rendering(pass.command_buffer); }
+    });
+    scene_image
+}
 
-    for (parent_transform, children) in actors_query.iter() {
-        for child_render_object in children {
-            if let Ok((render_object_relative_transform, render_object)) = render_objects_query.get(*child_render_object) {
+// draws the scene's objects, skipping those the occlusion culler found to be hidden
+fn record_opaque_pass(device: &Device, swapchain: &Swapchain, frame_data: &FrameData, frame_index: usize, scene: &SceneRenderData, graphics_settings: &GraphicsSettings, camera_position: Vec3, occlusion_culler: Option<&mut OcclusionCuller>, render_stats: Option<&mut RenderStats>, pass: &PassContext) {
+    let asset_manager = &scene.asset_manager;
+    let material_server = &scene.material_server;
+
+    let mut occlusion_candidates = Vec::new();
+    let mut draws: Vec<(Mat4, RenderObject)> = Vec::new();
+    let mut wireframe_draws: Vec<(Mat4, RenderObject)> = Vec::new();
+    let mut outline_draws: Vec<(Mat4, RenderObject)> = Vec::new();
+    let mut culled_objects = 0;
+    for (parent_transform, children) in scene.actors_query.iter() {
+        for child_render_object in actor_render_object_entities(children, &scene.mesh_groups) {
+            if let Ok((render_object_relative_transform, render_object)) = scene.render_objects_query.get(child_render_object) {
                 // TODO support relative transforms
-                let mesh_handle = render_object.mesh_handle;
-                let is_different_material = last_material_pipeline_handle.is_null() || last_material_pipeline_handle != render_object.material_pipeline_handle;
-                if let Some(loaded_material) = material_server.material_ref(&render_object.material_pipeline_handle) {
-                    if is_different_material {
-                        last_material_pipeline = Some(loaded_material);
-                        bind_material_pipeline(&frame_renderer.device, &swapchain, loaded_material, frame_data);
+                // hidden objects are still tested so they reappear once they come back into view
+                if let Some(culler) = occlusion_culler.as_deref() {
+                    let mesh = asset_manager.mesh_ref(&render_object.mesh_handle);
+                    occlusion_candidates.push(OcclusionCandidate {
+                        entity: child_render_object,
+                        model_matrix: parent_transform.matrix() * mesh.relative_transform,
+                        bounds: mesh.bounds,
+                    });
+                    if culler.is_occluded(child_render_object) {
+                        culled_objects += 1;
+                        continue;
                     }
-                } else {
-                    continue;
                 }
-
-                let current_material = unsafe { last_material_pipeline.unwrap_unchecked() };
-                // new model so bind model specific resources
-                if last_mesh_handle.is_null() || last_mesh_handle != mesh_handle {
-                    let mesh = asset_manager.mesh_ref(&mesh_handle);
-                    last_mesh = Some(mesh);
-                    bind_model(&frame_renderer.device, frame_data, mesh);
+                let render_object = lod_render_object(asset_manager, parent_transform.matrix(), render_object, camera_position);
+                draws.push((parent_transform.matrix(), render_object));
+                if graphics_settings.wireframe_overlay || scene.wireframe_objects.contains(child_render_object) {
+                    wireframe_draws.push((parent_transform.matrix(), render_object));
                 }
-                let mesh_material_handle = render_object.material_instance_handle;
-                // new material so bind material specific resources
-                if last_material_handle.is_null() || last_material_handle != mesh_material_handle {
-                    let material = asset_manager.material_ref(&mesh_material_handle);
-                    last_material_handle = mesh_material_handle;
-                    bind_material(&frame_renderer.device, frame_data, current_material, material, &lights, &asset_manager.global_light_map.as_ref().unwrap().0);
+                if scene.depth_pick.entity == Some(child_render_object) {
+                    outline_draws.push((parent_transform.matrix(), render_object));
                 }
+            };
+        }
+    }
+    if let Some(render_stats) = render_stats {
+        render_stats.culled_objects = culled_objects;
+        for (_, render_object) in &draws {
+            render_stats.add_draw(asset_manager.mesh_ref(&render_object.mesh_handle));
+        }
+    }
+    // draws sharing a pipeline, material and mesh end up next to each other, so most of the binds below are skipped
+    draws.sort_unstable_by_key(|(_, render_object)| (render_object.material_pipeline_handle, render_object.material_instance_handle, render_object.mesh_handle));
 
-                let current_model = unsafe { last_mesh.unwrap_unchecked() };
-                draw_object(&frame_renderer.device, frame_data, current_material, current_model, parent_transform.matrix());
-                last_material_pipeline_handle = render_object.material_pipeline_handle;
-                last_mesh_handle = mesh_handle;
+    let mut rendering_flags = pass.resuming_flags();
+    if !frame_data.recording_workers.is_empty() {
+        // a render pass instance either records inline or only executes secondary command buffers, so the draws
+        // recorded by the workers go in an instance of their own that the rest of the pass resumes
+        let secondary_flags = rendering_flags | vk::RenderingFlags::SUSPENDING;
+        let secondary_command_buffers = record_draws_in_parallel(device, swapchain, frame_data, frame_index, graphics_settings, secondary_flags, &draws, scene);
+        if !secondary_command_buffers.is_empty() {
+            cmd_begin_scene_rendering(device, swapchain, pass.command_buffer, graphics_settings, secondary_flags | vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS);
+            unsafe {
+                device.cmd_execute_commands(pass.command_buffer, &secondary_command_buffers);
+                device.cmd_end_rendering(pass.command_buffer);
+            }
+            rendering_flags = vk::RenderingFlags::RESUMING;
+        }
+    }
+    cmd_begin_scene_rendering(device, swapchain, pass.command_buffer, graphics_settings, rendering_flags | pass.suspending_flags());
+    if frame_data.recording_workers.is_empty() {
+        record_draws(device, swapchain, pass.command_buffer, frame_data.global_descriptor, frame_index, &draws, scene);
+    }
+    // drawn inline after the filled meshes they sit on, whichever way those were recorded
+    draw_wireframes(device, swapchain, pass.command_buffer, frame_data.global_descriptor, &wireframe_draws, scene);
+    draw_selection_outline(device, swapchain, pass.command_buffer, frame_data.global_descriptor, &outline_draws, scene);
+    if let Some(culler) = occlusion_culler {
+        query_occlusion(device, swapchain, pass.command_buffer, frame_data.global_descriptor, frame_index, culler, asset_manager, material_server, &occlusion_candidates);
+    }
+    unsafe { device.cmd_end_rendering(pass.command_buffer); }
+}
 
-            };
+// the render object with the mesh swapped for the lod to draw at its distance from the camera
+fn lod_render_object(asset_manager: &AssetManager, model_matrix: Mat4, render_object: &RenderObject, camera_position: Vec3) -> RenderObject {
+    let mesh = asset_manager.mesh_ref(&render_object.mesh_handle);
+    if mesh.lods.is_empty() {
+        return *render_object;
+    }
+    let bounds = mesh.bounds.transformed(model_matrix * mesh.relative_transform);
+    let distance = camera_position.distance(camera_position.clamp(bounds.min, bounds.max));
+    RenderObject {
+        mesh_handle: mesh.lod_mesh_handle(render_object.mesh_handle, distance),
+        ..*render_object
+    }
+}
+
+// splits the sorted draws into a contiguous run per worker, so each worker still skips most of its binds, and records
+// each run into the worker's secondary command buffer. Returns the command buffers to execute in order
+fn record_draws_in_parallel(device: &Device, swapchain: &Swapchain, frame_data: &FrameData, frame_index: usize, graphics_settings: &GraphicsSettings, rendering_flags: vk::RenderingFlags, draws: &[(Mat4, RenderObject)], scene: &SceneRenderData) -> Vec<vk::CommandBuffer> {
+    if draws.is_empty() {
+        return Vec::new();
+    }
+    let samples = graphics_settings.msaa_samples.to_sample_count_flags();
+    let draws_per_worker = draws.len().div_ceil(frame_data.recording_workers.len());
+    let recording_workers = &frame_data.recording_workers[..draws.len().div_ceil(draws_per_worker)];
+    std::thread::scope(|scope| {
+        for (worker, worker_draws) in recording_workers.iter().zip(draws.chunks(draws_per_worker)) {
+            scope.spawn(move || {
+                // the frame's fence has been waited on, so nothing from the pool is still executing
+                worker.command_pool.reset();
+                begin_secondary_command_buffer(device, swapchain, worker.command_buffer, rendering_flags, samples);
+                record_draws(device, swapchain, worker.command_buffer, frame_data.global_descriptor, frame_index, worker_draws, scene);
+                unsafe { device.end_command_buffer(worker.command_buffer) }
+                    .expect("Failed to record secondary command buffer");
+            });
+        }
+    });
+    recording_workers.iter().map(|worker| worker.command_buffer).collect()
+}
+
+// the secondary command buffer continues the scene's render pass instance, so must match its attachments
+fn begin_secondary_command_buffer(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, rendering_flags: vk::RenderingFlags, samples: vk::SampleCountFlags) {
+    let color_formats = [swapchain.scene_image.format];
+    let mut inheritance_rendering_info = vk::CommandBufferInheritanceRenderingInfo::builder()
+        .flags(rendering_flags)
+        .color_attachment_formats(&color_formats)
+        .depth_attachment_format(swapchain.depth_buffer.format)
+        .stencil_attachment_format(if DepthBuffer::format_has_stencil(swapchain.depth_buffer.format) { swapchain.depth_buffer.format } else { vk::Format::UNDEFINED })
+        .rasterization_samples(samples);
+    let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+        .push_next(&mut inheritance_rendering_info);
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        // not one time submit, as the main window can submit the scene executing it again
+        .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+        .inheritance_info(&inheritance_info);
+    unsafe { device.begin_command_buffer(command_buffer, &begin_info) }
+        .expect("Failed to begin recording secondary command buffer");
+}
+
+fn record_draws(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, frame_index: usize, draws: &[(Mat4, RenderObject)], scene: &SceneRenderData) {
+    let asset_manager = &scene.asset_manager;
+    let material_server = &scene.material_server;
+    let mut last_material_pipeline_handle = MaterialPipelineHandle::null();
+    let mut last_material_pipeline: Option<&MaterialPipeline> = None;
+    let mut last_material_handle = MaterialHandle::null();
+    let mut last_mesh_handle = MeshHandle::null();
+    let mut last_mesh: Option<&Mesh> = None;
+    for (model_matrix, render_object) in draws {
+        let model_matrix = *model_matrix;
+        let mesh_handle = render_object.mesh_handle;
+        let is_different_material = last_material_pipeline_handle.is_null() || last_material_pipeline_handle != render_object.material_pipeline_handle;
+        if let Some(loaded_material) = material_server.material_ref(&render_object.material_pipeline_handle) {
+            if is_different_material {
+                last_material_pipeline = Some(loaded_material);
+                bind_material_pipeline(device, swapchain, loaded_material, command_buffer);
+                // the material's sets are bound against the new pipeline's layout
+                last_material_handle = MaterialHandle::null();
+            }
+        } else {
+            continue;
+        }
+
+        let current_material = unsafe { last_material_pipeline.unwrap_unchecked() };
+        // new model so bind model specific resources
+        if last_mesh_handle.is_null() || last_mesh_handle != mesh_handle {
+            let mesh = asset_manager.mesh_ref(&mesh_handle);
+            last_mesh = Some(mesh);
+            bind_model(device, command_buffer, mesh);
+        }
+        let mesh_material_handle = render_object.material_instance_handle;
+        // new material so bind material specific resources
+        if last_material_handle.is_null() || last_material_handle != mesh_material_handle {
+            let material = asset_manager.material_ref(&mesh_material_handle);
+            last_material_handle = mesh_material_handle;
+            bind_material(device, command_buffer, global_descriptor, frame_index, current_material, material, &scene.lights, asset_manager.environment_maps());
+        }
+
+        let current_model = unsafe { last_mesh.unwrap_unchecked() };
+        draw_object(device, command_buffer, current_material, current_model, model_matrix);
+        last_material_pipeline_handle = render_object.material_pipeline_handle;
+        last_mesh_handle = mesh_handle;
+    }
+}
+
+fn draw_wireframes(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, draws: &[(Mat4, RenderObject)], scene: &SceneRenderData) {
+    let Some(pipeline) = scene.material_server.material_ref(&scene.wireframe_renderer.pipeline) else {
+        return;
+    };
+    if draws.is_empty() {
+        return;
+    }
+    bind_material_pipeline(device, swapchain, pipeline, command_buffer);
+    unsafe { device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[global_descriptor], &[]) };
+    for (model_matrix, render_object) in draws {
+        let mesh = scene.asset_manager.mesh_ref(&render_object.mesh_handle);
+        // points and lines are already drawn as their edges
+        if mesh.topology != vk::PrimitiveTopology::TRIANGLE_LIST {
+            continue;
         }
+        bind_model(device, command_buffer, mesh);
+        draw_object(device, command_buffer, pipeline, mesh, *model_matrix);
     }
+}
 
-    ui_painter.update_resources(&physical_device, &command_pool, &ui_output);
-    ui_painter.draw(&frame_renderer.device, &swapchain, frame_data.command_buffer, &ui_output);
+// marks the stencil with the selected object then draws the outline everywhere around it that isn't marked, so the
+// outline only shows outside of the object's silhouette
+fn draw_selection_outline(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, draws: &[(Mat4, RenderObject)], scene: &SceneRenderData) {
+    if draws.is_empty() || !DepthBuffer::format_has_stencil(swapchain.depth_buffer.format) {
+        return;
+    }
+    let (Some(mask_pipeline), Some(outline_pipeline)) = (scene.material_server.material_ref(&scene.outline_renderer.mask_pipeline), scene.material_server.material_ref(&scene.outline_renderer.outline_pipeline)) else {
+        return;
+    };
+    for pipeline in [mask_pipeline, outline_pipeline] {
+        bind_material_pipeline(device, swapchain, pipeline, command_buffer);
+        unsafe { device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[global_descriptor], &[]) };
+        for (model_matrix, render_object) in draws {
+            let mesh = scene.asset_manager.mesh_ref(&render_object.mesh_handle);
+            if mesh.topology != vk::PrimitiveTopology::TRIANGLE_LIST {
+                continue;
+            }
+            bind_model(device, command_buffer, mesh);
+            draw_object(device, command_buffer, pipeline, mesh, *model_matrix);
+        }
+    }
+}
 
-    cmd_end_rendering(&frame_renderer.device, &swapchain, frame_data.command_buffer, image_index);
+fn draw_sky_box(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, asset_manager: &AssetManager, material_server: &MaterialServer) {
+    // without an environment, or while the sky box pipeline fails to compile, only the clear color is behind the scene
+    let Some((environment_maps, pipeline_handle)) = &asset_manager.global_light_map else {
+        return;
+    };
+    let Some(pipeline) = material_server.material_ref(pipeline_handle) else {
+        return;
+    };
 
-    unsafe { frame_renderer.device.end_command_buffer(frame_data.command_buffer) }
-        .expect("Failed to record command buffer");
+    bind_material_pipeline(device, swapchain, pipeline, command_buffer);
+    unsafe {
+        device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[global_descriptor, environment_maps.sky_box_descriptor_set], &[]);
+        device.cmd_bind_vertex_buffers(command_buffer, 0, std::slice::from_ref(&asset_manager.cube_map_manager.cube_vertex_buffer.buffer), std::slice::from_ref(&0u64));
+        device.cmd_draw(command_buffer, cube::CUBE_VERTICES.len() as u32, 1, 0, 0);
+    }
+}
 
-    if let Err(_) = submit_draw(&frame_renderer.device, &swapchain, image_index, frame_data) {
-        swapchain.needs_recreation = true;
+fn query_occlusion(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, frame_index: usize, occlusion_culler: &mut OcclusionCuller, asset_manager: &AssetManager, material_server: &MaterialServer, candidates: &[OcclusionCandidate]) {
+    let Some(pipeline) = material_server.material_ref(&occlusion_culler.pipeline) else {
         return;
     };
+    bind_material_pipeline(device, swapchain, pipeline, command_buffer);
+    unsafe { device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[global_descriptor], &[]); }
+    occlusion_culler.cmd_query_visibility(command_buffer, frame_index, pipeline, asset_manager.cube_map_manager.cube_vertex_buffer.buffer, candidates);
+}
 
-    frame_renderer.current_frame += 1;
+fn draw_particles(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, frame_index: usize, scene: &SceneRenderData) {
+    let particle_renderer = &scene.particle_renderer;
+    let Some(pipeline) = scene.material_server.material_ref(&particle_renderer.pipeline) else {
+        return;
+    };
+    let (instance_buffer, instance_count) = particle_renderer.prepare_instances(frame_index, scene.emitters_query.iter());
+    if instance_count == 0 {
+        return;
+    }
+    bind_material_pipeline(device, swapchain, pipeline, command_buffer);
+    unsafe {
+        device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[global_descriptor], &[]);
+        device.cmd_bind_vertex_buffers(command_buffer, 0, std::slice::from_ref(&instance_buffer), std::slice::from_ref(&0u64));
+        // six vertices make up each particle's quad
+        device.cmd_draw(command_buffer, 6, instance_count, 0, 0);
+    }
 }
 
-fn draw_sky_box(device: &Device, swapchain: &Swapchain, frame_data: &FrameData, asset_manager: &AssetManager, material_server: &MaterialServer) {
-    if let Some((environment_maps, pipeline_handle)) = &asset_manager.global_light_map {
-        let pipeline = &material_server.material_ref(pipeline_handle).unwrap();
+fn draw_gizmos(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, frame_index: usize, gizmo_renderer: &GizmoRenderer, material_server: &MaterialServer) {
+    let Some(pipeline) = material_server.material_ref(&gizmo_renderer.pipeline) else {
+        return;
+    };
+    let (vertex_buffer, vertex_count) = gizmo_renderer.prepare_vertices(frame_index);
+    if vertex_count == 0 {
+        return;
+    }
+    bind_material_pipeline(device, swapchain, pipeline, command_buffer);
+    unsafe {
+        device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[global_descriptor], &[]);
+        device.cmd_bind_vertex_buffers(command_buffer, 0, std::slice::from_ref(&vertex_buffer), std::slice::from_ref(&0u64));
+        device.cmd_draw(command_buffer, vertex_count, 1, 0, 0);
+    }
+}
 
-        bind_material_pipeline(device, swapchain, pipeline, frame_data);
+// labels drawn on top go last so nothing else in the scene covers them
+fn draw_labels(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, frame_index: usize, label_renderer: &LabelRenderer, material_server: &MaterialServer) {
+    let Some(font_atlas_descriptor_set) = label_renderer.font_atlas_descriptor_set() else {
+        return;
+    };
+    let (instance_buffer, depth_tested_count, on_top_count) = label_renderer.prepare_instances(frame_index);
+    let batches = [(label_renderer.pipeline, 0, depth_tested_count), (label_renderer.always_on_top_pipeline, depth_tested_count, on_top_count)];
+    for (pipeline_handle, first_instance, instance_count) in batches {
+        let Some(pipeline) = material_server.material_ref(&pipeline_handle) else {
+            continue;
+        };
+        if instance_count == 0 {
+            continue;
+        }
+        bind_material_pipeline(device, swapchain, pipeline, command_buffer);
         unsafe {
-            device.cmd_bind_descriptor_sets(frame_data.command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[frame_data.global_descriptor, environment_maps.sky_box_descriptor_set], &[]);
-            device.cmd_bind_vertex_buffers(frame_data.command_buffer, 0, std::slice::from_ref(&asset_manager.cube_map_manager.cube_vertex_buffer.buffer), std::slice::from_ref(&0u64));
-            device.cmd_draw(frame_data.command_buffer, cube::CUBE_VERTICES.len() as u32, 1, 0, 0);
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[global_descriptor, font_atlas_descriptor_set], &[]);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, std::slice::from_ref(&instance_buffer), std::slice::from_ref(&0u64));
+            // six vertices make up each glyph's quad
+            device.cmd_draw(command_buffer, 6, instance_count, 0, first_instance);
         }
     }
 }
 
-fn update_global_buffer(frame_data: &FrameData, camera: &Camera) {
-    let view_proj = camera.to_view_proj();
-    let buffer_data: &[u8] = bytemuck::cast_slice(std::slice::from_ref(&view_proj));
+fn update_global_buffer(frame_data: &FrameData, camera: &Camera, scene: &SceneRenderData, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, exposure: f32) {
+    let environment_settings = &scene.environment_settings;
+    let environment_loaded = scene.asset_manager.global_light_map.is_some();
+    let fog = &scene.fog;
+    let viewport = swapchain.render_extent();
+    let global_data = GlobalUniformData {
+        view_projection: camera.to_view_proj(),
+        environment_params: Vec4::new(environment_settings.intensity, environment_settings.rotation_yaw_degrees.to_radians(), 0.0, 0.0),
+        depth_params: Vec4::new(graphics_settings.logarithmic_depth as u32 as f32, camera.z_far(), graphics_settings.reverse_z as u32 as f32, 0.0),
+        fog_color: fog.color.extend(fog.mode.shader_value()),
+        fog_params: Vec4::new(fog.density, fog.start, fog.end, 0.0),
+        debug_params: Vec4::new(graphics_settings.debug_render_mode.shader_value(), graphics_settings.normal_mapping as u32 as f32, 0.0, 0.0),
+        ambient_params: (environment_settings.ambient_color * environment_settings.ambient_intensity).extend(environment_loaded as u32 as f32),
+        output_params: output_params(swapchain, graphics_settings, exposure),
+        frame_params: Vec4::new(scene.time.elapsed_seconds_wrapped(), viewport.width as f32, viewport.height as f32, scene.lights.light_count as f32),
+    };
+    let buffer_data: &[u8] = bytemuck::cast_slice(std::slice::from_ref(&global_data));
     frame_data.global_data.write_data(buffer_data);
 }
 
-fn submit_draw(device: &Device, swapchain: &Swapchain, image_index: u32, frame_data: &FrameData) -> SwapchainResult<()> {
-    // we need swapchain image to be available before we reach the color output stage (fragment shader)
-    // so vertex shading could start before this point
+fn output_params(swapchain: &Swapchain, graphics_settings: &GraphicsSettings, exposure: f32) -> Vec4 {
+    Vec4::new(swapchain.output_encoding.shader_value(), graphics_settings.hdr_paper_white_nits, graphics_settings.hdr_peak_nits, exposure)
+}
+
+fn submit_draw(device: &Device, swapchain: &Swapchain, image_index: u32, frame_data: &FrameData, command_buffers: &[vk::CommandBuffer]) -> SwapchainResult<()> {
+    // we need swapchain image to be available before the scene is blitted into it,
+    // so the scene itself can be rendered before this point
     let signal_semaphores = &[frame_data.render_finished_semaphore];
     let submit_info = vk::SubmitInfo::builder()
         .wait_semaphores(std::slice::from_ref(&frame_data.image_available_semaphore))
-        .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
+        .wait_dst_stage_mask(&[vk::PipelineStageFlags::TRANSFER])
         .signal_semaphores(signal_semaphores)
-        .command_buffers(std::slice::from_ref(&frame_data.command_buffer));
+        .command_buffers(command_buffers);
 
     unsafe { device.queue_submit(device.graphics_queue, std::slice::from_ref(&submit_info), frame_data.in_flight_fence) }
         .expect("Failed to submit to graphics queue");
@@ -173,9 +850,6 @@ fn prepare_to_draw(device: &Device, swapchain: &Swapchain, frame_data: &FrameDat
     unsafe { device.wait_for_fences(&[frame_data.in_flight_fence], true, u64::MAX) }
         .expect("Failed to wait for in flight fence");
 
-    unsafe { device.reset_command_buffer(frame_data.command_buffer, vk::CommandBufferResetFlags::empty()) }
-        .expect("Failed to reset command buffer");
-
     let image_index = swapchain.acquire_next_image_and_get_index(frame_data.image_available_semaphore)?;
     unsafe { device.reset_fences(&[frame_data.in_flight_fence]) }
         .expect("Failed to reset fences");
@@ -183,41 +857,57 @@ fn prepare_to_draw(device: &Device, swapchain: &Swapchain, frame_data: &FrameDat
     Ok(image_index)
 }
 
-fn bind_material_pipeline(device: &Device, swapchain: &Swapchain, pipeline: &MaterialPipeline, frame_data: &FrameData) {
-    unsafe { device.cmd_bind_pipeline(frame_data.command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.graphics_pipeline()); }
+fn bind_material_pipeline(device: &Device, swapchain: &Swapchain, pipeline: &MaterialPipeline, command_buffer: vk::CommandBuffer) {
+    pipeline.cmd_bind(command_buffer);
     let viewport = [vk::Viewport::builder()
         .x(0.0)
         .y(0.0)
-        .width(swapchain.extent().width as f32)
-        .height(swapchain.extent().height as f32)
+        .width(swapchain.render_extent().width as f32)
+        .height(swapchain.render_extent().height as f32)
         .min_depth(0.0)
         .max_depth(1.0)
         .build()];
-    unsafe { device.cmd_set_viewport(frame_data.command_buffer, 0, &viewport); }
+    unsafe { device.cmd_set_viewport(command_buffer, 0, &viewport); }
 
     let scissor = [vk::Rect2D::builder()
         .offset(vk::Offset2D { x: 0, y: 0 })
-        .extent(swapchain.extent())
+        .extent(swapchain.render_extent())
         .build()];
-    unsafe { device.cmd_set_scissor(frame_data.command_buffer, 0, &scissor); }
+    unsafe { device.cmd_set_scissor(command_buffer, 0, &scissor); }
 }
 
-fn bind_material(device: &Device, frame_data: &FrameData, pipeline: &MaterialPipeline, material: &PbrMaterial, light_data: &LightingDataManager, environment_maps: &EnvironmentMaps) {
+fn bind_material(device: &Device, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, frame_index: usize, pipeline: &MaterialPipeline, material: &PbrMaterial, light_data: &LightingDataManager, environment_maps: &EnvironmentMaps) {
+    match pipeline.material_bindings {
+        MaterialBindings::Lit => bind_lit_material(device, command_buffer, global_descriptor, frame_index, pipeline, material, light_data, environment_maps),
+        MaterialBindings::Unlit => bind_unlit_material(device, command_buffer, global_descriptor, frame_index, pipeline, material),
+    }
+}
+
+fn bind_unlit_material(device: &Device, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, frame_index: usize, pipeline: &MaterialPipeline, material: &PbrMaterial) {
     unsafe {
-        device.cmd_bind_descriptor_sets(frame_data.command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[frame_data.global_descriptor, material.descriptor_set(), light_data.descriptor_set, environment_maps.ibl_descriptor_set], &[]);
+        device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[global_descriptor, material.descriptor_set()], &[PbrMaterial::animation_offset(frame_index)]);
     }
 }
 
-fn bind_model(device: &Device, frame_data: &FrameData, mesh: &Mesh) {
+fn bind_lit_material(device: &Device, command_buffer: vk::CommandBuffer, global_descriptor: vk::DescriptorSet, frame_index: usize, pipeline: &MaterialPipeline, material: &PbrMaterial, light_data: &LightingDataManager, environment_maps: &EnvironmentMaps) {
+    unsafe {
+        device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 0, &[global_descriptor, material.descriptor_set(), light_data.descriptor_set, environment_maps.ibl_descriptor_set], &[PbrMaterial::animation_offset(frame_index)]);
+        if let Some(bindless_descriptor_set) = pipeline.bindless_descriptor_set {
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline_layout, 4, &[bindless_descriptor_set], &[]);
+        }
+    }
+}
+
+fn bind_model(device: &Device, command_buffer: vk::CommandBuffer, mesh: &Mesh) {
     let buffers = &[mesh.vertex_buffer.buffer];
     let offsets = &[0u64];
     unsafe {
-        device.cmd_bind_vertex_buffers(frame_data.command_buffer, 0, buffers, offsets);
-        device.cmd_bind_index_buffer(frame_data.command_buffer, mesh.index_buffer.buffer, 0, vk::IndexType::UINT32);
+        device.cmd_bind_vertex_buffers(command_buffer, 0, buffers, offsets);
+        device.cmd_bind_index_buffer(command_buffer, mesh.index_buffer.buffer, 0, mesh.index_type);
     }
 }
 
-fn draw_object(device: &Device, frame_data: &FrameData, pipeline: &MaterialPipeline, mesh: &Mesh, world_transform: Mat4) {
+fn draw_object(device: &Device, command_buffer: vk::CommandBuffer, pipeline: &MaterialPipeline, mesh: &Mesh, world_transform: Mat4) {
     let model_matrix = world_transform * mesh.relative_transform;
 
     let push_constant = ModelPushConstants {
@@ -226,28 +916,19 @@ fn draw_object(device: &Device, frame_data: &FrameData, pipeline: &MaterialPipel
     };
     let model_data: &[u8] = bytemuck::cast_slice(std::slice::from_ref(&push_constant));
     unsafe {
-        device.cmd_push_constants(frame_data.command_buffer, pipeline.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, &[model_data].concat());
-        device.cmd_draw_indexed(frame_data.command_buffer, mesh.index_count, 1, 0, 0, 0);
+        device.cmd_push_constants(command_buffer, pipeline.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, &[model_data].concat());
+    }
+    pipeline.cmd_set_mirrored(command_buffer, model_matrix.determinant() < 0.0);
+    unsafe {
+        device.cmd_draw_indexed(command_buffer, mesh.index_count, 1, 0, 0, 0);
     }
 }
 
-fn cmd_begin_rendering(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, swapchain_image_index: u32) {
-    // with dynamic rendering we need to make the output image ready for writing to
-    image_transitions::transition_image_layout(device, &command_buffer, swapchain.images[swapchain_image_index as usize], &image_transitions::TransitionProps {
-        old_layout: vk::ImageLayout::UNDEFINED,
-        src_access_mask: vk::AccessFlags2::empty(),
-        src_stage_mask: vk::PipelineStageFlags2::TOP_OF_PIPE,
-        new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        dst_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-        dst_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-        aspect_mask: vk::ImageAspectFlags::COLOR,
-        base_mip_level: 0,
-        level_count: 1,
-        layer_count: 1,
-    });
+// the clear values are only used when the flags don't resume a suspended render pass instance
+fn cmd_begin_scene_rendering(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, graphics_settings: &GraphicsSettings, flags: vk::RenderingFlags) {
     let clear_color = vk::ClearValue {
         color: vk::ClearColorValue {
-            float32: [0.52, 0.8, 0.92, 1.0]
+            float32: graphics_settings.clear_color()
         }
     };
     let color_attachment_info = if swapchain.msaa_enabled {
@@ -258,60 +939,125 @@ fn cmd_begin_rendering(device: &Device, swapchain: &Swapchain, command_buffer: v
             .store_op(vk::AttachmentStoreOp::STORE)
             .resolve_mode(vk::ResolveModeFlags::AVERAGE)
             .resolve_image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
-            .resolve_image_view(swapchain.image_views[swapchain_image_index as usize])
+            .resolve_image_view(swapchain.scene_image.image_view)
             .clear_value(clear_color)
     } else {
         vk::RenderingAttachmentInfo::builder()
-            .image_view(swapchain.image_views[swapchain_image_index as usize])
+            .image_view(swapchain.scene_image.image_view)
             .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::STORE)
             .resolve_mode(vk::ResolveModeFlags::NONE)
             .clear_value(clear_color)
     };
-    let depth_attachment = vk::RenderingAttachmentInfo::builder()
-        .image_view(swapchain.depth_buffer.image.image_view)
-        .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .clear_value(vk::ClearValue {
-            depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 }
-        });
-    let rendering_info = vk::RenderingInfo::builder()
+    let depth_clear_value = vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue { depth: graphics_settings.depth_clear_value(), stencil: 0 }
+    };
+    // the depth is kept after rendering so it can be read back, with msaa only the resolved depth is kept
+    let depth_attachment = if let Some(resolved_image) = &swapchain.depth_buffer.resolved_image {
+        vk::RenderingAttachmentInfo::builder()
+            .image_view(swapchain.depth_buffer.image.image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .resolve_mode(vk::ResolveModeFlags::SAMPLE_ZERO)
+            .resolve_image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .resolve_image_view(resolved_image.image_view)
+            .clear_value(depth_clear_value)
+    } else {
+        vk::RenderingAttachmentInfo::builder()
+            .image_view(swapchain.depth_buffer.image.image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(depth_clear_value)
+    };
+    let mut rendering_info = vk::RenderingInfo::builder()
         .render_area(vk::Rect2D {
             offset: vk::Offset2D { x: 0, y: 0 },
-            extent: swapchain.extent,
+            extent: swapchain.render_extent(),
         })
         .layer_count(1)
+        .flags(flags)
         .color_attachments(std::slice::from_ref(&color_attachment_info))
         .depth_attachment(&depth_attachment);
+    // the stencil shares the depth image, so is cleared, kept and resolved with it
+    if DepthBuffer::format_has_stencil(swapchain.depth_buffer.format) {
+        rendering_info = rendering_info.stencil_attachment(&depth_attachment);
+    }
     unsafe { device.cmd_begin_rendering(command_buffer, &rendering_info); }
 }
 
-fn cmd_end_rendering(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, swapchain_image_index: u32) {
-    unsafe { device.cmd_end_rendering(command_buffer); }
+// upscales the scene into the swapchain image, returning the swapchain image for the passes drawn over the scene
+fn add_upscale_pass<'a>(render_graph: &mut RenderGraph<'a>, device: &'a Device, swapchain: &'a Swapchain, scene_image: GraphImage, swapchain_image_index: u32) -> GraphImage {
+    // the acquire semaphore is waited on at the transfer stage, so the first barrier has to wait there too
+    let swapchain_image = render_graph.import_image(swapchain.images[swapchain_image_index as usize], vk::ImageAspectFlags::COLOR, ImageAccess::TransferDst, false);
+    render_graph.add_pass("upscale", &[(scene_image, ImageAccess::TransferSrc), (swapchain_image, ImageAccess::TransferDst)], move |pass| {
+        cmd_blit_scene_to_swapchain(device, swapchain, pass.command_buffer, swapchain_image_index);
+    });
+    swapchain_image
+}
 
-    // For dynamic rendering we must manually transition the image layout for presentation
-    // after drawing. This means changing it from a "color attachment write" to a "present".
-    // This happens at the very last stage of render (i.e. BOTTOM_OF_PIPE)
-    image_transitions::transition_image_layout(device, &command_buffer, swapchain.images[swapchain_image_index as usize], &image_transitions::TransitionProps {
-        old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        src_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-        src_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-        new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-        dst_stage_mask: vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
-        dst_access_mask: vk::AccessFlags2::empty(),
+// upscales the scene image into the swapchain image with bilinear filtering
+fn cmd_blit_scene_to_swapchain(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, swapchain_image_index: u32) {
+    let swapchain_image = swapchain.images[swapchain_image_index as usize];
+    let subresource = vk::ImageSubresourceLayers {
         aspect_mask: vk::ImageAspectFlags::COLOR,
-        base_mip_level: 0,
-        level_count: 1,
+        mip_level: 0,
+        base_array_layer: 0,
         layer_count: 1,
-    });
+    };
+    let render_extent = swapchain.render_extent();
+    let blit = vk::ImageBlit::builder()
+        .src_subresource(subresource)
+        .src_offsets([vk::Offset3D::default(), vk::Offset3D { x: render_extent.width as i32, y: render_extent.height as i32, z: 1 }])
+        .dst_subresource(subresource)
+        .dst_offsets([vk::Offset3D::default(), vk::Offset3D { x: swapchain.extent.width as i32, y: swapchain.extent.height as i32, z: 1 }]);
+    unsafe {
+        device.cmd_blit_image(
+            command_buffer,
+            swapchain.scene_image.vk_image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            swapchain_image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            std::slice::from_ref(&blit),
+            vk::Filter::LINEAR,
+        );
+    }
+}
+
+fn cmd_begin_ui_rendering(device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, swapchain_image_index: u32) {
+    let color_attachment_info = vk::RenderingAttachmentInfo::builder()
+        .image_view(swapchain.image_views[swapchain_image_index as usize])
+        .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
+        .load_op(vk::AttachmentLoadOp::LOAD)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .resolve_mode(vk::ResolveModeFlags::NONE);
+    let rendering_info = vk::RenderingInfo::builder()
+        .render_area(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: swapchain.extent,
+        })
+        .layer_count(1)
+        .color_attachments(std::slice::from_ref(&color_attachment_info));
+    unsafe { device.cmd_begin_rendering(command_buffer, &rendering_info); }
+}
+
+impl FrameRenderContext {
+    // captures everything recorded and submitted for the next frame, does nothing unless running under RenderDoc
+    pub fn trigger_renderdoc_capture(&mut self) {
+        if self.renderdoc.is_some() {
+            self.capture_next_frame = true;
+        } else {
+            warn!("Frame capture requested, but the application wasn't launched from RenderDoc");
+        }
+    }
 }
 
 // initialisation
 impl FrameRenderContext {
-    pub fn create(device: ConstPtr<Device>, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager) -> FrameRenderContext {
-        let command_buffers = command_pool.allocate_command_buffers(MAX_FRAMES_IN_FLIGHT as u32);
+    pub fn create(device: ConstPtr<Device>, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, recording_threads: usize) -> FrameRenderContext {
+        let command_buffers = command_pool.allocate_command_buffers(2 * MAX_FRAMES_IN_FLIGHT as u32);
         let frame_data: [FrameData; MAX_FRAMES_IN_FLIGHT] = (0..MAX_FRAMES_IN_FLIGHT).map(|i| {
             let image_available_semaphore = unsafe { device.create_semaphore(&vkinit::SEMAPHORE_CREATE_INFO, None) }
                 .expect("Failed to create semaphore");
@@ -321,24 +1067,39 @@ impl FrameRenderContext {
                 .expect("Failed to create fence");
 
             let camera_buffer = HostMappedBuffer::create(device, HostMappedBufferCreateInfo {
-                size: size_of::<ViewProjectionMatrices>() as u64,
+                size: size_of::<GlobalUniformData>() as u64,
                 usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
             });
             let descriptor_buffer_info = vk::DescriptorBufferInfo::builder()
                 .buffer(camera_buffer.vk_buffer())
                 .offset(0)
-                .range(size_of::<ViewProjectionMatrices>() as u64);
+                .range(size_of::<GlobalUniformData>() as u64);
             let (descriptor_set, _) = descriptor_manager.descriptor_builder()
                 .bind_buffer(0, descriptor_buffer_info, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
                 .build()
                 .expect("Failed to build camera descriptor");
+            // every frame in flight has its own pools so they can be reset while the other frame is still executing
+            let recording_workers = if recording_threads > 1 {
+                (0..recording_threads).map(|_| {
+                    let worker_command_pool = command_pool.create_sibling();
+                    let command_buffer = worker_command_pool.allocate_secondary_command_buffers(1)[0];
+                    RecordingWorker {
+                        command_pool: worker_command_pool,
+                        command_buffer,
+                    }
+                }).collect()
+            } else {
+                Vec::new()
+            };
             FrameData {
                 image_available_semaphore,
                 render_finished_semaphore,
                 in_flight_fence,
                 global_data: camera_buffer,
-                command_buffer: command_buffers[i],
+                command_buffer: command_buffers[2 * i],
+                present_command_buffer: command_buffers[2 * i + 1],
                 global_descriptor: descriptor_set,
+                recording_workers,
             }
         })
             .collect::<Vec<FrameData>>()
@@ -349,6 +1110,11 @@ impl FrameRenderContext {
             device,
             frame_data,
             current_frame: 0,
+            renderdoc: RenderDoc::load(),
+            capture_next_frame: false,
+            recorded_scenes: Default::default(),
+            scene_version: 0,
+            exposure: 1.0,
         }
     }
 }
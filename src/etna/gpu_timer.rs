@@ -0,0 +1,77 @@
+use ash::vk;
+use bevy_ecs::prelude::*;
+
+use crate::etna::{Device, MAX_FRAMES_IN_FLIGHT, PhysicalDevice};
+use crate::rehnda_core::ConstPtr;
+
+/// Measures how long the gpu spends on each frame's command buffer using timestamp queries
+#[derive(Resource)]
+pub struct GpuTimer {
+    device: ConstPtr<Device>,
+    query_pool: vk::QueryPool,
+    // nanoseconds per timestamp tick
+    timestamp_period: f32,
+    // the bits of the timestamps the graphics queue writes, the rest are undefined
+    timestamp_mask: u64,
+    // whether a frame slot has timestamps waiting to be read
+    written: [bool; MAX_FRAMES_IN_FLIGHT],
+}
+
+impl GpuTimer {
+    // returns None if the device can't write timestamps on the graphics queue
+    pub fn create(device: ConstPtr<Device>, physical_device: &PhysicalDevice) -> Option<GpuTimer> {
+        let limits = &physical_device.device_properties.limits;
+        let timestamp_valid_bits = physical_device.graphics_timestamp_valid_bits();
+        if limits.timestamp_compute_and_graphics != vk::TRUE || limits.timestamp_period <= 0.0 || timestamp_valid_bits == 0 {
+            return None;
+        }
+        // a start and end timestamp per frame in flight
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(2 * MAX_FRAMES_IN_FLIGHT as u32);
+        let query_pool = unsafe { device.create_query_pool(&create_info, None) }
+            .expect("Failed to create timestamp query pool");
+        Some(GpuTimer {
+            device,
+            query_pool,
+            timestamp_period: limits.timestamp_period,
+            timestamp_mask: if timestamp_valid_bits >= 64 { u64::MAX } else { (1 << timestamp_valid_bits) - 1 },
+            written: [false; MAX_FRAMES_IN_FLIGHT],
+        })
+    }
+
+    // must be recorded outside of rendering, before any other commands of the frame
+    pub fn cmd_begin(&mut self, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        let slot = frame_index % MAX_FRAMES_IN_FLIGHT;
+        unsafe {
+            self.device.cmd_reset_query_pool(command_buffer, self.query_pool, 2 * slot as u32, 2);
+            self.device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, self.query_pool, 2 * slot as u32);
+        }
+    }
+
+    pub fn cmd_end(&mut self, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        let slot = frame_index % MAX_FRAMES_IN_FLIGHT;
+        unsafe { self.device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, self.query_pool, 2 * slot as u32 + 1); }
+        self.written[slot] = true;
+    }
+
+    // the gpu time of the last frame recorded in this slot, only valid once its fence has signaled
+    pub fn take_milliseconds(&mut self, frame_index: usize) -> Option<f64> {
+        let slot = frame_index % MAX_FRAMES_IN_FLIGHT;
+        if !std::mem::take(&mut self.written[slot]) {
+            return None;
+        }
+        let mut timestamps = [0u64; 2];
+        unsafe { self.device.get_query_pool_results(self.query_pool, 2 * slot as u32, 2, &mut timestamps, vk::QueryResultFlags::TYPE_64) }
+            .expect("Failed to read timestamp queries");
+        // wrapping so a counter that overflowed between the two timestamps still gives the right difference
+        let ticks = (timestamps[1] & self.timestamp_mask).wrapping_sub(timestamps[0] & self.timestamp_mask) & self.timestamp_mask;
+        Some(ticks as f64 * self.timestamp_period as f64 / 1_000_000.0)
+    }
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_query_pool(self.query_pool, None); }
+    }
+}
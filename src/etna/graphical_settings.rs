@@ -1,20 +1,175 @@
 use ash::vk;
+use bevy_ecs::system::Resource;
 
-#[derive(Debug, Copy, Clone)]
+/// What the renderer draws with, the ui edits this resource while the frame renderer reads it
+#[derive(Resource, Debug, Copy, Clone, PartialEq)]
 pub struct GraphicsSettings {
     // sample more than 1 will enable multisampling
     pub msaa_samples: MsaaSamples,
-    // sample rate shading makes shaders be multi-sampled, not just geometry, but at a performance cost
+    // sample rate shading makes shaders be multi-sampled, not just geometry, but at a performance cost. Only has an
+    // effect with msaa enabled, and as it's baked into the pipelines they are all rebuilt when it changes
     pub sample_rate_shading_enabled: bool,
+    // reverse z maps the near plane to a depth of 1 and the far plane to 0 for better precision in the distance.
+    // Every pipeline, the depth clear value and the camera projection must agree on this for depth testing to work
+    pub reverse_z: bool,
+    // default anisotropy for newly created texture samplers, clamped to the device max when used
+    pub anisotropy_level: AnisotropyLevel,
+    // the filtering for glTF textures whose sampler leaves it unspecified, applied when the model is loaded
+    pub default_sampler: DefaultSampler,
+    // writes a logarithmic depth from the fragment shaders to avoid z-fighting over very large view distances,
+    // at the cost of early depth testing
+    pub logarithmic_depth: bool,
+    // materials sample their textures from a single descriptor indexed array, only set when the device supports it.
+    // Fixed at device creation as the required features must be enabled on the logical device
+    pub bindless_textures: bool,
+    // skips drawing objects whose bounding boxes were hidden behind the depth buffer, using the query results from
+    // the last time the frame's data was used so objects can pop in a couple of frames late
+    pub occlusion_culling: bool,
+    // which of the pbr inputs the pbr shaders output, for checking imported materials
+    pub debug_render_mode: DebugRenderMode,
+    // when off the pbr shaders ignore normal maps and shade with the interpolated vertex normal
+    pub normal_mapping: bool,
+    // draws only the sky box, for checking a loaded environment without any geometry in front of it
+    pub environment_only: bool,
+    // how many threads record the scene's draws into secondary command buffers, 1 records them inline. Fixed when
+    // each window's frame renderer is created, as that is when the per thread command pools are made
+    pub recording_threads: usize,
+    // textures wider or taller than this are downscaled when loaded, keeping their aspect ratio. The device's max
+    // image dimension always applies on top of it
+    pub max_texture_dimension: u32,
+    // requests an HDR10 or scRGB swapchain when the surface supports one, otherwise the output stays sRGB. Fixed once
+    // the first swapchain is created, as the surface keeps the format it chose then
+    pub hdr_output: bool,
+    // the luminance in nits that 1.0 maps to on an hdr swapchain, where sRGB white and the ui sit
+    pub hdr_paper_white_nits: f32,
+    // the brightest the hdr display can show, the scene is tonemapped to this rather than to paper white
+    pub hdr_peak_nits: f32,
+    // the most frames rendered a second, 0 leaves it uncapped. Paced by sleeping after present, so it applies on top
+    // of whatever the present mode allows
+    pub frame_rate_cap: u32,
+    // model textures are loaded with only their small mips at full detail, the larger ones are streamed in once the
+    // objects using them come close to the camera
+    pub texture_streaming: bool,
+    // draws the edges of every object's triangles over it, objects with ShowWireframe get them when this is off
+    pub wireframe_overlay: bool,
+    // whether the main window's scene commands are recorded every frame or kept while the scene is static
+    pub command_buffer_reuse: CommandBufferReuse,
+    // scales the scene's exposure so its average brightness shows as middle gray, measured from a luminance histogram of
+    // each frame. Off leaves the exposure at 1
+    pub auto_exposure: bool,
+    // the darkest and brightest average scene luminance auto exposure adapts to, as EV100. Scenes outside the range are
+    // left darker or brighter, and the histogram only measures pixels within it
+    pub auto_exposure_min_ev: f32,
+    pub auto_exposure_max_ev: f32,
+    // how quickly the exposure follows a change in brightness, at 1 it covers about two thirds of a change each second
+    pub auto_exposure_adaptation_speed: f32,
+    // clears the scene to transparent black and skips the sky box, so screenshots keep an alpha channel that is 0
+    // wherever nothing was drawn
+    pub transparent_background: bool,
 }
 
 impl GraphicsSettings {
     pub fn is_msaa_enabled(&self) -> bool {
         !matches!(&self.msaa_samples, MsaaSamples::X1)
     }
+
+    pub fn depth_compare_op(&self) -> vk::CompareOp {
+        if self.reverse_z {
+            vk::CompareOp::GREATER_OR_EQUAL
+        } else {
+            vk::CompareOp::LESS_OR_EQUAL
+        }
+    }
+
+    pub fn clear_color(&self) -> [f32; 4] {
+        if self.transparent_background {
+            [0.0, 0.0, 0.0, 0.0]
+        } else {
+            [0.52, 0.8, 0.92, 1.0]
+        }
+    }
+
+    pub fn depth_clear_value(&self) -> f32 {
+        if self.reverse_z {
+            0.0
+        } else {
+            1.0
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebugRenderMode {
+    Final,
+    Normals,
+    Albedo,
+    Roughness,
+    Metallic,
+    AmbientOcclusion,
+    Emissive,
+    VertexNormals,
+    Tangents,
+    TexCoords,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl DebugRenderMode {
+    pub const ALL: [DebugRenderMode; 10] = [
+        Self::Final,
+        Self::Normals,
+        Self::Albedo,
+        Self::Roughness,
+        Self::Metallic,
+        Self::AmbientOcclusion,
+        Self::Emissive,
+        Self::VertexNormals,
+        Self::Tangents,
+        Self::TexCoords,
+    ];
+
+    // MUST KEEP IN SYNC WITH the debug modes in pbr_shading.glsl
+    pub fn shader_value(&self) -> f32 {
+        match self {
+            Self::Final => 0.0,
+            Self::Normals => 1.0,
+            Self::Albedo => 2.0,
+            Self::Roughness => 3.0,
+            Self::Metallic => 4.0,
+            Self::AmbientOcclusion => 5.0,
+            Self::Emissive => 6.0,
+            Self::VertexNormals => 7.0,
+            Self::Tangents => 8.0,
+            Self::TexCoords => 9.0,
+        }
+    }
+}
+
+/// How the main window records the commands drawing the scene. The upscale and ui are small and change every frame, so
+/// they are always recorded
+///
+/// Reused scene commands are recorded again when any of these change, as they are baked into the commands:
+/// - an actor or render object's transform, mesh, material or children, or one being added or removed
+/// - an object's ShowWireframe being added or removed
+/// - the loaded assets, such as a model being uploaded or the environment being replaced
+/// - a material pipeline being built, as shader reloads and some settings do
+/// - the swapchain being recreated
+/// - any graphics setting
+///
+/// The camera, lights, environment and fog settings, material values and material animations are read from buffers
+/// written every frame, so changing them doesn't need the commands recorded again. Scenes with particle emitters,
+/// gizmos or world labels, or with occlusion culling enabled, change what is drawn every frame so are always recorded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CommandBufferReuse {
+    // resets the frame's command buffer and records the whole scene every frame
+    RecordEveryFrame,
+    // submits the scene commands recorded for the frame's data again while nothing above has changed
+    ReuseWhileStatic,
+}
+
+impl CommandBufferReuse {
+    pub const ALL: [CommandBufferReuse; 2] = [Self::RecordEveryFrame, Self::ReuseWhileStatic];
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum MsaaSamples {
     X1,
     X2,
@@ -37,4 +192,61 @@ impl MsaaSamples {
             Self::X64 => vk::SampleCountFlags::TYPE_64,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Filtering for textures that don't specify their own, as glTF samplers may leave the min and mag filters out
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DefaultSampler {
+    // linear within and between mip levels, with the anisotropy from the graphics settings
+    Trilinear,
+    // keeps texels sharp edged, for pixel art
+    Nearest,
+}
+
+impl DefaultSampler {
+    pub const ALL: [DefaultSampler; 2] = [Self::Trilinear, Self::Nearest];
+
+    pub fn filter(&self) -> vk::Filter {
+        match self {
+            Self::Trilinear => vk::Filter::LINEAR,
+            Self::Nearest => vk::Filter::NEAREST,
+        }
+    }
+
+    pub fn mip_map_mode(&self) -> vk::SamplerMipmapMode {
+        match self {
+            Self::Trilinear => vk::SamplerMipmapMode::LINEAR,
+            Self::Nearest => vk::SamplerMipmapMode::NEAREST,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AnisotropyLevel {
+    X1,
+    X2,
+    X4,
+    X8,
+    X16,
+}
+
+impl AnisotropyLevel {
+    pub const ALL: [AnisotropyLevel; 5] = [Self::X1, Self::X2, Self::X4, Self::X8, Self::X16];
+
+    pub fn to_f32(&self) -> f32 {
+        match self {
+            Self::X1 => 1.0,
+            Self::X2 => 2.0,
+            Self::X4 => 4.0,
+            Self::X8 => 8.0,
+            Self::X16 => 16.0,
+        }
+    }
+
+    pub fn highest_supported(max_sampler_anisotropy: f32) -> AnisotropyLevel {
+        Self::ALL.into_iter()
+            .filter(|level| level.to_f32() <= max_sampler_anisotropy)
+            .last()
+            .unwrap_or(Self::X1)
+    }
+}
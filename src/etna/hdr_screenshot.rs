@@ -0,0 +1,65 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy_ecs::prelude::*;
+use image::{ImageFormat, Rgba32FImage, RgbaImage};
+use log::{error, info, warn};
+
+use crate::etna::{CommandPool, Swapchain};
+use crate::rehnda_core::input::InputState;
+use crate::rehnda_core::input_bindings::InputAction;
+
+// where the screenshot keys write, each named by the time it was taken
+const SCREENSHOT_DIR: &str = "screenshots";
+
+// writes linear colors to an OpenEXR file, keeping values above 1.0 and the alpha
+pub fn save_hdr_image(path: &Path, width: u32, height: u32, colors: Vec<[f32; 4]>) -> Result<(), String> {
+    let image = Rgba32FImage::from_raw(width, height, colors.into_iter().flatten().collect())
+        .ok_or("The colors don't fill the image")?;
+    image.save_with_format(path, ImageFormat::OpenExr).map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+// saves the scene before the ui is drawn over it, at the render extent. Only an HDR swapchain renders the scene to a
+// float target, the others have already been clamped to 1.0 so aren't worth saving
+pub fn hdr_screenshot_system(input_state: Res<InputState>, swapchain: Res<Swapchain>, command_pool: Res<CommandPool>) {
+    if !input_state.is_action_just_down(InputAction::HdrScreenshot) {
+        return;
+    }
+    let Some(colors) = swapchain.read_scene_colors(&command_pool) else {
+        warn!("The scene is only rendered to a float target with an scRGB swapchain, there is no HDR image to save");
+        return;
+    };
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let path = Path::new(SCREENSHOT_DIR).join(format!("scene_{}.exr", timestamp));
+    let extent = swapchain.render_extent();
+    let result = std::fs::create_dir_all(SCREENSHOT_DIR)
+        .map_err(|err| format!("Failed to create {}: {}", SCREENSHOT_DIR, err))
+        .and_then(|_| save_hdr_image(&path, extent.width, extent.height, colors));
+    match result {
+        Ok(()) => info!("Saved an HDR screenshot to {}", path.display()),
+        Err(err) => error!("Failed to save an HDR screenshot: {}", err),
+    }
+}
+
+// saves the scene before the ui is drawn over it as an sRGB PNG at the render extent. The alpha is kept, so with
+// GraphicsSettings::transparent_background it is 0 wherever nothing was drawn
+pub fn screenshot_system(input_state: Res<InputState>, swapchain: Res<Swapchain>, command_pool: Res<CommandPool>) {
+    if !input_state.is_action_just_down(InputAction::Screenshot) {
+        return;
+    }
+    let Some(pixels) = swapchain.read_scene_rgba8(&command_pool) else {
+        warn!("The scene's {:?} format can't be saved as a PNG, use the HDR screenshot instead", swapchain.scene_image.format);
+        return;
+    };
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let path = Path::new(SCREENSHOT_DIR).join(format!("scene_{}.png", timestamp));
+    let extent = swapchain.render_extent();
+    let result = std::fs::create_dir_all(SCREENSHOT_DIR)
+        .map_err(|err| format!("Failed to create {}: {}", SCREENSHOT_DIR, err))
+        .and_then(|_| RgbaImage::from_raw(extent.width, extent.height, pixels).ok_or_else(|| "The pixels don't fill the image".to_string()))
+        .and_then(|image| image.save(&path).map_err(|err| format!("Failed to write {}: {}", path.display(), err)));
+    match result {
+        Ok(()) => info!("Saved a screenshot to {}", path.display()),
+        Err(err) => error!("Failed to save a screenshot: {}", err),
+    }
+}
@@ -1,15 +1,15 @@
 use std::ffi::CString;
 use std::mem::size_of;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use ash::vk;
 use ash::vk::{CommandBuffer, DescriptorSet, Extent2D};
 use bytemuck_derive::{Pod, Zeroable};
+use serde::Deserialize;
 use crevice::std140::{AsStd140, Std140};
-use image::{EncodableLayout};
+use image::{DynamicImage, EncodableLayout, ImageFormat, Rgba32FImage};
 use lazy_static::lazy_static;
 use crate::assets::{cube, vulkan_projection_matrix};
-use crate::etna::{Buffer, BufferCreateInfo, CommandPool, Device, FramebufferCreateInfo, GraphicsSettings, HostMappedBuffer, HostMappedBufferCreateInfo, Image, image_transitions, ImageCreateInfo, ImageType, MsaaSamples, PhysicalDevice, SamplerOptions, TexSamplerOptions, Texture, TextureCreateInfo};
-use crate::etna::image_transitions::{transition_image_layout, TransitionProps};
+use crate::etna::{AnisotropyLevel, Buffer, BufferCreateInfo, CommandBufferReuse, CommandPool, DebugRenderMode, DefaultSampler, Device, FramebufferCreateInfo, GraphicsSettings, HostMappedBuffer, HostMappedBufferCreateInfo, Image, ImageCreateInfo, ImageType, Ktx2Image, MsaaSamples, PhysicalDevice, SamplerOptions, TexSamplerOptions, Texture, TextureCreateInfo};
 use crate::etna::material_pipeline::{DescriptorManager, layout_binding, MaterialPipeline, PipelineCreateInfo, PipelineMultisamplingInfo, PipelineVertexInputDescription, RasterizationOptions};
 use crate::etna::shader::ShaderModule;
 use crate::rehnda_core::{ConstPtr, Mat4};
@@ -29,7 +29,8 @@ impl Drop for CubeMapTexture {
 }
 
 impl CubeMapTexture {
-    pub fn create(device: ConstPtr<Device>, image: Image, descriptor_manager: &mut DescriptorManager) -> Self {
+    // the sampler can reach every one of the image's mip levels, the prefiltered specular map stores roughness in them
+    pub fn create(device: ConstPtr<Device>, image: Image, mip_levels: u32) -> Self {
         let sampler_ci = vk::SamplerCreateInfo::builder()
             .mag_filter(vk::Filter::LINEAR)
             .min_filter(vk::Filter::LINEAR)
@@ -44,7 +45,7 @@ impl CubeMapTexture {
             .compare_op(vk::CompareOp::ALWAYS)
             .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
             .min_lod(0.0)
-            .max_lod(1.0)
+            .max_lod(mip_levels as f32)
             .mip_lod_bias(0.0)
             .build()
             ;
@@ -69,12 +70,122 @@ pub struct CubeMapManager {
 }
 
 const HDR_CUBE_MAP_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
-const SKY_BOX_RESOLUTION: u32 = 4096;
-const DIFFUSE_MAP_RESOLUTION: u32 = 256;
-const SPECULAR_MAP_RESOLUTION: u32 = 512;
-const SPECULAR_MAX_MIP_LEVELS: u32 = 5;
+const SKY_BOX_FACE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
 const BRDF_LUT_TEXTURE_RESOLUTION: u32 = 512;
 
+// the files making up a baked environment directory
+const BAKED_SKY_BOX_FILE: &str = "sky_box.ktx2";
+const BAKED_IRRADIANCE_FILE: &str = "irradiance.ktx2";
+const BAKED_PREFILTER_FILE: &str = "prefilter.ktx2";
+const BAKED_BRDF_LUT_FILE: &str = "brdf_lut.ktx2";
+// the equirectangular image the maps were baked from, rendered from at runtime when the baked maps can't be loaded
+const BAKED_SOURCE_FILES: [&str; 2] = ["source.hdr", "source.exr"];
+
+/// The separate files of an environment baked offline. One KTX2 file can't hold images of different sizes, so each map
+/// is kept in its own uncompressed KTX2 file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BakedEnvironmentFiles {
+    pub sky_box: PathBuf,
+    pub irradiance: PathBuf,
+    // must have all of the specular map's roughness mips
+    pub prefilter: PathBuf,
+    // rendered when missing, as it doesn't depend on the environment
+    pub brdf_lut: Option<PathBuf>,
+    // an equirectangular .hdr or .exr image to render the maps from instead when the baked ones can't be loaded
+    pub source: Option<PathBuf>,
+}
+
+impl BakedEnvironmentFiles {
+    /// The files `EnvironmentMaps::export` writes to a directory: `sky_box.ktx2`, `irradiance.ktx2`, `prefilter.ktx2`
+    /// and `brdf_lut.ktx2`, along with an optional `source.hdr` or `source.exr` placed there by hand
+    pub fn in_dir(dir: &Path) -> Self {
+        let existing = |file_name: &str| Some(dir.join(file_name)).filter(|path| path.exists());
+        Self {
+            sky_box: dir.join(BAKED_SKY_BOX_FILE),
+            irradiance: dir.join(BAKED_IRRADIANCE_FILE),
+            prefilter: dir.join(BAKED_PREFILTER_FILE),
+            brdf_lut: existing(BAKED_BRDF_LUT_FILE),
+            source: BAKED_SOURCE_FILES.iter().find_map(|file_name| existing(file_name)),
+        }
+    }
+}
+
+/// The resolutions the environment maps are rendered at, trading the time to create them at startup for quality
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IblSettings {
+    pub sky_box_resolution: u32,
+    pub diffuse_map_resolution: u32,
+    pub specular_map_resolution: u32,
+    // the roughness range is spread over this many mips of the specular map
+    pub specular_mip_levels: u32,
+    // the environment samples averaged for each texel of the specular map's rough mips, fewer is faster but noisier.
+    // The smooth first mip is a direct copy of the sky box so doesn't sample
+    pub specular_sample_count: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize)]
+pub enum IblQuality {
+    Low,
+    Medium,
+    #[default]
+    High,
+}
+
+impl IblSettings {
+    pub fn preset(quality: IblQuality) -> Self {
+        match quality {
+            IblQuality::Low => Self {
+                sky_box_resolution: 1024,
+                diffuse_map_resolution: 32,
+                specular_map_resolution: 128,
+                specular_mip_levels: 5,
+                specular_sample_count: 256,
+            },
+            IblQuality::Medium => Self {
+                sky_box_resolution: 2048,
+                diffuse_map_resolution: 64,
+                specular_map_resolution: 256,
+                specular_mip_levels: 5,
+                specular_sample_count: 512,
+            },
+            IblQuality::High => Self {
+                sky_box_resolution: 4096,
+                diffuse_map_resolution: 256,
+                specular_map_resolution: 512,
+                specular_mip_levels: 5,
+                specular_sample_count: 1024,
+            },
+        }
+    }
+
+    // cube maps are limited by the cube dimension limit as well as the 2D one
+    pub fn validate(&self, limits: &vk::PhysicalDeviceLimits) -> Result<(), String> {
+        for (name, resolution) in [("sky box", self.sky_box_resolution), ("diffuse map", self.diffuse_map_resolution), ("specular map", self.specular_map_resolution)] {
+            if !resolution.is_power_of_two() {
+                return Err(format!("The {} resolution {} is not a power of two", name, resolution));
+            }
+        }
+        let max_resolution = limits.max_image_dimension2_d.min(limits.max_image_dimension_cube);
+        if self.sky_box_resolution > max_resolution {
+            return Err(format!("The sky box resolution {} is larger than the device's max of {}", self.sky_box_resolution, max_resolution));
+        }
+        let max_mip_levels = self.specular_map_resolution.ilog2() + 1;
+        if self.specular_mip_levels == 0 || self.specular_mip_levels > max_mip_levels {
+            return Err(format!("The specular map needs between 1 and {} mip levels, not {}", max_mip_levels, self.specular_mip_levels));
+        }
+        if self.specular_sample_count == 0 {
+            return Err("The specular map needs at least 1 sample".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for IblSettings {
+    fn default() -> Self {
+        Self::preset(IblQuality::default())
+    }
+}
+
 pub struct EnvironmentMaps {
     pub sky_box_texture: CubeMapTexture,
     pub sky_box_descriptor_set: vk::DescriptorSet,
@@ -84,11 +195,107 @@ pub struct EnvironmentMaps {
     pub ibl_descriptor_set: vk::DescriptorSet,
 }
 
+impl EnvironmentMaps {
+    /// Writes the maps to a directory that `BakedEnvironmentFiles::in_dir` finds, so the maps only have to be rendered
+    /// once
+    pub fn export(&self, command_pool: &CommandPool, dir: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(dir).map_err(|err| format!("Failed to create {}: {}", dir.display(), err))?;
+        let device = self.sky_box_texture.device;
+        read_back_image(device, command_pool, &self.sky_box_texture.image, 6).save(&dir.join(BAKED_SKY_BOX_FILE))?;
+        read_back_image(device, command_pool, &self.irradiance_map_texture.image, 6).save(&dir.join(BAKED_IRRADIANCE_FILE))?;
+        read_back_image(device, command_pool, &self.prefilter_map_texture.image, 6).save(&dir.join(BAKED_PREFILTER_FILE))?;
+        read_back_image(device, command_pool, &self.brdf_lut_texture.image, 1).save(&dir.join(BAKED_BRDF_LUT_FILE))
+    }
+}
+
+// copies every face and mip level of an image that is ready for sampling back to the cpu
+fn read_back_image(device: ConstPtr<Device>, command_pool: &CommandPool, image: &Image, face_count: u32) -> Ktx2Image {
+    let texel_size = match image.format {
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        vk::Format::R16G16_SFLOAT | vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => 4,
+        format => panic!("Can't read back images with the format {:?}", format),
+    };
+    let level_extent = |level: u32| ((image.width >> level).max(1), (image.height >> level).max(1));
+    let level_sizes: Vec<usize> = (0..image.mip_levels).map(|level| {
+        let (width, height) = level_extent(level);
+        (width * height * texel_size * face_count) as usize
+    }).collect();
+    let readback_buffer = Buffer::create_readback_buffer(device, level_sizes.iter().sum::<usize>() as u64);
+
+    let mut copy_regions = Vec::new();
+    let mut level_offset = 0;
+    for (mip_level, level_size) in level_sizes.iter().enumerate() {
+        let (width, height) = level_extent(mip_level as u32);
+        let face_size = level_size / face_count as usize;
+        copy_regions.extend((0..face_count).map(|face_index| {
+            vk::BufferImageCopy::builder()
+                .buffer_offset((level_offset + face_index as usize * face_size) as u64)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(mip_level as u32)
+                    .base_array_layer(face_index)
+                    .layer_count(1)
+                    .build()
+                )
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D { width, height, depth: 1 })
+                .build()
+        }));
+        level_offset += level_size;
+    }
+
+    let command_buffer = command_pool.one_time_command_buffer();
+    image.transition_subresources_to(*command_buffer, 0..image.mip_levels, 0..face_count, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ);
+    unsafe { device.cmd_copy_image_to_buffer(*command_buffer, image.vk_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, readback_buffer.buffer, &copy_regions) };
+    image.transition_subresources_to(*command_buffer, 0..image.mip_levels, 0..face_count, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ);
+    command_buffer.submit_and_wait().expect("Failed to copy cube map for readback");
+
+    let data = readback_buffer.read_data();
+    let mut level_offset = 0;
+    let levels = level_sizes.iter().map(|level_size| {
+        let level = data[level_offset..level_offset + level_size].to_vec();
+        level_offset += level_size;
+        level
+    }).collect();
+    Ktx2Image {
+        format: image.format,
+        width: image.width,
+        height: image.height,
+        face_count,
+        levels,
+    }
+}
+
 impl CubeMapManager {
     pub fn create(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, command_pool: &CommandPool) -> Self {
         let settings = GraphicsSettings {
             msaa_samples: MsaaSamples::X1,
             sample_rate_shading_enabled: false,
+            reverse_z: false,
+            anisotropy_level: AnisotropyLevel::X1,
+            default_sampler: DefaultSampler::Trilinear,
+            logarithmic_depth: false,
+            bindless_textures: false,
+            occlusion_culling: false,
+            debug_render_mode: DebugRenderMode::Final,
+            normal_mapping: true,
+            environment_only: false,
+            recording_threads: 1,
+            max_texture_dimension: u32::MAX,
+            hdr_output: false,
+            hdr_paper_white_nits: 200.0,
+            hdr_peak_nits: 1000.0,
+            frame_rate_cap: 0,
+            texture_streaming: false,
+            wireframe_overlay: false,
+            command_buffer_reuse: CommandBufferReuse::RecordEveryFrame,
+            auto_exposure: false,
+            auto_exposure_min_ev: -4.0,
+            auto_exposure_max_ev: 12.0,
+            auto_exposure_adaptation_speed: 1.5,
+            transparent_background: false,
         };
         let prefilter_params_buffer = descriptor_manager.layout_cache.create_descriptor_layout_for_binding(&[
             layout_binding(0, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::FRAGMENT),
@@ -121,62 +328,124 @@ impl CubeMapManager {
         }
     }
 
-    pub fn create_environment_maps(&self, physical_device: &PhysicalDevice, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, path: &Path) -> EnvironmentMaps {
-        let (_equirectangular_texture, equirectangular_texture_descriptor_set) = self.load_equirectangular_texture(physical_device, command_pool, descriptor_manager, path);
+    pub fn create_environment_maps(&self, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, path: &Path, settings: &IblSettings) -> EnvironmentMaps {
+        settings.validate(&physical_device.device_properties.limits).unwrap_or_else(|err| panic!("Invalid IBL settings: {}", err));
+        let (_equirectangular_texture, equirectangular_texture_descriptor_set) = self.load_equirectangular_texture(physical_device, graphics_settings, command_pool, descriptor_manager, path);
 
         let sky_box_buffer = command_pool.one_time_command_buffer();
 
         // render skybox to cube map
-        let sky_box_image = self.create_cube_image_ready_to_render_to(SKY_BOX_RESOLUTION, *sky_box_buffer, 1);
+        let sky_box_image = self.create_cube_image_ready_to_render_to(settings.sky_box_resolution, *sky_box_buffer, 1);
         let projection_matrix = vulkan_projection_matrix(90.0f32.to_radians(), 1.0, 0.1, 10.0);
         for i in 0..6 {
             draw_cube_face(&self.device, command_pool, &DrawCubeFaceInfo {
                 cube_image: sky_box_image.vk_image,
                 face_index: i,
                 cube_vertex_buffer: &self.cube_vertex_buffer,
-                resolution: SKY_BOX_RESOLUTION,
+                resolution: settings.sky_box_resolution,
                 projection_matrix,
                 view_matrix: CUBE_CAPTURE_VIEWS[i],
                 pipeline: &self.cube_map_pipeline,
                 descriptor_sets: std::slice::from_ref(&equirectangular_texture_descriptor_set),
             });
         }
-        self.transition_image_for_sampling(*sky_box_buffer, &sky_box_image, 1);
-        drop(sky_box_buffer);
+        sky_box_image.transition_to(*sky_box_buffer, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ);
+        sky_box_buffer.submit_and_wait().expect("Failed to draw sky box");
 
-        let sky_box_texture = CubeMapTexture::create(self.device, sky_box_image, descriptor_manager);
-        let sky_box_image_info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(sky_box_texture.image.image_view)
-            .sampler(sky_box_texture.sampler);
+        let sky_box_texture = CubeMapTexture::create(self.device, sky_box_image, 1);
+        self.create_environment_maps_from_sky_box(physical_device, graphics_settings, command_pool, descriptor_manager, sky_box_texture, settings)
+    }
 
-        let (sky_box_descriptor_set, _descriptor_set_layout) = descriptor_manager.descriptor_builder()
-            .bind_image(0, sky_box_image_info, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)
-            .build()
-            .expect("Failed to allocate bindings");
+    // the sky box keeps the resolution of the faces, the settings only apply to the maps rendered from it
+    pub fn create_environment_maps_from_faces(&self, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, face_paths: [&Path; 6], settings: &IblSettings) -> EnvironmentMaps {
+        settings.validate(&physical_device.device_properties.limits).unwrap_or_else(|err| panic!("Invalid IBL settings: {}", err));
+        let sky_box_texture = self.load_cube_from_faces(physical_device, command_pool, face_paths);
+        self.create_environment_maps_from_sky_box(physical_device, graphics_settings, command_pool, descriptor_manager, sky_box_texture, settings)
+    }
+
+    /// Loads a cube map from six face images, given in the vulkan layer order +X, -X, +Y, -Y, +Z, -Z
+    pub fn load_cube_from_faces(&self, physical_device: &PhysicalDevice, command_pool: &CommandPool, face_paths: [&Path; 6]) -> CubeMapTexture {
+        let faces: Vec<image::RgbaImage> = face_paths.iter()
+            .map(|path| image::open(path).unwrap_or_else(|err| panic!("Failed to open cube face {}: {}", path.display(), err)).to_rgba8())
+            .collect();
+        let resolution = faces[0].width();
+        for (face, path) in faces.iter().zip(face_paths.iter()) {
+            assert_eq!(face.width(), face.height(), "Cube face {} is not square", path.display());
+            assert_eq!(face.width(), resolution, "Cube face {} is not the same size as the other faces", path.display());
+        }
+
+        let face_size = faces[0].as_bytes().len();
+        let data: Vec<u8> = faces.iter().flat_map(|face| face.as_bytes().iter().copied()).collect();
+        let staging_buffer = Buffer::create_buffer_with_data(self.device, BufferCreateInfo {
+            data: data.as_slice(),
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+        });
+        let mip_levels = resolution.ilog2() + 1;
+        let cube_image = Image::create_image(self.device, &ImageCreateInfo {
+            image_type: ImageType::Cube,
+            width: resolution,
+            height: resolution,
+            format: SKY_BOX_FACE_FORMAT,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            mip_levels,
+            memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            image_aspect_flags: vk::ImageAspectFlags::COLOR,
+            num_samples: vk::SampleCountFlags::TYPE_1,
+            create_flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        });
+
+        let command_buffer = command_pool.one_time_command_buffer();
+        cube_image.transition_to(*command_buffer, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE);
+        let copy_regions: Vec<vk::BufferImageCopy> = (0..6u32).map(|face_index| {
+            vk::BufferImageCopy::builder()
+                .buffer_offset((face_index as usize * face_size) as u64)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(face_index)
+                    .layer_count(1)
+                    .build()
+                )
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D { width: resolution, height: resolution, depth: 1 })
+                .build()
+        }).collect();
+        unsafe { self.device.cmd_copy_buffer_to_image(*command_buffer, staging_buffer.buffer, cube_image.vk_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &copy_regions) };
+        Texture::generate_mipmaps(&self.device, physical_device, &cube_image, resolution, resolution, mip_levels, 6, *command_buffer);
+        command_buffer.submit_and_wait().expect("Failed to upload cube map");
+
+        CubeMapTexture::create(self.device, cube_image, mip_levels)
+    }
+
+    fn create_environment_maps_from_sky_box(&self, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, sky_box_texture: CubeMapTexture, settings: &IblSettings) -> EnvironmentMaps {
+        let projection_matrix = vulkan_projection_matrix(90.0f32.to_radians(), 1.0, 0.1, 10.0);
+        let sky_box_descriptor_set = Self::create_sky_box_descriptor_set(descriptor_manager, &sky_box_texture);
 
         let diffuse_buffer = command_pool.one_time_command_buffer();
         // render diffuse map
-        let diffuse_map_image = self.create_cube_image_ready_to_render_to(DIFFUSE_MAP_RESOLUTION, *diffuse_buffer, 1);
+        let diffuse_map_image = self.create_cube_image_ready_to_render_to(settings.diffuse_map_resolution, *diffuse_buffer, 1);
         for i in 0..6 {
             draw_cube_face(&self.device, command_pool, &DrawCubeFaceInfo {
                 cube_image: diffuse_map_image.vk_image,
                 face_index: i,
                 cube_vertex_buffer: &self.cube_vertex_buffer,
-                resolution: DIFFUSE_MAP_RESOLUTION,
+                resolution: settings.diffuse_map_resolution,
                 projection_matrix,
                 view_matrix: CUBE_CAPTURE_VIEWS[i],
                 pipeline: &self.diffuse_map_pipeline,
                 descriptor_sets: std::slice::from_ref(&sky_box_descriptor_set),
             });
         }
-        self.transition_image_for_sampling(*diffuse_buffer, &diffuse_map_image, 1);
-        drop(diffuse_buffer);
-        let diffuse_map_texture = CubeMapTexture::create(self.device, diffuse_map_image, descriptor_manager);
+        diffuse_map_image.transition_to(*diffuse_buffer, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ);
+        diffuse_buffer.submit_and_wait().expect("Failed to draw diffuse irradiance map");
+        let diffuse_map_texture = CubeMapTexture::create(self.device, diffuse_map_image, 1);
 
         let specular_buffer = command_pool.one_time_command_buffer();
         // render diffuse map
-        let specular_map_image = self.create_cube_image_ready_to_render_to(SPECULAR_MAP_RESOLUTION, *specular_buffer, SPECULAR_MAX_MIP_LEVELS);
+        let specular_map_image = self.create_cube_image_ready_to_render_to(settings.specular_map_resolution, *specular_buffer, settings.specular_mip_levels);
         let prefilter_params_buffer = HostMappedBuffer::create(self.device, HostMappedBufferCreateInfo {
             size: PrefilterParams::std140_size_static() as u64,
             usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
@@ -195,19 +464,203 @@ impl CubeMapManager {
                 cube_image: specular_map_image.vk_image,
                 face_index: i,
                 cube_vertex_buffer: &self.cube_vertex_buffer,
-                resolution: SPECULAR_MAP_RESOLUTION,
+                resolution: settings.specular_map_resolution,
                 projection_matrix,
                 view_matrix: CUBE_CAPTURE_VIEWS[i],
                 pipeline: &self.prefilter_map_pipeline,
                 descriptor_sets: &[sky_box_descriptor_set, prefilter_params_set],
-            }, &prefilter_params_buffer);
+            }, &prefilter_params_buffer, settings.specular_mip_levels, settings.specular_sample_count);
+        }
+        specular_map_image.transition_to(*specular_buffer, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ);
+        specular_buffer.submit_and_wait().expect("Failed to draw specular prefilter map");
+        let specular_map_texture = CubeMapTexture::create(self.device, specular_map_image, settings.specular_mip_levels);
+
+        let brdf_lut_texture = self.draw_brdf_lut(physical_device, graphics_settings, command_pool);
+
+        let ibl_descriptor_set = Self::create_ibl_descriptor_set(descriptor_manager, &diffuse_map_texture, &specular_map_texture, &brdf_lut_texture);
+
+        EnvironmentMaps {
+            sky_box_texture,
+            sky_box_descriptor_set,
+            irradiance_map_texture: diffuse_map_texture,
+            prefilter_map_texture: specular_map_texture,
+            brdf_lut_texture,
+            ibl_descriptor_set,
+        }
+    }
+
+    /// Loads environment maps baked offline instead of rendering them at startup. The prefilter map must have the
+    /// settings' number of specular mips, so it is sampled the same as a rendered one
+    pub fn load_baked_environment(&self, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, files: &BakedEnvironmentFiles, settings: &IblSettings) -> Result<EnvironmentMaps, String> {
+        let sky_box = Ktx2Image::load(&files.sky_box)?;
+        let irradiance = Ktx2Image::load(&files.irradiance)?;
+        let prefilter = Ktx2Image::load(&files.prefilter)?;
+        for (path, cube) in [(&files.sky_box, &sky_box), (&files.irradiance, &irradiance), (&files.prefilter, &prefilter)] {
+            if cube.face_count != 6 {
+                return Err(format!("{} is not a cube map", path.display()));
+            }
+            if cube.width != cube.height {
+                return Err(format!("{} does not have square faces", path.display()));
+            }
+        }
+        if prefilter.level_count() != settings.specular_mip_levels {
+            return Err(format!("{} has {} mip levels, the specular map needs {}", files.prefilter.display(), prefilter.level_count(), settings.specular_mip_levels));
         }
-        self.transition_image_for_sampling(*specular_buffer, &specular_map_image, SPECULAR_MAX_MIP_LEVELS);
-        drop(specular_buffer);
-        let specular_map_texture = CubeMapTexture::create(self.device, specular_map_image, descriptor_manager);
+        let brdf_lut = match &files.brdf_lut {
+            Some(path) => {
+                let brdf_lut = Ktx2Image::load(path)?;
+                if brdf_lut.face_count != 1 {
+                    return Err(format!("{} must be a 2D image", path.display()));
+                }
+                Some(brdf_lut)
+            }
+            None => None,
+        };
 
-        let brdf_lut_texture = self.draw_brdf_lut(physical_device, command_pool);
+        let sky_box_texture = self.upload_baked_cube(command_pool, &sky_box);
+        let irradiance_map_texture = self.upload_baked_cube(command_pool, &irradiance);
+        let prefilter_map_texture = self.upload_baked_cube(command_pool, &prefilter);
+        let brdf_lut_texture = match brdf_lut {
+            Some(brdf_lut) => Texture::create(self.device, physical_device, graphics_settings, command_pool, descriptor_manager, &TextureCreateInfo {
+                width: brdf_lut.width,
+                height: brdf_lut.height,
+                format: brdf_lut.format,
+                mip_levels: None,
+                data: brdf_lut.levels[0].as_slice(),
+                sampler_info: SamplerOptions::FilterOptions(&TexSamplerOptions {
+                    min_filter: Some(vk::Filter::LINEAR),
+                    mag_filter: Some(vk::Filter::LINEAR),
+                    mip_map_mode: None,
+                    address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    anisotropy_level: None,
+                    mip_lod_bias: None,
+                    max_lod: None,
+                }),
+            }),
+            None => self.draw_brdf_lut(physical_device, graphics_settings, command_pool),
+        };
+
+        Ok(EnvironmentMaps {
+            sky_box_descriptor_set: Self::create_sky_box_descriptor_set(descriptor_manager, &sky_box_texture),
+            ibl_descriptor_set: Self::create_ibl_descriptor_set(descriptor_manager, &irradiance_map_texture, &prefilter_map_texture, &brdf_lut_texture),
+            sky_box_texture,
+            irradiance_map_texture,
+            prefilter_map_texture,
+            brdf_lut_texture,
+        })
+    }
 
+    /// Single black texel maps bound in place of an environment while none is loaded, so lit materials can always bind
+    /// their environment set. The pbr shaders use a constant ambient color instead of sampling them
+    pub fn create_default_environment(&self, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager) -> EnvironmentMaps {
+        let black_cube = Ktx2Image {
+            format: vk::Format::R8G8B8A8_UNORM,
+            width: 1,
+            height: 1,
+            face_count: 6,
+            levels: vec![vec![0; 4 * 6]],
+        };
+        let sky_box_texture = self.upload_baked_cube(command_pool, &black_cube);
+        let irradiance_map_texture = self.upload_baked_cube(command_pool, &black_cube);
+        let prefilter_map_texture = self.upload_baked_cube(command_pool, &black_cube);
+        let brdf_lut_texture = Texture::create(self.device, physical_device, graphics_settings, command_pool, descriptor_manager, &TextureCreateInfo {
+            width: 1,
+            height: 1,
+            format: vk::Format::R8G8B8A8_UNORM,
+            mip_levels: None,
+            data: &[0; 4],
+            sampler_info: SamplerOptions::FilterOptions(&TexSamplerOptions {
+                min_filter: None,
+                mag_filter: None,
+                mip_map_mode: None,
+                address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                anisotropy_level: None,
+                mip_lod_bias: None,
+                max_lod: None,
+            }),
+        });
+
+        EnvironmentMaps {
+            sky_box_descriptor_set: Self::create_sky_box_descriptor_set(descriptor_manager, &sky_box_texture),
+            ibl_descriptor_set: Self::create_ibl_descriptor_set(descriptor_manager, &irradiance_map_texture, &prefilter_map_texture, &brdf_lut_texture),
+            sky_box_texture,
+            irradiance_map_texture,
+            prefilter_map_texture,
+            brdf_lut_texture,
+        }
+    }
+
+    // copies every face and mip level of the cube as stored, nothing is generated
+    fn upload_baked_cube(&self, command_pool: &CommandPool, cube: &Ktx2Image) -> CubeMapTexture {
+        let data: Vec<u8> = cube.levels.concat();
+        let staging_buffer = Buffer::create_buffer_with_data(self.device, BufferCreateInfo {
+            data: data.as_slice(),
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+        });
+        let mip_levels = cube.level_count();
+        let cube_image = Image::create_image(self.device, &ImageCreateInfo {
+            image_type: ImageType::Cube,
+            width: cube.width,
+            height: cube.height,
+            format: cube.format,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            mip_levels,
+            memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            image_aspect_flags: vk::ImageAspectFlags::COLOR,
+            num_samples: vk::SampleCountFlags::TYPE_1,
+            create_flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        });
+
+        let mut copy_regions = Vec::new();
+        let mut level_offset = 0;
+        for (mip_level, level) in cube.levels.iter().enumerate() {
+            let (width, height) = cube.level_extent(mip_level as u32);
+            let face_size = level.len() / 6;
+            copy_regions.extend((0..6u32).map(|face_index| {
+                vk::BufferImageCopy::builder()
+                    .buffer_offset((level_offset + face_index as usize * face_size) as u64)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(vk::ImageSubresourceLayers::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(mip_level as u32)
+                        .base_array_layer(face_index)
+                        .layer_count(1)
+                        .build()
+                    )
+                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                    .image_extent(vk::Extent3D { width, height, depth: 1 })
+                    .build()
+            }));
+            level_offset += level.len();
+        }
+
+        let command_buffer = command_pool.one_time_command_buffer();
+        cube_image.transition_to(*command_buffer, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE);
+        unsafe { self.device.cmd_copy_buffer_to_image(*command_buffer, staging_buffer.buffer, cube_image.vk_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &copy_regions) };
+        cube_image.transition_to(*command_buffer, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ);
+        command_buffer.submit_and_wait().expect("Failed to upload cube map");
+
+        CubeMapTexture::create(self.device, cube_image, mip_levels)
+    }
+
+    fn create_sky_box_descriptor_set(descriptor_manager: &mut DescriptorManager, sky_box_texture: &CubeMapTexture) -> vk::DescriptorSet {
+        let sky_box_image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(sky_box_texture.image.image_view)
+            .sampler(sky_box_texture.sampler);
+
+        let (sky_box_descriptor_set, _descriptor_set_layout) = descriptor_manager.descriptor_builder()
+            .bind_image(0, sky_box_image_info, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)
+            .build()
+            .expect("Failed to allocate bindings");
+        sky_box_descriptor_set
+    }
+
+    fn create_ibl_descriptor_set(descriptor_manager: &mut DescriptorManager, diffuse_map_texture: &CubeMapTexture, specular_map_texture: &CubeMapTexture, brdf_lut_texture: &Texture) -> vk::DescriptorSet {
         let irradiance_map_image_info = vk::DescriptorImageInfo::builder()
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
             .image_view(diffuse_map_texture.image.image_view)
@@ -227,47 +680,26 @@ impl CubeMapManager {
             .bind_image(2, brdf_lut_image_info, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)
             .build()
             .expect("Failed to allocate bindings");
-
-        EnvironmentMaps {
-            sky_box_texture,
-            sky_box_descriptor_set,
-            irradiance_map_texture: diffuse_map_texture,
-            prefilter_map_texture: specular_map_texture,
-            brdf_lut_texture,
-            ibl_descriptor_set,
-        }
+        ibl_descriptor_set
     }
 
-    fn transition_image_for_sampling(&self, command_buffer: CommandBuffer, image: &Image, mip_levels: u32) {
-        image_transitions::transition_image_layout(&self.device, &command_buffer, image.vk_image, &TransitionProps {
-            old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            src_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-            dst_stage_mask: vk::PipelineStageFlags2::FRAGMENT_SHADER,
-            src_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-            dst_access_mask: vk::AccessFlags2::SHADER_SAMPLED_READ,
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: mip_levels,
-            layer_count: 6,
-        });
-    }
-
-    fn load_equirectangular_texture(&self, physical_device: &PhysicalDevice, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, path: &Path) -> (Texture, DescriptorSet) {
-        let img = image::open(path).unwrap();
-        let data = img.to_rgba32f();
-        let equirectangular_texture = Texture::create(self.device, physical_device, command_pool, descriptor_manager, &TextureCreateInfo {
+    fn load_equirectangular_texture(&self, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, path: &Path) -> (Texture, DescriptorSet) {
+        let img = read_equirectangular_image(path).unwrap_or_else(|err| panic!("Failed to load environment: {}", err));
+        let equirectangular_texture = Texture::create(self.device, physical_device, graphics_settings, command_pool, descriptor_manager, &TextureCreateInfo {
             width: img.width(),
             height: img.height(),
             format: vk::Format::R32G32B32A32_SFLOAT,
             mip_levels: None,
-            data: data.as_bytes(),
+            data: img.as_bytes(),
             sampler_info: SamplerOptions::FilterOptions(&TexSamplerOptions {
                 min_filter: Some(vk::Filter::LINEAR),
                 mag_filter: Some(vk::Filter::LINEAR),
                 mip_map_mode: None,
                 address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
                 address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                anisotropy_level: None,
+                mip_lod_bias: None,
+                max_lod: None,
             }),
         });
         let equirectangular_image_info = vk::DescriptorImageInfo::builder()
@@ -288,34 +720,24 @@ impl CubeMapManager {
             height: resolution,
             format: HDR_CUBE_MAP_FORMAT,
             tiling: vk::ImageTiling::OPTIMAL,
-            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            // transfer source so the maps can be read back to be baked
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC,
             mip_levels,
             memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
             image_aspect_flags: vk::ImageAspectFlags::COLOR,
             num_samples: vk::SampleCountFlags::TYPE_1,
             create_flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
         });
-        transition_image_layout(&self.device, &command_buffer, cube_image.vk_image, &TransitionProps {
-            old_layout: vk::ImageLayout::UNDEFINED,
-            new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            src_stage_mask: vk::PipelineStageFlags2::TOP_OF_PIPE,
-            dst_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-            src_access_mask: vk::AccessFlags2::empty(),
-            dst_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: mip_levels,
-            layer_count: 6,
-        });
+        cube_image.transition_to(command_buffer, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags2::COLOR_ATTACHMENT_WRITE);
         cube_image
     }
 
-    fn draw_brdf_lut(&self, physical_device: &PhysicalDevice, command_pool: &CommandPool) -> Texture {
-        let brdf_lut_texture = Texture::create_framebuffer(self.device, physical_device, command_pool, &FramebufferCreateInfo {
+    fn draw_brdf_lut(&self, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool) -> Texture {
+        let brdf_lut_texture = Texture::create_framebuffer(self.device, physical_device, graphics_settings, command_pool, &FramebufferCreateInfo {
             width: BRDF_LUT_TEXTURE_RESOLUTION,
             height: BRDF_LUT_TEXTURE_RESOLUTION,
             format: vk::Format::R16G16_SFLOAT,
-            usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
             mip_levels: None,
             sampler_info: SamplerOptions::FilterOptions(&TexSamplerOptions {
                 min_filter: Some(vk::Filter::LINEAR),
@@ -323,6 +745,9 @@ impl CubeMapManager {
                 mip_map_mode: None,
                 address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
                 address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                anisotropy_level: None,
+                mip_lod_bias: None,
+                max_lod: None,
             }),
         });
 
@@ -353,7 +778,7 @@ impl CubeMapManager {
         // ----------------------------------------------------------
 
         // bind the cube map pipeline
-        unsafe { self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.brdf_lut_pipeline.graphics_pipeline()) }
+        self.brdf_lut_pipeline.cmd_bind(command_buffer);
         let viewport = [vk::Viewport::builder()
             .x(0.0)
             .y(0.0)
@@ -383,25 +808,32 @@ impl CubeMapManager {
         // ------------------  end the render pass ------------------
         unsafe { self.device.cmd_end_rendering(command_buffer) };
 
-        image_transitions::transition_image_layout(&self.device, &command_buffer, brdf_lut_texture.image.vk_image, &TransitionProps {
-            old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            src_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-            dst_stage_mask: vk::PipelineStageFlags2::FRAGMENT_SHADER,
-            src_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-            dst_access_mask: vk::AccessFlags2::SHADER_SAMPLED_READ,
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: 1,
-            layer_count: 1,
-        });
+        brdf_lut_texture.image.transition_to(command_buffer, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ);
 
-        drop(one_time_command_buffer);
+        one_time_command_buffer.submit_and_wait().expect("Failed to draw brdf lut");
 
         brdf_lut_texture
     }
 }
 
+// reads a Radiance .hdr or OpenEXR .exr equirectangular image, chosen by the extension. 8 and 16 bit images are refused
+// rather than converted, as their clamped values would light the scene far too dimly
+fn read_equirectangular_image(path: &Path) -> Result<Rgba32FImage, String> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase);
+    let format = match extension.as_deref() {
+        Some("hdr") => ImageFormat::Hdr,
+        Some("exr") => ImageFormat::OpenExr,
+        _ => return Err(format!("{} isn't a .hdr or .exr file", path.display())),
+    };
+    let mut reader = image::io::Reader::open(path).map_err(|err| format!("Failed to open {}: {}", path.display(), err))?;
+    reader.set_format(format);
+    let image = reader.decode().map_err(|err| format!("Failed to decode {}: {}", path.display(), err))?;
+    match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => Ok(image.to_rgba32f()),
+        _ => Err(format!("{} holds {:?} pixels, an HDR environment needs floating point ones", path.display(), image.color())),
+    }
+}
+
 struct DrawCubeFaceInfo<'a> {
     face_index: usize,
     cube_image: vk::Image,
@@ -455,7 +887,7 @@ fn draw_cube_face(device: &Device, command_pool: &CommandPool, draw_info: &DrawC
     // ----------------------------------------------------------
 
     // bind the cube map pipeline
-    unsafe { device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, draw_info.pipeline.graphics_pipeline()) }
+    draw_info.pipeline.cmd_bind(command_buffer);
     let viewport = [vk::Viewport::builder()
         .x(0.0)
         .y(0.0)
@@ -491,17 +923,19 @@ fn draw_cube_face(device: &Device, command_pool: &CommandPool, draw_info: &DrawC
 
     // ------------------  end the render pass ------------------
     unsafe { device.cmd_end_rendering(command_buffer) };
-    drop(one_time_command_buffer);
+    one_time_command_buffer.submit_and_wait().expect("Failed to draw cube map face");
     unsafe { device.destroy_image_view(view, None) };
 }
 
+// MUST KEEP IN SYNC WITH prefilter.frag
 #[derive(AsStd140)]
 struct PrefilterParams {
     roughness: f32,
+    sample_count: u32,
 }
 
-fn draw_cube_face_for_specular(device: ConstPtr<Device>, command_pool: &CommandPool, draw_info: &DrawCubeFaceInfo, prefilter_params_buffer: &HostMappedBuffer) {
-    let mip_views: Vec<vk::ImageView> = (0..SPECULAR_MAX_MIP_LEVELS).map(|mip_level| {
+fn draw_cube_face_for_specular(device: ConstPtr<Device>, command_pool: &CommandPool, draw_info: &DrawCubeFaceInfo, prefilter_params_buffer: &HostMappedBuffer, mip_levels: u32, sample_count: u32) {
+    let mip_views: Vec<vk::ImageView> = (0..mip_levels).map(|mip_level| {
         let view_ci = vk::ImageViewCreateInfo::builder()
             .image(draw_info.cube_image)
             .view_type(vk::ImageViewType::TYPE_2D)
@@ -525,13 +959,13 @@ fn draw_cube_face_for_specular(device: ConstPtr<Device>, command_pool: &CommandP
     };
 
 
-    for mip_level in 0..SPECULAR_MAX_MIP_LEVELS {
+    for mip_level in 0..mip_levels {
         // IMPROVEMENT currently we wait for idle on each draw because we are using one buffer for roughness
         // which means we have to wait for the draw to finish before updating the contents
         let one_time_command_buffer = command_pool.one_time_command_buffer();
         let command_buffer = *one_time_command_buffer;
-        let roughness = mip_level as f32 / (SPECULAR_MAX_MIP_LEVELS - 1) as f32;
-        let data = PrefilterParams { roughness }.as_std140();
+        let roughness = mip_level as f32 / (mip_levels - 1).max(1) as f32;
+        let data = PrefilterParams { roughness, sample_count }.as_std140();
         prefilter_params_buffer.write_data(data.as_bytes());
         let mip_resolution = (draw_info.resolution as f32 * 0.5f32.powi(mip_level as i32)) as u32;
         let color_attachment_info = vk::RenderingAttachmentInfo::builder()
@@ -552,7 +986,7 @@ fn draw_cube_face_for_specular(device: ConstPtr<Device>, command_pool: &CommandP
         // ----------------------------------------------------------
 
         // bind the cube map pipeline
-        unsafe { device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, draw_info.pipeline.graphics_pipeline()) }
+        draw_info.pipeline.cmd_bind(command_buffer);
         let viewport = [vk::Viewport::builder()
             .x(0.0)
             .y(0.0)
@@ -588,7 +1022,7 @@ fn draw_cube_face_for_specular(device: ConstPtr<Device>, command_pool: &CommandP
 
         // ------------------  end the render pass ------------------
         unsafe { device.cmd_end_rendering(command_buffer) };
-        drop(one_time_command_buffer);
+        one_time_command_buffer.submit_and_wait().expect("Failed to draw cube map face");
     }
 
     for view in mip_views {
@@ -653,6 +1087,8 @@ fn cube_map_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut Descript
         vertex_input,
         multisampling,
         rasterization_options: &RasterizationOptions::default(),
+        depth_compare_op: graphics_settings.depth_compare_op(),
+        depth_format: vk::Format::D32_SFLOAT,
     };
 
     MaterialPipeline::create(device, &create_info)
@@ -698,6 +1134,8 @@ fn screen_quad_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut Descr
         vertex_input,
         multisampling,
         rasterization_options: &RasterizationOptions::default(),
+        depth_compare_op: graphics_settings.depth_compare_op(),
+        depth_format: vk::Format::D32_SFLOAT,
     };
 
     MaterialPipeline::create(device, &create_info)
@@ -2,11 +2,12 @@ use ash::vk;
 use ash::vk::Extent2D;
 
 use crate::rehnda_core::ConstPtr;
-use crate::etna::{CommandPool, Device, Image, ImageCreateInfo, ImageType, PhysicalDevice};
-use crate::etna::image_transitions::{transition_image_layout, TransitionProps};
+use crate::etna::{Buffer, CommandPool, Device, GraphicsSettings, Image, ImageCreateInfo, ImageType, PhysicalDevice};
 
 pub struct DepthBuffer {
     pub image: Image,
+    // with msaa the depth is resolved into this single sampled image so it can be read back
+    pub resolved_image: Option<Image>,
     pub format: vk::Format,
 }
 
@@ -16,49 +17,94 @@ impl Drop for DepthBuffer {
 }
 
 impl DepthBuffer {
-    pub fn create(device: ConstPtr<Device>, physical_device: &PhysicalDevice, command_pool: &CommandPool, extent: Extent2D) -> DepthBuffer {
-        let candidate_formats = [vk::Format::D32_SFLOAT, vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D24_UNORM_S8_UINT];
+    pub fn create(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, extent: Extent2D) -> DepthBuffer {
+        // stencil is preferred for the selection outline, which is left out without it
+        let candidate_formats = [vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D24_UNORM_S8_UINT, vk::Format::D32_SFLOAT];
         let depth_format = physical_device.find_supported_format(&candidate_formats, vk::ImageTiling::OPTIMAL, vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
             .expect("Failed to find supported format for depth buffer");
-        let image = Image::create_image(device, &ImageCreateInfo {
+        let msaa_samples = graphics_settings.msaa_samples.to_sample_count_flags();
+        let create_depth_image = |num_samples: vk::SampleCountFlags| Image::create_image(device, &ImageCreateInfo {
             image_type: ImageType::SingleImage,
             width: extent.width,
             height: extent.height,
             mip_levels: 1,
             format: depth_format,
             tiling: vk::ImageTiling::OPTIMAL,
-            usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            // multisampled images can't be copied to buffers, so only single sampled depth is read back
+            usage: if num_samples == vk::SampleCountFlags::TYPE_1 {
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC
+            } else {
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+            },
             memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            image_aspect_flags: vk::ImageAspectFlags::DEPTH,
-            num_samples: physical_device.graphics_settings.msaa_samples.to_sample_count_flags(),
+            // attachment views of depth stencil formats cover both aspects
+            image_aspect_flags: Self::aspect_mask(depth_format),
+            num_samples,
             create_flags: vk::ImageCreateFlags::empty(),
         });
+        let image = create_depth_image(msaa_samples);
+        let resolved_image = (msaa_samples != vk::SampleCountFlags::TYPE_1).then(|| create_depth_image(vk::SampleCountFlags::TYPE_1));
 
         let one_time_command_buffer = command_pool.one_time_command_buffer();
-        transition_image_layout(&device, &one_time_command_buffer, image.vk_image, &TransitionProps {
-            old_layout: vk::ImageLayout::UNDEFINED,
-            src_access_mask: vk::AccessFlags2::NONE,
-            src_stage_mask: vk::PipelineStageFlags2::TOP_OF_PIPE,
-            new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-            dst_access_mask: vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
-            dst_stage_mask: vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS,
-            aspect_mask: if Self::format_has_stencil(depth_format) {
-                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
-            } else {
-                vk::ImageAspectFlags::DEPTH
-            },
-            base_mip_level: 0,
-            level_count: 1,
-            layer_count: 1,
-        });
+        for image in std::iter::once(&image).chain(resolved_image.as_ref()) {
+            image.transition_to(*one_time_command_buffer, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL, vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS, vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE);
+        }
 
         DepthBuffer {
             image,
+            resolved_image,
             format: depth_format,
         }
     }
 
-    fn format_has_stencil(format: vk::Format) -> bool {
+    // the single sampled depth of the last rendered frame, the resolved depth when using msaa
+    pub fn readable_image(&self) -> &Image {
+        self.resolved_image.as_ref().unwrap_or(&self.image)
+    }
+
+    // copies a single depth value back to the cpu, the device must be idle so the last frame's depth is complete
+    pub fn read_depth(&self, device: ConstPtr<Device>, command_pool: &CommandPool, x: u32, y: u32) -> f32 {
+        let image = self.readable_image();
+        let readback_buffer = Buffer::create_readback_buffer(device, 4);
+        let copy_region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build()
+            )
+            .image_offset(vk::Offset3D { x: x as i32, y: y as i32, z: 0 })
+            .image_extent(vk::Extent3D { width: 1, height: 1, depth: 1 })
+            .build();
+
+        let command_buffer = command_pool.one_time_command_buffer();
+        // the render graph leaves the depth as an attachment without going through the image's tracked layout
+        image.assume_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL, vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS, vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE);
+        image.transition_to(*command_buffer, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ);
+        unsafe { device.cmd_copy_image_to_buffer(*command_buffer, image.vk_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, readback_buffer.buffer, std::slice::from_ref(&copy_region)) };
+        image.transition_to(*command_buffer, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL, vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS, vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE);
+        // waits for the copy to finish
+        command_buffer.submit_and_wait().expect("Failed to copy depth for readback");
+
+        let texel = u32::from_le_bytes(readback_buffer.read_data()[..4].try_into().unwrap());
+        if self.format == vk::Format::D24_UNORM_S8_UINT {
+            // the depth is in the low 24 bits, the rest is undefined
+            (texel & 0x00FF_FFFF) as f32 / 0x00FF_FFFF as f32
+        } else {
+            f32::from_bits(texel)
+        }
+    }
+
+    pub fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+        if Self::format_has_stencil(format) {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        }
+    }
+
+    pub fn format_has_stencil(format: vk::Format) -> bool {
         format == vk::Format::D32_SFLOAT_S8_UINT || format == vk::Format::D24_UNORM_S8_UINT
     }
 }
\ No newline at end of file
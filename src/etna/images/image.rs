@@ -1,15 +1,19 @@
 use std::mem::ManuallyDrop;
+use std::ops::Range;
+use std::sync::Mutex;
 
 use ash::vk;
 use gpu_allocator::MemoryLocation;
 use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
 
-use crate::etna::Device;
+use crate::etna::{DepthBuffer, Device};
 use crate::rehnda_core::ConstPtr;
 
 pub enum ImageType {
     SingleImage,
     Cube,
+    // a 2D array with the given number of layers, all the same size and sampled by layer index
+    Array(u32),
 }
 
 pub struct Image {
@@ -17,8 +21,22 @@ pub struct Image {
     pub vk_image: vk::Image,
     pub allocation: ManuallyDrop<Allocation>,
     pub image_view: vk::ImageView,
+    pub width: u32,
+    pub height: u32,
     pub mip_levels: u32,
+    pub array_layers: u32,
     pub format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+    // the state of every mip level of every layer, indexed by layer * mip_levels + mip level
+    subresource_states: Mutex<Vec<SubresourceState>>,
+}
+
+// how a subresource was last used, which the next barrier on it waits for
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct SubresourceState {
+    layout: vk::ImageLayout,
+    stage_mask: vk::PipelineStageFlags2,
+    access_mask: vk::AccessFlags2,
 }
 
 impl Drop for Image {
@@ -49,6 +67,7 @@ impl Image {
     pub fn create_image(device: ConstPtr<Device>, create_info: &ImageCreateInfo) -> Image {
         let (image_type, view_type, array_layers) = match create_info.image_type {
             ImageType::Cube => (vk::ImageType::TYPE_2D, vk::ImageViewType::CUBE, 6),
+            ImageType::Array(layers) => (vk::ImageType::TYPE_2D, vk::ImageViewType::TYPE_2D_ARRAY, layers),
             _ => (vk::ImageType::TYPE_2D, vk::ImageViewType::TYPE_2D, 1),
         };
         let image_ci = vk::ImageCreateInfo::builder()
@@ -103,8 +122,97 @@ impl Image {
             vk_image: image,
             image_view,
             allocation: ManuallyDrop::new(allocation),
+            width: create_info.width,
+            height: create_info.height,
             mip_levels: create_info.mip_levels,
+            array_layers,
             format: create_info.format,
+            aspect_mask: create_info.image_aspect_flags,
+            subresource_states: Mutex::new(vec![SubresourceState {
+                layout: vk::ImageLayout::UNDEFINED,
+                stage_mask: vk::PipelineStageFlags2::TOP_OF_PIPE,
+                access_mask: vk::AccessFlags2::empty(),
+            }; (create_info.mip_levels * array_layers) as usize]),
+        }
+    }
+
+    // transitions every mip level and layer, waiting on however each was last used
+    pub fn transition_to(&self, command_buffer: vk::CommandBuffer, new_layout: vk::ImageLayout, dst_stage_mask: vk::PipelineStageFlags2, dst_access_mask: vk::AccessFlags2) {
+        self.transition_subresources_to(command_buffer, 0..self.mip_levels, 0..self.array_layers, new_layout, dst_stage_mask, dst_access_mask);
+    }
+
+    pub fn transition_mips_to(&self, command_buffer: vk::CommandBuffer, mip_levels: Range<u32>, new_layout: vk::ImageLayout, dst_stage_mask: vk::PipelineStageFlags2, dst_access_mask: vk::AccessFlags2) {
+        self.transition_subresources_to(command_buffer, mip_levels, 0..self.array_layers, new_layout, dst_stage_mask, dst_access_mask);
+    }
+
+    // the source side of each barrier comes from the tracked state, subresources in different states get their own
+    // barriers
+    pub fn transition_subresources_to(&self, command_buffer: vk::CommandBuffer, mip_levels: Range<u32>, layers: Range<u32>, new_layout: vk::ImageLayout, dst_stage_mask: vk::PipelineStageFlags2, dst_access_mask: vk::AccessFlags2) {
+        assert!(mip_levels.end <= self.mip_levels && layers.end <= self.array_layers, "Transition is outside of the image's subresources");
+        let new_state = SubresourceState {
+            layout: new_layout,
+            stage_mask: dst_stage_mask,
+            access_mask: dst_access_mask,
+        };
+        let mut states = self.subresource_states.lock().unwrap();
+        let state_index = |mip_level: u32, layer: u32| (layer * self.mip_levels + mip_level) as usize;
+        let old_states: Vec<(u32, u32, SubresourceState)> = layers.clone()
+            .flat_map(|layer| mip_levels.clone().map(move |mip_level| (mip_level, layer)))
+            .map(|(mip_level, layer)| (mip_level, layer, states[state_index(mip_level, layer)]))
+            .collect();
+        let Some(&(_, _, first_state)) = old_states.first() else {
+            return;
+        };
+
+        let barrier = |old_state: SubresourceState, mip_levels: Range<u32>, layers: Range<u32>| vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(old_state.stage_mask)
+            .src_access_mask(old_state.access_mask)
+            .old_layout(old_state.layout)
+            .new_layout(new_layout)
+            .dst_stage_mask(dst_stage_mask)
+            .dst_access_mask(dst_access_mask)
+            .image(self.vk_image)
+            .subresource_range(vk::ImageSubresourceRange::builder()
+                .aspect_mask(self.barrier_aspect_mask())
+                .base_mip_level(mip_levels.start)
+                .level_count(mip_levels.end - mip_levels.start)
+                .base_array_layer(layers.start)
+                .layer_count(layers.end - layers.start)
+                .build()
+            )
+            .build();
+        let barriers: Vec<vk::ImageMemoryBarrier2> = if old_states.iter().all(|(_, _, state)| *state == first_state) {
+            vec![barrier(first_state, mip_levels, layers)]
+        } else {
+            old_states.iter()
+                .map(|&(mip_level, layer, state)| barrier(state, mip_level..mip_level + 1, layer..layer + 1))
+                .collect()
+        };
+        for (mip_level, layer, _) in old_states {
+            states[state_index(mip_level, layer)] = new_state;
+        }
+
+        let dependency_info = vk::DependencyInfo::builder()
+            .image_memory_barriers(&barriers);
+        unsafe { self.device.cmd_pipeline_barrier2(command_buffer, &dependency_info) };
+    }
+
+    // for layout changes made without going through the tracker, such as by the render graph which also works with
+    // images the engine doesn't own like the swapchain's
+    pub fn assume_layout(&self, layout: vk::ImageLayout, stage_mask: vk::PipelineStageFlags2, access_mask: vk::AccessFlags2) {
+        self.subresource_states.lock().unwrap().fill(SubresourceState {
+            layout,
+            stage_mask,
+            access_mask,
+        });
+    }
+
+    // layout transitions of depth stencil images cover both aspects
+    fn barrier_aspect_mask(&self) -> vk::ImageAspectFlags {
+        if self.aspect_mask.contains(vk::ImageAspectFlags::DEPTH) {
+            DepthBuffer::aspect_mask(self.format)
+        } else {
+            self.aspect_mask
         }
     }
 }
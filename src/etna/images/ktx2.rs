@@ -0,0 +1,181 @@
+use std::path::Path;
+
+use ash::vk;
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+/// The images of an uncompressed KTX2 file, each level holds its faces one after another in the vulkan layer order
+/// https://registry.khronos.org/KTX/specs/2.0/ktxspec.v2.html
+pub struct Ktx2Image {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub face_count: u32,
+    // from the full size image down
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl Ktx2Image {
+    // only files without supercompression or array layers are supported, as written by `toktx` or `ktx create`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        if bytes.len() < HEADER_SIZE || bytes[..12] != KTX2_IDENTIFIER {
+            return Err(format!("{} is not a KTX2 file", path.display()));
+        }
+        let read_u32 = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let read_u64 = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize;
+
+        let format = vk::Format::from_raw(read_u32(12) as i32);
+        let width = read_u32(20);
+        let height = read_u32(24);
+        let depth = read_u32(28);
+        let layer_count = read_u32(32);
+        let face_count = read_u32(36);
+        let level_count = read_u32(40);
+        let supercompression_scheme = read_u32(44);
+        if format == vk::Format::UNDEFINED || supercompression_scheme != 0 {
+            return Err(format!("{} is compressed, only uncompressed KTX2 files are supported", path.display()));
+        }
+        if depth > 1 || layer_count > 1 {
+            return Err(format!("{} is a 3D or array texture, which isn't supported", path.display()));
+        }
+        if level_count == 0 {
+            return Err(format!("{} asks for its mip levels to be generated, they must be stored in the file", path.display()));
+        }
+        if bytes.len() < HEADER_SIZE + level_count as usize * LEVEL_INDEX_ENTRY_SIZE {
+            return Err(format!("{} is too short to hold its mip level index", path.display()));
+        }
+
+        let levels = (0..level_count as usize).map(|level| {
+            let entry = HEADER_SIZE + level * LEVEL_INDEX_ENTRY_SIZE;
+            let (byte_offset, byte_length) = (read_u64(entry), read_u64(entry + 8));
+            bytes.get(byte_offset..byte_offset + byte_length)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| format!("Mip level {} of {} is outside the file", level, path.display()))
+        }).collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            format,
+            width,
+            height,
+            face_count,
+            levels,
+        })
+    }
+
+    // writes the levels uncompressed, smallest first as the spec asks
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let format_info = KtxFormatInfo::from_format(self.format).ok_or_else(|| format!("Can't write {:?} images to KTX2", self.format))?;
+        let data_format_descriptor = format_info.data_format_descriptor();
+        let level_index_size = self.levels.len() * LEVEL_INDEX_ENTRY_SIZE;
+        let dfd_offset = HEADER_SIZE + level_index_size;
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&KTX2_IDENTIFIER);
+        for value in [self.format.as_raw() as u32, format_info.channel_bits / 8, self.width, self.height, 0, 0, self.face_count, self.level_count(), 0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [dfd_offset as u32, data_format_descriptor.len() as u32, 0, 0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        // no supercompression global data
+        bytes.extend_from_slice(&[0; 16]);
+        bytes.resize(dfd_offset, 0);
+        bytes.extend_from_slice(&data_format_descriptor);
+
+        // each level starts aligned to the texel size and 4 bytes
+        let alignment = (format_info.texel_size() as usize).max(4);
+        for (level, data) in self.levels.iter().enumerate().rev() {
+            bytes.resize(bytes.len().next_multiple_of(alignment), 0);
+            let entry = HEADER_SIZE + level * LEVEL_INDEX_ENTRY_SIZE;
+            for (offset, value) in [bytes.len(), data.len(), data.len()].into_iter().enumerate() {
+                bytes[entry + offset * 8..entry + offset * 8 + 8].copy_from_slice(&(value as u64).to_le_bytes());
+            }
+            bytes.extend_from_slice(data);
+        }
+        std::fs::write(path, bytes).map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+    }
+
+    pub fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    pub fn level_extent(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+}
+
+// the formats that can be written, all with one sample per channel
+struct KtxFormatInfo {
+    channel_count: u32,
+    channel_bits: u32,
+    float: bool,
+    srgb: bool,
+}
+
+impl KtxFormatInfo {
+    fn from_format(format: vk::Format) -> Option<Self> {
+        let (channel_count, channel_bits, float, srgb) = match format {
+            vk::Format::R16G16B16A16_SFLOAT => (4, 16, true, false),
+            vk::Format::R16G16_SFLOAT => (2, 16, true, false),
+            vk::Format::R8G8B8A8_UNORM => (4, 8, false, false),
+            vk::Format::R8G8B8A8_SRGB => (4, 8, false, true),
+            _ => return None,
+        };
+        Some(Self {
+            channel_count,
+            channel_bits,
+            float,
+            srgb,
+        })
+    }
+
+    fn texel_size(&self) -> u32 {
+        self.channel_count * self.channel_bits / 8
+    }
+
+    // a basic descriptor block describing the channel layout, which the spec requires every file to have
+    fn data_format_descriptor(&self) -> Vec<u8> {
+        const SAMPLE_SIZE: u32 = 16;
+        const BASIC_BLOCK_HEADER_SIZE: u32 = 24;
+        const CHANNEL_ALPHA: u8 = 15;
+        const SAMPLE_FLOAT: u8 = 0x80;
+        const SAMPLE_SIGNED: u8 = 0x40;
+        const SAMPLE_LINEAR: u8 = 0x10;
+
+        let block_size = BASIC_BLOCK_HEADER_SIZE + SAMPLE_SIZE * self.channel_count;
+        let mut dfd = Vec::new();
+        dfd.extend_from_slice(&(block_size + 4).to_le_bytes());
+        // khronos vendor, basic descriptor type
+        dfd.extend_from_slice(&0u32.to_le_bytes());
+        dfd.extend_from_slice(&2u16.to_le_bytes());
+        dfd.extend_from_slice(&(block_size as u16).to_le_bytes());
+        // rgbsda color model, bt709 primaries, then the transfer function and straight alpha
+        dfd.extend_from_slice(&[1, 1, if self.srgb { 2 } else { 1 }, 0]);
+        // single texel blocks
+        dfd.extend_from_slice(&[0; 4]);
+        dfd.extend_from_slice(&[self.texel_size() as u8, 0, 0, 0, 0, 0, 0, 0]);
+        for channel in 0..self.channel_count {
+            let is_alpha = channel == 3;
+            let mut channel_type = if is_alpha { CHANNEL_ALPHA } else { channel as u8 };
+            let (lower, upper) = if self.float {
+                channel_type |= SAMPLE_FLOAT | SAMPLE_SIGNED;
+                ((-1.0f32).to_bits(), 1.0f32.to_bits())
+            } else {
+                (0, (1 << self.channel_bits) - 1)
+            };
+            // alpha is never sRGB encoded
+            if self.srgb && is_alpha {
+                channel_type |= SAMPLE_LINEAR;
+            }
+            dfd.extend_from_slice(&((channel * self.channel_bits) as u16).to_le_bytes());
+            dfd.extend_from_slice(&[(self.channel_bits - 1) as u8, channel_type]);
+            dfd.extend_from_slice(&[0; 4]);
+            dfd.extend_from_slice(&lower.to_le_bytes());
+            dfd.extend_from_slice(&upper.to_le_bytes());
+        }
+        dfd
+    }
+}
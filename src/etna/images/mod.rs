@@ -4,4 +4,6 @@ mod depth_buffer;
 pub use depth_buffer::*;
 mod texture;
 pub use texture::*;
+mod ktx2;
+pub use ktx2::*;
 pub mod cube_map;
\ No newline at end of file
@@ -1,18 +1,29 @@
+use std::ops::Range;
 use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use ash::vk;
 use image::EncodableLayout;
+use image::imageops::FilterType;
+use log::{info, warn};
 
 use crate::rehnda_core::ConstPtr;
 use crate::etna;
-use crate::etna::{Buffer, BufferCreateInfo, CommandPool, Device, Image, image_transitions, ImageCreateInfo, ImageType, PhysicalDevice};
-use crate::etna::image_transitions::TransitionProps;
+use crate::etna::{AnisotropyLevel, Buffer, DefaultSampler, BufferCreateInfo, CommandPool, Device, GraphicsSettings, Image, ImageCreateInfo, ImageType, PhysicalDevice};
 use crate::etna::material_pipeline::DescriptorManager;
 
+// streamed textures are created with the mips up to this size uploaded, the larger ones are streamed in later
+const STREAMING_RESIDENT_DIMENSION: u32 = 128;
+
 pub struct Texture {
     device: ConstPtr<Device>,
     pub image: Image,
     pub sampler: vk::Sampler,
+    // the mips from this one down hold the texture's own data, the larger ones hold it scaled up until streamed in
+    first_resident_mip: AtomicU32,
+    // the full size data of a streamed texture, kept until it is streamed in
+    pending_data: Mutex<Option<Vec<u8>>>,
 }
 
 impl Drop for Texture {
@@ -42,7 +53,7 @@ pub struct FramebufferCreateInfo<'a> {
 }
 
 impl Texture {
-    pub fn create_framebuffer(device: ConstPtr<Device>, physical_device: &PhysicalDevice, command_pool: &CommandPool, create_info: &FramebufferCreateInfo) -> Self {
+    pub fn create_framebuffer(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, create_info: &FramebufferCreateInfo) -> Self {
         let command_buffer = command_pool.one_time_command_buffer();
         let mip_levels = create_info.mip_levels.unwrap_or(1);
 
@@ -60,59 +71,27 @@ impl Texture {
             create_flags: vk::ImageCreateFlags::empty(),
         });
 
-        image_transitions::transition_image_layout(&device, &command_buffer, image.vk_image, &TransitionProps {
-            old_layout: vk::ImageLayout::UNDEFINED,
-            new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            src_stage_mask: vk::PipelineStageFlags2::TOP_OF_PIPE,
-            dst_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-            src_access_mask: vk::AccessFlags2::empty(),
-            dst_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: 1,
-            layer_count: 1,
-        });
+        image.transition_to(*command_buffer, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags2::COLOR_ATTACHMENT_WRITE);
 
         let sampler_create_info = match create_info.sampler_info {
-            SamplerOptions::FilterOptions(filter_options) => {
-                vk::SamplerCreateInfo::builder()
-                    .mag_filter(filter_options.mag_filter.unwrap_or(vk::Filter::LINEAR))
-                    .min_filter(filter_options.min_filter.unwrap_or(vk::Filter::LINEAR))
-                    .address_mode_u(filter_options.address_mode_u)
-                    .address_mode_v(filter_options.address_mode_v)
-                    .address_mode_w(vk::SamplerAddressMode::REPEAT)
-                    // only use anisotropy if the feature is enabled
-                    .anisotropy_enable(device.enabled_features.sampler_anisotropy == vk::TRUE)
-                    .max_anisotropy(if device.enabled_features.sampler_anisotropy == vk::TRUE {
-                        physical_device.device_properties.limits.max_sampler_anisotropy
-                    } else {
-                        1.0
-                    })
-                    .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-                    .unnormalized_coordinates(false)
-                    .compare_enable(false)
-                    .compare_op(vk::CompareOp::ALWAYS)
-                    .mipmap_mode(filter_options.mip_map_mode.unwrap_or(vk::SamplerMipmapMode::LINEAR))
-                    .min_lod(0.0)
-                    .max_lod(mip_levels as f32)
-                    .mip_lod_bias(0.0)
-                    .build()
-            },
+            SamplerOptions::FilterOptions(filter_options) => Self::sampler_create_info_from_options(&device, physical_device, graphics_settings, filter_options, mip_levels),
             SamplerOptions::CreateInfo(create_info) => create_info
         };
 
         let sampler = unsafe { device.create_sampler(&sampler_create_info, None) }
             .expect("Failed to create sampler for Texture");
 
-        drop(command_buffer);
+        command_buffer.submit_and_wait().expect("Failed to transition framebuffer texture");
         Texture {
             device,
             image,
             sampler,
+            first_resident_mip: AtomicU32::new(0),
+            pending_data: Mutex::new(None),
         }
     }
 
-    pub fn create_from_image_file(device: ConstPtr<Device>, physical_device: &PhysicalDevice, command_pool: &CommandPool, image_path: &Path, descriptor_manager: &mut DescriptorManager) -> Texture {
+    pub fn create_from_image_file(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, image_path: &Path, descriptor_manager: &mut DescriptorManager) -> Texture {
         let img = image::open(image_path).expect("Failed to open image");
         let rgba_img = img.to_rgba8();
         let create_info = TextureCreateInfo {
@@ -127,22 +106,118 @@ impl Texture {
                 mip_map_mode: None,
                 address_mode_u: vk::SamplerAddressMode::REPEAT,
                 address_mode_v: vk::SamplerAddressMode::REPEAT,
+                anisotropy_level: None,
+                mip_lod_bias: None,
+                max_lod: None,
             }),
         };
-        Self::create(device, physical_device, command_pool, descriptor_manager, &create_info)
+        Self::create(device, physical_device, graphics_settings, command_pool, descriptor_manager, &create_info)
     }
 
-    pub fn create(device: ConstPtr<Device>, physical_device: &PhysicalDevice, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, create_info: &TextureCreateInfo) -> Texture {
+    /// Loads same sized images into the layers of a 2D array texture, in the order given, sampled in shaders with a sampler2DArray
+    pub fn create_array(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, image_paths: &[&Path]) -> Texture {
+        assert!(!image_paths.is_empty(), "A texture array needs at least one image");
+        let max_dimension = physical_device.max_texture_dimension(graphics_settings);
+        let layers: Vec<image::RgbaImage> = image_paths.iter()
+            .map(|path| image::open(path).unwrap_or_else(|err| panic!("Failed to open texture array layer {}: {}", path.display(), err)).to_rgba8())
+            .map(|layer| match fit_within(layer.width(), layer.height(), max_dimension) {
+                Some((width, height)) => {
+                    info!("Downscaled texture array layer from {}x{} to {}x{}", layer.width(), layer.height(), width, height);
+                    image::imageops::resize(&layer, width, height, FilterType::Triangle)
+                }
+                None => layer,
+            })
+            .collect();
+        let (width, height) = layers[0].dimensions();
+        for (layer, path) in layers.iter().zip(image_paths.iter()) {
+            assert_eq!(layer.dimensions(), (width, height), "Texture array layer {} is not the same size as the other layers", path.display());
+        }
+        let layer_count = layers.len() as u32;
+        let layer_size = layers[0].as_bytes().len();
+        let data: Vec<u8> = layers.iter().flat_map(|layer| layer.as_bytes().iter().copied()).collect();
+
         let command_buffer = command_pool.one_time_command_buffer();
-        let mip_levels = create_info.mip_levels.unwrap_or(1);
+        let mip_levels = width.max(height).ilog2() + 1;
         let src_buffer = Buffer::create_buffer_with_data(device, BufferCreateInfo {
-            data: create_info.data,
+            data: data.as_slice(),
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+        });
+        let image = Image::create_image(device, &ImageCreateInfo {
+            image_type: ImageType::Array(layer_count),
+            width,
+            height,
+            mip_levels,
+            format: vk::Format::R8G8B8A8_SRGB,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            image_aspect_flags: vk::ImageAspectFlags::COLOR,
+            num_samples: vk::SampleCountFlags::TYPE_1,
+            create_flags: vk::ImageCreateFlags::empty(),
+        });
+
+        image.transition_to(*command_buffer, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE);
+        let copy_regions: Vec<vk::BufferImageCopy> = (0..layer_count).map(|layer_index| {
+            vk::BufferImageCopy::builder()
+                .buffer_offset((layer_index as usize * layer_size) as u64)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(layer_index)
+                    .layer_count(1)
+                    .build()
+                )
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D { width, height, depth: 1 })
+                .build()
+        }).collect();
+        unsafe { device.cmd_copy_buffer_to_image(*command_buffer, src_buffer.buffer, image.vk_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &copy_regions) };
+
+        // each blit covers every layer, so the layers get their own mip chains
+        Self::generate_mipmaps(&device, physical_device, &image, width, height, mip_levels, layer_count, *command_buffer);
+
+        let sampler_create_info = Self::sampler_create_info_from_options(&device, physical_device, graphics_settings, &TexSamplerOptions {
+            min_filter: None,
+            mag_filter: None,
+            mip_map_mode: None,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            anisotropy_level: None,
+            mip_lod_bias: None,
+            max_lod: None,
+        }, mip_levels);
+        let sampler = unsafe { device.create_sampler(&sampler_create_info, None) }
+            .expect("Failed to create sampler for Texture");
+
+        command_buffer.submit_and_wait().expect("Failed to upload texture");
+        Texture {
+            device,
+            image,
+            sampler,
+            first_resident_mip: AtomicU32::new(0),
+            pending_data: Mutex::new(None),
+        }
+    }
+
+    pub fn create(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, create_info: &TextureCreateInfo) -> Texture {
+        let downscaled = downscale_to_fit(create_info, physical_device.max_texture_dimension(graphics_settings));
+        let (width, height, data) = match &downscaled {
+            Some((width, height, data)) => (*width, *height, data.as_slice()),
+            None => (create_info.width, create_info.height, create_info.data),
+        };
+        let command_buffer = command_pool.one_time_command_buffer();
+        // a downscaled image has fewer mips than were asked for
+        let mip_levels = create_info.mip_levels.unwrap_or(1).min(width.max(height).ilog2() + 1);
+        let src_buffer = Buffer::create_buffer_with_data(device, BufferCreateInfo {
+            data,
             usage: vk::BufferUsageFlags::TRANSFER_SRC,
         });
         let image = Image::create_image(device, &ImageCreateInfo {
             image_type: ImageType::SingleImage,
-            width: create_info.width,
-            height: create_info.height,
+            width,
+            height,
             mip_levels,
             format: create_info.format,
             tiling: vk::ImageTiling::OPTIMAL,
@@ -153,16 +228,126 @@ impl Texture {
             create_flags: vk::ImageCreateFlags::empty(),
         });
 
-        image_transitions::transition_image_layout(&device, &command_buffer, image.vk_image, &image_transitions::TransitionProps::undefined_to_transfer_dst(mip_levels));
+        image.transition_to(*command_buffer, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE);
+        Self::cmd_copy_to_mip(&device, *command_buffer, &src_buffer, &image, 0, width, height);
+        Self::generate_mipmaps(&device, physical_device, &image, width, height, mip_levels, 1, *command_buffer);
 
-        // let command_buffer = command_pool.one_time_command_buffer();
+        let sampler_create_info = match create_info.sampler_info {
+            SamplerOptions::FilterOptions(filter_options) => Self::sampler_create_info_from_options(&device, physical_device, graphics_settings, filter_options, mip_levels),
+            SamplerOptions::CreateInfo(create_info) => create_info
+        };
+
+        let sampler = unsafe { device.create_sampler(&sampler_create_info, None) }
+            .expect("Failed to create sampler for Texture");
+        
+        command_buffer.submit_and_wait().expect("Failed to upload texture");
+        Texture {
+            device,
+            image,
+            sampler,
+            first_resident_mip: AtomicU32::new(0),
+            pending_data: Mutex::new(None),
+        }
+    }
+
+    /// Creates the texture with only the mips up to STREAMING_RESIDENT_DIMENSION holding its data, the larger ones hold
+    /// the largest of those scaled up until stream_in uploads the full size data. Textures that are already small, or
+    /// in a format that can't be resized, are created as usual
+    pub fn create_streamed(device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, create_info: &TextureCreateInfo) -> Texture {
+        let (width, height, data) = match downscale_to_fit(create_info, physical_device.max_texture_dimension(graphics_settings)) {
+            Some(downscaled) => downscaled,
+            None => (create_info.width, create_info.height, create_info.data.to_vec()),
+        };
+        let mip_levels = create_info.mip_levels.unwrap_or(1).min(width.max(height).ilog2() + 1);
+        let first_resident_mip = (0..mip_levels)
+            .find(|&mip| width.max(height) >> mip <= STREAMING_RESIDENT_DIMENSION)
+            .unwrap_or(mip_levels - 1);
+        let (resident_width, resident_height) = mip_size(width, height, first_resident_mip);
+        let resident_data = (first_resident_mip > 0)
+            .then(|| resize_data(create_info.format, width, height, &data, resident_width, resident_height))
+            .flatten();
+        let Some(resident_data) = resident_data else {
+            return Self::create(device, physical_device, graphics_settings, command_pool, descriptor_manager, &TextureCreateInfo {
+                width,
+                height,
+                data: &data,
+                ..*create_info
+            });
+        };
+
+        let command_buffer = command_pool.one_time_command_buffer();
+        let src_buffer = Buffer::create_buffer_with_data(device, BufferCreateInfo {
+            data: &resident_data,
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+        });
+        let image = Image::create_image(device, &ImageCreateInfo {
+            image_type: ImageType::SingleImage,
+            width,
+            height,
+            mip_levels,
+            format: create_info.format,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            image_aspect_flags: vk::ImageAspectFlags::COLOR,
+            num_samples: vk::SampleCountFlags::TYPE_1,
+            create_flags: vk::ImageCreateFlags::empty(),
+        });
+        image.transition_to(*command_buffer, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE);
+        Self::cmd_copy_to_mip(&device, *command_buffer, &src_buffer, &image, first_resident_mip, resident_width, resident_height);
+        Self::generate_mip_range(&device, physical_device, &image, resident_width, resident_height, first_resident_mip..mip_levels, 1, *command_buffer);
+        Self::cmd_scale_up_into_larger_mips(&device, &image, first_resident_mip, *command_buffer);
+
+        let sampler_create_info = match create_info.sampler_info {
+            SamplerOptions::FilterOptions(filter_options) => Self::sampler_create_info_from_options(&device, physical_device, graphics_settings, filter_options, mip_levels),
+            SamplerOptions::CreateInfo(create_info) => create_info
+        };
+        let sampler = unsafe { device.create_sampler(&sampler_create_info, None) }
+            .expect("Failed to create sampler for Texture");
+
+        command_buffer.submit_and_wait().expect("Failed to upload streamed texture");
+        Texture {
+            device,
+            image,
+            sampler,
+            first_resident_mip: AtomicU32::new(first_resident_mip),
+            pending_data: Mutex::new(Some(data)),
+        }
+    }
+
+    pub fn first_resident_mip(&self) -> u32 {
+        self.first_resident_mip.load(Ordering::Relaxed)
+    }
+
+    // uploads the full size data of a streamed texture and regenerates the larger mips from it, returning false if
+    // there was nothing left to stream in. Frames already submitted finish sampling the old mips first
+    pub fn stream_in(&self, physical_device: &PhysicalDevice, command_pool: &CommandPool) -> bool {
+        let Some(data) = self.pending_data.lock().unwrap().take() else {
+            return false;
+        };
+        let first_resident_mip = self.first_resident_mip();
+        let command_buffer = command_pool.one_time_command_buffer();
+        let src_buffer = Buffer::create_buffer_with_data(self.device, BufferCreateInfo {
+            data: &data,
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+        });
+        self.image.transition_mips_to(*command_buffer, 0..first_resident_mip, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE);
+        Self::cmd_copy_to_mip(&self.device, *command_buffer, &src_buffer, &self.image, 0, self.image.width, self.image.height);
+        Self::generate_mip_range(&self.device, physical_device, &self.image, self.image.width, self.image.height, 0..first_resident_mip, 1, *command_buffer);
+        command_buffer.submit_and_wait().expect("Failed to stream in texture");
+        self.first_resident_mip.store(0, Ordering::Relaxed);
+        true
+    }
+
+    // the mip must be in the transfer dst layout
+    fn cmd_copy_to_mip(device: &Device, command_buffer: vk::CommandBuffer, src_buffer: &Buffer, image: &Image, mip_level: u32, width: u32, height: u32) {
         let copy_region = vk::BufferImageCopy::builder()
             .buffer_offset(0)
             .buffer_row_length(0)
             .buffer_image_height(0)
             .image_subresource(vk::ImageSubresourceLayers::builder()
                 .aspect_mask(vk::ImageAspectFlags::COLOR)
-                .mip_level(0)
+                .mip_level(mip_level)
                 .base_array_layer(0)
                 .layer_count(1).build()
             )
@@ -172,77 +357,102 @@ impl Texture {
                 z: 0,
             })
             .image_extent(vk::Extent3D {
-                width: create_info.width,
-                height: create_info.height,
+                width,
+                height,
                 depth: 1,
             })
             .build();
-        let copy_regions = &[copy_region];
-
-        unsafe { device.cmd_copy_buffer_to_image(*command_buffer, src_buffer.buffer, image.vk_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, copy_regions) };
-
-        Self::generate_mipmaps(&device, physical_device, &image, create_info.width, create_info.height, mip_levels, *command_buffer);
+        unsafe { device.cmd_copy_buffer_to_image(command_buffer, src_buffer.buffer, image.vk_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, std::slice::from_ref(&copy_region)) };
+    }
 
-        let sampler_create_info = match create_info.sampler_info {
-            SamplerOptions::FilterOptions(filter_options) => {
-                vk::SamplerCreateInfo::builder()
-                    .mag_filter(filter_options.mag_filter.unwrap_or(vk::Filter::LINEAR))
-                    .min_filter(filter_options.min_filter.unwrap_or(vk::Filter::LINEAR))
-                    .address_mode_u(filter_options.address_mode_u)
-                    .address_mode_v(filter_options.address_mode_v)
-                    .address_mode_w(vk::SamplerAddressMode::REPEAT)
-                    // only use anisotropy if the feature is enabled
-                    .anisotropy_enable(device.enabled_features.sampler_anisotropy == vk::TRUE)
-                    .max_anisotropy(if device.enabled_features.sampler_anisotropy == vk::TRUE {
-                        physical_device.device_properties.limits.max_sampler_anisotropy
-                    } else {
-                        1.0
-                    })
-                    .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-                    .unnormalized_coordinates(false)
-                    .compare_enable(false)
-                    .compare_op(vk::CompareOp::ALWAYS)
-                    .mipmap_mode(filter_options.mip_map_mode.unwrap_or(vk::SamplerMipmapMode::LINEAR))
-                    .min_lod(0.0)
-                    .max_lod(mip_levels as f32)
-                    .mip_lod_bias(0.0)
+    // fills the mips above the source mip, which must be ready to sample, with it scaled up so they can be sampled
+    // before their own data is streamed in. The mips above are expected in the transfer dst layout
+    fn cmd_scale_up_into_larger_mips(device: &Device, image: &Image, source_mip: u32, command_buffer: vk::CommandBuffer) {
+        image.transition_mips_to(command_buffer, source_mip..source_mip + 1, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ);
+        let (source_width, source_height) = mip_size(image.width, image.height, source_mip);
+        for mip in 0..source_mip {
+            let (mip_width, mip_height) = mip_size(image.width, image.height, mip);
+            let image_blit = vk::ImageBlit::builder()
+                .src_offsets([
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D { x: source_width as i32, y: source_height as i32, z: 1 },
+                ])
+                .src_subresource(vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(source_mip)
+                    .base_array_layer(0)
+                    .layer_count(1)
                     .build()
-            },
-            SamplerOptions::CreateInfo(create_info) => create_info
+                )
+                .dst_offsets([
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D { x: mip_width as i32, y: mip_height as i32, z: 1 },
+                ])
+                .dst_subresource(vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(mip)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build()
+                );
+            unsafe {
+                device.cmd_blit_image(
+                    command_buffer,
+                    image.vk_image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    image.vk_image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    std::slice::from_ref(&image_blit), vk::Filter::LINEAR)
+            };
+        }
+        image.transition_mips_to(command_buffer, 0..source_mip + 1, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_READ);
+    }
+
+    fn sampler_create_info_from_options(device: &Device, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, filter_options: &TexSamplerOptions, mip_levels: u32) -> vk::SamplerCreateInfo {
+        // only use anisotropy if the feature is enabled
+        let anisotropy_level = filter_options.anisotropy_level.unwrap_or(graphics_settings.anisotropy_level);
+        let max_anisotropy = if device.enabled_features.sampler_anisotropy == vk::TRUE {
+            anisotropy_level.to_f32().min(physical_device.device_properties.limits.max_sampler_anisotropy)
+        } else {
+            1.0
         };
+        let max_lod_bias = physical_device.device_properties.limits.max_sampler_lod_bias;
+        let mip_lod_bias = filter_options.mip_lod_bias.unwrap_or(0.0).clamp(-max_lod_bias, max_lod_bias);
+        vk::SamplerCreateInfo::builder()
+            .mag_filter(filter_options.mag_filter.unwrap_or(vk::Filter::LINEAR))
+            .min_filter(filter_options.min_filter.unwrap_or(vk::Filter::LINEAR))
+            .address_mode_u(filter_options.address_mode_u)
+            .address_mode_v(filter_options.address_mode_v)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .anisotropy_enable(max_anisotropy > 1.0)
+            .max_anisotropy(max_anisotropy)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(filter_options.mip_map_mode.unwrap_or(vk::SamplerMipmapMode::LINEAR))
+            .min_lod(0.0)
+            .max_lod(filter_options.max_lod.unwrap_or(mip_levels as f32).min(mip_levels as f32))
+            .mip_lod_bias(mip_lod_bias)
+            .build()
+    }
 
-        let sampler = unsafe { device.create_sampler(&sampler_create_info, None) }
-            .expect("Failed to create sampler for Texture");
-        
-        drop(command_buffer);
-        Texture {
-            device,
-            image,
-            sampler,
-        }
+    pub fn generate_mipmaps(device: &Device, physical_device: &PhysicalDevice, image: &etna::Image, width: u32, height: u32, mip_levels: u32, layer_count: u32, command_buffer: vk::CommandBuffer) {
+        Self::generate_mip_range(device, physical_device, image, width, height, 0..mip_levels, layer_count, command_buffer);
     }
 
-    fn generate_mipmaps(device: &Device, physical_device: &PhysicalDevice, image: &etna::Image, width: u32, height: u32, mip_levels: u32, command_buffer: vk::CommandBuffer) {
+    // blits each mip in the range from the one before it, the width and height are those of the first mip. Every mip
+    // in the range is expected in the transfer dst layout, the first holding its data, and all are left ready to sample
+    fn generate_mip_range(device: &Device, physical_device: &PhysicalDevice, image: &etna::Image, width: u32, height: u32, mips: Range<u32>, layer_count: u32, command_buffer: vk::CommandBuffer) {
         let format_properties = physical_device.get_format_properties(image.format);
         if (format_properties.optimal_tiling_features & vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR).is_empty() {
             panic!("Texture image format does not support linear blitting!");
         }
         let mut mip_width = width as i32;
         let mut mip_height = height as i32;
-        for i in 1..mip_levels {
+        for i in mips.start + 1..mips.end {
             // image was just copied into (transfer dst) and now we want to prepare to make it the source for blitting
-            image_transitions::transition_image_layout(device, &command_buffer, image.vk_image, &image_transitions::TransitionProps {
-                old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                src_stage_mask: vk::PipelineStageFlags2::TRANSFER,
-                dst_stage_mask: vk::PipelineStageFlags2::TRANSFER,
-                src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
-                dst_access_mask: vk::AccessFlags2::TRANSFER_READ,
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: i - 1,
-                level_count: 1,
-                layer_count: 1,
-            });
+            image.transition_mips_to(command_buffer, i - 1..i, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ);
 
             let image_blit = vk::ImageBlit::builder()
                 .src_offsets([
@@ -253,7 +463,7 @@ impl Texture {
                     .aspect_mask(vk::ImageAspectFlags::COLOR)
                     .mip_level(i - 1)
                     .base_array_layer(0)
-                    .layer_count(1)
+                    .layer_count(layer_count)
                     .build()
                 )
                 .dst_offsets([
@@ -264,7 +474,7 @@ impl Texture {
                     .aspect_mask(vk::ImageAspectFlags::COLOR)
                     .mip_level(i)
                     .base_array_layer(0)
-                    .layer_count(1)
+                    .layer_count(layer_count)
                     .build()
                 );
 
@@ -279,18 +489,7 @@ impl Texture {
             };
 
             // now the image has been used to form the below mip level it can be prepared for being used in a shader
-            image_transitions::transition_image_layout(device, &command_buffer, image.vk_image, &image_transitions::TransitionProps {
-                old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                src_stage_mask: vk::PipelineStageFlags2::TRANSFER,
-                dst_stage_mask: vk::PipelineStageFlags2::FRAGMENT_SHADER,
-                src_access_mask: vk::AccessFlags2::TRANSFER_READ,
-                dst_access_mask: vk::AccessFlags2::SHADER_READ,
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: i - 1,
-                level_count: 1,
-                layer_count: 1,
-            });
+            image.transition_mips_to(command_buffer, i - 1..i, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_READ);
 
             if mip_width > 1 {
                 mip_width /= 2;
@@ -300,21 +499,11 @@ impl Texture {
             }
         }
 
-        image_transitions::transition_image_layout(device, &command_buffer, image.vk_image, &image_transitions::TransitionProps {
-            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            src_stage_mask: vk::PipelineStageFlags2::TRANSFER,
-            dst_stage_mask: vk::PipelineStageFlags2::FRAGMENT_SHADER,
-            src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags2::SHADER_READ,
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: mip_levels - 1,
-            level_count: 1,
-            layer_count: 1,
-        });
+        image.transition_mips_to(command_buffer, mips.end - 1..mips.end, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_READ);
     }
 }
 
+#[derive(Copy, Clone)]
 pub enum SamplerOptions<'a> {
     FilterOptions(&'a TexSamplerOptions),
     CreateInfo(vk::SamplerCreateInfo),
@@ -327,30 +516,45 @@ pub struct TexSamplerOptions {
     pub mip_map_mode: Option<vk::SamplerMipmapMode>,
     pub address_mode_u: vk::SamplerAddressMode,
     pub address_mode_v: vk::SamplerAddressMode,
+    // overrides the anisotropy level from the graphics settings
+    pub anisotropy_level: Option<AnisotropyLevel>,
+    // negative values sharpen, positive values soften, clamped to the device limit
+    pub mip_lod_bias: Option<f32>,
+    // limits sampling to the first max_lod mip levels, defaults to all of them
+    pub max_lod: Option<f32>,
 }
 
 impl TexSamplerOptions {
-    pub fn from_gltf(sampler: &gltf::texture::Sampler) -> Self {
-        let mip_map_mode = sampler.min_filter().and_then(|filter| match filter {
-            gltf::texture::MinFilter::Nearest | gltf::texture::MinFilter::Linear => None,
-            gltf::texture::MinFilter::NearestMipmapNearest | gltf::texture::MinFilter::NearestMipmapLinear => Some(vk::SamplerMipmapMode::NEAREST),
-            gltf::texture::MinFilter::LinearMipmapNearest | gltf::texture::MinFilter::LinearMipmapLinear => Some(vk::SamplerMipmapMode::LINEAR),
-        });
-        let min_filter = sampler.min_filter().map(|filter| match filter {
-            gltf::texture::MinFilter::Nearest | gltf::texture::MinFilter::NearestMipmapNearest | gltf::texture::MinFilter::LinearMipmapNearest => vk::Filter::NEAREST,
-            gltf::texture::MinFilter::Linear | gltf::texture::MinFilter::NearestMipmapLinear | gltf::texture::MinFilter::LinearMipmapLinear => vk::Filter::LINEAR,
-        });
-        let mag_filter = sampler.mag_filter().map(|filter| match filter {
-            gltf::texture::MagFilter::Nearest => vk::Filter::NEAREST,
-            gltf::texture::MagFilter::Linear => vk::Filter::LINEAR,
-        });
+    // the default sampler fills in the filters the gltf sampler leaves out
+    pub fn from_gltf(sampler: &gltf::texture::Sampler, default_sampler: DefaultSampler) -> Self {
+        // gltf min filters combine the filter within a mip level with the filter between mip levels
+        let (min_filter, mip_map_mode, max_lod) = match sampler.min_filter() {
+            None => (Some(default_sampler.filter()), Some(default_sampler.mip_map_mode()), None),
+            // no mipmapping, only the base level is sampled
+            Some(gltf::texture::MinFilter::Nearest) => (Some(vk::Filter::NEAREST), Some(vk::SamplerMipmapMode::NEAREST), Some(0.0)),
+            Some(gltf::texture::MinFilter::Linear) => (Some(vk::Filter::LINEAR), Some(vk::SamplerMipmapMode::NEAREST), Some(0.0)),
+            Some(gltf::texture::MinFilter::NearestMipmapNearest) => (Some(vk::Filter::NEAREST), Some(vk::SamplerMipmapMode::NEAREST), None),
+            Some(gltf::texture::MinFilter::LinearMipmapNearest) => (Some(vk::Filter::LINEAR), Some(vk::SamplerMipmapMode::NEAREST), None),
+            Some(gltf::texture::MinFilter::NearestMipmapLinear) => (Some(vk::Filter::NEAREST), Some(vk::SamplerMipmapMode::LINEAR), None),
+            Some(gltf::texture::MinFilter::LinearMipmapLinear) => (Some(vk::Filter::LINEAR), Some(vk::SamplerMipmapMode::LINEAR), None),
+        };
+        let mag_filter = match sampler.mag_filter() {
+            None => default_sampler.filter(),
+            Some(gltf::texture::MagFilter::Nearest) => vk::Filter::NEAREST,
+            Some(gltf::texture::MagFilter::Linear) => vk::Filter::LINEAR,
+        };
+        // anisotropic filtering would blur the texels nearest filtering keeps sharp
+        let anisotropy_level = (sampler.min_filter().is_none() && default_sampler == DefaultSampler::Nearest).then_some(AnisotropyLevel::X1);
 
         TexSamplerOptions {
             min_filter,
-            mag_filter,
+            mag_filter: Some(mag_filter),
             mip_map_mode,
             address_mode_u: Self::to_vk_sampler_mode(&sampler.wrap_s()),
             address_mode_v: Self::to_vk_sampler_mode(&sampler.wrap_t()),
+            anisotropy_level,
+            mip_lod_bias: None,
+            max_lod,
         }
     }
 
@@ -361,4 +565,47 @@ impl TexSamplerOptions {
             gltf::texture::WrappingMode::Repeat => vk::SamplerAddressMode::REPEAT,
         }
     }
-}
\ No newline at end of file
+}
+
+// the size that fits an image within max_dimension keeping its aspect ratio, if it needs to shrink
+fn fit_within(width: u32, height: u32, max_dimension: u32) -> Option<(u32, u32)> {
+    let largest = width.max(height);
+    if largest <= max_dimension {
+        return None;
+    }
+    let scale = max_dimension as f64 / largest as f64;
+    let fit = |dimension: u32| ((dimension as f64 * scale).round() as u32).clamp(1, max_dimension);
+    Some((fit(width), fit(height)))
+}
+
+// the size of a mip, halving each level without going below a pixel as the mip blits do
+fn mip_size(width: u32, height: u32, mip_level: u32) -> (u32, u32) {
+    ((width >> mip_level).max(1), (height >> mip_level).max(1))
+}
+
+// only 8 bit and float rgba data can be resized, other formats are uploaded at their original size
+fn downscale_to_fit(create_info: &TextureCreateInfo, max_dimension: u32) -> Option<(u32, u32, Vec<u8>)> {
+    let (width, height) = fit_within(create_info.width, create_info.height, max_dimension)?;
+    let Some(data) = resize_data(create_info.format, create_info.width, create_info.height, create_info.data, width, height) else {
+        warn!("Can't downscale a {:?} texture, uploading it at {}x{}", create_info.format, create_info.width, create_info.height);
+        return None;
+    };
+    info!("Downscaled texture from {}x{} to {}x{}", create_info.width, create_info.height, width, height);
+    Some((width, height, data))
+}
+
+// None for formats other than 8 bit and float rgba
+fn resize_data(format: vk::Format, width: u32, height: u32, data: &[u8], new_width: u32, new_height: u32) -> Option<Vec<u8>> {
+    match format {
+        vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => {
+            let image = image::RgbaImage::from_raw(width, height, data.to_vec())?;
+            Some(image::imageops::resize(&image, new_width, new_height, FilterType::Triangle).into_raw())
+        }
+        vk::Format::R32G32B32A32_SFLOAT => {
+            let pixels = data.chunks_exact(4).map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap())).collect();
+            let image = image::Rgba32FImage::from_raw(width, height, pixels)?;
+            Some(image::imageops::resize(&image, new_width, new_height, FilterType::Triangle).into_raw().iter().flat_map(|value| value.to_ne_bytes()).collect())
+        }
+        _ => None,
+    }
+}
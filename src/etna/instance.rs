@@ -1,29 +1,25 @@
-use std::ffi::CString;
+use std::ffi::{c_void, CString};
 use std::mem::ManuallyDrop;
 use std::ops::Deref;
 use std::os::raw::c_char;
 
 use ash::{Entry, vk};
 use ash::extensions::{ext, khr};
-use log::info;
+use log::{info, warn};
 
 use crate::etna;
-use crate::etna::PotentialQueueFamilyIndices;
-use crate::etna::debug::DebugLayer;
+use crate::etna::{PotentialQueueFamilyIndices, vk_version_to_string};
+use crate::etna::debug::{DebugLayer, VALIDATION_LAYER_NAME, ValidationCallback, ValidationSettings};
 use crate::etna::utility::vk_cstr_to_string;
 
 pub struct Instance {
     instance: ash::Instance,
     debug_layer: ManuallyDrop<Option<DebugLayer>>,
+    // boxed so its address, handed to the messengers as their user data, stays put. Freed after the instance, as
+    // destroying the instance can still report to it
+    validation_callback: Option<Box<ValidationCallback>>,
 }
 
-#[cfg(debug_assertions)]
-pub const VALIDATION_LAYERS: [&str; 1] = [
-    "VK_LAYER_KHRONOS_validation"
-];
-#[cfg(not(debug_assertions))]
-pub const VALIDATION_LAYERS: [&str; 0] = [];
-
 impl Deref for Instance {
     type Target = ash::Instance;
 
@@ -34,10 +30,8 @@ impl Deref for Instance {
 
 // creation
 impl Instance {
-    pub fn new(entry: &Entry) -> Instance {
-        if !are_desired_validation_layers_supported(entry) {
-            panic!("Required validation layers not supported");
-        }
+    pub fn new(entry: &Entry, mut validation_settings: ValidationSettings) -> Instance {
+        let validation_enabled = validation_settings.enabled && is_validation_layer_supported(entry);
 
         let application_name: CString = CString::new("Fast Rehnda").unwrap();
         let application_version: u32 = vk::make_api_version(0, 0, 1, 0);
@@ -45,6 +39,14 @@ impl Instance {
         let engine_version: u32 = vk::make_api_version(0, 0, 1, 0);
         // vulkan spec 1.3.0
         let vulkan_api_version: u32 = vk::make_api_version(0, 1, 3, 0);
+        // a 1.0 loader doesn't report a version at all
+        let instance_api_version = entry.try_enumerate_instance_version()
+            .expect("Failed to query the instance version")
+            .unwrap_or(vk::API_VERSION_1_0);
+        info!("Vulkan instance version {}", vk_version_to_string(instance_api_version));
+        if instance_api_version < vulkan_api_version {
+            warn!("The Vulkan loader supports version {}, but {} is required", vk_version_to_string(instance_api_version), vk_version_to_string(vulkan_api_version));
+        }
 
         let app_info = vk::ApplicationInfo::builder()
             .application_name(&application_name)
@@ -53,33 +55,44 @@ impl Instance {
             .engine_version(engine_version)
             .api_version(vulkan_api_version);
 
-        let _needed_extensions = entry.enumerate_instance_extension_properties(None)
+        let available_extensions = entry.enumerate_instance_extension_properties(None)
             .expect("Couldn't enumerate extension properties");
 
-        let required_extension_names = required_extension_names();
-        let validation_layer_names = VALIDATION_LAYERS.map(|layer| layer.as_ptr() as *const c_char);
+        let mut required_extension_names = required_extension_names();
+        if validation_enabled {
+            required_extension_names.push(ext::DebugUtils::name().as_ptr());
+        }
+        // optional, lets surfaces report the hdr color spaces
+        let swapchain_colorspace_name = vk::ExtSwapchainColorspaceFn::name();
+        if available_extensions.iter().any(|extension| vk_cstr_to_string(&extension.extension_name) == swapchain_colorspace_name.to_str().unwrap()) {
+            required_extension_names.push(swapchain_colorspace_name.as_ptr());
+        } else {
+            info!("{} is not available, hdr output is unsupported", swapchain_colorspace_name.to_str().unwrap());
+        }
+        let validation_layer_name = CString::new(VALIDATION_LAYER_NAME).unwrap();
+        let validation_layer_names: Vec<*const c_char> = if validation_enabled { vec![validation_layer_name.as_ptr()] } else { Vec::new() };
         let mut create_info = vk::InstanceCreateInfo::builder()
             .application_info(&app_info)
             .enabled_extension_names(required_extension_names.as_slice())
             .enabled_layer_names(validation_layer_names.as_slice());
 
-        let mut debug_create_info = DebugLayer::debug_messenger_create_info();
-        if cfg!(debug_assertions) {
+        // also reports problems creating and destroying the instance, which the messenger can't see
+        let validation_callback = validation_settings.callback.take().map(Box::new);
+        let user_data = validation_callback.as_ref().map_or(std::ptr::null_mut(), |callback| callback.as_ref() as *const ValidationCallback as *mut c_void);
+        let mut debug_create_info = DebugLayer::debug_messenger_create_info(&validation_settings, user_data);
+        if validation_enabled {
             create_info = create_info.push_next(&mut debug_create_info);
         }
 
         let instance = unsafe {
             entry.create_instance(&create_info, None).expect("Failed to create instance")
         };
-        let debug_layer = if cfg!(debug_assertions) {
-            Some(DebugLayer::init(entry, &instance))
-        } else {
-            None
-        };
+        let debug_layer = validation_enabled.then(|| DebugLayer::init(entry, &instance, &validation_settings, user_data));
 
         Instance {
             instance,
             debug_layer: ManuallyDrop::new(debug_layer),
+            validation_callback,
         }
     }
 
@@ -94,6 +107,7 @@ impl Drop for Instance {
             ManuallyDrop::drop(&mut self.debug_layer);
             self.instance.destroy_instance(None);
         }
+        drop(self.validation_callback.take());
     }
 }
 
@@ -125,17 +139,17 @@ impl Instance {
 
 }
 
-fn are_desired_validation_layers_supported(entry: &Entry) -> bool {
+// a missing layer only loses the validation, so it is reported rather than stopping the app
+fn is_validation_layer_supported(entry: &Entry) -> bool {
     let layer_properties = entry.enumerate_instance_layer_properties().expect("Could enumerate layer properties");
-    for layer_name in VALIDATION_LAYERS {
-        let layer_found = layer_properties.iter()
-            .any(|layer_property| vk_cstr_to_string(&layer_property.layer_name) == layer_name);
-        if !layer_found {
-            return false;
-        }
+    let layer_found = layer_properties.iter()
+        .any(|layer_property| vk_cstr_to_string(&layer_property.layer_name) == VALIDATION_LAYER_NAME);
+    if layer_found {
+        info!("Validation enabled with {}", VALIDATION_LAYER_NAME);
+    } else {
+        warn!("Validation was requested but {} isn't installed, continuing without it", VALIDATION_LAYER_NAME);
     }
-    info!("All required validation layers are supported");
-    true
+    layer_found
 }
 
 
@@ -144,6 +158,5 @@ fn required_extension_names() -> Vec<*const i8> {
     vec![
         khr::Surface::name().as_ptr(),
         khr::Win32Surface::name().as_ptr(),
-        #[cfg(debug_assertions)] ext::DebugUtils::name().as_ptr(),
     ]
 }
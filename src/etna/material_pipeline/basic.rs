@@ -7,7 +7,7 @@ use bytemuck_derive::{Pod, Zeroable};
 
 use crate::rehnda_core::{ConstPtr, Mat4};
 use crate::etna::{Device, GraphicsSettings, Swapchain};
-use crate::etna::material_pipeline::{DescriptorManager, layout_binding, MaterialPipeline, PipelineCreateInfo, PipelineMultisamplingInfo, PipelineVertexInputDescription, RasterizationOptions};
+use crate::etna::material_pipeline::{BlendMode, DepthBias, DescriptorManager, layout_binding, MaterialBindings, MaterialPipeline, PipelineCreateInfo, PipelineMultisamplingInfo, PipelineVertexInputDescription, RasterizationOptions, StencilOptions};
 use crate::etna::shader::ShaderModule;
 use crate::assets::{Vertex};
 
@@ -19,11 +19,99 @@ pub struct ModelPushConstants {
 }
 
 pub fn textured_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    create_textured_pipeline(device, descriptor_manager, graphics_settings, swapchain, vert_shader_path, frag_shader_path, &RasterizationOptions::default(), MaterialBindings::Lit)
+}
+
+// only has the global and material sets, for shaders that ignore the scene lighting
+pub fn unlit_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    create_textured_pipeline(device, descriptor_manager, graphics_settings, swapchain, vert_shader_path, frag_shader_path, &RasterizationOptions::default(), MaterialBindings::Unlit)
+}
+
+// for materials that should be visible from behind, e.g. foliage and cloth
+pub fn double_sided_textured_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    create_textured_pipeline(device, descriptor_manager, graphics_settings, swapchain, vert_shader_path, frag_shader_path, &RasterizationOptions {
+        cull_mode: vk::CullModeFlags::NONE,
+        ..Default::default()
+    }, MaterialBindings::Lit)
+}
+
+// points and lines have no faces to cull
+pub fn point_textured_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    create_textured_pipeline(device, descriptor_manager, graphics_settings, swapchain, vert_shader_path, frag_shader_path, &RasterizationOptions {
+        cull_mode: vk::CullModeFlags::NONE,
+        topology: vk::PrimitiveTopology::POINT_LIST,
+        ..Default::default()
+    }, MaterialBindings::Lit)
+}
+
+pub fn line_textured_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    create_textured_pipeline(device, descriptor_manager, graphics_settings, swapchain, vert_shader_path, frag_shader_path, &RasterizationOptions {
+        cull_mode: vk::CullModeFlags::NONE,
+        topology: vk::PrimitiveTopology::LINE_LIST,
+        ..Default::default()
+    }, MaterialBindings::Lit)
+}
+
+// the edges of the triangles pulled slightly towards the camera, to draw over the same mesh already drawn filled. Only
+// the global set is used by the wireframe shader
+pub fn wireframe_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    let bias_direction = if graphics_settings.reverse_z { 1.0 } else { -1.0 };
+    create_textured_pipeline(device, descriptor_manager, graphics_settings, swapchain, vert_shader_path, frag_shader_path, &RasterizationOptions {
+        cull_mode: vk::CullModeFlags::NONE,
+        depth_write_enabled: false,
+        polygon_mode: vk::PolygonMode::LINE,
+        depth_bias: Some(DepthBias {
+            constant_factor: bias_direction,
+            slope_factor: bias_direction,
+        }),
+        ..Default::default()
+    }, MaterialBindings::Unlit)
+}
+
+// marks the stencil where the selected mesh is visible, drawn over the filled mesh like the wireframe so only its
+// depth test matters
+pub fn outline_mask_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    let bias_direction = if graphics_settings.reverse_z { 1.0 } else { -1.0 };
+    create_textured_pipeline(device, descriptor_manager, graphics_settings, swapchain, vert_shader_path, frag_shader_path, &RasterizationOptions {
+        cull_mode: vk::CullModeFlags::NONE,
+        depth_write_enabled: false,
+        blend_mode: BlendMode::NoColorWrites,
+        depth_bias: Some(DepthBias {
+            constant_factor: bias_direction,
+            slope_factor: bias_direction,
+        }),
+        stencil: Some(StencilOptions {
+            compare_op: vk::CompareOp::ALWAYS,
+            reference: OUTLINE_STENCIL_REFERENCE,
+            pass_op: vk::StencilOp::REPLACE,
+        }),
+        ..Default::default()
+    }, MaterialBindings::Unlit)
+}
+
+// the selected mesh pushed out along its normals, only drawn outside of the stencil the mask pipeline marked
+pub fn outline_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    create_textured_pipeline(device, descriptor_manager, graphics_settings, swapchain, vert_shader_path, frag_shader_path, &RasterizationOptions {
+        cull_mode: vk::CullModeFlags::NONE,
+        depth_write_enabled: false,
+        stencil: Some(StencilOptions {
+            compare_op: vk::CompareOp::NOT_EQUAL,
+            reference: OUTLINE_STENCIL_REFERENCE,
+            pass_op: vk::StencilOp::KEEP,
+        }),
+        ..Default::default()
+    }, MaterialBindings::Unlit)
+}
+
+const OUTLINE_STENCIL_REFERENCE: u32 = 1;
+
+fn create_textured_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path, rasterization_options: &RasterizationOptions, material_bindings: MaterialBindings) -> MaterialPipeline {
     let base_color_texture_sampler_layout = descriptor_manager.layout_cache.create_descriptor_layout_for_binding(&[
         layout_binding(0, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::FRAGMENT),
         layout_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT),
         layout_binding(2, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT),
         layout_binding(3, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT),
+        layout_binding(4, vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, vk::ShaderStageFlags::FRAGMENT),
     ]);
     let lighting_set = descriptor_manager.layout_cache.create_descriptor_layout_for_binding(&[
         layout_binding(0, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::FRAGMENT),
@@ -63,17 +151,35 @@ pub fn textured_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut Desc
         enable_sample_rate_shading: graphics_settings.sample_rate_shading_enabled,
     };
 
+    // the bindless set goes last so the layout stays compatible with shaders that don't use it
+    let mut additional_descriptor_set_layouts = vec![base_color_texture_sampler_layout];
+    let bindless_textures = match material_bindings {
+        MaterialBindings::Lit => {
+            additional_descriptor_set_layouts.extend([lighting_set, environment_map_set]);
+            descriptor_manager.bindless_textures.as_ref()
+        }
+        MaterialBindings::Unlit => None,
+    };
+    if let Some(bindless_textures) = bindless_textures {
+        additional_descriptor_set_layouts.push(bindless_textures.descriptor_set_layout);
+    }
+
     let create_info = PipelineCreateInfo {
         global_set_layouts: &[descriptor_manager.global_descriptor_layout],
-        additional_descriptor_set_layouts: &[base_color_texture_sampler_layout, lighting_set, environment_map_set],
+        additional_descriptor_set_layouts: additional_descriptor_set_layouts.as_slice(),
         shader_stages: &[vertex_shader_stage_ci, frag_shader_stage_ci],
         push_constants: &[model_matrix_push_constant],
         extent: swapchain.extent,
         image_format: swapchain.image_format,
         vertex_input,
         multisampling,
-        rasterization_options: &RasterizationOptions::default(),
+        rasterization_options,
+        depth_compare_op: graphics_settings.depth_compare_op(),
+        depth_format: swapchain.depth_buffer.format,
     };
 
-    MaterialPipeline::create(device, &create_info)
+    let mut pipeline = MaterialPipeline::create(device, &create_info);
+    pipeline.bindless_descriptor_set = bindless_textures.map(|bindless_textures| bindless_textures.descriptor_set);
+    pipeline.material_bindings = material_bindings;
+    pipeline
 }
@@ -0,0 +1,106 @@
+use ahash::AHashMap;
+use ash::vk;
+
+use crate::etna::{Device, Texture};
+use crate::rehnda_core::ConstPtr;
+
+pub const MAX_BINDLESS_TEXTURES: u32 = 1024;
+
+#[derive(Debug)]
+pub enum BindlessTextureError {
+    // every slot in the array is already taken
+    ArrayFull,
+}
+
+// a single large array of combined image samplers that materials index into, requires descriptor indexing.
+// IMPROVEMENT slots are never freed, textures are not unloaded yet anyway
+pub struct BindlessTextures {
+    device: ConstPtr<Device>,
+    descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_set: vk::DescriptorSet,
+    // the slot each texture was registered in, so textures shared between materials only take one
+    registered_textures: AHashMap<(vk::ImageView, vk::Sampler), u32>,
+}
+
+impl Drop for BindlessTextures {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+impl BindlessTextures {
+    pub fn create(device: ConstPtr<Device>) -> BindlessTextures {
+        let binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(MAX_BINDLESS_TEXTURES)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        // partially bound as only the registered slots are ever written, update after bind so textures can be
+        // registered while the set is bound in a frame in flight
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND];
+        let mut binding_flags_ci = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+            .binding_flags(&binding_flags);
+        let layout_ci = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(std::slice::from_ref(&binding))
+            .push_next(&mut binding_flags_ci);
+        let descriptor_set_layout = unsafe { device.create_descriptor_set_layout(&layout_ci, None) }
+            .expect("Failed to create bindless descriptor set layout");
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: MAX_BINDLESS_TEXTURES,
+        };
+        let pool_ci = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .max_sets(1)
+            .pool_sizes(std::slice::from_ref(&pool_size));
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_ci, None) }
+            .expect("Failed to create bindless descriptor pool");
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(std::slice::from_ref(&descriptor_set_layout));
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info) }
+            .expect("Failed to allocate bindless descriptor set")[0];
+
+        BindlessTextures {
+            device,
+            descriptor_pool,
+            descriptor_set_layout,
+            descriptor_set,
+            registered_textures: AHashMap::new(),
+        }
+    }
+
+    // returns the index of the texture in the bindless array, textures already registered keep their index
+    pub fn register_texture(&mut self, texture: &Texture) -> Result<u32, BindlessTextureError> {
+        let key = (texture.image.image_view, texture.sampler);
+        if let Some(index) = self.registered_textures.get(&key) {
+            return Ok(*index);
+        }
+        let index = self.registered_textures.len() as u32;
+        if index >= MAX_BINDLESS_TEXTURES {
+            return Err(BindlessTextureError::ArrayFull);
+        }
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.image.image_view)
+            .sampler(texture.sampler)
+            .build();
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(index)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(std::slice::from_ref(&image_info));
+        unsafe { self.device.update_descriptor_sets(std::slice::from_ref(&write), &[]); }
+        self.registered_textures.insert(key, index);
+        Ok(index)
+    }
+}
@@ -76,12 +76,14 @@ impl DescriptorAllocator {
         }
     }
 
+    // frees every set allocated so far, the caller must make sure no pending command buffer still uses them
     pub fn reset_pools(&mut self) {
         for used_pool in self.used_pools.iter() {
             unsafe { self.device.reset_descriptor_pool(*used_pool, vk::DescriptorPoolResetFlags::empty()) }
                 .expect("Failed to reset descriptor pool");
         }
-        self.used_pools.clear();
+        // the reset pools are kept to be reused rather than recreated
+        self.free_pools.append(&mut self.used_pools);
         self.current_pool = None;
     }
 
@@ -1,32 +1,51 @@
 use ash::vk;
 use bevy_ecs::system::Resource;
 use crate::rehnda_core::ConstPtr;
-use crate::etna::Device;
-use crate::etna::material_pipeline::{layout_binding, DescriptorAllocator, DescriptorBuilder, DescriptorLayoutCache};
+use crate::etna::{Device, MAX_FRAMES_IN_FLIGHT};
+use crate::etna::material_pipeline::{layout_binding, BindlessTextures, DescriptorAllocator, DescriptorBuilder, DescriptorLayoutCache};
 
 #[derive(Resource)]
 pub struct DescriptorManager {
+    // long lived sets such as materials, never reset
     pub allocator: DescriptorAllocator,
+    // sets only used by a single frame, each frame in flight's allocator is reset once its fence has signaled
+    frame_allocators: [DescriptorAllocator; MAX_FRAMES_IN_FLIGHT],
     pub layout_cache: DescriptorLayoutCache,
 
     pub global_descriptor_layout: vk::DescriptorSetLayout,
+    pub bindless_textures: Option<BindlessTextures>,
 }
 
 impl DescriptorManager {
-    pub fn create(device: ConstPtr<Device>) -> DescriptorManager {
+    pub fn create(device: ConstPtr<Device>, bindless_textures_enabled: bool) -> DescriptorManager {
         let allocator = DescriptorAllocator::create(device);
+        let frame_allocators = std::array::from_fn(|_| DescriptorAllocator::create(device));
         let mut layout_cache = DescriptorLayoutCache::create(device);
         let global_descriptor_layout = layout_cache.create_descriptor_layout_for_binding(&[
             layout_binding(0, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
         ]);
+        let bindless_textures = bindless_textures_enabled.then(|| BindlessTextures::create(device));
         DescriptorManager {
             allocator,
+            frame_allocators,
             layout_cache,
-            global_descriptor_layout
+            global_descriptor_layout,
+            bindless_textures,
         }
     }
 
     pub fn descriptor_builder(&mut self) -> DescriptorBuilder {
         DescriptorBuilder::begin(&mut self.layout_cache, &mut self.allocator)
     }
+
+    // builds a set that is only valid for the frame it was allocated in
+    pub fn frame_descriptor_builder(&mut self, frame_index: usize) -> DescriptorBuilder {
+        DescriptorBuilder::begin(&mut self.layout_cache, &mut self.frame_allocators[frame_index % MAX_FRAMES_IN_FLIGHT])
+    }
+
+    // frees the sets allocated the last time this frame's data was used, must be called after the frame's fence
+    // has been waited on. Follows the main window's frames, so secondary windows can't use per frame sets
+    pub fn begin_frame(&mut self, frame_index: usize) {
+        self.frame_allocators[frame_index % MAX_FRAMES_IN_FLIGHT].reset_pools();
+    }
 }
\ No newline at end of file
@@ -1,13 +1,30 @@
 use ash::vk;
+use log::warn;
 
 use crate::rehnda_core::ConstPtr;
 use crate::etna;
-use crate::etna::MsaaSamples;
+use crate::etna::{DepthBuffer, MsaaSamples};
 
 pub struct MaterialPipeline {
     device: ConstPtr<etna::Device>,
     pub pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
+    // bound after the pipeline's other sets when the pipeline samples from the bindless texture array
+    pub bindless_descriptor_set: Option<vk::DescriptorSet>,
+    pub material_bindings: MaterialBindings,
+    // set when the line width is dynamic, which is whenever the device supports wide lines
+    dynamic_line_width: Option<f32>,
+    // dynamic so objects with mirroring transforms can flip it
+    front_face: vk::FrontFace,
+}
+
+// the descriptor sets a render object's pipeline expects after the global set
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MaterialBindings {
+    // material, lighting and environment map sets
+    Lit,
+    // only the material set, for shaders that don't use scene lighting
+    Unlit,
 }
 
 impl Drop for MaterialPipeline {
@@ -30,20 +47,106 @@ pub struct PipelineCreateInfo<'a> {
     pub extent: vk::Extent2D,
     pub multisampling: PipelineMultisamplingInfo,
     pub rasterization_options: &'a RasterizationOptions,
+    // should come from GraphicsSettings::depth_compare_op so it matches the reverse z setting
+    pub depth_compare_op: vk::CompareOp,
+    // must match the depth attachment the pipeline is drawn with, which for the scene is the swapchain's depth buffer
+    pub depth_format: vk::Format,
 }
 
 pub struct RasterizationOptions {
     pub cull_mode: vk::CullModeFlags,
+    // the winding of front facing triangles, counter clockwise for glTF
+    pub front_face: vk::FrontFace,
+    pub depth_write_enabled: bool,
+    pub blend_mode: BlendMode,
+    // the width of lines and wireframe edges, clamped to what the device supports
+    pub line_width: f32,
+    // meshes are only drawn with pipelines matching their topology
+    pub topology: vk::PrimitiveTopology,
+    // lets the max index value start a new strip or fan, list topologies can't use it
+    pub primitive_restart_enable: bool,
+    // clamps depth to the depth range instead of clipping against the near and far planes, so shadow casters behind
+    // the light's near plane still write depth. Ignored when the device doesn't support it
+    pub depth_clamp_enable: bool,
+    // lines draws only the edges of triangles, falling back to filled when the device doesn't support it
+    pub polygon_mode: vk::PolygonMode,
+    pub depth_bias: Option<DepthBias>,
+    // needs a depth format with stencil, the test is skipped without one
+    pub stencil: Option<StencilOptions>,
+}
+
+/// Offsets the depth of the pipeline's fragments, so geometry drawn over a surface at the same depth wins the depth test.
+/// Negative factors move fragments towards the camera with a regular depth range, positive ones with reverse z
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    pub slope_factor: f32,
+}
+
+/// Tests the stencil buffer against the reference and writes to it where the test passes, the same for front and back
+/// faces
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StencilOptions {
+    pub compare_op: vk::CompareOp,
+    pub reference: u32,
+    // KEEP leaves the stencil as it was, REPLACE writes the reference
+    pub pass_op: vk::StencilOp,
+}
+
+impl StencilOptions {
+    fn op_state(&self) -> vk::StencilOpState {
+        vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: self.pass_op,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: self.compare_op,
+            compare_mask: 0xFF,
+            write_mask: 0xFF,
+            reference: self.reference,
+        }
+    }
+}
+
+impl RasterizationOptions {
+    pub fn input_assembly_state(&self) -> vk::PipelineInputAssemblyStateCreateInfo {
+        let restartable_topology = matches!(self.topology, vk::PrimitiveTopology::LINE_STRIP | vk::PrimitiveTopology::TRIANGLE_STRIP | vk::PrimitiveTopology::TRIANGLE_FAN);
+        assert!(!self.primitive_restart_enable || restartable_topology, "Primitive restart needs a strip or fan topology, not {:?}", self.topology);
+        vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(self.topology)
+            .primitive_restart_enable(self.primitive_restart_enable)
+            .build()
+    }
 }
 
 impl Default for RasterizationOptions {
     fn default() -> Self {
         RasterizationOptions {
-            cull_mode: vk::CullModeFlags::BACK
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_write_enabled: true,
+            blend_mode: BlendMode::Opaque,
+            line_width: 1.0,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart_enable: false,
+            depth_clamp_enable: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            depth_bias: None,
+            stencil: None,
         }
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    Opaque,
+    // adds the fragment color onto the target, order independent so good for particles and glows
+    Additive,
+    // blends the fragment over the target, expecting the fragment color to be premultiplied by its alpha
+    PremultipliedAlpha,
+    // only depth testing, for passes like occlusion queries that shouldn't show up in the image
+    NoColorWrites,
+}
+
 pub struct PipelineMultisamplingInfo {
     pub msaa_samples: MsaaSamples,
     pub enable_sample_rate_shading: bool,
@@ -60,10 +163,7 @@ impl MaterialPipeline {
             .vertex_binding_descriptions(create_info.vertex_input.bindings)
             .vertex_attribute_descriptions(create_info.vertex_input.attributes);
 
-        // let us change viewport and scissor state without rebuilding the pipeline
-        let input_assembly_ci = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-            .primitive_restart_enable(false);
+        let input_assembly_ci = create_info.rasterization_options.input_assembly_state();
 
         let viewport = vk::Viewport::builder()
             .x(0.0)
@@ -83,20 +183,37 @@ impl MaterialPipeline {
             .viewports(viewports)
             .scissors(scissors);
 
+        let line_width = device.clamp_line_width(create_info.rasterization_options.line_width);
+        let dynamic_line_width = device.supports_wide_lines().then_some(line_width);
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR, vk::DynamicState::FRONT_FACE];
+        if dynamic_line_width.is_some() {
+            dynamic_states.push(vk::DynamicState::LINE_WIDTH);
+        }
         let dynamic_state_ci = vk::PipelineDynamicStateCreateInfo::builder()
-            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+            .dynamic_states(&dynamic_states);
 
+        let depth_clamp_enable = create_info.rasterization_options.depth_clamp_enable && device.supports_depth_clamp();
+        if create_info.rasterization_options.depth_clamp_enable && !depth_clamp_enable {
+            warn!("Depth clamp isn't supported by the device, the pipeline clips against the near and far planes instead");
+        }
+        let polygon_mode = if create_info.rasterization_options.polygon_mode != vk::PolygonMode::FILL && !device.supports_fill_mode_non_solid() {
+            warn!("{:?} polygons aren't supported by the device, the pipeline fills them instead", create_info.rasterization_options.polygon_mode);
+            vk::PolygonMode::FILL
+        } else {
+            create_info.rasterization_options.polygon_mode
+        };
+        let depth_bias = create_info.rasterization_options.depth_bias;
         let rasterization_ci = vk::PipelineRasterizationStateCreateInfo::builder()
-            .depth_clamp_enable(false)
+            .depth_clamp_enable(depth_clamp_enable)
             .rasterizer_discard_enable(false)
-            .polygon_mode(vk::PolygonMode::FILL)
-            .line_width(1.0)
+            .polygon_mode(polygon_mode)
+            .line_width(line_width)
             .cull_mode(create_info.rasterization_options.cull_mode)
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-            .depth_bias_enable(false)
-            .depth_bias_constant_factor(0.0)
+            .front_face(create_info.rasterization_options.front_face)
+            .depth_bias_enable(depth_bias.is_some())
+            .depth_bias_constant_factor(depth_bias.map_or(0.0, |bias| bias.constant_factor))
             .depth_bias_clamp(0.0)
-            .depth_bias_slope_factor(0.0);
+            .depth_bias_slope_factor(depth_bias.map_or(0.0, |bias| bias.slope_factor));
 
         let multisample_state_ci = vk::PipelineMultisampleStateCreateInfo::builder()
             .rasterization_samples(create_info.multisampling.msaa_samples.to_sample_count_flags())
@@ -105,14 +222,23 @@ impl MaterialPipeline {
             .alpha_to_coverage_enable(false)
             .alpha_to_one_enable(false);
 
+        let (blend_enabled, dst_blend_factor) = match create_info.rasterization_options.blend_mode {
+            BlendMode::Opaque | BlendMode::NoColorWrites => (false, vk::BlendFactor::ZERO),
+            BlendMode::Additive => (true, vk::BlendFactor::ONE),
+            BlendMode::PremultipliedAlpha => (true, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+        };
+        let color_write_mask = match create_info.rasterization_options.blend_mode {
+            BlendMode::NoColorWrites => vk::ColorComponentFlags::empty(),
+            _ => vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A,
+        };
         let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
-            .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A)
-            .blend_enable(false)
+            .color_write_mask(color_write_mask)
+            .blend_enable(blend_enabled)
             .src_color_blend_factor(vk::BlendFactor::ONE)
-            .dst_color_blend_factor(vk::BlendFactor::ZERO)
+            .dst_color_blend_factor(dst_blend_factor)
             .color_blend_op(vk::BlendOp::ADD)
             .src_alpha_blend_factor(vk::BlendFactor::ONE)
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .dst_alpha_blend_factor(dst_blend_factor)
             .alpha_blend_op(vk::BlendOp::ADD);
         let color_blend_attachments = &[color_blend_attachment.build()];
 
@@ -122,17 +248,26 @@ impl MaterialPipeline {
             .attachments(color_blend_attachments)
             .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
+        let has_stencil = DepthBuffer::format_has_stencil(create_info.depth_format);
+        let stencil = create_info.rasterization_options.stencil.filter(|_| has_stencil);
+        if create_info.rasterization_options.stencil.is_some() && stencil.is_none() {
+            warn!("{:?} has no stencil, the pipeline skips its stencil test", create_info.depth_format);
+        }
+        let stencil_op_state = stencil.map_or_else(vk::StencilOpState::default, |stencil| stencil.op_state());
         let depth_stencil_ci = vk::PipelineDepthStencilStateCreateInfo::builder()
             .depth_test_enable(true)
-            .depth_write_enable(true)
-            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .depth_write_enable(create_info.rasterization_options.depth_write_enabled)
+            .depth_compare_op(create_info.depth_compare_op)
             .depth_bounds_test_enable(false)
-            .stencil_test_enable(false);
+            .stencil_test_enable(stencil.is_some())
+            .front(stencil_op_state)
+            .back(stencil_op_state);
 
         let color_attachment_formats = &[create_info.image_format];
         let mut pipeline_rendering_create_info = vk::PipelineRenderingCreateInfo::builder()
             .color_attachment_formats(color_attachment_formats)
-            .depth_attachment_format(vk::Format::D32_SFLOAT); // TODO don't assume this format
+            .depth_attachment_format(create_info.depth_format)
+            .stencil_attachment_format(if has_stencil { create_info.depth_format } else { vk::Format::UNDEFINED });
 
         let set_layouts: Vec<vk::DescriptorSetLayout> = [create_info.global_set_layouts, create_info.additional_descriptor_set_layouts].concat();
         let pipeline_layout_ci = vk::PipelineLayoutCreateInfo::builder()
@@ -164,10 +299,29 @@ impl MaterialPipeline {
             device,
             pipeline_layout,
             pipeline,
+            bindless_descriptor_set: None,
+            material_bindings: MaterialBindings::Lit,
+            dynamic_line_width,
+            front_face: create_info.rasterization_options.front_face,
+        }
+    }
+
+    // binds the pipeline and sets the dynamic state that isn't the viewport and scissor
+    pub fn cmd_bind(&self, command_buffer: vk::CommandBuffer) {
+        unsafe { self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline) };
+        if let Some(line_width) = self.dynamic_line_width {
+            unsafe { self.device.cmd_set_line_width(command_buffer, line_width) };
         }
+        self.cmd_set_mirrored(command_buffer, false);
     }
 
-    pub fn graphics_pipeline(&self) -> vk::Pipeline {
-        self.pipeline
+    // a transform with a negative determinant mirrors the object, reversing its triangles' winding
+    pub fn cmd_set_mirrored(&self, command_buffer: vk::CommandBuffer, mirrored: bool) {
+        let front_face = match (self.front_face, mirrored) {
+            (front_face, false) => front_face,
+            (vk::FrontFace::CLOCKWISE, true) => vk::FrontFace::COUNTER_CLOCKWISE,
+            (_, true) => vk::FrontFace::CLOCKWISE,
+        };
+        unsafe { self.device.cmd_set_front_face(command_buffer, front_face) };
     }
 }
\ No newline at end of file
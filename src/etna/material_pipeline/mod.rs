@@ -9,4 +9,6 @@ pub use descriptor_layout_cache::*;
 mod descriptor_builder;
 pub use descriptor_builder::*;
 mod descriptor_manager;
-pub use descriptor_manager::*;
\ No newline at end of file
+pub use descriptor_manager::*;
+mod bindless_textures;
+pub use bindless_textures::*;
\ No newline at end of file
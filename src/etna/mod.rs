@@ -2,6 +2,8 @@ mod buffer;
 pub use buffer::*;
 mod command_pool;
 pub use command_pool::*;
+mod deletion_queue;
+pub use deletion_queue::*;
 mod device;
 pub use device::*;
 mod frame_renderer;
@@ -16,6 +18,24 @@ mod surface;
 pub use surface::*;
 mod swapchain;
 pub use swapchain::*;
+mod secondary_window;
+pub use secondary_window::*;
+mod gpu_timer;
+pub use gpu_timer::*;
+mod occlusion_culler;
+pub use occlusion_culler::*;
+mod renderdoc;
+pub use renderdoc::*;
+mod render_graph;
+pub use render_graph::*;
+mod hdr_screenshot;
+pub use hdr_screenshot::*;
+mod frame_pacing;
+pub use frame_pacing::*;
+mod compute_pipeline;
+pub use compute_pipeline::*;
+mod auto_exposure;
+pub use auto_exposure::*;
 pub mod material_pipeline;
 pub mod vkinit;
 
@@ -27,4 +47,5 @@ pub use aggregate_commands::*;
 
 pub mod utility;
 mod debug;
+pub use debug::*;
 pub mod shader;
\ No newline at end of file
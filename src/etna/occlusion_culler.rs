@@ -0,0 +1,191 @@
+use std::ffi::CString;
+use std::mem::size_of;
+use std::path::Path;
+
+use ahash::AHashSet;
+use ash::vk;
+use bevy_ecs::prelude::*;
+use bytemuck_derive::{Pod, Zeroable};
+
+use crate::assets::cube;
+use crate::assets::material_server::{MaterialPipelineHandle, MaterialServer, Shader};
+use crate::assets::render_object::Aabb;
+use crate::etna::{Device, DeviceRes, GraphicsSettings, MAX_FRAMES_IN_FLIGHT, Swapchain};
+use crate::etna::material_pipeline::{BlendMode, DescriptorManager, MaterialPipeline, PipelineCreateInfo, PipelineMultisamplingInfo, PipelineVertexInputDescription, RasterizationOptions};
+use crate::etna::shader::ShaderModule;
+use crate::rehnda_core::{ConstPtr, Mat4, Vec3, Vec4};
+
+// the most objects tested in a frame, any more are always drawn
+pub const MAX_OCCLUSION_QUERIES: u32 = 4096;
+
+#[repr(C)]
+#[derive(Zeroable, Pod, Debug, Copy, Clone)]
+struct BoundsPushConstants {
+    model_matrix: Mat4,
+    bounds_min: Vec4,
+    bounds_max: Vec4,
+}
+
+/// Tests each object's bounding box against the depth buffer with occlusion queries. The results are read the next
+/// time the frame's data is used, once its fence has signaled, so hidden objects are skipped a couple of frames late
+#[derive(Resource)]
+pub struct OcclusionCuller {
+    device: ConstPtr<Device>,
+    query_pool: vk::QueryPool,
+    pub pipeline: MaterialPipelineHandle,
+    // the object tested by each query of a frame slot, in query order
+    queried_entities: [Vec<Entity>; MAX_FRAMES_IN_FLIGHT],
+    occluded_entities: AHashSet<Entity>,
+    camera_position: Vec3,
+}
+
+/// An object drawn this frame that should have its visibility tested
+pub struct OcclusionCandidate {
+    pub entity: Entity,
+    pub model_matrix: Mat4,
+    pub bounds: Aabb,
+}
+
+impl OcclusionCuller {
+    pub fn create(device: ConstPtr<Device>, material_server: &mut MaterialServer) -> Self {
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::OCCLUSION)
+            .query_count(MAX_OCCLUSION_QUERIES * MAX_FRAMES_IN_FLIGHT as u32);
+        let query_pool = unsafe { device.create_query_pool(&create_info, None) }
+            .expect("Failed to create occlusion query pool");
+        Self {
+            device,
+            query_pool,
+            pipeline: material_server.load_material(bounding_box_pipeline, Shader::BoundingBox),
+            queried_entities: Default::default(),
+            occluded_entities: AHashSet::new(),
+            camera_position: Vec3::ZERO,
+        }
+    }
+
+    pub fn is_occluded(&self, entity: Entity) -> bool {
+        self.occluded_entities.contains(&entity)
+    }
+
+    // forgets all results, so nothing stays hidden when culling is turned back on
+    pub fn reset(&mut self) {
+        self.occluded_entities.clear();
+        self.queried_entities.iter_mut().for_each(Vec::clear);
+    }
+
+    // reads the results of the last frame to use this slot and resets its queries, must be recorded outside of rendering
+    // after the slot's fence has been waited on
+    pub fn cmd_begin_frame(&mut self, command_buffer: vk::CommandBuffer, frame_index: usize, camera_position: Vec3) {
+        let slot = frame_index % MAX_FRAMES_IN_FLIGHT;
+        let queried_entities = std::mem::take(&mut self.queried_entities[slot]);
+        if !queried_entities.is_empty() {
+            let mut samples_passed = vec![0u64; queried_entities.len()];
+            unsafe { self.device.get_query_pool_results(self.query_pool, self.first_query(slot), queried_entities.len() as u32, &mut samples_passed, vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT) }
+                .expect("Failed to read occlusion queries");
+            for (entity, samples) in std::iter::zip(queried_entities, samples_passed) {
+                if samples == 0 {
+                    self.occluded_entities.insert(entity);
+                } else {
+                    self.occluded_entities.remove(&entity);
+                }
+            }
+        }
+        unsafe { self.device.cmd_reset_query_pool(command_buffer, self.query_pool, self.first_query(slot), MAX_OCCLUSION_QUERIES); }
+        self.camera_position = camera_position;
+    }
+
+    // draws the candidates' bounding boxes against the depth buffer, must be recorded after the opaque geometry
+    pub fn cmd_query_visibility(&mut self, command_buffer: vk::CommandBuffer, frame_index: usize, pipeline: &MaterialPipeline, cube_vertex_buffer: vk::Buffer, candidates: &[OcclusionCandidate]) {
+        let slot = frame_index % MAX_FRAMES_IN_FLIGHT;
+        unsafe { self.device.cmd_bind_vertex_buffers(command_buffer, 0, std::slice::from_ref(&cube_vertex_buffer), std::slice::from_ref(&0u64)); }
+        for candidate in candidates {
+            // the near plane would clip away the box around the camera, so anything the camera is inside is visible
+            let local_camera_position = candidate.model_matrix.inverse().transform_point3(self.camera_position);
+            if candidate.bounds.contains(local_camera_position) || self.queried_entities[slot].len() as u32 >= MAX_OCCLUSION_QUERIES {
+                self.occluded_entities.remove(&candidate.entity);
+                continue;
+            }
+            let query = self.first_query(slot) + self.queried_entities[slot].len() as u32;
+            let push_constants = BoundsPushConstants {
+                model_matrix: candidate.model_matrix,
+                bounds_min: candidate.bounds.min.extend(1.0),
+                bounds_max: candidate.bounds.max.extend(1.0),
+            };
+            unsafe {
+                self.device.cmd_begin_query(command_buffer, self.query_pool, query, vk::QueryControlFlags::empty());
+                self.device.cmd_push_constants(command_buffer, pipeline.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, bytemuck::cast_slice(std::slice::from_ref(&push_constants)));
+                self.device.cmd_draw(command_buffer, cube::CUBE_VERTICES.len() as u32 / 3, 1, 0, 0);
+                self.device.cmd_end_query(command_buffer, self.query_pool, query);
+            }
+            self.queried_entities[slot].push(candidate.entity);
+        }
+    }
+
+    fn first_query(&self, slot: usize) -> u32 {
+        slot as u32 * MAX_OCCLUSION_QUERIES
+    }
+}
+
+impl Drop for OcclusionCuller {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_query_pool(self.query_pool, None); }
+    }
+}
+
+pub fn occlusion_culler_startup_system(mut commands: Commands, device: DeviceRes, mut material_server: ResMut<MaterialServer>) {
+    commands.insert_resource(OcclusionCuller::create(device.ptr(), &mut material_server));
+}
+
+pub fn bounding_box_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain, vert_shader_path: &Path, frag_shader_path: &Path) -> MaterialPipeline {
+    let vert_shader_module = ShaderModule::load_from_file(device, Path::new(vert_shader_path));
+    let frag_shader_module = ShaderModule::load_from_file(device, Path::new(frag_shader_path));
+    let main_function_name = CString::new("main").unwrap();
+    let vertex_shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module.handle())
+        .name(main_function_name.as_c_str())
+        .build();
+    let frag_shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module.handle())
+        .name(main_function_name.as_c_str())
+        .build();
+
+    let vertex_attributes = cube::cube_vertex_attributes();
+    let vertex_input = PipelineVertexInputDescription {
+        bindings: &[cube::cube_vertex_input_bindings()],
+        attributes: vertex_attributes.as_slice(),
+    };
+    let bounds_push_constant = vk::PushConstantRange::builder()
+        .offset(0)
+        .size(size_of::<BoundsPushConstants>() as u32)
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .build();
+
+    let multisampling = PipelineMultisamplingInfo {
+        msaa_samples: graphics_settings.msaa_samples,
+        enable_sample_rate_shading: false,
+    };
+
+    let create_info = PipelineCreateInfo {
+        global_set_layouts: &[descriptor_manager.global_descriptor_layout],
+        additional_descriptor_set_layouts: &[],
+        shader_stages: &[vertex_shader_stage_ci, frag_shader_stage_ci],
+        push_constants: &[bounds_push_constant],
+        extent: swapchain.extent,
+        image_format: swapchain.image_format,
+        vertex_input,
+        multisampling,
+        // both sides are drawn so boxes the camera is near still produce samples, nothing is written
+        rasterization_options: &RasterizationOptions {
+            cull_mode: vk::CullModeFlags::NONE,
+            depth_write_enabled: false,
+            blend_mode: BlendMode::NoColorWrites,
+            ..RasterizationOptions::default()
+        },
+        depth_compare_op: graphics_settings.depth_compare_op(),
+        depth_format: swapchain.depth_buffer.format,
+    };
+
+    MaterialPipeline::create(device, &create_info)
+}
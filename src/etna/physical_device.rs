@@ -1,15 +1,18 @@
 use std::collections::HashSet;
 use std::ffi::CStr;
+use std::fmt::{Display, Formatter};
 use std::ops::Deref;
 
 use ash::extensions::khr;
 use ash::vk;
 use bevy_ecs::prelude::Res;
 use bevy_ecs::system::Resource;
+use log::info;
 
 use crate::rehnda_core::{ConstPtr, LongLivedObject};
 use crate::etna;
-use crate::etna::{GraphicsSettings, MsaaSamples};
+use crate::etna::{AnisotropyLevel, CommandBufferReuse, DebugRenderMode, DefaultSampler, GraphicsSettings, MsaaSamples};
+use crate::etna::material_pipeline::MAX_BINDLESS_TEXTURES;
 use crate::etna::utility::vk_cstr_to_string;
 
 pub const DEVICE_EXTENSIONS: [&CStr; 4] = [
@@ -21,6 +24,43 @@ pub const DEVICE_EXTENSIONS: [&CStr; 4] = [
 
 pub type PhysicalDeviceRes<'w> = Res<'w, LongLivedObject<PhysicalDevice>>;
 
+const NVIDIA_VENDOR_ID: u32 = 0x10DE;
+
+// the global, material, light and environment sets, then the bindless textures
+const BINDLESS_DESCRIPTOR_SETS: u32 = 5;
+
+/// Identifies the gpu and driver in use, for logs and bug reports
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub device_name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub api_version: String,
+    pub driver_version: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub sample_rate_shading_supported: bool,
+    pub max_image_dimension: u32,
+}
+
+impl Display for DeviceInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({:?}), Vulkan {}, driver {}, vendor 0x{:04X}, device 0x{:04X}", self.device_name, self.device_type, self.api_version, self.driver_version, self.vendor_id, self.device_id)
+    }
+}
+
+pub fn vk_version_to_string(version: u32) -> String {
+    format!("{}.{}.{}", vk::api_version_major(version), vk::api_version_minor(version), vk::api_version_patch(version))
+}
+
+// driver versions are vendor specific, nvidia packs theirs as 10.8.8.6 bits, most others follow the vulkan encoding
+fn driver_version_to_string(vendor_id: u32, driver_version: u32) -> String {
+    if vendor_id == NVIDIA_VENDOR_ID {
+        format!("{}.{}.{}.{}", driver_version >> 22, (driver_version >> 14) & 0xFF, (driver_version >> 6) & 0xFF, driver_version & 0x3F)
+    } else {
+        vk_version_to_string(driver_version)
+    }
+}
+
 
 #[derive(Resource)]
 pub struct PhysicalDevice {
@@ -28,7 +68,7 @@ pub struct PhysicalDevice {
     physical_device: vk::PhysicalDevice,
     pub device_properties: vk::PhysicalDeviceProperties,
     pub supported_features: vk::PhysicalDeviceFeatures,
-    pub graphics_settings: GraphicsSettings,
+    pub bindless_textures_supported: bool,
     queue_family_indices: QueueFamilyIndices,
 }
 
@@ -49,6 +89,12 @@ impl PhysicalDevice {
         self.queue_family_indices
     }
 
+    // how many of the low bits of a timestamp written on the graphics queue are meaningful, 0 when it can't write them
+    pub fn graphics_timestamp_valid_bits(&self) -> u32 {
+        let queue_families = unsafe { self.instance.get_physical_device_queue_family_properties(self.physical_device) };
+        queue_families[self.queue_family_indices.graphics_family as usize].timestamp_valid_bits
+    }
+
     pub fn pick_physical_device(instance: ConstPtr<etna::Instance>, surface: &etna::Surface) -> PhysicalDevice {
         let physical_devices = unsafe { instance.enumerate_physical_devices() }
             .expect("Couldn't enumerate physical devices");
@@ -62,18 +108,58 @@ impl PhysicalDevice {
         let chosen_queue_family_indices = instance.find_queue_families(surface, picked_device);
         let device_properties = unsafe { instance.get_physical_device_properties(picked_device) };
         let supported_features = unsafe { instance.get_physical_device_features(picked_device) };
-        let graphical_settings = Self::determine_graphical_settings(&device_properties);
-        PhysicalDevice {
+        let bindless_textures_supported = Self::supports_bindless_textures(&instance, picked_device, &device_properties.limits);
+        let physical_device = PhysicalDevice {
             instance,
             physical_device: picked_device,
             device_properties,
             supported_features,
-            graphics_settings: graphical_settings,
+            bindless_textures_supported,
             queue_family_indices: chosen_queue_family_indices.unwrap(),
+        };
+        info!("Using GPU: {}", physical_device.device_info());
+        physical_device
+    }
+
+    pub fn device_info(&self) -> DeviceInfo {
+        DeviceInfo {
+            device_name: vk_cstr_to_string(&self.device_properties.device_name),
+            device_type: self.device_properties.device_type,
+            api_version: vk_version_to_string(self.device_properties.api_version),
+            driver_version: driver_version_to_string(self.device_properties.vendor_id, self.device_properties.driver_version),
+            vendor_id: self.device_properties.vendor_id,
+            device_id: self.device_properties.device_id,
+            sample_rate_shading_supported: self.supported_features.sample_rate_shading == vk::TRUE,
+            max_image_dimension: self.device_properties.limits.max_image_dimension2_d,
         }
     }
 
-    pub fn determine_graphical_settings(device_properties: &vk::PhysicalDeviceProperties) -> GraphicsSettings {
+    // the descriptor indexing features needed to index an unbounded texture array from the fragment shader, and the
+    // limits needed to bind the whole array alongside the material's other sets
+    fn supports_bindless_textures(instance: &etna::Instance, physical_device: vk::PhysicalDevice, limits: &vk::PhysicalDeviceLimits) -> bool {
+        let mut descriptor_indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut descriptor_indexing_features);
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+        let mut descriptor_indexing_properties = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::builder()
+            .push_next(&mut descriptor_indexing_properties);
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties) };
+        descriptor_indexing_features.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+            && descriptor_indexing_features.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+            && descriptor_indexing_features.descriptor_binding_partially_bound == vk::TRUE
+            && descriptor_indexing_features.runtime_descriptor_array == vk::TRUE
+            && limits.max_bound_descriptor_sets >= BINDLESS_DESCRIPTOR_SETS
+            && descriptor_indexing_properties.max_per_stage_descriptor_update_after_bind_samplers >= MAX_BINDLESS_TEXTURES
+            && descriptor_indexing_properties.max_descriptor_set_update_after_bind_sampled_images >= MAX_BINDLESS_TEXTURES
+    }
+
+    // the settings the renderer starts with, the best the device supports
+    pub fn default_graphics_settings(&self) -> GraphicsSettings {
+        Self::determine_graphical_settings(&self.device_properties, self.bindless_textures_supported)
+    }
+
+    pub fn determine_graphical_settings(device_properties: &vk::PhysicalDeviceProperties, bindless_textures_supported: bool) -> GraphicsSettings {
         let counts = device_properties.limits.framebuffer_color_sample_counts & device_properties.limits.framebuffer_depth_sample_counts;
         let msaa_samples = if counts.contains(vk::SampleCountFlags::TYPE_64) {
             MsaaSamples::X64
@@ -94,9 +180,37 @@ impl PhysicalDevice {
         GraphicsSettings {
             msaa_samples,
             sample_rate_shading_enabled: false,
+            reverse_z: false,
+            anisotropy_level: AnisotropyLevel::highest_supported(device_properties.limits.max_sampler_anisotropy),
+            default_sampler: DefaultSampler::Trilinear,
+            logarithmic_depth: false,
+            bindless_textures: bindless_textures_supported,
+            occlusion_culling: false,
+            debug_render_mode: DebugRenderMode::Final,
+            normal_mapping: true,
+            environment_only: false,
+            recording_threads: std::thread::available_parallelism().map_or(1, |threads| threads.get().min(4)),
+            max_texture_dimension: device_properties.limits.max_image_dimension2_d,
+            hdr_output: false,
+            hdr_paper_white_nits: 200.0,
+            hdr_peak_nits: 1000.0,
+            frame_rate_cap: 0,
+            texture_streaming: false,
+            wireframe_overlay: false,
+            command_buffer_reuse: CommandBufferReuse::RecordEveryFrame,
+            auto_exposure: false,
+            auto_exposure_min_ev: -4.0,
+            auto_exposure_max_ev: 12.0,
+            auto_exposure_adaptation_speed: 1.5,
+            transparent_background: false,
         }
     }
 
+    // the largest width or height textures are loaded at
+    pub fn max_texture_dimension(&self, graphics_settings: &GraphicsSettings) -> u32 {
+        graphics_settings.max_texture_dimension.min(self.device_properties.limits.max_image_dimension2_d)
+    }
+
     pub fn _find_memory_type(&self, type_filter: u32, properties: vk::MemoryPropertyFlags) -> u32 {
         let memory_properties = unsafe { self.instance.get_physical_device_memory_properties(self.physical_device) };
         for i in 0..memory_properties.memory_type_count {
@@ -0,0 +1,278 @@
+use ash::vk;
+
+use crate::etna::{Device, image_transitions};
+
+/// How a pass uses an image, which decides the layout the image has to be in for the pass and what the barrier
+/// before it waits on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageAccess {
+    ColorAttachment,
+    DepthAttachment,
+    TransferSrc,
+    TransferDst,
+    // sampled by a compute shader
+    ComputeRead,
+    Present,
+}
+
+impl ImageAccess {
+    fn layout(&self) -> vk::ImageLayout {
+        match self {
+            Self::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Self::DepthAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            Self::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Self::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            Self::ComputeRead => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            Self::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
+    fn stage_mask(&self) -> vk::PipelineStageFlags2 {
+        match self {
+            Self::ColorAttachment => vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            Self::DepthAttachment => vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            Self::TransferSrc | Self::TransferDst => vk::PipelineStageFlags2::TRANSFER,
+            Self::ComputeRead => vk::PipelineStageFlags2::COMPUTE_SHADER,
+            Self::Present => vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+        }
+    }
+
+    fn access_mask(&self) -> vk::AccessFlags2 {
+        match self {
+            Self::ColorAttachment => vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            Self::DepthAttachment => vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            Self::TransferSrc => vk::AccessFlags2::TRANSFER_READ,
+            Self::TransferDst => vk::AccessFlags2::TRANSFER_WRITE,
+            Self::ComputeRead => vk::AccessFlags2::SHADER_SAMPLED_READ,
+            Self::Present => vk::AccessFlags2::empty(),
+        }
+    }
+
+    fn writes(&self) -> bool {
+        matches!(self, Self::ColorAttachment | Self::DepthAttachment | Self::TransferDst)
+    }
+
+    fn is_attachment(&self) -> bool {
+        matches!(self, Self::ColorAttachment | Self::DepthAttachment)
+    }
+}
+
+/// An image imported into a [RenderGraph], used to declare which images a pass reads and writes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GraphImage(usize);
+
+// single mip, single layer images like render targets and swapchain images
+struct TrackedImage {
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+    // how the image was last used, by an earlier pass or from before the graph
+    last_access: ImageAccess,
+    // false until the first barrier when the image's earlier contents aren't needed, which then starts from UNDEFINED
+    has_contents: bool,
+}
+
+impl TrackedImage {
+    fn needs_barrier(&self, access: ImageAccess) -> bool {
+        !self.has_contents || self.last_access.layout() != access.layout() || self.last_access.writes() || access.writes()
+    }
+}
+
+/// Given to a pass when it is recorded
+pub struct PassContext {
+    pub command_buffer: vk::CommandBuffer,
+    resumes: bool,
+    suspends: bool,
+}
+
+impl PassContext {
+    // consecutive passes rendering to the same attachments are recorded as one suspended and resumed render pass
+    // instance, so no barriers are needed between them and the attachments are only loaded and stored once.
+    // Every render pass instance the pass begins has to include these flags
+    pub fn rendering_flags(&self) -> vk::RenderingFlags {
+        self.resuming_flags() | self.suspending_flags()
+    }
+
+    pub fn resuming_flags(&self) -> vk::RenderingFlags {
+        if self.resumes { vk::RenderingFlags::RESUMING } else { vk::RenderingFlags::empty() }
+    }
+
+    pub fn suspending_flags(&self) -> vk::RenderingFlags {
+        if self.suspends { vk::RenderingFlags::SUSPENDING } else { vk::RenderingFlags::empty() }
+    }
+}
+
+struct Pass<'a> {
+    images: Vec<(GraphImage, ImageAccess)>,
+    record: Box<dyn FnOnce(&PassContext) + 'a>,
+}
+
+/// The passes of a frame in the order they are recorded. Each pass declares the images it uses and how, and the graph
+/// transitions the images between passes so the passes only record their own work
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    images: Vec<TrackedImage>,
+    passes: Vec<Pass<'a>>,
+    final_accesses: Vec<(GraphImage, ImageAccess)>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // last_access is how the image was last used before this graph, such as by the previous frame, so the first pass
+    // waits for it. Images whose contents aren't kept are transitioned from UNDEFINED for their first use
+    pub fn import_image(&mut self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, last_access: ImageAccess, keep_contents: bool) -> GraphImage {
+        self.images.push(TrackedImage {
+            image,
+            aspect_mask,
+            last_access,
+            has_contents: keep_contents,
+        });
+        GraphImage(self.images.len() - 1)
+    }
+
+    pub fn add_pass(&mut self, name: &'static str, images: &[(GraphImage, ImageAccess)], record: impl FnOnce(&PassContext) + 'a) {
+        for (index, (image, _)) in images.iter().enumerate() {
+            assert!(images[index + 1..].iter().all(|(other, _)| other != image), "Pass {} uses an image more than once", name);
+        }
+        self.passes.push(Pass {
+            images: images.to_vec(),
+            record: Box::new(record),
+        });
+    }
+
+    // the access the image is left in after every pass, such as presenting a swapchain image
+    pub fn finish_image(&mut self, image: GraphImage, access: ImageAccess) {
+        self.final_accesses.push((image, access));
+    }
+
+    pub fn execute(mut self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let passes = std::mem::take(&mut self.passes);
+        let continuations = Self::render_pass_continuations(&passes);
+        for (pass, (resumes, suspends)) in passes.into_iter().zip(continuations) {
+            if !resumes {
+                for &(image, access) in &pass.images {
+                    self.cmd_transition(device, command_buffer, image, access);
+                }
+            }
+            let context = PassContext {
+                command_buffer,
+                resumes,
+                suspends,
+            };
+            (pass.record)(&context);
+        }
+        for (image, access) in std::mem::take(&mut self.final_accesses) {
+            self.cmd_transition(device, command_buffer, image, access);
+        }
+    }
+
+    // whether each pass resumes the render pass instance of the one before it, and whether it suspends its own for the
+    // one after it
+    fn render_pass_continuations(passes: &[Pass]) -> Vec<(bool, bool)> {
+        let continues_previous: Vec<bool> = (0..passes.len())
+            .map(|index| index > 0 && Self::shares_render_pass_instance(&passes[index - 1], &passes[index]))
+            .collect();
+        (0..passes.len())
+            .map(|index| (continues_previous[index], continues_previous.get(index + 1).copied().unwrap_or(false)))
+            .collect()
+    }
+
+    fn shares_render_pass_instance(previous: &Pass, next: &Pass) -> bool {
+        !next.images.is_empty()
+            && next.images.iter().all(|(_, access)| access.is_attachment())
+            && previous.images.len() == next.images.len()
+            && next.images.iter().all(|image| previous.images.contains(image))
+    }
+
+    // reads after reads in the same layout don't need a barrier, anything else waits on the image's last use
+    fn cmd_transition(&mut self, device: &Device, command_buffer: vk::CommandBuffer, image: GraphImage, access: ImageAccess) {
+        let tracked = &mut self.images[image.0];
+        if !tracked.needs_barrier(access) {
+            return;
+        }
+        let last_access = tracked.last_access;
+        image_transitions::transition_image_layout(device, &command_buffer, tracked.image, &image_transitions::TransitionProps {
+            old_layout: if tracked.has_contents { last_access.layout() } else { vk::ImageLayout::UNDEFINED },
+            src_access_mask: if last_access.writes() { last_access.access_mask() } else { vk::AccessFlags2::empty() },
+            src_stage_mask: last_access.stage_mask(),
+            new_layout: access.layout(),
+            dst_access_mask: access.access_mask(),
+            dst_stage_mask: access.stage_mask(),
+            aspect_mask: tracked.aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            layer_count: 1,
+        });
+        tracked.last_access = access;
+        tracked.has_contents = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachments(render_graph: &mut RenderGraph) -> [(GraphImage, ImageAccess); 2] {
+        let color_image = render_graph.import_image(vk::Image::null(), vk::ImageAspectFlags::COLOR, ImageAccess::ColorAttachment, false);
+        let depth_image = render_graph.import_image(vk::Image::null(), vk::ImageAspectFlags::DEPTH, ImageAccess::DepthAttachment, true);
+        [(color_image, ImageAccess::ColorAttachment), (depth_image, ImageAccess::DepthAttachment)]
+    }
+
+    #[test]
+    fn test_passes_sharing_attachments_suspend_and_resume() {
+        let mut render_graph = RenderGraph::new();
+        let [color, depth] = attachments(&mut render_graph);
+        render_graph.add_pass("sky box", &[color, depth], |_| {});
+        // the order the attachments are listed in doesn't matter
+        render_graph.add_pass("opaque", &[depth, color], |_| {});
+        render_graph.add_pass("transparent", &[color, depth], |_| {});
+        assert_eq!(RenderGraph::render_pass_continuations(&render_graph.passes), vec![(false, true), (true, true), (true, false)]);
+    }
+
+    #[test]
+    fn test_passes_with_different_images_start_new_render_pass_instances() {
+        let mut render_graph = RenderGraph::new();
+        let [color, depth] = attachments(&mut render_graph);
+        render_graph.add_pass("scene", &[color, depth], |_| {});
+        render_graph.add_pass("color only", &[color], |_| {});
+        render_graph.add_pass("copy", &[(color.0, ImageAccess::TransferSrc)], |_| {});
+        render_graph.add_pass("no images", &[], |_| {});
+        render_graph.add_pass("after no images", &[], |_| {});
+        assert_eq!(RenderGraph::render_pass_continuations(&render_graph.passes), vec![(false, false); 5]);
+    }
+
+    #[test]
+    fn test_passes_reading_the_same_image_never_resume() {
+        let mut render_graph = RenderGraph::new();
+        let [color, _] = attachments(&mut render_graph);
+        let read = (color.0, ImageAccess::TransferSrc);
+        render_graph.add_pass("first read", &[read], |_| {});
+        render_graph.add_pass("second read", &[read], |_| {});
+        assert_eq!(RenderGraph::render_pass_continuations(&render_graph.passes), vec![(false, false), (false, false)]);
+    }
+
+    #[test]
+    fn test_only_reads_in_the_same_layout_skip_the_barrier() {
+        let image = |last_access, has_contents| TrackedImage {
+            image: vk::Image::null(),
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            last_access,
+            has_contents,
+        };
+        assert!(!image(ImageAccess::TransferSrc, true).needs_barrier(ImageAccess::TransferSrc));
+        assert!(image(ImageAccess::TransferSrc, false).needs_barrier(ImageAccess::TransferSrc));
+        assert!(image(ImageAccess::TransferSrc, true).needs_barrier(ImageAccess::ComputeRead));
+        assert!(image(ImageAccess::ColorAttachment, true).needs_barrier(ImageAccess::ColorAttachment));
+        assert!(image(ImageAccess::TransferDst, true).needs_barrier(ImageAccess::TransferSrc));
+    }
+
+    #[test]
+    #[should_panic(expected = "uses an image more than once")]
+    fn test_pass_using_an_image_twice_panics() {
+        let mut render_graph = RenderGraph::new();
+        let [color, _] = attachments(&mut render_graph);
+        render_graph.add_pass("twice", &[color, (color.0, ImageAccess::TransferSrc)], |_| {});
+    }
+}
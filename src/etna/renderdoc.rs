@@ -0,0 +1,78 @@
+use std::ffi::c_void;
+
+use log::{info, warn};
+
+// eRENDERDOC_API_Version_1_1_2
+const RENDERDOC_API_VERSION: i32 = 10102;
+
+type GetApiFn = unsafe extern "C" fn(version: i32, api: *mut *mut c_void) -> i32;
+type FrameCaptureFn = unsafe extern "C" fn(device: *mut c_void, window: *mut c_void);
+type EndFrameCaptureFn = unsafe extern "C" fn(device: *mut c_void, window: *mut c_void) -> u32;
+
+// the start of RENDERDOC_API_1_1_2 from renderdoc_app.h, only the entries we call are typed
+#[repr(C)]
+struct RenderDocApi {
+    _unused: [*const c_void; 19],
+    start_frame_capture: FrameCaptureFn,
+    _is_frame_capturing: *const c_void,
+    end_frame_capture: EndFrameCaptureFn,
+}
+
+/// The in-application API of RenderDoc, only available when the program was launched or injected by RenderDoc
+pub struct RenderDoc {
+    // keeps the library loaded for as long as the api is used
+    _library: libloading::Library,
+    api: *const RenderDocApi,
+}
+
+// the api table is owned by renderdoc and lives as long as the library
+unsafe impl Send for RenderDoc {}
+unsafe impl Sync for RenderDoc {}
+
+impl RenderDoc {
+    // never loads renderdoc itself, only finds it if it has already been injected into the process
+    pub fn load() -> Option<Self> {
+        let library = Self::open_injected_library()?;
+        let api = unsafe {
+            let get_api = library.get::<GetApiFn>(b"RENDERDOC_GetAPI\0").ok()?;
+            let mut api: *mut c_void = std::ptr::null_mut();
+            if get_api(RENDERDOC_API_VERSION, &mut api) != 1 || api.is_null() {
+                return None;
+            }
+            api as *const RenderDocApi
+        };
+        info!("RenderDoc detected, frame captures are available");
+        Some(Self {
+            _library: library,
+            api,
+        })
+    }
+
+    #[cfg(unix)]
+    fn open_injected_library() -> Option<libloading::Library> {
+        unsafe { libloading::os::unix::Library::open(Some("librenderdoc.so"), libc::RTLD_NOW | libc::RTLD_NOLOAD) }
+            .ok()
+            .map(libloading::Library::from)
+    }
+
+    #[cfg(windows)]
+    fn open_injected_library() -> Option<libloading::Library> {
+        libloading::os::windows::Library::open_already_loaded("renderdoc.dll")
+            .ok()
+            .map(libloading::Library::from)
+    }
+
+    // null device and window handles capture whichever device and window renderdoc considers active
+    pub fn start_frame_capture(&self) {
+        unsafe { ((*self.api).start_frame_capture)(std::ptr::null_mut(), std::ptr::null_mut()); }
+    }
+
+    pub fn end_frame_capture(&self) {
+        let succeeded = unsafe { ((*self.api).end_frame_capture)(std::ptr::null_mut(), std::ptr::null_mut()) };
+        if succeeded == 1 {
+            info!("Captured frame in RenderDoc");
+        } else {
+            warn!("RenderDoc failed to capture the frame");
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use bevy_ecs::prelude::*;
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use winit::window::{Window, WindowId};
+
+use crate::assets::Camera;
+use crate::etna::{CommandPool, Device, draw_scene_to_window, FrameRenderContext, FrameSlots, GraphicsSettings, Instance, PhysicalDevice, PhysicalDeviceRes, SceneRenderData, SharedFrameResources, Surface, Swapchain};
+use crate::etna::material_pipeline::DescriptorManager;
+use crate::rehnda_core::ConstPtr;
+
+/// An extra window presenting its own view of the scene, sharing the device, assets and pipelines with the main window
+pub struct SecondaryWindow {
+    // fields are dropped in declaration order, the swapchain must be destroyed before its surface
+    frame_renderer: FrameRenderContext,
+    swapchain: Swapchain,
+    surface: Surface,
+    // copied from the main camera on the first frame, then independent of it
+    pub camera: Option<Camera>,
+    pub winit_window: Window,
+}
+
+impl SecondaryWindow {
+    pub fn create(entry: &ash::Entry, instance: &Instance, device: ConstPtr<Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, descriptor_manager: &mut DescriptorManager, main_swapchain: &Swapchain, winit_window: Window) -> SecondaryWindow {
+        let surface = Surface::new(entry, instance, winit_window.raw_display_handle(), winit_window.raw_window_handle()).expect("Failed to create surface");
+        // the device only has queues for the families chosen against the main window's surface
+        let queue_families = physical_device.queue_families();
+        assert!(surface.physical_device_surface_support(physical_device.handle(), queue_families.present_family).unwrap_or(false), "Secondary window surface can't be presented to from the main present queue");
+        let swapchain = Swapchain::create(
+            instance,
+            device,
+            physical_device,
+            graphics_settings,
+            &surface,
+            command_pool,
+            &queue_families,
+            surface.query_best_swapchain_creation_details(winit_window.inner_size(), physical_device.handle(), graphics_settings.hdr_output),
+        );
+        // pipelines are shared, so they must be compatible with every window's swapchain format
+        assert_eq!(swapchain.image_format, main_swapchain.image_format, "Secondary window swapchain format must match the main window");
+        let frame_renderer = FrameRenderContext::create(device, command_pool, descriptor_manager, graphics_settings.recording_threads);
+        SecondaryWindow {
+            frame_renderer,
+            swapchain,
+            surface,
+            camera: None,
+            winit_window,
+        }
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.winit_window.id()
+    }
+
+    pub fn request_swapchain_recreation(&mut self) {
+        self.swapchain.needs_recreation = true;
+    }
+
+    fn is_minimized(&self) -> bool {
+        self.winit_window.inner_size().height == 0 || self.winit_window.inner_size().width == 0
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct SecondaryWindows {
+    windows: Vec<SecondaryWindow>,
+}
+
+impl SecondaryWindows {
+    pub fn add(&mut self, window: SecondaryWindow) {
+        self.windows.push(window);
+    }
+
+    pub fn find_mut(&mut self, window_id: WindowId) -> Option<&mut SecondaryWindow> {
+        self.windows.iter_mut().find(|window| window.id() == window_id)
+    }
+
+    // waits for the device to finish with the window's frames before destroying it
+    pub fn remove(&mut self, device: &Device, frame_slots: &mut FrameSlots, window_id: WindowId) {
+        if let Some(index) = self.windows.iter().position(|window| window.id() == window_id) {
+            unsafe { device.device_wait_idle() }
+                .expect("Failed to wait for device idle when closing a window");
+            frame_slots.forget_fences();
+            self.windows.remove(index);
+        }
+    }
+}
+
+pub fn secondary_windows_draw_system(mut secondary_windows: ResMut<SecondaryWindows>, physical_device: PhysicalDeviceRes, graphics_settings: Res<GraphicsSettings>, command_pool: Res<CommandPool>, main_camera: Res<Camera>, scene: SceneRenderData, mut shared: SharedFrameResources) {
+    for window in secondary_windows.windows.iter_mut() {
+        if window.is_minimized() {
+            continue;
+        }
+        if window.swapchain.needs_recreation {
+            let swapchain_props = window.surface.query_best_swapchain_creation_details(window.winit_window.inner_size(), physical_device.handle(), graphics_settings.hdr_output);
+            window.swapchain.back_off_before_recreation(window.winit_window.inner_size(), &swapchain_props);
+            window.swapchain.recreate(&physical_device, &graphics_settings, &window.surface, &command_pool, &physical_device.queue_families(), swapchain_props);
+            if let Some(camera) = window.camera.as_mut() {
+                camera.update_aspect_ratio(window.swapchain.aspect_ratio());
+            }
+        }
+        let camera = window.camera.get_or_insert_with(|| {
+            let mut camera = main_camera.clone();
+            camera.update_aspect_ratio(window.swapchain.aspect_ratio());
+            camera
+        });
+        draw_scene_to_window(&mut window.frame_renderer, &mut shared, &mut window.swapchain, camera, &scene, &graphics_settings);
+    }
+}
@@ -3,12 +3,26 @@ use ash::{Entry, Instance, vk};
 use ash::prelude::VkResult;
 use ash::vk::PhysicalDevice;
 use bevy_ecs::system::Resource;
+use log::{info, warn};
+use once_cell::sync::OnceCell;
 use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
 
+// in order of preference, all encode linear shader output to sRGB on write
+const PREFERRED_SURFACE_FORMATS: [vk::Format; 2] = [vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB];
+// in order of preference when hdr output is on. The color spaces are only reported with VK_EXT_swapchain_colorspace
+const PREFERRED_HDR_SURFACE_FORMATS: [(vk::Format, vk::ColorSpaceKHR); 3] = [
+    (vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+    (vk::Format::A2R10G10B10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+    (vk::Format::R16G16B16A16_SFLOAT, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT),
+];
+
 #[derive(Resource)]
 pub struct Surface {
     surface: vk::SurfaceKHR,
     surface_fn: ash::extensions::khr::Surface,
+    // the formats and present modes a surface supports don't change, so they are chosen the first time a swapchain is
+    // created for it and every recreation keeps them. Only the capabilities, with the current extent, are queried again
+    chosen_format_and_present_mode: OnceCell<(vk::SurfaceFormatKHR, vk::PresentModeKHR)>,
 }
 
 impl Deref for Surface {
@@ -26,6 +40,7 @@ impl Surface {
         Ok(Surface {
             surface,
             surface_fn,
+            chosen_format_and_present_mode: OnceCell::new(),
         })
     }
 
@@ -35,34 +50,62 @@ impl Surface {
 
 
     pub fn query_swapchain_support_details(&self, physical_device: PhysicalDevice) -> SwapchainSupportDetails {
-        let capabilities = unsafe { self.surface_fn.get_physical_device_surface_capabilities(physical_device, self.surface) }
-            .expect("Failed to get physical device surface capabilities");
         let formats = unsafe { self.surface_fn.get_physical_device_surface_formats(physical_device, self.surface) }
             .expect("Failed to get physical device surface formats");
         let present_modes = unsafe { self.surface_fn.get_physical_device_surface_present_modes(physical_device, self.surface) }
             .expect("Failed to get physical device present modes");
         SwapchainSupportDetails {
-            capabilities,
             formats,
             present_modes,
         }
     }
 
-    pub fn query_best_swapchain_creation_details(&self, window_size: winit::dpi::PhysicalSize<u32>, physical_device: PhysicalDevice) -> ChosenSwapchainProps {
-        let support_details = self.query_swapchain_support_details(physical_device);
+    // the current extent and transform change with the window, so these are queried for every recreation
+    fn query_capabilities(&self, physical_device: PhysicalDevice) -> vk::SurfaceCapabilitiesKHR {
+        unsafe { self.surface_fn.get_physical_device_surface_capabilities(physical_device, self.surface) }
+            .expect("Failed to get physical device surface capabilities")
+    }
+
+    // hdr output is only considered the first time, as the format is kept for every recreation after it
+    pub fn query_best_swapchain_creation_details(&self, window_size: winit::dpi::PhysicalSize<u32>, physical_device: PhysicalDevice, hdr_output: bool) -> ChosenSwapchainProps {
+        let (surface_format, present_mode) = *self.chosen_format_and_present_mode.get_or_init(|| {
+            let support_details = self.query_swapchain_support_details(physical_device);
+            let surface_format = hdr_output.then(|| Self::choose_hdr_surface_format(&support_details.formats))
+                .flatten()
+                .unwrap_or_else(|| Self::choose_surface_format(&support_details.formats));
+            (surface_format, Self::choose_present_mode(&support_details.present_modes))
+        });
+        let capabilities = self.query_capabilities(physical_device);
         ChosenSwapchainProps {
-            capabilities: support_details.capabilities,
-            surface_format: Self::choose_surface_format(&support_details.formats),
-            present_mode: Self::choose_present_mode(&support_details.present_modes),
-            extent: Self::choose_swapchain_extent(window_size, &support_details.capabilities),
+            capabilities,
+            surface_format,
+            present_mode,
+            extent: Self::choose_swapchain_extent(window_size, &capabilities),
         }
     }
 
+    // the scene writes linear color, an _SRGB format encodes it on write and any other format has the shaders encode it
     fn choose_surface_format(available_formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
-        available_formats.iter()
-            .find(|&&available_format|
-                available_format.format == vk::Format::B8G8R8A8_SRGB && available_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
-            .map_or(available_formats[0], |chosen_format| *chosen_format)
+        PREFERRED_SURFACE_FORMATS.iter()
+            .find_map(|&preferred_format| available_formats.iter().find(|&&available_format|
+                available_format.format == preferred_format && available_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR))
+            .copied()
+            .unwrap_or_else(|| {
+                warn!("No sRGB surface format available, falling back to {:?} with the shaders encoding sRGB", available_formats[0].format);
+                available_formats[0]
+            })
+    }
+
+    fn choose_hdr_surface_format(available_formats: &[vk::SurfaceFormatKHR]) -> Option<vk::SurfaceFormatKHR> {
+        let hdr_format = PREFERRED_HDR_SURFACE_FORMATS.iter()
+            .find_map(|&(preferred_format, preferred_color_space)| available_formats.iter().find(|&&available_format|
+                available_format.format == preferred_format && available_format.color_space == preferred_color_space))
+            .copied();
+        match hdr_format {
+            Some(surface_format) => info!("Using {:?} in {:?} for hdr output", surface_format.format, surface_format.color_space),
+            None => warn!("Hdr output is on but the surface has no HDR10 or scRGB format, falling back to sRGB"),
+        }
+        hdr_format
     }
 
     fn choose_present_mode(available_present_modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
@@ -93,7 +136,6 @@ impl Drop for Surface {
 }
 
 pub struct SwapchainSupportDetails {
-    pub capabilities: vk::SurfaceCapabilitiesKHR,
     pub formats: Vec<vk::SurfaceFormatKHR>,
     pub present_modes: Vec<vk::PresentModeKHR>,
 }
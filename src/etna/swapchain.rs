@@ -1,10 +1,15 @@
+use std::time::Duration;
+
 use ash::extensions::khr;
 use ash::vk;
 use bevy_ecs::prelude::*;
-use log::debug;
+use glam::UVec2;
+use half::f16;
+use log::{debug, error, info};
+use winit::dpi::PhysicalSize;
 
 use crate::etna;
-use crate::etna::{ChosenSwapchainProps, CommandPool, DepthBuffer, Image, ImageCreateInfo, ImageType, PhysicalDevice, QueueFamilyIndices, Surface};
+use crate::etna::{Buffer, ChosenSwapchainProps, CommandPool, DepthBuffer, GraphicsSettings, Image, ImageCreateInfo, ImageType, PhysicalDevice, QueueFamilyIndices};
 use crate::rehnda_core::ConstPtr;
 
 #[derive(Resource)]
@@ -13,16 +18,32 @@ pub struct Swapchain {
     swapchain: vk::SwapchainKHR,
     swapchain_fn: khr::Swapchain,
     pub image_format: vk::Format,
+    // how the shaders encode their output for the swapchain's format and color space
+    pub output_encoding: OutputEncoding,
     pub extent: vk::Extent2D,
     pub images: Vec<vk::Image>,
     pub image_views: Vec<vk::ImageView>,
     pub depth_buffer: DepthBuffer,
     pub color_image: Image,
+    // the scene is rendered into this at the render extent, then upscaled into the swapchain image
+    pub scene_image: Image,
+    pub render_scale: f32,
+    pub render_extent: vk::Extent2D,
     pub msaa_enabled: bool,
 
     pub needs_recreation: bool,
+    // counts the recreations, so commands recorded against the old images can tell they're stale
+    pub generation: u64,
+    // frames in a row the image couldn't be acquired or presented. Each one recreates the swapchain, so a growing
+    // count means recreating it isn't helping
+    consecutive_failures: u32,
 }
 
+// failures in a row before the swapchain is reported as not recovering
+const FAILURES_BEFORE_DIAGNOSTIC: u32 = 10;
+// the longest wait between recreations of a swapchain that keeps going out of date
+const MAX_RECREATION_BACKOFF: Duration = Duration::from_millis(500);
+
 pub type SwapchainResult<T> = Result<T, SwapchainError>;
 
 pub enum SwapchainError {
@@ -54,6 +75,44 @@ impl Swapchain {
         }
     }
 
+    // flags the swapchain for recreation after an acquire or present found it out of date
+    pub fn mark_out_of_date(&mut self) {
+        self.needs_recreation = true;
+        self.consecutive_failures += 1;
+    }
+
+    pub fn mark_presented(&mut self) {
+        if self.consecutive_failures >= FAILURES_BEFORE_DIAGNOSTIC {
+            info!("Swapchain recovered after {} failed frames", self.consecutive_failures);
+        }
+        self.consecutive_failures = 0;
+    }
+
+    // waits longer before each recreation while the swapchain keeps going out of date, giving the driver or window
+    // system time to settle, and reports what it is being recreated with once it doesn't look like recovering
+    pub fn back_off_before_recreation(&self, window_size: PhysicalSize<u32>, chosen_swapchain_props: &ChosenSwapchainProps) {
+        if self.consecutive_failures < 2 {
+            return;
+        }
+        if self.consecutive_failures == FAILURES_BEFORE_DIAGNOSTIC {
+            let capabilities = &chosen_swapchain_props.capabilities;
+            error!(
+                "The swapchain has been out of date for {} frames in a row despite being recreated each time, rendering is stalled. \
+                Window size {}x{}, surface current extent {}x{}, min extent {}x{}, max extent {}x{}, image count {}..{}, transform {:?}. \
+                Recreating with extent {}x{}, format {:?}, present mode {:?}",
+                self.consecutive_failures, window_size.width, window_size.height,
+                capabilities.current_extent.width, capabilities.current_extent.height,
+                capabilities.min_image_extent.width, capabilities.min_image_extent.height,
+                capabilities.max_image_extent.width, capabilities.max_image_extent.height,
+                capabilities.min_image_count, capabilities.max_image_count, capabilities.current_transform,
+                chosen_swapchain_props.extent.width, chosen_swapchain_props.extent.height,
+                chosen_swapchain_props.surface_format.format, chosen_swapchain_props.present_mode,
+            );
+        }
+        let backoff = Duration::from_millis(1 << (self.consecutive_failures - 2).min(16)).min(MAX_RECREATION_BACKOFF);
+        std::thread::sleep(backoff);
+    }
+
     pub fn extent(&self) -> vk::Extent2D {
         self.extent
     }
@@ -61,34 +120,174 @@ impl Swapchain {
     pub fn aspect_ratio(&self) -> f32 {
         self.extent.width as f32 / self.extent.height as f32
     }
+
+    pub fn render_extent(&self) -> vk::Extent2D {
+        self.render_extent
+    }
+
+    // the depth of the last rendered frame at a pixel of the render extent, waits for the device to be idle
+    pub fn read_depth(&self, command_pool: &CommandPool, pixel: UVec2) -> Option<f32> {
+        if pixel.x >= self.render_extent.width || pixel.y >= self.render_extent.height {
+            return None;
+        }
+        unsafe { self.device.device_wait_idle() }
+            .expect("Failed to wait for device idle when reading back depth");
+        Some(self.depth_buffer.read_depth(self.device, command_pool, pixel.x, pixel.y))
+    }
+
+    // the linear colors of the last rendered frame at the render extent, only the float scene image of an scRGB
+    // swapchain keeps values above 1.0 so other formats return None. Waits for the device to be idle
+    pub fn read_scene_colors(&self, command_pool: &CommandPool) -> Option<Vec<[f32; 4]>> {
+        if self.scene_image.format != vk::Format::R16G16B16A16_SFLOAT {
+            return None;
+        }
+        let colors = self.read_scene_texels(command_pool, 8)
+            .chunks_exact(8)
+            .map(|texel| [0, 2, 4, 6].map(|offset| f16::from_le_bytes([texel[offset], texel[offset + 1]]).to_f32()))
+            .collect();
+        Some(colors)
+    }
+
+    // the last rendered frame at the render extent as sRGB encoded RGBA8, with the alpha it was drawn with. The float
+    // scene image is clamped to 1.0, HDR10's PQ encoded colors aren't converted so return None. Waits for the device
+    // to be idle
+    pub fn read_scene_rgba8(&self, command_pool: &CommandPool) -> Option<Vec<u8>> {
+        match self.scene_image.format {
+            vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => Some(self.read_scene_texels(command_pool, 4)),
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => Some(self.read_scene_texels(command_pool, 4)
+                .chunks_exact(4)
+                .flat_map(|texel| [texel[2], texel[1], texel[0], texel[3]])
+                .collect()),
+            vk::Format::R16G16B16A16_SFLOAT => Some(self.read_scene_colors(command_pool)?
+                .into_iter()
+                .flat_map(|[r, g, b, a]| [srgb_encode(r), srgb_encode(g), srgb_encode(b), (a.clamp(0.0, 1.0) * 255.0).round() as u8])
+                .collect()),
+            _ => None,
+        }
+    }
+
+    fn read_scene_texels(&self, command_pool: &CommandPool, texel_size: u64) -> Vec<u8> {
+        unsafe { self.device.device_wait_idle() }
+            .expect("Failed to wait for device idle when reading back the scene");
+        let image = &self.scene_image;
+        let size = image.width as u64 * image.height as u64 * texel_size;
+        let readback_buffer = Buffer::create_readback_buffer(self.device, size);
+        let copy_region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build()
+            )
+            .image_extent(vk::Extent3D { width: image.width, height: image.height, depth: 1 })
+            .build();
+
+        let command_buffer = command_pool.one_time_command_buffer();
+        // the render graph leaves the scene image ready to be blitted from at the end of every frame
+        image.assume_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ);
+        unsafe { self.device.cmd_copy_image_to_buffer(*command_buffer, image.vk_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, readback_buffer.buffer, std::slice::from_ref(&copy_region)) };
+        command_buffer.submit_and_wait().expect("Failed to copy the scene for readback");
+        readback_buffer.read_data()[..size as usize].to_vec()
+    }
+
+    fn scaled_extent(extent: vk::Extent2D, render_scale: f32) -> vk::Extent2D {
+        vk::Extent2D {
+            width: ((extent.width as f32 * render_scale).round() as u32).max(1),
+            height: ((extent.height as f32 * render_scale).round() as u32).max(1),
+        }
+    }
+}
+
+// a linear color channel clamped to 1.0 and encoded with the sRGB curve
+fn srgb_encode(linear: f32) -> u8 {
+    let linear = linear.clamp(0.0, 1.0);
+    let encoded = if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+pub fn is_srgb_format(format: vk::Format) -> bool {
+    matches!(format, vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32)
+}
+
+/// What the shaders write so the swapchain's format and color space show the scene's linear color correctly
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputEncoding {
+    // an _SRGB format encodes the linear output on write
+    SrgbFormat,
+    // a UNORM format in the sRGB color space, the shaders apply the sRGB curve themselves
+    Srgb,
+    // extended linear sRGB in a float format, where 1.0 is 80 nits and brighter values go above it
+    ScRgb,
+    // BT.2020 primaries with the ST 2084 (PQ) curve, encoding absolute luminance
+    Hdr10,
+}
+
+impl OutputEncoding {
+    pub fn for_surface_format(surface_format: vk::SurfaceFormatKHR) -> OutputEncoding {
+        match surface_format.color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => Self::Hdr10,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Self::ScRgb,
+            _ if is_srgb_format(surface_format.format) => Self::SrgbFormat,
+            _ => Self::Srgb,
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, Self::ScRgb | Self::Hdr10)
+    }
+
+    // MUST KEEP IN SYNC WITH the output encodings in common.glsl
+    pub fn shader_value(&self) -> f32 {
+        match self {
+            Self::SrgbFormat => 0.0,
+            Self::Srgb => 1.0,
+            Self::ScRgb => 2.0,
+            Self::Hdr10 => 3.0,
+        }
+    }
 }
 
 // intialisation functionality
 impl Swapchain {
-    pub fn recreate(&mut self, physical_device: &PhysicalDevice, surface: &vk::SurfaceKHR, command_pool: &CommandPool, queue_family_indices: &QueueFamilyIndices, chosen_swapchain_props: ChosenSwapchainProps) {
+    pub fn recreate(&mut self, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, surface: &vk::SurfaceKHR, command_pool: &CommandPool, queue_family_indices: &QueueFamilyIndices, chosen_swapchain_props: ChosenSwapchainProps) {
         debug!("Recreating swapchain");
         unsafe { self.device.device_wait_idle() }
             .expect("Failed to wait for device idle when recreating swapchain");
         self.destroy_resources();
         let image_format = chosen_swapchain_props.surface_format.format;
+        let output_encoding = OutputEncoding::for_surface_format(chosen_swapchain_props.surface_format);
         let extent = chosen_swapchain_props.extent;
         let (swapchain, images, image_views) = Self::create_swapchain_resources(&self.device, &self.swapchain_fn, surface, queue_family_indices, chosen_swapchain_props);
         self.image_format = image_format;
+        self.output_encoding = output_encoding;
         self.extent = extent;
         self.swapchain = swapchain;
         self.images = images;
         self.image_views = image_views;
-        self.depth_buffer = DepthBuffer::create(self.device, physical_device, command_pool, extent);
-        self.color_image = Image::create_image(self.device, &multisampling_color_image_create_info(physical_device, extent, image_format));
+        self.render_extent = Self::scaled_extent(extent, self.render_scale);
+        self.depth_buffer = DepthBuffer::create(self.device, physical_device, graphics_settings, command_pool, self.render_extent);
+        self.color_image = Image::create_image(self.device, &multisampling_color_image_create_info(graphics_settings, self.render_extent, image_format));
+        self.scene_image = Image::create_image(self.device, &scene_image_create_info(self.render_extent, image_format));
+        self.needs_recreation = false;
+        self.generation += 1;
     }
-    pub fn create(instance: &ash::Instance, device: ConstPtr<etna::Device>, physical_device: &PhysicalDevice, surface: &vk::SurfaceKHR, command_pool: &CommandPool, queue_family_indices: &QueueFamilyIndices, chosen_swapchain_props: ChosenSwapchainProps) -> Swapchain {
+    pub fn create(instance: &ash::Instance, device: ConstPtr<etna::Device>, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, surface: &vk::SurfaceKHR, command_pool: &CommandPool, queue_family_indices: &QueueFamilyIndices, chosen_swapchain_props: ChosenSwapchainProps) -> Swapchain {
         let swapchain_fn = khr::Swapchain::new(instance, &device);
 
         let image_format = chosen_swapchain_props.surface_format.format;
+        let output_encoding = OutputEncoding::for_surface_format(chosen_swapchain_props.surface_format);
         let extent = chosen_swapchain_props.extent;
         let (swapchain, images, image_views) = Self::create_swapchain_resources(&device, &swapchain_fn, surface, queue_family_indices, chosen_swapchain_props);
-        let depth_buffer = DepthBuffer::create(device, physical_device, command_pool, extent);
-        let color_image = Image::create_image(device, &multisampling_color_image_create_info(physical_device, extent, image_format));
+        let render_scale = 1.0;
+        let render_extent = Self::scaled_extent(extent, render_scale);
+        let depth_buffer = DepthBuffer::create(device, physical_device, graphics_settings, command_pool, render_extent);
+        let color_image = Image::create_image(device, &multisampling_color_image_create_info(graphics_settings, render_extent, image_format));
+        let scene_image = Image::create_image(device, &scene_image_create_info(render_extent, image_format));
         Swapchain {
             device,
             swapchain_fn,
@@ -96,11 +295,17 @@ impl Swapchain {
             images,
             image_views,
             image_format,
+            output_encoding,
             extent,
             depth_buffer,
             color_image,
-            msaa_enabled: physical_device.graphics_settings.is_msaa_enabled(),
+            scene_image,
+            render_scale,
+            render_extent,
+            msaa_enabled: graphics_settings.is_msaa_enabled(),
             needs_recreation: false,
+            generation: 0,
+            consecutive_failures: 0,
         }
     }
 
@@ -118,7 +323,7 @@ impl Swapchain {
             .image_color_space(chosen_swapchain_props.surface_format.color_space)
             .image_extent(chosen_swapchain_props.extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST)
             .pre_transform(chosen_swapchain_props.capabilities.current_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
             .present_mode(chosen_swapchain_props.present_mode)
@@ -188,7 +393,7 @@ impl Drop for Swapchain {
 }
 
 
-fn multisampling_color_image_create_info(physical_device: &PhysicalDevice, extent: vk::Extent2D, format: vk::Format) -> ImageCreateInfo {
+fn multisampling_color_image_create_info(graphics_settings: &GraphicsSettings, extent: vk::Extent2D, format: vk::Format) -> ImageCreateInfo {
     ImageCreateInfo {
         image_type: ImageType::SingleImage,
         width: extent.width,
@@ -199,24 +404,68 @@ fn multisampling_color_image_create_info(physical_device: &PhysicalDevice, exten
         mip_levels: 1,
         memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
         image_aspect_flags: vk::ImageAspectFlags::COLOR,
-        num_samples: physical_device.graphics_settings.msaa_samples.to_sample_count_flags(),
+        num_samples: graphics_settings.msaa_samples.to_sample_count_flags(),
+        create_flags: vk::ImageCreateFlags::empty(),
+    }
+}
+
+fn scene_image_create_info(extent: vk::Extent2D, format: vk::Format) -> ImageCreateInfo {
+    ImageCreateInfo {
+        image_type: ImageType::SingleImage,
+        width: extent.width,
+        height: extent.height,
+        format,
+        tiling: vk::ImageTiling::OPTIMAL,
+        // sampled by the auto exposure histogram
+        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::SAMPLED,
+        mip_levels: 1,
+        memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        image_aspect_flags: vk::ImageAspectFlags::COLOR,
+        num_samples: vk::SampleCountFlags::TYPE_1,
         create_flags: vk::ImageCreateFlags::empty(),
     }
 }
 
 pub mod swapchain_systems {
     use bevy_ecs::prelude::*;
+    use winit::dpi::PhysicalSize;
 
     use crate::ecs_engine::EtnaWindow;
-    use crate::etna::{CommandPool, PhysicalDeviceRes, Surface, Swapchain};
+    use crate::etna::{CommandPool, GraphicsSettings, PhysicalDeviceRes, Surface, Swapchain};
     use crate::assets::Camera;
 
-    pub fn swap_chain_recreation_system(mut swapchain: ResMut<Swapchain>, physical_device: PhysicalDeviceRes, surface: Res<Surface>, command_pool: Res<CommandPool>, window: Res<EtnaWindow>, mut camera: ResMut<Camera>) {
-        swapchain.recreate(&physical_device, &surface, &command_pool, &physical_device.queue_families(), surface.query_best_swapchain_creation_details(window.winit_window.inner_size(), physical_device.handle()));
+    // frames the window size has to stay the same for before the swapchain is rebuilt for it
+    const RESIZE_STABLE_FRAMES: u32 = 3;
+
+    #[derive(Default)]
+    pub struct ResizeDebounce {
+        last_size: PhysicalSize<u32>,
+        stable_frames: u32,
+    }
+
+    pub fn swap_chain_recreation_system(mut swapchain: ResMut<Swapchain>, physical_device: PhysicalDeviceRes, graphics_settings: Res<GraphicsSettings>, surface: Res<Surface>, command_pool: Res<CommandPool>, window: Res<EtnaWindow>, mut camera: ResMut<Camera>) {
+        let window_size = window.winit_window.inner_size();
+        let swapchain_props = surface.query_best_swapchain_creation_details(window_size, physical_device.handle(), graphics_settings.hdr_output);
+        swapchain.back_off_before_recreation(window_size, &swapchain_props);
+        swapchain.recreate(&physical_device, &graphics_settings, &surface, &command_pool, &physical_device.queue_families(), swapchain_props);
         camera.update_aspect_ratio(swapchain.aspect_ratio());
     }
 
-    pub fn swap_chain_needs_recreation(swapchain: Res<Swapchain>) -> bool {
-        swapchain.needs_recreation
+    // while the window is being drag resized the intermediate sizes are skipped, the old swapchain keeps being
+    // presented (stretched) where the platform allows it until the size settles
+    pub fn swap_chain_needs_recreation(swapchain: Res<Swapchain>, window: Res<EtnaWindow>, mut debounce: Local<ResizeDebounce>) -> bool {
+        if !swapchain.needs_recreation {
+            return false;
+        }
+        let size = window.winit_window.inner_size();
+        if size != debounce.last_size {
+            debounce.last_size = size;
+            debounce.stable_frames = 0;
+        } else {
+            debounce.stable_frames += 1;
+        }
+        // recreations that aren't for a new size, like render scale changes, don't need to wait
+        let size_unchanged = size.width == swapchain.extent.width && size.height == swapchain.extent.height;
+        size_unchanged || debounce.stable_frames >= RESIZE_STABLE_FRAMES
     }
 }
\ No newline at end of file
@@ -0,0 +1,18 @@
+extern crate core;
+extern crate alloc;
+
+mod rehnda_core;
+mod etna;
+mod assets;
+mod ui;
+mod application;
+mod ecs_engine;
+mod benchmark;
+mod renderer;
+
+pub use application::{Application, WindowConfig};
+pub use renderer::{InitialScene, LightId, ModelId, RenderError, Renderer, RendererSettings};
+pub use assets::gltf_loader::PivotMode;
+pub use assets::light_source::PointLight;
+pub use assets::render_object::Transform;
+pub use glam::{Quat, Vec3};
@@ -1,25 +1,37 @@
-extern crate core;
-extern crate alloc;
-
+use fast_rehnda::{Application, WindowConfig};
 use log::LevelFilter;
 use simplelog::{ColorChoice, Config, TerminalMode, TermLogger};
 use winit::event_loop::EventLoop;
 
-use crate::application::Application;
-
-mod rehnda_core;
-mod etna;
-mod assets;
-mod ui;
-mod application;
-mod ecs_engine;
-
+const DEFAULT_BENCHMARK_FRAMES: usize = 500;
 
 fn main() {
     TermLogger::init(LevelFilter::Info, Config::default(), TerminalMode::Mixed, ColorChoice::Auto).unwrap();
 
     let event_loop = EventLoop::new();
-    let application = Application::new(&event_loop);
+    // --bench [frame count] renders offscreen, prints the frame timings and exits
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(bench_position) = args.iter().position(|arg| arg == "--bench") {
+        let frame_count = args.get(bench_position + 1)
+            .and_then(|frames| frames.parse().ok())
+            .unwrap_or(DEFAULT_BENCHMARK_FRAMES);
+        println!("{}", Application::benchmark(&event_loop, frame_count));
+        return;
+    }
+    // --scene path.ron loads a scene file instead of the demo scene
+    let scene_path = args.iter().position(|arg| arg == "--scene")
+        .map(|scene_position| args.get(scene_position + 1).expect("--scene requires the path of a scene file").into());
+    // --hdr presents in HDR10 or scRGB when the display supports it
+    let hdr_output = args.iter().any(|arg| arg == "--hdr");
+    let mut application = Application::new(&event_loop, WindowConfig::default(), scene_path, hdr_output);
+    if args.iter().any(|arg| arg == "--second-window") {
+        application.add_window(&event_loop, WindowConfig {
+            title: "Fast Rehnda - Second View".to_string(),
+            width: 800,
+            height: 600,
+            ..Default::default()
+        });
+    }
 
     application.main_loop(event_loop);
 }
@@ -10,7 +10,14 @@ pub struct ColorRgbaF {
 }
 
 impl ColorRgbaF {
-
+    pub fn lerp(&self, other: &ColorRgbaF, t: f32) -> ColorRgbaF {
+        ColorRgbaF::new(
+            self.r + (other.r - self.r) * t,
+            self.g + (other.g - self.g) * t,
+            self.b + (other.b - self.b) * t,
+            self.a + (other.a - self.a) * t,
+        )
+    }
 }
 
 impl ColorRgbaF {
@@ -1,6 +1,9 @@
 use ahash::AHashMap;
 use bevy_ecs::prelude::*;
-use winit::event::VirtualKeyCode;
+use winit::event::{ElementState, MouseButton, VirtualKeyCode};
+
+use crate::rehnda_core::Vec2;
+use crate::rehnda_core::input_bindings::{InputAction, InputBindings};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum KeyState {
@@ -15,10 +18,38 @@ pub enum KeyStateChange {
     JustUp,
 }
 
+// mouse events the ui didn't want, sent by the engine alongside the winit keyboard events
+#[derive(Debug, Copy, Clone)]
+pub struct MouseButtonInput {
+    pub button: MouseButton,
+    pub state: ElementState,
+}
+
+// in lines, positive when scrolling away from the user
+#[derive(Debug, Copy, Clone)]
+pub struct MouseWheelInput {
+    pub delta: f32,
+}
+
+// in physical pixels from the top left of the window
+#[derive(Debug, Copy, Clone)]
+pub struct CursorMovedInput {
+    pub position: Vec2,
+}
+
 #[derive(Resource, Default)]
 pub struct InputState {
     key_state: AHashMap<VirtualKeyCode, KeyState>,
     key_state_change: AHashMap<VirtualKeyCode, KeyStateChange>,
+    mouse_button_state: AHashMap<MouseButton, KeyState>,
+    mouse_button_state_change: AHashMap<MouseButton, KeyStateChange>,
+    // accumulated over the frame
+    scroll_delta: f32,
+    cursor_position: Option<Vec2>,
+    // how far the cursor moved this frame
+    cursor_delta: Vec2,
+    // copied from the InputBindings resource whenever it changes
+    bindings: InputBindings,
 }
 
 impl InputState {
@@ -41,15 +72,73 @@ impl InputState {
     pub fn is_just_up(&self, key_code: VirtualKeyCode) -> bool {
         self.key_state_change.get(&key_code).map_or(false, |a| *a == KeyStateChange::JustUp)
     }
+
+    pub fn is_mouse_down(&self, button: MouseButton) -> bool {
+        self.mouse_button_state.get(&button).map_or(false, |a| *a == KeyState::Down)
+    }
+
+    pub fn mouse_just_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_button_state_change.get(&button).map_or(false, |a| *a == KeyStateChange::JustDown)
+    }
+
+    pub fn mouse_just_released(&self, button: MouseButton) -> bool {
+        self.mouse_button_state_change.get(&button).map_or(false, |a| *a == KeyStateChange::JustUp)
+    }
+
+    pub fn scroll_delta(&self) -> f32 {
+        self.scroll_delta
+    }
+
+    pub fn cursor_position(&self) -> Option<Vec2> {
+        self.cursor_position
+    }
+
+    pub fn cursor_delta(&self) -> Vec2 {
+        self.cursor_delta
+    }
+
+    pub fn is_action_down(&self, action: InputAction) -> bool {
+        self.bindings.keys(action).iter().any(|key_code| self.is_down(*key_code))
+    }
+
+    pub fn is_action_just_down(&self, action: InputAction) -> bool {
+        self.bindings.keys(action).iter().any(|key_code| self.is_just_down(*key_code))
+    }
 }
 
 pub mod input_systems {
     use bevy_ecs::prelude::*;
     use winit::event::{ElementState, KeyboardInput};
-    use crate::rehnda_core::input::{InputState, KeyState, KeyStateChange};
+    use crate::rehnda_core::input::{CursorMovedInput, InputState, KeyState, KeyStateChange, MouseButtonInput, MouseWheelInput};
+    use crate::rehnda_core::input_bindings::InputBindings;
+    use crate::rehnda_core::Vec2;
 
-    pub fn input_system(mut input_state: ResMut<InputState>, mut keyboard_events: EventReader<KeyboardInput>) {
+    pub fn input_system(mut input_state: ResMut<InputState>, input_bindings: Res<InputBindings>, mut keyboard_events: EventReader<KeyboardInput>, mut mouse_button_events: EventReader<MouseButtonInput>, mut mouse_wheel_events: EventReader<MouseWheelInput>, mut cursor_events: EventReader<CursorMovedInput>) {
+        if input_bindings.is_changed() {
+            input_state.bindings = input_bindings.clone();
+        }
         input_state.key_state_change.clear();
+        input_state.mouse_button_state_change.clear();
+        input_state.scroll_delta = mouse_wheel_events.iter().map(|event| event.delta).sum();
+        input_state.cursor_delta = Vec2::ZERO;
+        for event in cursor_events.iter() {
+            if let Some(previous_position) = input_state.cursor_position {
+                input_state.cursor_delta += event.position - previous_position;
+            }
+            input_state.cursor_position = Some(event.position);
+        }
+        for event in mouse_button_events.iter() {
+            let previous_state = input_state.mouse_button_state.get(&event.button).copied().unwrap_or(KeyState::Up);
+            let new_state = match event.state {
+                ElementState::Pressed => KeyState::Down,
+                ElementState::Released => KeyState::Up,
+            };
+            if previous_state != new_state {
+                let change = if new_state == KeyState::Down { KeyStateChange::JustDown } else { KeyStateChange::JustUp };
+                input_state.mouse_button_state_change.insert(event.button, change);
+            }
+            input_state.mouse_button_state.insert(event.button, new_state);
+        }
         for event in keyboard_events.iter() {
             if let Some(virtual_keycode) = event.virtual_keycode {
                 match event.state {
@@ -0,0 +1,208 @@
+use std::fs;
+use std::path::Path;
+
+use ahash::AHashMap;
+use bevy_ecs::prelude::*;
+use log::{info, warn};
+use winit::event::VirtualKeyCode;
+
+// optional overrides for the default bindings, read from the working directory at startup
+pub const INPUT_BINDINGS_PATH: &str = "input_bindings.cfg";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum InputAction {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    TurnLeft,
+    TurnRight,
+    ZoomIn,
+    ZoomOut,
+    SlowMovement,
+    ToggleCameraMode,
+    ReloadShaders,
+    ToggleUi,
+    CaptureFrame,
+    ToggleEnvironmentOnly,
+    ReloadScene,
+    PickDepth,
+    FocusSelected,
+    PanCamera,
+    HdrScreenshot,
+    ToggleWireframe,
+    Screenshot,
+    // only toggles while FullscreenModifier is held, ExclusiveFullscreen picks the mode when entering fullscreen
+    ToggleFullscreen,
+    FullscreenModifier,
+    ExclusiveFullscreen,
+}
+
+impl InputAction {
+    pub const ALL: [InputAction; 26] = [
+        InputAction::MoveForward,
+        InputAction::MoveBackward,
+        InputAction::MoveLeft,
+        InputAction::MoveRight,
+        InputAction::MoveUp,
+        InputAction::MoveDown,
+        InputAction::TurnLeft,
+        InputAction::TurnRight,
+        InputAction::ZoomIn,
+        InputAction::ZoomOut,
+        InputAction::SlowMovement,
+        InputAction::ToggleCameraMode,
+        InputAction::ReloadShaders,
+        InputAction::ToggleUi,
+        InputAction::CaptureFrame,
+        InputAction::ToggleEnvironmentOnly,
+        InputAction::ReloadScene,
+        InputAction::PickDepth,
+        InputAction::FocusSelected,
+        InputAction::PanCamera,
+        InputAction::HdrScreenshot,
+        InputAction::ToggleWireframe,
+        InputAction::Screenshot,
+        InputAction::ToggleFullscreen,
+        InputAction::FullscreenModifier,
+        InputAction::ExclusiveFullscreen,
+    ];
+
+    fn from_name(name: &str) -> Option<InputAction> {
+        Self::ALL.into_iter().find(|action| format!("{:?}", action) == name)
+    }
+}
+
+/// Maps named actions to the keys that trigger them, an action is active if any of its keys are
+#[derive(Resource, Clone, Debug)]
+pub struct InputBindings {
+    bindings: AHashMap<InputAction, Vec<VirtualKeyCode>>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let bindings = [
+            (InputAction::MoveForward, vec![VirtualKeyCode::W]),
+            (InputAction::MoveBackward, vec![VirtualKeyCode::S]),
+            (InputAction::MoveLeft, vec![VirtualKeyCode::A]),
+            (InputAction::MoveRight, vec![VirtualKeyCode::D]),
+            (InputAction::MoveUp, vec![VirtualKeyCode::Space]),
+            (InputAction::MoveDown, vec![VirtualKeyCode::LControl]),
+            (InputAction::TurnLeft, vec![VirtualKeyCode::Q]),
+            (InputAction::TurnRight, vec![VirtualKeyCode::E]),
+            (InputAction::ZoomIn, vec![VirtualKeyCode::Equals]),
+            (InputAction::ZoomOut, vec![VirtualKeyCode::Minus]),
+            (InputAction::SlowMovement, vec![VirtualKeyCode::LShift]),
+            (InputAction::ToggleCameraMode, vec![VirtualKeyCode::T]),
+            (InputAction::ReloadShaders, vec![VirtualKeyCode::Semicolon]),
+            (InputAction::ToggleUi, vec![VirtualKeyCode::F1]),
+            (InputAction::CaptureFrame, vec![VirtualKeyCode::F11]),
+            (InputAction::ToggleEnvironmentOnly, vec![VirtualKeyCode::F2]),
+            (InputAction::ReloadScene, vec![VirtualKeyCode::F5]),
+            (InputAction::PickDepth, vec![VirtualKeyCode::P]),
+            (InputAction::FocusSelected, vec![VirtualKeyCode::F]),
+            (InputAction::PanCamera, vec![VirtualKeyCode::G]),
+            (InputAction::HdrScreenshot, vec![VirtualKeyCode::F12]),
+            (InputAction::ToggleWireframe, vec![VirtualKeyCode::X]),
+            (InputAction::Screenshot, vec![VirtualKeyCode::F10]),
+            (InputAction::ToggleFullscreen, vec![VirtualKeyCode::Return]),
+            (InputAction::FullscreenModifier, vec![VirtualKeyCode::LAlt, VirtualKeyCode::RAlt]),
+            (InputAction::ExclusiveFullscreen, vec![VirtualKeyCode::LShift, VirtualKeyCode::RShift]),
+        ];
+        Self {
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+impl InputBindings {
+    pub fn keys(&self, action: InputAction) -> &[VirtualKeyCode] {
+        self.bindings.get(&action).map_or(&[], |keys| keys.as_slice())
+    }
+
+    pub fn bind(&mut self, action: InputAction, keys: Vec<VirtualKeyCode>) {
+        self.bindings.insert(action, keys);
+    }
+
+    // the defaults with any bindings from the config file applied over them
+    pub fn load_or_default(path: &Path) -> Self {
+        let mut bindings = Self::default();
+        if let Ok(config) = fs::read_to_string(path) {
+            info!("Loading input bindings from {}", path.display());
+            bindings.apply_config(&config);
+        }
+        bindings
+    }
+
+    // each line is "Action = Key" or "Action = Key, Key", blank lines and lines starting with # are ignored
+    fn apply_config(&mut self, config: &str) {
+        for line in config.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let Some((action_name, key_names)) = line.split_once('=') else {
+                warn!("Ignoring input binding without an '=': {}", line);
+                continue;
+            };
+            let Some(action) = InputAction::from_name(action_name.trim()) else {
+                warn!("Ignoring binding for unknown input action: {}", action_name.trim());
+                continue;
+            };
+            let keys: Option<Vec<VirtualKeyCode>> = key_names.split(',').map(|key_name| key_code_from_name(key_name.trim())).collect();
+            match keys {
+                Some(keys) => self.bind(action, keys),
+                None => warn!("Ignoring binding with an unknown key: {}", line),
+            }
+        }
+    }
+}
+
+// winit has no way to parse key codes, so the keys that make sense to bind are listed here
+fn key_code_from_name(name: &str) -> Option<VirtualKeyCode> {
+    use VirtualKeyCode::*;
+    let key_code = match name {
+        "A" => A, "B" => B, "C" => C, "D" => D, "E" => E, "F" => F, "G" => G, "H" => H, "I" => I,
+        "J" => J, "K" => K, "L" => L, "M" => M, "N" => N, "O" => O, "P" => P, "Q" => Q, "R" => R,
+        "S" => S, "T" => T, "U" => U, "V" => V, "W" => W, "X" => X, "Y" => Y, "Z" => Z,
+        "Key0" => Key0, "Key1" => Key1, "Key2" => Key2, "Key3" => Key3, "Key4" => Key4,
+        "Key5" => Key5, "Key6" => Key6, "Key7" => Key7, "Key8" => Key8, "Key9" => Key9,
+        "F1" => F1, "F2" => F2, "F3" => F3, "F4" => F4, "F5" => F5, "F6" => F6,
+        "F7" => F7, "F8" => F8, "F9" => F9, "F10" => F10, "F11" => F11, "F12" => F12,
+        "Up" => Up, "Down" => Down, "Left" => Left, "Right" => Right,
+        "Space" => Space, "Return" => Return, "Tab" => Tab, "Back" => Back,
+        "Insert" => Insert, "Delete" => Delete, "Home" => Home, "End" => End, "PageUp" => PageUp, "PageDown" => PageDown,
+        "LShift" => LShift, "RShift" => RShift, "LControl" => LControl, "RControl" => RControl, "LAlt" => LAlt, "RAlt" => RAlt,
+        "Semicolon" => Semicolon, "Comma" => Comma, "Period" => Period, "Slash" => Slash, "Backslash" => Backslash,
+        "Minus" => Minus, "Equals" => Equals, "LBracket" => LBracket, "RBracket" => RBracket, "Apostrophe" => Apostrophe, "Grave" => Grave,
+        _ => return None,
+    };
+    Some(key_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_overrides_default_keys() {
+        let mut bindings = InputBindings::default();
+        bindings.apply_config("# zoom with the brackets\nZoomIn = RBracket\n\nZoomOut = LBracket, PageDown");
+        assert_eq!(bindings.keys(InputAction::ZoomIn), &[VirtualKeyCode::RBracket]);
+        assert_eq!(bindings.keys(InputAction::ZoomOut), &[VirtualKeyCode::LBracket, VirtualKeyCode::PageDown]);
+        assert_eq!(bindings.keys(InputAction::MoveForward), &[VirtualKeyCode::W]);
+    }
+
+    #[test]
+    fn test_unknown_action_is_ignored() {
+        let mut bindings = InputBindings::default();
+        bindings.apply_config("Jump = Space\nMoveUp = PageUp");
+        assert_eq!(bindings.keys(InputAction::MoveUp), &[VirtualKeyCode::PageUp]);
+        assert_eq!(bindings.bindings.len(), InputAction::ALL.len());
+    }
+
+    #[test]
+    fn test_binding_with_unknown_key_keeps_the_default() {
+        let mut bindings = InputBindings::default();
+        bindings.apply_config("MoveForward = Up, NotAKey");
+        assert_eq!(bindings.keys(InputAction::MoveForward), &[VirtualKeyCode::W]);
+    }
+}
@@ -4,4 +4,5 @@ mod math;
 pub use math::*;
 mod color;
 pub use color::*;
-pub mod input;
\ No newline at end of file
+pub mod input;
+pub mod input_bindings;
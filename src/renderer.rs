@@ -0,0 +1,166 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::CommandQueue;
+use winit::event::WindowEvent;
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::{Window, WindowId};
+
+use crate::assets::{AssetManager, Camera};
+use crate::assets::demo_scenes::{Actor, add_lights_to_parent, load_pbr_pipeline, SpawnModel};
+use crate::assets::gltf_loader::{GltfSource, PivotMode};
+use crate::assets::light_source::PointLight;
+use crate::assets::material_server::MaterialServer;
+use crate::assets::render_object::Transform;
+use crate::ecs_engine::EcsEngine;
+use crate::etna::material_pipeline::DescriptorManager;
+use crate::etna::GraphicsSettings;
+use crate::rehnda_core::Vec3;
+use crate::ui::UiVisibility;
+
+/// What is in the scene when the renderer starts
+#[derive(Debug, Clone, Default)]
+pub enum InitialScene {
+    // nothing but a camera, everything is added through the renderer
+    #[default]
+    Empty,
+    // the spheres, helmet and floor the engine is developed against
+    Demo,
+    // a RON scene file, reloaded whenever it changes
+    File(PathBuf),
+}
+
+/// Chosen when the renderer is created, the rest of the graphics settings are changed from the debug ui
+#[derive(Debug, Clone, Default)]
+pub struct RendererSettings {
+    pub scene: InitialScene,
+    // presents in HDR10 or scRGB when the display supports it
+    pub hdr_output: bool,
+    // the debug ui starts shown, it can always be toggled with its key
+    pub show_ui: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderError {
+    // the glTF file couldn't be read, with why
+    ModelLoad(String),
+    // the window has no area, so the frame was updated but nothing was drawn
+    WindowMinimized,
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::ModelLoad(err) => write!(f, "Failed to load model: {}", err),
+            RenderError::WindowMinimized => write!(f, "The window is minimized"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+/// A model added with [Renderer::load_gltf]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ModelId(Entity);
+
+/// A light added with [Renderer::add_light]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LightId(Entity);
+
+/// Draws a scene to a window, for embedding the engine in another application. The window's events must be passed to
+/// [Renderer::handle_window_event] for the camera controls and debug ui to respond to them
+pub struct Renderer {
+    engine: EcsEngine,
+}
+
+impl Renderer {
+    pub fn new(window: Window, event_loop: &EventLoopWindowTarget<()>, settings: RendererSettings) -> Renderer {
+        let mut engine = EcsEngine::new(window, event_loop, settings.scene, settings.hdr_output);
+        engine.world_mut().resource_mut::<UiVisibility>().show_ui = settings.show_ui;
+        Renderer {
+            engine,
+        }
+    }
+
+    // reads and uploads the model's meshes, materials and lights, placing them in the scene at the transform with the
+    // pivot mode choosing which point of the model the transform places. Blocks until the upload has finished
+    pub fn load_gltf(&mut self, path: &Path, transform: Transform, pivot_mode: PivotMode) -> Result<ModelId, RenderError> {
+        let source = GltfSource::try_read(path, &mut |_| {})
+            .map_err(RenderError::ModelLoad)?
+            .with_pivot_mode(pivot_mode);
+        let world = self.engine.world_mut();
+        let graphics_settings = *world.resource::<GraphicsSettings>();
+        let pipeline = load_pbr_pipeline(&mut world.resource_mut::<MaterialServer>(), &graphics_settings);
+        let meshes = world.resource_scope(|world, mut descriptor_manager: Mut<DescriptorManager>| {
+            world.resource_scope(|world, mut asset_manager: Mut<AssetManager>| {
+                asset_manager.upload_gltf(&source, &mut descriptor_manager, &graphics_settings, world.resource::<MaterialServer>(), pipeline)
+            })
+        });
+        let name = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+
+        let mut command_queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut command_queue, world);
+        let mut entity = commands.spawn_model(meshes.as_slice(), transform, name);
+        add_lights_to_parent(&mut entity, &source.lights());
+        let model = ModelId(entity.id());
+        command_queue.apply(world);
+        Ok(model)
+    }
+
+    pub fn add_light(&mut self, position: Vec3, light: PointLight) -> LightId {
+        let entity = self.engine.world_mut().spawn((
+            Actor {
+                name: "Light".to_string(),
+            },
+            Transform {
+                translation: position,
+                ..Default::default()
+            },
+            light,
+        ));
+        LightId(entity.id())
+    }
+
+    // yaw and pitch are in degrees, a yaw of 0 looks along +x and -90 along -z. The demo and file scenes place the
+    // camera themselves when the first frame is rendered
+    pub fn set_camera(&mut self, position: Vec3, yaw: f32, pitch: f32) {
+        let mut camera = self.engine.world_mut().resource_mut::<Camera>();
+        camera.position = position;
+        camera.yaw = yaw;
+        camera.pitch = pitch.clamp(-89.0, 89.0);
+        camera.front = Vec3::new(
+            camera.yaw.to_radians().cos() * camera.pitch.to_radians().cos(),
+            camera.pitch.to_radians().sin(),
+            camera.yaw.to_radians().sin() * camera.pitch.to_radians().cos(),
+        ).normalize();
+    }
+
+    // updates the scene and draws it to every window
+    pub fn render_frame(&mut self) -> Result<(), RenderError> {
+        self.engine.render();
+        if self.engine.is_minimized() {
+            return Err(RenderError::WindowMinimized);
+        }
+        Ok(())
+    }
+
+    // events for windows added with add_window are accepted too
+    pub fn handle_window_event(&mut self, window_id: WindowId, window_event: &WindowEvent) {
+        self.engine.handle_window_event(window_id, window_event);
+    }
+
+    // opens another window rendering the scene from its own camera
+    pub fn add_window(&mut self, window: Window) {
+        self.engine.add_secondary_window(window);
+    }
+
+    // the window the renderer was created with, as opposed to one added with add_window
+    pub fn is_main_window(&self, window_id: WindowId) -> bool {
+        self.engine.is_main_window(window_id)
+    }
+
+    pub fn close_window(&mut self, window_id: WindowId) {
+        self.engine.close_secondary_window(window_id);
+    }
+}
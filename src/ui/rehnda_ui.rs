@@ -1,18 +1,140 @@
+use std::path::Path;
+
 use bevy_ecs::prelude::*;
-use bevy_ecs::system::{NonSendMut, Query};
-use egui::{DragValue, Separator, Ui};
+use bevy_ecs::entity::Entities;
+use bevy_ecs::system::{NonSendMut, Query, SystemParam};
+use bevy_hierarchy::{Children, Parent};
+use enumflags2::BitFlags;
+use log::{error, info};
+use egui::{Checkbox, Color32, ComboBox, DragValue, ProgressBar, Sense, Separator, Slider, Ui};
 
 use crate::ecs_engine::EtnaWindow;
-use crate::assets::Camera;
+use crate::etna::{AnisotropyLevel, CommandBufferReuse, DebugRenderMode, DefaultSampler, DeviceInfo, GraphicsSettings, is_validation_active, PhysicalDeviceRes, RenderStats, Swapchain, validation_message_counts};
+use crate::rehnda_core::input::InputState;
+use crate::rehnda_core::input_bindings::InputAction;
+use crate::assets::{AssetManager, Camera, DepthPick};
 use crate::assets::demo_scenes::{Actor, ShouldDrawDebug};
-use crate::assets::light_source::PointLight;
-use crate::assets::render_object::{Transform};
+use crate::assets::model_loader::PendingModels;
+use crate::assets::light_source::{EnvironmentSettings, Fog, FogMode, PointLight};
+use crate::assets::render_object::{MaterialHandle, MeshGroup, PbrMaterialFeatureFlags, RenderObject, Transform};
+use crate::rehnda_core::ColorRgbaF;
 use crate::ui::ui_painter::{EguiOutput, ScreenState};
 
-pub fn ui_builder_system(mut camera: ResMut<Camera>, mut actors: Query<(&Actor, &mut Transform), With<ShouldDrawDebug>>, mut lights: Query<&mut PointLight>, egui_ctx: NonSend<egui::Context>, mut winit_state: NonSendMut<egui_winit::State>, mut ui_output: ResMut<EguiOutput>, window: Res<EtnaWindow>) {
+// where the environment bake button writes, load it by using the directory as a scene's environment
+const BAKED_ENVIRONMENT_PATH: &str = "baked_environment";
+
+/// Hides the egui overlay for clean captures, while hidden the ui is neither built, updated nor drawn and doesn't
+/// take mouse input from the scene
+#[derive(Resource)]
+pub struct UiVisibility {
+    pub show_ui: bool,
+}
+
+impl Default for UiVisibility {
+    fn default() -> Self {
+        Self {
+            show_ui: true,
+        }
+    }
+}
+
+/// The entity whose materials are shown in the material editor, an actor chosen from the list or the last object
+/// picked in the scene
+#[derive(Resource, Default)]
+pub struct MaterialEditor {
+    pub selected: Option<Entity>,
+}
+
+/// What the material editor needs to find and edit the selected entity's materials
+#[derive(SystemParam)]
+pub struct MaterialEditorParams<'w, 's> {
+    material_editor: ResMut<'w, MaterialEditor>,
+    depth_pick: Res<'w, DepthPick>,
+    actors: Query<'w, 's, (Entity, &'static Actor)>,
+    entities: Query<'w, 's, (Option<&'static RenderObject>, Option<&'static Children>, Option<&'static Parent>)>,
+    mesh_groups: Query<'w, 's, &'static MeshGroup>,
+}
+
+/// The settings the graphics panel edits and the device limits it offers them within
+#[derive(SystemParam)]
+pub struct GraphicsSettingsParams<'w> {
+    graphics_settings: ResMut<'w, GraphicsSettings>,
+    physical_device: PhysicalDeviceRes<'w>,
+}
+
+impl<'w, 's> MaterialEditorParams<'w, 's> {
+    // the materials of the entity's own render object and of any render objects below it, without duplicates
+    fn materials_of(&self, entity: Entity) -> Vec<MaterialHandle> {
+        let mut materials = Vec::new();
+        self.collect_materials(entity, &mut materials);
+        materials.sort();
+        materials.dedup();
+        materials
+    }
+
+    fn collect_materials(&self, entity: Entity, materials: &mut Vec<MaterialHandle>) {
+        let Ok((render_object, children, _)) = self.entities.get(entity) else {
+            return;
+        };
+        materials.extend(render_object.map(|render_object| render_object.material_instance_handle));
+        for child in children.into_iter().flatten() {
+            self.collect_materials(*child, materials);
+        }
+    }
+
+    // picked objects are the primitives of an actor's meshes, so are named after the actor and the mesh
+    fn entity_name(&self, entity: Entity) -> String {
+        if let Ok((_, actor)) = self.actors.get(entity) {
+            return actor.name.clone();
+        }
+        let mut mesh_name = None;
+        let mut ancestor = self.parent_of(entity);
+        while let Some(parent) = ancestor {
+            if let Ok((_, actor)) = self.actors.get(parent) {
+                return match mesh_name {
+                    Some(mesh_name) => format!("{} / {} ({:?})", actor.name, mesh_name, entity),
+                    None => format!("{} ({:?})", actor.name, entity),
+                };
+            }
+            if let Ok(mesh_group) = self.mesh_groups.get(parent) {
+                mesh_name = mesh_name.or(mesh_group.name.as_deref());
+            }
+            ancestor = self.parent_of(parent);
+        }
+        format!("{:?}", entity)
+    }
+
+    fn parent_of(&self, entity: Entity) -> Option<Entity> {
+        self.entities.get(entity).ok()
+            .and_then(|(_, _, parent)| parent)
+            .map(Parent::get)
+    }
+}
+
+pub fn ui_visible(ui_visibility: Res<UiVisibility>) -> bool {
+    ui_visibility.show_ui
+}
+
+pub fn ui_visibility_toggle_system(input_state: Res<InputState>, mut ui_visibility: ResMut<UiVisibility>) {
+    if input_state.is_action_just_down(InputAction::ToggleUi) {
+        ui_visibility.show_ui = !ui_visibility.show_ui;
+    }
+}
+
+pub fn environment_only_toggle_system(input_state: Res<InputState>, mut graphics_settings: ResMut<GraphicsSettings>) {
+    if input_state.is_action_just_down(InputAction::ToggleEnvironmentOnly) {
+        graphics_settings.environment_only = !graphics_settings.environment_only;
+    }
+}
+
+pub fn ui_builder_system(mut camera: ResMut<Camera>, actors: Query<(&Actor, &mut Transform), With<ShouldDrawDebug>>, lights: Query<&mut PointLight>, mut environment_settings: ResMut<EnvironmentSettings>, mut fog: ResMut<Fog>, mut graphics: GraphicsSettingsParams, mut swapchain: ResMut<Swapchain>, egui_ctx: NonSend<egui::Context>, mut winit_state: NonSendMut<egui_winit::State>, mut ui_output: ResMut<EguiOutput>, window: Res<EtnaWindow>, mut asset_manager: ResMut<AssetManager>, pending_models: Res<PendingModels>, mut material_editor: MaterialEditorParams, render_stats: Res<RenderStats>, entities: &Entities) {
     let new_input = winit_state.take_egui_input(&window.winit_window);
+    let device_info = graphics.physical_device.device_info();
     let full_output = egui_ctx.run(new_input, |egui_ctx| {
-        draw_ui(egui_ctx, &mut camera, actors, lights);
+        draw_ui(egui_ctx, &mut camera, actors, lights, &mut environment_settings, &mut fog, &mut graphics.graphics_settings, &mut swapchain, &device_info, &asset_manager, &material_editor.depth_pick);
+        draw_material_editor(egui_ctx, &mut material_editor, &mut asset_manager);
+        draw_loading_models(egui_ctx, &pending_models);
+        draw_render_stats(egui_ctx, &render_stats, &asset_manager, entities.len());
     });
 
     winit_state.handle_platform_output(&window.winit_window,  &egui_ctx, full_output.platform_output);
@@ -24,11 +146,18 @@ pub fn ui_builder_system(mut camera: ResMut<Camera>, mut actors: Query<(&Actor,
     ui_output.texture_delta = full_output.textures_delta;
 }
 
-fn draw_ui(egui_ctx: &egui::Context, camera: &mut Camera, mut actors: Query<(&Actor, &mut Transform), With<ShouldDrawDebug>>, mut lights: Query<(&mut PointLight)>) {
+fn draw_ui(egui_ctx: &egui::Context, camera: &mut Camera, mut actors: Query<(&Actor, &mut Transform), With<ShouldDrawDebug>>, mut lights: Query<&mut PointLight>, environment_settings: &mut EnvironmentSettings, fog: &mut Fog, graphics_settings: &mut GraphicsSettings, swapchain: &mut Swapchain, device_info: &DeviceInfo, asset_manager: &AssetManager, depth_pick: &DepthPick) {
     egui::Window::new("Scene").show(egui_ctx, |ui| {
         ui.heading("Camera");
         ui.label(format!("x: {:.1}, y: {:.1}, z: {:.1}", camera.position.x, camera.position.y, camera.position.z));
         ui.label(format!("yaw: {:.0}, pitch: {:.0}", camera.yaw, camera.pitch));
+        draw_camera_planes(ui, camera);
+        if let Some(position) = depth_pick.world_position {
+            ui.label(format!("picked x: {:.2}, y: {:.2}, z: {:.2}", position.x, position.y, position.z));
+        }
+        if let Some(entity) = depth_pick.entity {
+            ui.label(format!("selected {:?}", entity));
+        }
 
         ui.heading("Objects");
         for (actor, mut transform) in &mut actors {
@@ -38,19 +167,150 @@ fn draw_ui(egui_ctx: &egui::Context, camera: &mut Camera, mut actors: Query<(&Ac
         }
 
         ui.heading("Lights");
-        for (mut light) in &mut lights {
+        for mut light in &mut lights {
             draw_light(ui, &mut light);
         }
+
+        ui.heading("Environment");
+        draw_environment_settings(ui, environment_settings);
+        draw_environment_bake(ui, asset_manager);
+
+        ui.heading("Fog");
+        draw_fog(ui, fog);
+
+        ui.heading("Graphics");
+        draw_graphics_settings(ui, graphics_settings, swapchain, device_info);
+
+        ui.collapsing("About", |ui| draw_device_info(ui, device_info));
     });
 }
 
-fn draw_transform(ui: &mut Ui, transform: &mut Transform) {
+// only borrows the asset manager mutably when a material is edited, so it doesn't read as changed every frame
+fn draw_material_editor(egui_ctx: &egui::Context, params: &mut MaterialEditorParams, asset_manager: &mut ResMut<AssetManager>) {
+    if params.depth_pick.is_changed() && params.depth_pick.entity.is_some() {
+        params.material_editor.selected = params.depth_pick.entity;
+    }
+    egui::Window::new("Materials").default_open(false).show(egui_ctx, |ui| {
+        let selected_text = params.material_editor.selected.map_or("None".to_string(), |entity| params.entity_name(entity));
+        let mut selected = params.material_editor.selected;
+        ComboBox::from_label("Object")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                for (entity, actor) in params.actors.iter() {
+                    ui.selectable_value(&mut selected, Some(entity), &actor.name);
+                }
+            });
+        params.material_editor.selected = selected;
+
+        let Some(entity) = selected else {
+            ui.label("Pick an object or choose one above");
+            return;
+        };
+        for (material_index, material_handle) in params.materials_of(entity).into_iter().enumerate() {
+            let mut options = *asset_manager.material_ref(&material_handle).options();
+            let mut feature_toggled = None;
+            ui.collapsing(format!("Material {}", material_index), |ui| {
+                let mut base_color = [options.base_color.r, options.base_color.g, options.base_color.b, options.base_color.a];
+                ui.horizontal(|ui| {
+                    ui.label("Base color: ");
+                    ui.color_edit_button_rgba_unmultiplied(&mut base_color);
+                });
+                options.base_color = ColorRgbaF::new_from_array(base_color);
+                ui.add(Slider::new(&mut options.roughness, 0.0..=1.0).text("Roughness"));
+                ui.add(Slider::new(&mut options.metallic, 0.0..=1.0).text("Metallic"));
+                ui.horizontal(|ui| {
+                    ui.label("Emissive: ");
+                    ui.add(DragValue::new(&mut options.emissive.x).speed(0.01).clamp_range(0.0..=f32::MAX));
+                    ui.add(DragValue::new(&mut options.emissive.y).speed(0.01).clamp_range(0.0..=f32::MAX));
+                    ui.add(DragValue::new(&mut options.emissive.z).speed(0.01).clamp_range(0.0..=f32::MAX));
+                });
+                ui.label("Features");
+                let loaded_features = asset_manager.material_ref(&material_handle).loaded_features();
+                for feature in BitFlags::<PbrMaterialFeatureFlags>::all().iter() {
+                    let mut enabled = options.features.contains(feature);
+                    let checkbox = ui.add_enabled(loaded_features.contains(feature), Checkbox::new(&mut enabled, format!("{:?}", feature)))
+                        .on_disabled_hover_text("The material has no texture for this");
+                    if checkbox.changed() {
+                        feature_toggled = Some((feature, enabled));
+                    }
+                }
+            });
+            if let Some((feature, enabled)) = feature_toggled {
+                asset_manager.set_material_feature(&material_handle, feature, enabled);
+            } else if options != *asset_manager.material_ref(&material_handle).options() {
+                asset_manager.update_material_options(&material_handle, &options);
+            }
+        }
+    });
+}
+
+fn draw_loading_models(egui_ctx: &egui::Context, pending_models: &PendingModels) {
+    if pending_models.is_empty() {
+        return;
+    }
+    egui::Window::new("Loading").show(egui_ctx, |ui| {
+        for (name, progress) in pending_models.progress() {
+            ui.label(name);
+            ui.add(ProgressBar::new(progress.fraction()).text(format!("{}/{} textures", progress.loaded_textures, progress.total_textures)));
+        }
+    });
+}
+
+fn draw_render_stats(egui_ctx: &egui::Context, render_stats: &RenderStats, asset_manager: &AssetManager, entity_count: u32) {
+    egui::Window::new("Statistics").default_open(false).show(egui_ctx, |ui| {
+        ui.label(format!("Triangles: {}", render_stats.triangles));
+        ui.label(format!("Draw calls: {}", render_stats.draw_calls));
+        ui.label(format!("Objects visible: {}, culled: {}", render_stats.visible_objects, render_stats.culled_objects));
+        ui.label(format!("Meshes: {}, materials: {}, textures: {}", asset_manager.mesh_count(), asset_manager.material_count(), asset_manager.texture_count()));
+        ui.label(format!("Entities: {}", entity_count));
+    });
+}
+
+fn draw_device_info(ui: &mut Ui, device_info: &DeviceInfo) {
+    ui.label(format!("GPU: {}", device_info.device_name));
+    ui.label(format!("Type: {:?}", device_info.device_type));
+    ui.label(format!("Vulkan: {}", device_info.api_version));
+    ui.label(format!("Driver: {}", device_info.driver_version));
+    ui.label(format!("Vendor: 0x{:04X}, device: 0x{:04X}", device_info.vendor_id, device_info.device_id));
+    if is_validation_active() {
+        let counts = validation_message_counts();
+        let color = if counts.errors > 0 { Color32::RED } else if counts.warnings > 0 { Color32::YELLOW } else { ui.visuals().text_color() };
+        ui.colored_label(color, format!("Validation errors: {}, warnings: {}", counts.errors, counts.warnings));
+    } else {
+        ui.label("Validation layers disabled");
+    }
+}
+
+fn draw_camera_planes(ui: &mut Ui, camera: &mut Camera) {
+    let mut z_near = camera.z_near();
+    let mut z_far = camera.z_far();
+    ui.horizontal(|ui| {
+        ui.label("Near: ");
+        ui.add(DragValue::new(&mut z_near).speed(0.01).clamp_range(0.001..=f32::MAX));
+        ui.label("Far: ");
+        ui.add(DragValue::new(&mut z_far).speed(10.0).clamp_range(0.001..=f32::MAX));
+    });
+    // ignore edits that would leave the near plane beyond the far plane
+    if z_near != camera.z_near() && z_near < camera.z_far() {
+        camera.set_near(z_near);
+    }
+    if z_far != camera.z_far() && z_far > camera.z_near() {
+        camera.set_far(z_far);
+    }
+}
+
+fn draw_transform(ui: &mut Ui, transform: &mut Mut<Transform>) {
+    let mut translation = transform.translation;
     ui.horizontal(|ui| {
         ui.label("Translation: ");
-        ui.add(DragValue::new(&mut transform.translation.x).speed(0.03));
-        ui.add(DragValue::new(&mut transform.translation.y).speed(0.03));
-        ui.add(DragValue::new(&mut transform.translation.z).speed(0.03));
+        ui.add(DragValue::new(&mut translation.x).speed(0.03));
+        ui.add(DragValue::new(&mut translation.y).speed(0.03));
+        ui.add(DragValue::new(&mut translation.z).speed(0.03));
     });
+    // only written when edited, so objects left alone don't read as changed
+    if translation != transform.translation {
+        transform.translation = translation;
+    }
 }
 
 fn draw_light(ui: &mut Ui, light: &mut PointLight) {
@@ -66,4 +326,142 @@ fn draw_light(ui: &mut Ui, light: &mut PointLight) {
     });
     light.emissivity = emissivity;
     light.light_color = color;
+}
+
+fn draw_environment_settings(ui: &mut Ui, environment_settings: &mut EnvironmentSettings) {
+    ui.add(Slider::new(&mut environment_settings.intensity, 0.0..=10.0).text("Intensity"));
+    ui.add(Slider::new(&mut environment_settings.rotation_yaw_degrees, -180.0..=180.0).text("Rotation"));
+    // only lights the scene while no environment is loaded
+    let mut ambient_color = environment_settings.ambient_color.to_array();
+    ui.horizontal(|ui| {
+        ui.label("Ambient color: ");
+        ui.color_edit_button_rgb(&mut ambient_color);
+    });
+    environment_settings.ambient_color = ambient_color.into();
+    ui.add(Slider::new(&mut environment_settings.ambient_intensity, 0.0..=1.0).text("Ambient intensity"));
+}
+
+fn draw_environment_bake(ui: &mut Ui, asset_manager: &AssetManager) {
+    if ui.button(format!("Bake to {}", BAKED_ENVIRONMENT_PATH)).clicked() {
+        match asset_manager.export_global_light_map(Path::new(BAKED_ENVIRONMENT_PATH)) {
+            Ok(()) => info!("Baked the environment to {}", BAKED_ENVIRONMENT_PATH),
+            Err(err) => error!("Failed to bake the environment: {}", err),
+        }
+    }
+}
+
+fn draw_fog(ui: &mut Ui, fog: &mut Fog) {
+    ComboBox::from_label("Mode")
+        .selected_text(format!("{:?}", fog.mode))
+        .show_ui(ui, |ui| {
+            for mode in FogMode::ALL {
+                ui.selectable_value(&mut fog.mode, mode, format!("{:?}", mode));
+            }
+        });
+    let mut color = fog.color.to_array();
+    ui.horizontal(|ui| {
+        ui.label("Color: ");
+        ui.color_edit_button_rgb(&mut color);
+    });
+    fog.color = color.into();
+    ui.add(Slider::new(&mut fog.density, 0.0..=0.5).text("Density"));
+    ui.horizontal(|ui| {
+        ui.label("Start: ");
+        ui.add(DragValue::new(&mut fog.start).speed(0.5).clamp_range(0.0..=fog.end));
+        ui.label("End: ");
+        ui.add(DragValue::new(&mut fog.end).speed(0.5).clamp_range(fog.start..=f32::MAX));
+    });
+}
+
+fn draw_graphics_settings(ui: &mut Ui, graphics_settings: &mut GraphicsSettings, swapchain: &mut Swapchain, device_info: &DeviceInfo) {
+    ui.checkbox(&mut graphics_settings.logarithmic_depth, "Logarithmic depth");
+    // every pipeline is rebuilt when this changes
+    let sample_rate_shading_available = device_info.sample_rate_shading_supported && graphics_settings.is_msaa_enabled();
+    ui.add_enabled(sample_rate_shading_available, Checkbox::new(&mut graphics_settings.sample_rate_shading_enabled, "Sample rate shading"))
+        .on_disabled_hover_text("Needs msaa and a device supporting sample rate shading");
+    ui.checkbox(&mut graphics_settings.occlusion_culling, "Occlusion culling");
+    ui.checkbox(&mut graphics_settings.normal_mapping, "Normal mapping");
+    ui.checkbox(&mut graphics_settings.environment_only, "Environment only");
+    ComboBox::from_label("Debug view")
+        .selected_text(format!("{:?}", graphics_settings.debug_render_mode))
+        .show_ui(ui, |ui| {
+            for mode in DebugRenderMode::ALL {
+                ui.selectable_value(&mut graphics_settings.debug_render_mode, mode, format!("{:?}", mode));
+            }
+        });
+    let mut render_scale = swapchain.render_scale;
+    if ui.add(Slider::new(&mut render_scale, 0.25..=1.0).text("Render scale")).changed() {
+        // the scene targets are resized on recreation
+        swapchain.render_scale = render_scale;
+        swapchain.needs_recreation = true;
+    }
+    ui.add(Slider::new(&mut graphics_settings.frame_rate_cap, 0..=240).text("Frame rate cap"))
+        .on_hover_text("0 is uncapped");
+    ui.checkbox(&mut graphics_settings.wireframe_overlay, "Wireframe overlay")
+        .on_hover_text("ToggleWireframe, X by default, shows it on just the picked object");
+    ui.checkbox(&mut graphics_settings.texture_streaming, "Texture streaming")
+        .on_hover_text("Applies to models loaded after the change");
+    ComboBox::from_label("Command buffers")
+        .selected_text(format!("{:?}", graphics_settings.command_buffer_reuse))
+        .show_ui(ui, |ui| {
+            for reuse in CommandBufferReuse::ALL {
+                ui.selectable_value(&mut graphics_settings.command_buffer_reuse, reuse, format!("{:?}", reuse));
+            }
+        })
+        .response
+        .on_hover_text("Reusing them records the scene again only once it changes");
+    // only applies to textures created after the change
+    ComboBox::from_label("Anisotropy")
+        .selected_text(format!("{:?}", graphics_settings.anisotropy_level))
+        .show_ui(ui, |ui| {
+            for level in AnisotropyLevel::ALL {
+                ui.selectable_value(&mut graphics_settings.anisotropy_level, level, format!("{:?}", level));
+            }
+        });
+    ComboBox::from_label("Default sampler")
+        .selected_text(format!("{:?}", graphics_settings.default_sampler))
+        .show_ui(ui, |ui| {
+            for default_sampler in DefaultSampler::ALL {
+                ui.selectable_value(&mut graphics_settings.default_sampler, default_sampler, format!("{:?}", default_sampler));
+            }
+        })
+        .response
+        .on_hover_text("Filtering for model textures that don't specify it, applies to models loaded after the change");
+    ComboBox::from_label("Max texture size")
+        .selected_text(format!("{}", graphics_settings.max_texture_dimension))
+        .show_ui(ui, |ui| {
+            for dimension in MAX_TEXTURE_DIMENSIONS.into_iter().filter(|dimension| *dimension < device_info.max_image_dimension) {
+                ui.selectable_value(&mut graphics_settings.max_texture_dimension, dimension, format!("{}", dimension));
+            }
+            ui.selectable_value(&mut graphics_settings.max_texture_dimension, device_info.max_image_dimension, format!("{} (device max)", device_info.max_image_dimension));
+        });
+    ui.checkbox(&mut graphics_settings.transparent_background, "Transparent background")
+        .on_hover_text("Hides the sky box so screenshots are transparent where nothing is drawn");
+    ui.checkbox(&mut graphics_settings.auto_exposure, "Auto exposure")
+        .on_hover_text("Adapts the exposure to the brightness of the scene");
+    if graphics_settings.auto_exposure {
+        ui.add(Slider::new(&mut graphics_settings.auto_exposure_min_ev, -10.0..=graphics_settings.auto_exposure_max_ev).text("Min EV"));
+        ui.add(Slider::new(&mut graphics_settings.auto_exposure_max_ev, graphics_settings.auto_exposure_min_ev..=20.0).text("Max EV"));
+        ui.add(Slider::new(&mut graphics_settings.auto_exposure_adaptation_speed, 0.1..=10.0).logarithmic(true).text("Adaptation speed"));
+    }
+    if swapchain.output_encoding.is_hdr() {
+        ui.add(Slider::new(&mut graphics_settings.hdr_paper_white_nits, 80.0..=500.0).text("Paper white (nits)"));
+        ui.add(Slider::new(&mut graphics_settings.hdr_peak_nits, 400.0..=4000.0).text("Peak luminance (nits)"));
+    }
+    draw_gamma_test_pattern(ui, swapchain);
+}
+
+// the choices offered below the device's own limit
+const MAX_TEXTURE_DIMENSIONS: [u32; 4] = [1024, 2048, 4096, 8192];
+
+// sRGB 128 gray, if any stage applies the gamma curve twice (or not at all) a screenshot of the swatch won't read 128, 128, 128
+const GAMMA_TEST_GRAY: u8 = 128;
+
+fn draw_gamma_test_pattern(ui: &mut Ui, swapchain: &Swapchain) {
+    ui.label(format!("Swapchain format: {:?}, {:?} output", swapchain.image_format, swapchain.output_encoding));
+    ui.horizontal(|ui| {
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(32.0, 16.0), Sense::hover());
+        ui.painter().rect_filled(rect, 0.0, Color32::from_gray(GAMMA_TEST_GRAY));
+        ui.label(format!("Gamma test, should read {0}, {0}, {0}", GAMMA_TEST_GRAY));
+    });
 }
\ No newline at end of file
@@ -2,14 +2,14 @@ use ahash::AHashMap;
 use ash::vk;
 use ash::vk::DescriptorSet;
 use bevy_ecs::prelude::Resource;
-use egui::{ClippedPrimitive, Color32, ImageData, Rect, TextureId, TextureOptions, TexturesDelta};
+use egui::{ClippedPrimitive, Color32, ImageData, Rect, TextureFilter, TextureId, TextureOptions, TexturesDelta};
 use egui::epaint::{Primitive, Vertex};
 use log::info;
 
-use crate::etna::{CommandPool, Device, GraphicsSettings, HostMappedBuffer, HostMappedBufferCreateInfo, PhysicalDevice, SamplerOptions, Swapchain, TexSamplerOptions, Texture, TextureCreateInfo};
+use crate::etna::{CommandPool, DeletionQueue, Device, GraphicsSettings, HostMappedBuffer, HostMappedBufferCreateInfo, PhysicalDevice, SamplerOptions, Swapchain, Texture, TextureCreateInfo};
 use crate::etna::material_pipeline::DescriptorManager;
 use crate::rehnda_core::ConstPtr;
-use crate::ui::ui_pipeline::{egui_pipeline, UiPipeline};
+use crate::ui::ui_pipeline::{egui_pipeline, UiPipeline, UiPushConstants};
 
 #[derive(Resource)]
 pub struct UiPainter {
@@ -17,10 +17,7 @@ pub struct UiPainter {
     descriptor_manager: DescriptorManager,
     pipeline: UiPipeline,
     textures: AHashMap<TextureId, (Texture, vk::DescriptorSet)>,
-    texture_free_queue: Vec<Texture>,
     ui_meshes: Vec<UiMesh>,
-    mesh_destroy_queue: Vec<HostMappedBuffer>,
-    ui_mesh_destroy_queue: Vec<UiMesh>,
 }
 
 #[derive(Resource, Default)]
@@ -40,33 +37,31 @@ struct UiMesh {
 
 impl UiPainter {
     pub fn create(device: ConstPtr<Device>, graphics_settings: &GraphicsSettings, swapchain: &Swapchain) -> Self {
-        let mut descriptor_manager = DescriptorManager::create(device);
+        let mut descriptor_manager = DescriptorManager::create(device, false);
         UiPainter {
             device,
             ui_meshes: Vec::new(),
             pipeline: egui_pipeline(device, &mut descriptor_manager, graphics_settings, swapchain),
             descriptor_manager,
-            mesh_destroy_queue: Vec::new(),
             textures: AHashMap::new(),
-            texture_free_queue: Vec::new(),
-            ui_mesh_destroy_queue: Vec::new(),
         }
     }
 
-    fn create_ui_texture(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, physical_device: &PhysicalDevice, command_pool: &CommandPool, size: &[usize; 2], texture_options: &TextureOptions, data: &[u8]) -> (Texture, DescriptorSet) {
-        let texture = Texture::create(device, physical_device, command_pool, descriptor_manager, &TextureCreateInfo {
+    fn create_ui_texture(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, size: &[usize; 2], texture_options: &TextureOptions, data: &[u8]) -> (Texture, DescriptorSet) {
+        let texture = Texture::create(device, physical_device, graphics_settings, command_pool, descriptor_manager, &TextureCreateInfo {
             width: size[0] as _,
             height: size[1] as _,
             mip_levels: None,
             data,
+            // egui 0.21 has no wrap mode in its texture options, its uvs never leave the texture so edges are clamped
             sampler_info: SamplerOptions::CreateInfo(
                 vk::SamplerCreateInfo::builder()
                     .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
                     .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
                     .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
                     .anisotropy_enable(false)
-                    .min_filter(vk::Filter::LINEAR)
-                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk_filter(texture_options.minification))
+                    .mag_filter(vk_filter(texture_options.magnification))
                     .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
                     .min_lod(0.0)
                     .max_lod(vk::LOD_CLAMP_NONE)
@@ -85,10 +80,12 @@ impl UiPainter {
         (texture, descriptor_set)
     }
 
-    pub fn update_resources(&mut self, physical_device: &PhysicalDevice, command_pool: &CommandPool, egui_output: &EguiOutput) {
-        self.mesh_destroy_queue.clear();
-        self.ui_mesh_destroy_queue.clear();
-        self.texture_free_queue.clear();
+    // egui's font atlas, missing until the first ui output has been uploaded
+    pub fn font_texture(&self) -> Option<&Texture> {
+        self.textures.get(&TextureId::default()).map(|(texture, _)| texture)
+    }
+
+    pub fn update_resources(&mut self, physical_device: &PhysicalDevice, graphics_settings: &GraphicsSettings, command_pool: &CommandPool, egui_output: &EguiOutput, deletion_queue: &mut DeletionQueue) {
         for (texture_id, image_delta) in egui_output.texture_delta.set.iter() {
             if let Some(po) = image_delta.pos {
                 // TODO copy new data
@@ -96,11 +93,17 @@ impl UiPainter {
             } else {
                 match &image_delta.image {
                     ImageData::Color(color_image) => {
-                        self.textures.insert(*texture_id, Self::create_ui_texture(self.device, &mut self.descriptor_manager, physical_device, command_pool, &color_image.size, &image_delta.options, bytemuck::cast_slice(color_image.pixels.as_slice())));
+                        let replaced_texture = self.textures.insert(*texture_id, Self::create_ui_texture(self.device, &mut self.descriptor_manager, physical_device, graphics_settings, command_pool, &color_image.size, &image_delta.options, bytemuck::cast_slice(color_image.pixels.as_slice())));
+                        if let Some((texture, _)) = replaced_texture {
+                            deletion_queue.queue_deletion(texture);
+                        }
                     }
                     ImageData::Font(font_image) => {
                         let data: Vec<Color32> = font_image.srgba_pixels(None).collect();
-                        self.textures.insert(*texture_id, Self::create_ui_texture(self.device, &mut self.descriptor_manager, physical_device, command_pool, &font_image.size, &image_delta.options, bytemuck::cast_slice(data.as_slice())));
+                        let replaced_texture = self.textures.insert(*texture_id, Self::create_ui_texture(self.device, &mut self.descriptor_manager, physical_device, graphics_settings, command_pool, &font_image.size, &image_delta.options, bytemuck::cast_slice(data.as_slice())));
+                        if let Some((texture, _)) = replaced_texture {
+                            deletion_queue.queue_deletion(texture);
+                        }
                     }
                 }
             }
@@ -134,7 +137,7 @@ impl UiPainter {
                             });
 
                             std::mem::swap(&mut self.ui_meshes.get_mut(i).unwrap().vertex_buffer, &mut new_buffer);
-                            self.mesh_destroy_queue.push(new_buffer);
+                            deletion_queue.queue_deletion(new_buffer);
                         }
                         if self.ui_meshes.get(i).unwrap().index_buffer.size() < required_vertex_buffer_size {
                             let mut new_buffer = HostMappedBuffer::create(self.device, HostMappedBufferCreateInfo {
@@ -142,7 +145,7 @@ impl UiPainter {
                                 usage: vk::BufferUsageFlags::INDEX_BUFFER,
                             });
                             std::mem::swap(&mut self.ui_meshes.get_mut(i).unwrap().index_buffer, &mut new_buffer);
-                            self.mesh_destroy_queue.push(new_buffer);
+                            deletion_queue.queue_deletion(new_buffer);
                         }
                     }
 
@@ -161,16 +164,17 @@ impl UiPainter {
 
         if egui_output.clipped_primitives.len() < self.ui_meshes.len() {
             for _ in 0..(self.ui_meshes.len() - egui_output.clipped_primitives.len()) {
-                self.ui_mesh_destroy_queue.push(self.ui_meshes.pop().unwrap());
+                deletion_queue.queue_deletion(self.ui_meshes.pop().unwrap());
             }
         }
 
         for texture_id in egui_output.texture_delta.free.iter() {
-            self.textures.remove(texture_id).unwrap();
+            let (texture, _) = self.textures.remove(texture_id).unwrap();
+            deletion_queue.queue_deletion(texture);
         }
     }
 
-    pub fn draw(&self, device: &Device, swapchain: &Swapchain, command_buffer: vk::CommandBuffer, egui_output: &EguiOutput) {
+    pub fn draw(&self, device: &Device, swapchain: &Swapchain, graphics_settings: &GraphicsSettings, command_buffer: vk::CommandBuffer, egui_output: &EguiOutput) {
         // bind the pipeline
         unsafe { device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline.pipeline); }
         let viewport = [vk::Viewport::builder()
@@ -195,9 +199,13 @@ impl UiPainter {
                 device.cmd_bind_index_buffer(command_buffer, ui_mesh.index_buffer.vk_buffer(), 0, vk::IndexType::UINT32);
                 let descriptor_sets = &[self.textures.get(&ui_mesh.texture_id).unwrap().1];
                 device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline.pipeline_layout, 0, descriptor_sets, &[]);
-                let screen_size = egui_output.screen_state.size_in_points();
-                let screen_size_data: &[u8] = bytemuck::cast_slice(&screen_size);
-                device.cmd_push_constants(command_buffer, self.pipeline.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, screen_size_data);
+                let push_constants = UiPushConstants {
+                    screen_size: egui_output.screen_state.size_in_points(),
+                    output_encoding: swapchain.output_encoding.shader_value(),
+                    paper_white_nits: graphics_settings.hdr_paper_white_nits,
+                };
+                let push_constants_data: &[u8] = bytemuck::cast_slice(std::slice::from_ref(&push_constants));
+                device.cmd_push_constants(command_buffer, self.pipeline.pipeline_layout, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT, 0, push_constants_data);
 
                 device.cmd_draw_indexed(command_buffer, ui_mesh.index_count, 1, 0, 0, 0);
             }
@@ -248,3 +256,9 @@ impl ScreenState {
     }
 }
 
+fn vk_filter(texture_filter: TextureFilter) -> vk::Filter {
+    match texture_filter {
+        TextureFilter::Nearest => vk::Filter::NEAREST,
+        TextureFilter::Linear => vk::Filter::LINEAR,
+    }
+}
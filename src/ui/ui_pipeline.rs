@@ -3,14 +3,24 @@ use std::mem::size_of;
 use std::path::Path;
 
 use ash::vk;
+use bytemuck_derive::{Pod, Zeroable};
 use egui::epaint::Vertex;
 use memoffset::offset_of;
 
-use crate::etna::{Device, GraphicsSettings, Swapchain};
-use crate::etna::material_pipeline::{DescriptorManager, layout_binding, PipelineCreateInfo, PipelineMultisamplingInfo, PipelineVertexInputDescription, RasterizationOptions};
+use crate::etna::{Device, GraphicsSettings, MsaaSamples, Swapchain};
+use crate::etna::material_pipeline::{BlendMode, DescriptorManager, layout_binding, PipelineCreateInfo, PipelineMultisamplingInfo, PipelineVertexInputDescription, RasterizationOptions};
 use crate::etna::shader::ShaderModule;
 use crate::rehnda_core::ConstPtr;
 
+#[repr(C)]
+#[derive(Zeroable, Pod, Debug, Copy, Clone)]
+pub struct UiPushConstants {
+    pub screen_size: [f32; 2],
+    // the swapchain's OutputEncoding, the ui is shown at paper white on hdr swapchains
+    pub output_encoding: f32,
+    pub paper_white_nits: f32,
+}
+
 pub fn egui_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut DescriptorManager, graphics_settings: &GraphicsSettings, swapchain: &Swapchain) -> UiPipeline {
     let texture_binding_description = descriptor_manager.layout_cache.create_descriptor_layout_for_binding(&[layout_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)]);
     let vert_shader_module = ShaderModule::load_from_file(device, Path::new("shaders/spirv/egui.vert_spv"));
@@ -32,16 +42,17 @@ pub fn egui_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut Descript
         bindings: &[egui_binding_description()],
         attributes: vertex_attributes.as_slice(),
     };
-    // push constant for pushing screen size
+    // push constant for pushing screen size and whether the swapchain encodes to sRGB
     let push_constant = vk::PushConstantRange::builder()
         .offset(0)
-        .size((size_of::<u32>() * 2) as u32)
-        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .size(size_of::<UiPushConstants>() as u32)
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
         .build();
 
+    // the ui renders directly into the single sampled swapchain image
     let multisampling = PipelineMultisamplingInfo {
-        msaa_samples: graphics_settings.msaa_samples,
-        enable_sample_rate_shading: graphics_settings.sample_rate_shading_enabled,
+        msaa_samples: MsaaSamples::X1,
+        enable_sample_rate_shading: false,
     };
 
     let create_info = PipelineCreateInfo {
@@ -55,7 +66,13 @@ pub fn egui_pipeline(device: ConstPtr<Device>, descriptor_manager: &mut Descript
         multisampling,
         rasterization_options: &RasterizationOptions {
             cull_mode: vk::CullModeFlags::NONE,
+            depth_write_enabled: false,
+            blend_mode: BlendMode::Opaque, // the ui pipeline sets up its own alpha blending
+            ..RasterizationOptions::default()
         },
+        depth_compare_op: graphics_settings.depth_compare_op(),
+        // the swapchain image is drawn to without a depth attachment
+        depth_format: vk::Format::UNDEFINED,
     };
 
     create_ui_pipeline(device, &create_info)
@@ -118,9 +135,7 @@ pub fn create_ui_pipeline(device: ConstPtr<Device>, create_info: &PipelineCreate
         .vertex_binding_descriptions(create_info.vertex_input.bindings)
         .vertex_attribute_descriptions(create_info.vertex_input.attributes);
 
-    // let us change viewport and scissor state without rebuilding the pipeline
-    let input_assembly_ci = vk::PipelineInputAssemblyStateCreateInfo::builder()
-        .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+    let input_assembly_ci = create_info.rasterization_options.input_assembly_state();
 
     let viewport = vk::Viewport::builder()
         .x(0.0)
@@ -174,10 +189,11 @@ pub fn create_ui_pipeline(device: ConstPtr<Device>, create_info: &PipelineCreate
         .pass_op(vk::StencilOp::KEEP)
         .compare_op(vk::CompareOp::ALWAYS)
         .build();
+    // the ui is drawn last over the top of the scene, so it doesn't need to depth test against it
     let depth_stencil_ci = vk::PipelineDepthStencilStateCreateInfo::builder()
-        .depth_test_enable(true)
-        .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .depth_test_enable(false)
+        .depth_write_enable(create_info.rasterization_options.depth_write_enabled)
+        .depth_compare_op(create_info.depth_compare_op)
         .depth_bounds_test_enable(false)
         .stencil_test_enable(false)
         .front(stencil_op)
@@ -186,7 +202,7 @@ pub fn create_ui_pipeline(device: ConstPtr<Device>, create_info: &PipelineCreate
     let color_attachment_formats = &[create_info.image_format];
     let mut pipeline_rendering_create_info = vk::PipelineRenderingCreateInfo::builder()
         .color_attachment_formats(color_attachment_formats)
-        .depth_attachment_format(vk::Format::D32_SFLOAT); // TODO don't assume this format
+        .depth_attachment_format(create_info.depth_format);
 
     let set_layouts: Vec<vk::DescriptorSetLayout> = [create_info.global_set_layouts, create_info.additional_descriptor_set_layouts].concat();
     let pipeline_layout_ci = vk::PipelineLayoutCreateInfo::builder()